@@ -94,7 +94,7 @@ impl PrefixMap {
         // output key, count in sorted order
         for (prefix, count) in btree.iter() {
             let line = format!("\"{}\",\"{}\"\n", prefix, count);
-            writer.write(line.as_bytes()).await?;
+            writer.write_all(line.as_bytes()).await?;
             object_count += count;
             if let Some(ref b) = bar {
                 b.inc(1);
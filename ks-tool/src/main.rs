@@ -59,7 +59,7 @@ async fn main() -> Result<(), tokio::io::Error> {
 
     match &cli.cmd {
         Commands::Split { ks, count, output } => {
-            utils::handle_ks_input(ks, *count, &output).await?;
+            utils::handle_ks_input(ks, *count, output).await?;
         },
         Commands::Inventory { region, manifest, ks, concurrency } => {
             utils::inventory_to_ks(region, manifest, ks.as_ref(), *concurrency).await?;
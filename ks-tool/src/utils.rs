@@ -12,7 +12,7 @@ use aws_sdk_s3::Client as S3Client;
 use csv::ReaderBuilder;
 use serde::Deserialize;
 use indicatif::{ProgressBar, style::ProgressStyle};
-use flate2::read::GzDecoder;
+use flate2::read::{GzDecoder, MultiGzDecoder};
 use s3_transfer_manager::manager::S3TransferManager;
 use s3_manifest::inventory::InventoryManifest;
 use crate::arn::Arn;
@@ -28,12 +28,66 @@ const TICK_STRINGS: [&str; 7] = [
     "▪▪▪▪▪",
 ];
 
+const KS_BINARY_MAGIC: &[u8; 4] = b"KSB\0";
+const KS_BINARY_VERSION: u16 = 1;
+
 #[derive(Debug, Deserialize)]
 struct KsRow {
     prefix: String,
     objects: usize,
 }
 
+// decode binary ks exported by s3-fast-list with "--ks-format binary"
+//   header: magic "KSB\0", version u16, reserved u16, count of entries u64
+//   entry:  length of prefix u32, prefix in utf-8, count of objects u64
+fn decode_ks_binary(buf: &[u8]) -> Result<Vec<KsRow>, Error> {
+
+    fn take<'a>(buf: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+        if buf.len() < len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated binary ks file"));
+        }
+        let (head, tail) = buf.split_at(len);
+        *buf = tail;
+        Ok(head)
+    }
+
+    let mut buf = buf;
+    if take(&mut buf, 4)? != KS_BINARY_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a binary ks file"));
+    }
+    let version = u16::from_le_bytes(take(&mut buf, 2)?.try_into().unwrap());
+    if version != KS_BINARY_VERSION {
+        return Err(Error::new(ErrorKind::InvalidData, format!("unsupported binary ks version {version}")));
+    }
+    let _reserved = take(&mut buf, 2)?;
+    let entries = u64::from_le_bytes(take(&mut buf, 8)?.try_into().unwrap()) as usize;
+
+    let mut rows = Vec::with_capacity(entries);
+    for _ in 0..entries {
+        let len = u32::from_le_bytes(take(&mut buf, 4)?.try_into().unwrap()) as usize;
+        let prefix = std::str::from_utf8(take(&mut buf, len)?)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?
+            .to_string();
+        let objects = u64::from_le_bytes(take(&mut buf, 8)?.try_into().unwrap()) as usize;
+        rows.push(KsRow { prefix, objects });
+    }
+
+    Ok(rows)
+}
+
+// decompress ks if it starts with magic of gzip or zstd, otherwise as is
+fn decompress_ks(buf: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if buf.starts_with(b"\x28\xb5\x2f\xfd") {
+        zstd::stream::decode_all(buf.as_slice())
+    } else if buf.starts_with(b"\x1f\x8b") {
+        let mut plain = Vec::new();
+        MultiGzDecoder::new(buf.as_slice()).read_to_end(&mut plain)?;
+        Ok(plain)
+    } else {
+        Ok(buf)
+    }
+}
+
 pub(crate) async fn handle_ks_input(input: &str, splits: usize, output: &str) -> Result<(), Error> {
 
     let mut map: BTreeMap<String, usize> = BTreeMap::new();
@@ -58,14 +112,20 @@ pub(crate) async fn handle_ks_input(input: &str, splits: usize, output: &str) ->
 
     bar.finish_with_message(msg + " .. Done");
 
-    // building in memory btree map for prefix
-    let cursor = Cursor::new(buf);
-    let mut rd = ReaderBuilder::new()
-            .has_headers(false)
-            .double_quote(true)
-            .from_reader(cursor);
+    // ks of s3-fast-list with "--ks-compression"
+    let buf = decompress_ks(buf)?;
 
-    let iter = rd.deserialize();
+    // building in memory btree map for prefix
+    let rows: Box<dyn Iterator<Item = KsRow>> = if buf.starts_with(KS_BINARY_MAGIC) {
+        Box::new(decode_ks_binary(&buf)?.into_iter())
+    } else {
+        let cursor = Cursor::new(buf);
+        let rd = ReaderBuilder::new()
+                .has_headers(false)
+                .double_quote(true)
+                .from_reader(cursor);
+        Box::new(rd.into_deserialize().map(|res| res.unwrap()))
+    };
 
     let bar = ProgressBar::new_spinner();
     bar.enable_steady_tick(Duration::from_millis(200));
@@ -75,8 +135,7 @@ pub(crate) async fn handle_ks_input(input: &str, splits: usize, output: &str) ->
             .tick_strings(&TICK_STRINGS)
     );
 
-    for res in iter {
-        let row: KsRow = res.unwrap();
+    for row in rows {
         total_objects += row.objects;
         map.insert(row.prefix, row.objects);
         bar.inc(1);
@@ -107,8 +166,8 @@ pub(crate) async fn handle_ks_input(input: &str, splits: usize, output: &str) ->
     for (prefix, count) in map.iter() {
         cum_count += count;
         if cum_count > avg_target {
-            writer.write(last_prefix.as_bytes()).await?;
-            writer.write(b"\n").await?;
+            writer.write_all(last_prefix.as_bytes()).await?;
+            writer.write_all(b"\n").await?;
             bar.inc(cum_count as u64);
             cum_count = 0;
         }
@@ -139,7 +198,7 @@ async fn build_and_dump_prefix_map(client: S3Client,
     let mut started = 0;
 
     loop {
-        if set.len() >= max_concurrency || (set.len() > 0 && keys.len() == 0) {
+        if set.len() >= max_concurrency || (!set.is_empty() && keys.is_empty()) {
             // if reached max concurrency
             //   or
             // all keys consumed but still has task in progress
@@ -212,7 +271,7 @@ async fn build_and_dump_prefix_map(client: S3Client,
             });
         }
 
-        if set.len() == 0 && keys.len() == 0 {
+        if set.is_empty() && keys.is_empty() {
             break;
         }
     }
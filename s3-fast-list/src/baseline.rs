@@ -0,0 +1,298 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use arrow::array::{Array, AsArray, RecordBatch};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Int64Type, TimeUnit};
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{Object, ObjectStorageClass};
+use parquet::arrow::arrow_reader::{ArrowReaderMetadata, ArrowReaderOptions, ParquetRecordBatchReaderBuilder};
+use log::{info, error};
+use crate::core::{self, S3TaskContext};
+use crate::tasks_s3;
+use crate::utils;
+
+// columns of list output only, a diff, versions or delimiter output is not a listing of one bucket
+const BASELINE_UNSUPPORTED_COLUMNS: [&str; 3] = ["DiffAttrs", "VersionId", "Type"];
+
+// parquet file of a previous list run, or base directory of its partitioned output
+fn baseline_files(path: &str) -> std::io::Result<Vec<PathBuf>> {
+    let root = Path::new(path);
+    if !root.is_dir() {
+        return Ok(vec![root.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "parquet") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// objects of one record batch, in the shape of a list response
+fn batch_to_objects(batch: &RecordBatch) -> Result<Vec<Object>, String> {
+    if let Some(name) = BASELINE_UNSUPPORTED_COLUMNS.iter().find(|name| batch.column_by_name(name).is_some()) {
+        return Err(format!("column {} found, baseline should be output of list, without --versions or --delimiter", name));
+    }
+    let column = |name: &str, data_type: &DataType| -> Result<std::sync::Arc<dyn Array>, String> {
+        let c = batch.column_by_name(name).ok_or(format!("no {} column", name))?;
+        cast(c, data_type).map_err(|e| format!("column {}: {}", name, e))
+    };
+    let keys = column("Key", &DataType::Utf8)?;
+    let sizes = column("Size", &DataType::Int64)?;
+    // timestamp column of current output, unix seconds of older ones
+    let mtimes = match batch.column_by_name("LastModified").map(|c| c.data_type()) {
+        Some(DataType::Timestamp(unit, _)) if *unit != TimeUnit::Second => {
+            let secs = cast(batch.column_by_name("LastModified").unwrap(), &DataType::Timestamp(TimeUnit::Second, None))
+                .map_err(|e| format!("column LastModified: {}", e))?;
+            cast(&secs, &DataType::Int64).map_err(|e| format!("column LastModified: {}", e))?
+        },
+        _ => column("LastModified", &DataType::Int64)?,
+    };
+    let etags = column("ETag", &DataType::Utf8)?;
+    // storage class is missing in output of older versions
+    let classes = batch.column_by_name("StorageClass").map(|c| cast(c, &DataType::Utf8)).transpose().map_err(|e| e.to_string())?;
+
+    (0..batch.num_rows()).map(|i| {
+        let key = keys.as_string::<i32>().value(i);
+        // etag of list response is quoted, output one is not
+        let etag = etags.as_string::<i32>().value(i);
+        if !etag.is_empty() && !core::is_etag(etag) {
+            return Err(format!("key {}: invalid etag {}", key, etag));
+        }
+        let class = classes.as_ref().map(|c| c.as_string::<i32>().value(i)).filter(|c| !c.is_empty());
+        Ok(Object::builder()
+            .key(key)
+            .size(sizes.as_primitive::<Int64Type>().value(i))
+            .last_modified(DateTime::from_secs(mtimes.as_primitive::<Int64Type>().value(i)))
+            .set_e_tag((!etag.is_empty()).then(|| format!("\"{}\"", etag)))
+            .set_storage_class(class.map(ObjectStorageClass::from))
+            .build())
+    }).collect()
+}
+
+// row groups of each baseline file as (file, row group) units, read one unit per thread,
+// footer of each file is decoded once here and shared by all its row groups
+fn row_groups(files: &[PathBuf]) -> Result<Vec<(PathBuf, ArrowReaderMetadata, usize)>, String> {
+    let mut units = Vec::new();
+    for path in files {
+        let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let metadata = ArrowReaderMetadata::load(&file, ArrowReaderOptions::default()).map_err(|e| format!("{}: {}", path.display(), e))?;
+        units.extend((0..metadata.metadata().num_row_groups()).map(|i| (path.clone(), metadata.clone(), i)));
+    }
+    Ok(units)
+}
+
+// read one row group batch by batch, objects under start prefixes are sent to data map
+fn read_row_group(ctx: &S3TaskContext, path: &Path, metadata: ArrowReaderMetadata, row_group: usize,
+        start_prefixes: &[String]) -> Result<usize, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let reader = ParquetRecordBatchReaderBuilder::new_with_metadata(file, metadata)
+        .with_row_groups(vec![row_group])
+        .build().map_err(|e| e.to_string())?;
+
+    let mut count = 0;
+    for batch in reader {
+        if ctx.is_quit() {
+            break;
+        }
+        let batch = batch.map_err(|e| e.to_string())?;
+        let objects: Vec<Object> = batch_to_objects(&batch)?.into_iter()
+            .filter(|o| o.key().is_some_and(|k| start_prefixes.iter().any(|p| k.starts_with(p.as_str())) && !ctx.is_excluded(k)))
+            .collect();
+        count += objects.len();
+        tasks_s3::send_objects(ctx, &objects);
+    }
+    Ok(count)
+}
+
+/*
+ * task to load parquet output of a previous run into data map as one side of diff, in place of list task.
+ * row groups of all files are read in parallel, up to one per worker thread of runtime, each streamed batch by batch.
+ */
+pub async fn baseline_main_task(ctx: &S3TaskContext, baseline: &str, start_prefixes: Vec<String>, threads: usize) {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("Baseline Task - {} - started, baseline {}", ctx.s3_bucket_name, baseline);
+    let started = Instant::now();
+
+    let baseline_c = baseline.to_string();
+    let units = tokio::task::spawn_blocking(move || {
+        let files = baseline_files(&baseline_c).map_err(|e| format!("{}: {}", baseline_c, e))?;
+        row_groups(&files).map(|units| (files.len(), units))
+    }).await.unwrap_or_else(|e| Err(e.to_string()));
+
+    let mut failed = None;
+    let mut count = 0;
+    let (files, row_groups) = match units {
+        Ok((files, units)) => {
+            info!("Baseline Task - {} - {} row groups in {} files, read by {} threads", ctx.s3_bucket_name, units.len(), files, threads);
+            let start_prefixes = Arc::new(start_prefixes);
+            let row_groups = units.len();
+            let mut set = tokio::task::JoinSet::new();
+            for (path, metadata, row_group) in units {
+                while set.len() >= threads && failed.is_none() {
+                    match set.join_next().await {
+                        Some(Ok(Ok(n))) => count += n,
+                        Some(Ok(Err(e))) => failed = Some(e),
+                        Some(Err(e)) => failed = Some(e.to_string()),
+                        None => {},
+                    }
+                }
+                ctx.g_state.wait_for_memory().await;
+                if ctx.is_quit() || failed.is_some() {
+                    break;
+                }
+                let task_ctx = ctx.clone();
+                let start_prefixes = start_prefixes.clone();
+                set.spawn_blocking(move || {
+                    read_row_group(&task_ctx, &path, metadata, row_group, &start_prefixes)
+                        .map_err(|e| format!("{} row group {}: {}", path.display(), row_group, e))
+                });
+            }
+            while let Some(res) = set.join_next().await {
+                match res {
+                    Ok(Ok(n)) => count += n,
+                    Ok(Err(e)) => failed = failed.or(Some(e)),
+                    Err(e) => failed = failed.or(Some(e.to_string())),
+                }
+            }
+            (files, row_groups)
+        },
+        Err(e) => {
+            failed = Some(e);
+            (0, 0)
+        },
+    };
+
+    match failed {
+        None => {
+            let elapsed = started.elapsed().as_secs_f64();
+            info!("Baseline Task - {} - {} objects loaded from {} row groups of {} files in {:.1}s, {} objects/s", ctx.s3_bucket_name,
+                utils::fmt_count(count), row_groups, files, elapsed, utils::fmt_count((count as f64 / elapsed.max(0.001)) as usize));
+            if ctx.is_running() {
+                ctx.complete();
+                info!("Baseline Task - {} - completed", ctx.s3_bucket_name);
+                tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+            }
+        },
+        Some(e) => {
+            error!("Baseline Task - {} - unable to read baseline {}", ctx.s3_bucket_name, e);
+            ctx.g_state.set_list_failed();
+            if ctx.is_running() {
+                ctx.complete();
+            }
+        },
+    }
+    info!("Baseline Task - {} - quit", ctx.s3_bucket_name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{StringArray, TimestampMillisecondArray, TimestampSecondArray, UInt64Array};
+
+    #[test]
+    fn test_batch_to_objects() {
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Key", Arc::new(StringArray::from(vec!["a", "b"])) as Arc<dyn Array>),
+            ("Size", Arc::new(UInt64Array::from(vec![1, 2]))),
+            ("LastModified", Arc::new(UInt64Array::from(vec![1704067200, 0]))),
+            ("ETag", Arc::new(StringArray::from(vec!["d41d8cd98f00b204e9800998ecf8427e", ""]))),
+            ("StorageClass", Arc::new(StringArray::from(vec!["GLACIER", "STANDARD"]))),
+        ]).unwrap();
+        let objects = batch_to_objects(&batch).unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].e_tag(), Some("\"d41d8cd98f00b204e9800998ecf8427e\""));
+        assert_eq!(objects[0].last_modified().map(|t| t.secs()), Some(1704067200));
+        assert_eq!(objects[0].storage_class(), Some(&ObjectStorageClass::Glacier));
+        assert_eq!(objects[1].e_tag(), None);
+        assert_eq!(objects[1].size(), Some(2));
+
+        // not an output of list, rejected rather than taken as an object without etag
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Key", Arc::new(StringArray::from(vec!["a", "b"])) as Arc<dyn Array>),
+            ("Size", Arc::new(UInt64Array::from(vec![1, 2]))),
+            ("LastModified", Arc::new(UInt64Array::from(vec![0, 0]))),
+            ("ETag", Arc::new(StringArray::from(vec!["d41d8cd98f00b204e9800998ecf8427e-2", "d41d8cd98f00b204e9800998ecf8427e-abc"]))),
+        ]).unwrap();
+        assert_eq!(batch_to_objects(&batch).unwrap_err(), "key b: invalid etag d41d8cd98f00b204e9800998ecf8427e-abc");
+
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Key", Arc::new(StringArray::from(vec!["a"])) as Arc<dyn Array>),
+            ("DiffAttrs", Arc::new(StringArray::from(vec!["size"]))),
+        ]).unwrap();
+        assert!(batch_to_objects(&batch).is_err());
+
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Key", Arc::new(StringArray::from(vec!["a"])) as Arc<dyn Array>),
+            ("Size", Arc::new(UInt64Array::from(vec![1]))),
+            ("LastModified", Arc::new(TimestampSecondArray::from(vec![1704067200]).with_timezone("UTC"))),
+            ("ETag", Arc::new(StringArray::from(vec![""]))),
+        ]).unwrap();
+        assert_eq!(batch_to_objects(&batch).unwrap()[0].last_modified().map(|t| t.secs()), Some(1704067200));
+        let batch = RecordBatch::try_from_iter(vec![
+            ("Key", Arc::new(StringArray::from(vec!["a"])) as Arc<dyn Array>),
+            ("Size", Arc::new(UInt64Array::from(vec![1]))),
+            ("LastModified", Arc::new(TimestampMillisecondArray::from(vec![1704067200000]))),
+            ("ETag", Arc::new(StringArray::from(vec![""]))),
+        ]).unwrap();
+        assert_eq!(batch_to_objects(&batch).unwrap()[0].last_modified().map(|t| t.secs()), Some(1704067200));
+    }
+
+    #[test]
+    fn test_read_row_groups() {
+        let dir = std::env::temp_dir().join(format!("baseline-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("part")).unwrap();
+        // 2 files of 5 objects each, in row groups of 2 objects
+        for (i, path) in [dir.join("a.parquet"), dir.join("part/b.parquet")].iter().enumerate() {
+            let keys: Vec<String> = (0..5).map(|j| format!("dir/{}-{}", i, j)).collect();
+            let batch = RecordBatch::try_from_iter(vec![
+                ("Key", Arc::new(StringArray::from(keys)) as Arc<dyn Array>),
+                ("Size", Arc::new(UInt64Array::from((0..5).map(|j| (i * 5 + j) as u64).collect::<Vec<_>>()))),
+                ("LastModified", Arc::new(UInt64Array::from(vec![1704067200; 5]))),
+                ("ETag", Arc::new(StringArray::from(vec!["d41d8cd98f00b204e9800998ecf8427e"; 5]))),
+            ]).unwrap();
+            let props = parquet::file::properties::WriterProperties::builder().set_max_row_group_size(2).build();
+            let mut writer = parquet::arrow::ArrowWriter::try_new(std::fs::File::create(path).unwrap(), batch.schema(), Some(props)).unwrap();
+            writer.write(&batch).unwrap();
+            writer.close().unwrap();
+        }
+
+        let files = baseline_files(dir.to_str().unwrap()).unwrap();
+        let units = row_groups(&files).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(units.len(), 6);
+
+        let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+        let config = aws_sdk_s3::Config::builder().behavior_version(aws_sdk_s3::config::BehaviorVersion::latest()).build();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let ctx = S3TaskContext::with_client("b", aws_sdk_s3::Client::from_conf(config), tx, core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE, g_state);
+        let start_prefixes = vec!["dir/".to_string()];
+        let count: usize = units.into_iter()
+            .map(|(path, metadata, row_group)| read_row_group(&ctx, &path, metadata, row_group, &start_prefixes).unwrap())
+            .sum();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(count, 10);
+
+        let mut objects = Vec::new();
+        while let Ok(hash) = rx.try_recv() {
+            for (prefix, items) in hash {
+                objects.extend(items.iter().map(|(name, props)| (core::ObjectKey::encode(&prefix, name).as_str().to_string(), props.size())));
+            }
+        }
+        objects.sort();
+        let expected: Vec<(String, u64)> = (0..10).map(|n| (format!("dir/{}-{}", n / 5, n % 5), n as u64)).collect();
+        assert_eq!(objects, expected);
+    }
+}
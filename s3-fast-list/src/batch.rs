@@ -0,0 +1,135 @@
+use crate::error::TargetsError;
+use crate::utils;
+
+// one bucket of a batch run, line "region,bucket[,endpoint]" of targets file
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchTarget {
+    // empty region is resolved by AWS SDK defaults
+    pub region: Option<String>,
+    pub bucket: String,
+    // custom endpoint of this bucket [default: --endpoint-url]
+    pub endpoint: Option<String>,
+}
+
+// targets of file content, blank lines and "#" comments skipped
+fn parse_targets(content: &str) -> Result<Vec<BatchTarget>, String> {
+    let mut targets = Vec::new();
+    for (n, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let opt = |s: &str| (!s.is_empty()).then(|| s.to_string());
+        let (region, bucket, endpoint) = match fields[..] {
+            [region, bucket] => (region, bucket, ""),
+            [region, bucket, endpoint] => (region, bucket, endpoint),
+            _ => ("", "", ""),
+        };
+        if bucket.is_empty() {
+            return Err(format!("line {}: expect \"region,bucket[,endpoint]\" with a bucket, got \"{}\"", n + 1, line));
+        }
+        let endpoint = opt(endpoint).map(|e| utils::parse_endpoint_url(&e)).transpose().map_err(|e| format!("line {}: {}", n + 1, e))?;
+        targets.push(BatchTarget { region: opt(region), bucket: bucket.to_string(), endpoint });
+    }
+    if targets.is_empty() {
+        return Err("no target found".to_string());
+    }
+    Ok(targets)
+}
+
+pub fn read_targets(filename: &str) -> Result<Vec<BatchTarget>, TargetsError> {
+    let content = std::fs::read_to_string(filename).map_err(|e| TargetsError::new(filename, e.to_string()))?;
+    parse_targets(&content).map_err(|e| TargetsError::new(filename, e))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchStatus {
+    Ok,
+    // list of some key space failed, output is incomplete
+    Failed,
+    // stopped by ctrl-c before done
+    Interrupted,
+    // not started, batch stopped before its turn
+    Skipped,
+}
+
+impl BatchStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            BatchStatus::Ok => "ok",
+            BatchStatus::Failed => "failed",
+            BatchStatus::Interrupted => "interrupted",
+            BatchStatus::Skipped => "skipped",
+        }
+    }
+}
+
+// outcome of one target of batch run
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    pub target: BatchTarget,
+    pub status: BatchStatus,
+    pub objects: usize,
+    pub bytes: u64,
+    // percent of list requests failed
+    pub error_rate: f64,
+    // none with --count-only
+    pub output_file: Option<String>,
+}
+
+// combined summary of all targets, one row each and a total line
+pub fn summary_table(results: &[BatchResult]) -> Vec<String> {
+    let header = ["region", "bucket", "objects", "bytes", "status", "output"].map(|s| s.to_string());
+    let mut rows = vec![header];
+    for r in results {
+        rows.push([
+            r.target.region.clone().unwrap_or_else(|| "-".to_string()),
+            r.target.bucket.clone(),
+            utils::fmt_count(r.objects),
+            utils::fmt_bytes_human(r.bytes),
+            r.status.name().to_string(),
+            r.output_file.clone().unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut lines: Vec<String> = rows.iter().map(|row| {
+        let cells: Vec<String> = row.iter().zip(&widths).map(|(cell, width)| format!("{:<width$}", cell, width = width)).collect();
+        format!("Batch - {}", cells.join("  ").trim_end())
+    }).collect();
+
+    let ok = results.iter().filter(|r| r.status == BatchStatus::Ok).count();
+    let objects: usize = results.iter().map(|r| r.objects).sum();
+    let bytes: u64 = results.iter().map(|r| r.bytes).sum();
+    lines.push(format!("Batch - total {} targets, {} ok, {} objects, {}", results.len(), ok, utils::fmt_count(objects),
+        utils::fmt_bytes_human(bytes)));
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        let content = "# region,bucket[,endpoint]\nus-east-1,logs\n\n,data,http://127.0.0.1:9000\neu-west-1, b2 ,\n";
+        let targets = parse_targets(content).unwrap();
+        assert_eq!(targets, vec![
+            BatchTarget { region: Some("us-east-1".to_string()), bucket: "logs".to_string(), endpoint: None },
+            BatchTarget { region: None, bucket: "data".to_string(), endpoint: Some("http://127.0.0.1:9000".to_string()) },
+            BatchTarget { region: Some("eu-west-1".to_string()), bucket: "b2".to_string(), endpoint: None },
+        ]);
+
+        assert!(parse_targets("us-east-1\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse_targets("a,b\nus-east-1,,x\n").unwrap_err().starts_with("line 2:"));
+        assert!(parse_targets("a,b,c,d\n").is_err());
+        assert_eq!(parse_targets("a,b,minio:9000/\n").unwrap()[0].endpoint.as_deref(), Some("https://minio:9000"));
+        assert!(parse_targets("a,b,ftp://minio\n").unwrap_err().starts_with("line 1:"));
+        assert!(parse_targets("# nothing\n").is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use crate::coverage::{PartitionCoverage, PartitionStatus};
+use crate::data_map::KeySpaceHints;
+
+// progress of a list run, objects of complete partitions are already in output file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub bucket: String,
+    pub prefix: String,
+    pub output_file: String,
+    pub output_format: String,
+    pub versions: bool,
+    pub partitions: Vec<PartitionCoverage>,
+}
+
+impl Checkpoint {
+    pub fn new(bucket: &str, prefix: &str, output_file: &str, output_format: &str, versions: bool, hints: &KeySpaceHints) -> Self {
+        let mut partitions: Vec<PartitionCoverage> = hints.unfinished().map(|pair| {
+            let (start, end) = pair.to_task_input();
+            PartitionCoverage {
+                bucket: bucket.to_string(),
+                prefix: prefix.to_string(),
+                start: start.to_string(),
+                end: end.map(|s| s.to_string()),
+                objects: 0,
+                status: PartitionStatus::Incomplete,
+            }
+        }).collect();
+        partitions.sort_by(|a, b| a.start.cmp(&b.start));
+
+        Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+            output_file: output_file.to_string(),
+            output_format: output_format.to_string(),
+            versions,
+            partitions,
+        }
+    }
+
+    pub fn load(filename: &str) -> std::io::Result<Self> {
+        let buf = std::fs::read(filename)?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    // hints of partitions left to list
+    pub fn hints(&self) -> KeySpaceHints {
+        KeySpaceHints::new_from_pairs(self.partitions.iter()
+            .filter(|p| p.status != PartitionStatus::Complete)
+            .map(|p| (p.start.clone(), p.end.clone())))
+    }
+
+    // (complete partitions, total partitions, objects exported)
+    pub fn progress(&self) -> (usize, usize, usize) {
+        let complete: Vec<_> = self.partitions.iter().filter(|p| p.status == PartitionStatus::Complete).collect();
+        (complete.len(), self.partitions.len(), complete.iter().map(|p| p.objects).sum())
+    }
+}
+
+// partitions are not overlapped, check if key is in (start, end) of any of them sorted by start
+pub fn in_partitions(sorted: &[PartitionCoverage], key: &str) -> bool {
+    let i = sorted.partition_point(|p| p.start.as_str() < key);
+    if i == 0 {
+        return false;
+    }
+    sorted[i - 1].end.as_ref().map_or(true, |end| key < end.as_str())
+}
+
+/*
+ * periodic checkpoint shared by list task and data map task:
+ *   - list task hands over each complete partition
+ *   - data map task takes them when due, appends their objects to output file,
+ *     then marks them complete in checkpoint file
+ * a partition is only marked after its objects are flushed, so it is listed again if run died in between
+ */
+#[derive(Debug)]
+pub struct Checkpointer {
+    filename: String,
+    interval_secs: u64,
+    resumed: bool,
+    last_ts: AtomicU64,
+    checkpoint: Mutex<Checkpoint>,
+    pending: Mutex<Vec<PartitionCoverage>>,
+}
+
+impl Checkpointer {
+    pub fn new(filename: &str, interval_secs: u64, checkpoint: Checkpoint, resumed: bool) -> Self {
+        Self {
+            filename: filename.to_string(),
+            interval_secs,
+            resumed,
+            last_ts: AtomicU64::new(now_secs()),
+            checkpoint: Mutex::new(checkpoint),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    // output file is appended to if resumed, otherwise created
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    pub fn progress(&self) -> (usize, usize, usize) {
+        self.checkpoint.lock().unwrap().progress()
+    }
+
+    pub fn partition_done(&self, record: &PartitionCoverage) {
+        if record.status == PartitionStatus::Complete {
+            self.pending.lock().unwrap().push(record.clone());
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        now_secs() - self.last_ts.load(Ordering::Relaxed) >= self.interval_secs
+    }
+
+    // complete partitions not yet flushed, sorted by start
+    pub fn take_pending(&self) -> Vec<PartitionCoverage> {
+        self.last_ts.store(now_secs(), Ordering::Relaxed);
+        let mut done = std::mem::take(&mut *self.pending.lock().unwrap());
+        done.sort_by(|a, b| a.start.cmp(&b.start));
+        done
+    }
+
+    // mark partitions complete and rewrite checkpoint file, objects of them should be flushed already
+    pub async fn commit(&self, done: &[PartitionCoverage]) -> std::io::Result<(usize, usize)> {
+        let checkpoint = {
+            let mut checkpoint = self.checkpoint.lock().unwrap();
+            for p in checkpoint.partitions.iter_mut() {
+                if let Some(d) = done.iter().find(|d| d.start == p.start && d.end == p.end) {
+                    p.objects = d.objects;
+                    p.status = PartitionStatus::Complete;
+                }
+            }
+            checkpoint.clone()
+        };
+
+        // write to temp file then rename, never leave a truncated checkpoint behind
+        let tmp = format!("{}.tmp", self.filename);
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(&checkpoint)?).await?;
+        tokio::fs::rename(&tmp, &self.filename).await?;
+
+        let (complete, total, _) = checkpoint.progress();
+        Ok((complete, total))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint_partitions() {
+        let hints = KeySpaceHints::new_from(&vec!["b".to_string(), "d".to_string()]);
+        let mut checkpoint = Checkpoint::new("bucket", "", "out.ndjson", "ndjson", false, &hints);
+        assert_eq!(checkpoint.partitions.len(), 3);
+
+        assert!(in_partitions(&checkpoint.partitions, "a"));
+        assert!(in_partitions(&checkpoint.partitions, "z"));
+        // start is exclusive as of start after
+        assert!(!in_partitions(&checkpoint.partitions[1..2], "b"));
+        assert!(in_partitions(&checkpoint.partitions[1..2], "c"));
+        assert!(!in_partitions(&checkpoint.partitions[1..2], "d"));
+        assert!(!in_partitions(&[], "a"));
+
+        checkpoint.partitions[1].status = PartitionStatus::Complete;
+        checkpoint.partitions[1].objects = 10;
+        assert_eq!(checkpoint.progress(), (1, 3, 10));
+        let mut hints = checkpoint.hints();
+        assert_eq!(hints.len(), 2);
+        assert_eq!(hints.next().unwrap().to_task_input(), ("", Some("b")));
+        assert_eq!(hints.next().unwrap().to_task_input(), ("d", None));
+    }
+}
@@ -0,0 +1,3072 @@
+use std::collections::BTreeMap;
+use std::io::{IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use futures::StreamExt;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::{info, warn, error};
+use crate::{error, core, data_map, tasks_s3, utils, mon, coverage, checkpoint, inventory, baseline,
+    watch, script, config, provenance, credentials, batch};
+use crate::core::{RunMode, KsFormat, KsCompression, ArrowCompression, LogFormat, FilterCombine, OutputFormat, TimeFormat, KeyCompare, DiffOn, DiffStatus, DiffDirection, ChecksumAlgorithm, DuplicateOn, OnError, OnThrottle};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+#[command(propagate_version = true)]
+struct Cli {
+    #[command(subcommand)]
+    cmd: Commands,
+
+    /// toml file of defaults of global options, in kebab or snake case, options on command line take precedence
+    #[arg(long, global=true)]
+    config: Option<String>,
+
+    /// take options of table [profiles.<name>] of config file over top-level ones
+    #[arg(long, global=true, requires = "config")]
+    config_profile: Option<String>,
+
+    /// prefix to start with, repeat or separate by comma to list several prefixes in one run
+    #[arg(short, long, default_value = "/", global=true, value_delimiter = ',')]
+    prefix: Vec<String>,
+
+    /// treat each prefix as a directory, "/" is appended if missing so "foo" does not list "foobar/..."
+    #[arg(long, global=true)]
+    prefix_is_dir: bool,
+
+    /// worker threads for runtime
+    #[arg(short, long, default_value_t = 10, global=true)]
+    threads: usize,
+
+    /// max concurrency tasks for list operation
+    #[arg(short, long, default_value_t = 100, global=true)]
+    concurrency: usize,
+
+    /// input key space hints file, could be given multiple times to merge them [default: {region}_{bucket}_ks_hints.input]
+    #[arg(short, long, global=true)]
+    ks_file: Vec<String>,
+
+    /// ks file of a previous run, only objects of prefixes added or changed in object count or key names since are exported
+    #[arg(long, global=true, value_name = "PATH")]
+    since_ks: Option<String>,
+
+    /// object filter expresion, could be given multiple times
+    #[arg(short, long, global=true)]
+    filter: Vec<String>,
+
+    /// how multiple filter expressions are combined
+    #[arg(long, value_enum, default_value_t = FilterCombine::And, global=true)]
+    filter_combine: FilterCombine,
+
+    /// how keys of both sides are matched in diff and compared by filter, output keeps keys as listed
+    #[arg(long, value_enum, default_value_t = KeyCompare::Exact, global=true)]
+    key_compare: KeyCompare,
+
+    /// log to file [default: fastlist_{datetime}.log]
+    #[arg(short, long, global=true)]
+    log: bool,
+
+    /// only log warnings and errors, no progress lines, output files and final summary are still written
+    #[arg(short, long, global=true)]
+    quiet: bool,
+
+    /// live progress bar of objects listed with eta in place of heartbeat lines of mon task, only if stdout is a tty and not --quiet
+    #[arg(long, global=true)]
+    progress: bool,
+
+    /// print peak and average objects/s with a recommended setting at end of run, e.g. more ks hints or lower --max-rps
+    #[arg(long, global=true)]
+    tuning_report: bool,
+
+    /// custom S3 endpoint URL, https if only host[:port] is given
+    #[arg(long = "endpoint-url", global=true, value_parser = utils::parse_endpoint_url)]
+    endpoint: Option<String>,
+
+    /// force path-style addressing (default when using --endpoint-url)
+    #[arg(long, global=true)]
+    force_path_style: bool,
+
+    /// trust CA certificates of this pem file for TLS of custom endpoint, besides system ones
+    #[arg(long, global=true)]
+    tls_ca_cert: Option<String>,
+
+    /// skip TLS certificate verification of custom endpoint, e.g. of self-signed one
+    #[arg(long, global=true, conflicts_with = "tls_ca_cert")]
+    tls_insecure: bool,
+
+    /// log file path (implies --log) [default: fastlist_{datetime}.log]
+    #[arg(long, global=true)]
+    output_log_file: Option<String>,
+
+    /// format of log lines, to stdout or log file
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global=true)]
+    log_format: LogFormat,
+
+    /// keyspace file output path [default: {region}_{bucket}_{datetime}.ks]
+    #[arg(long, global=true)]
+    output_ks_file: Option<String>,
+
+    /// leave datetime out of default output and ks file names, e.g. {region}_{bucket}.parquet, overwriting those of previous runs
+    #[arg(long, global=true)]
+    no_timestamp: bool,
+
+    /// directory of default output and ks files, created if missing [default: current directory]
+    #[arg(long, global=true)]
+    output_dir: Option<String>,
+
+    /// keyspace file output format
+    #[arg(long, value_enum, default_value_t = KsFormat::Text, global=true)]
+    ks_format: KsFormat,
+
+    /// compression of keyspace file, ".gz" or ".zst" is appended to default file name
+    #[arg(long, value_enum, default_value_t = KsCompression::None, global=true)]
+    ks_compression: KsCompression,
+
+    /// format of exported object metadata
+    #[arg(long, value_enum, default_value_t = OutputFormat::Parquet, global=true)]
+    output_format: OutputFormat,
+
+    /// rendering of last modified in csv and ndjson output, parquet and arrow keep a timestamp column [default: rfc3339]
+    #[arg(long, value_enum, global=true)]
+    time_format: Option<TimeFormat>,
+
+    /// compression codec of parquet output, one of none, snappy, gzip[:level], zstd[:level], lz4
+    #[arg(long, value_parser = utils::parse_parquet_compression, default_value = "gzip:6", global=true)]
+    parquet_compression: parquet::basic::Compression,
+
+    /// max rows of each parquet row group
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global=true)]
+    parquet_row_group_size: Option<u64>,
+
+    /// max encoded bytes of each parquet row group, e.g. "128MB"
+    #[arg(long, value_parser = utils::parse_size, global=true)]
+    parquet_max_row_group_bytes: Option<usize>,
+
+    /// write bloom filter of Key column in each parquet row group, for point lookups of keys
+    #[arg(long, global=true)]
+    parquet_bloom_filter: bool,
+
+    /// false positive probability of key bloom filter
+    #[arg(long, value_parser = utils::parse_probability, default_value_t = utils::DEFAULT_BLOOM_FILTER_FPP, global=true,
+        requires = "parquet_bloom_filter")]
+    parquet_bloom_fpp: f64,
+
+    /// compression of record batches in arrow output
+    #[arg(long, value_enum, default_value_t = ArrowCompression::None, global=true)]
+    arrow_compression: ArrowCompression,
+
+    /// compression of csv or ndjson output, ".gz" or ".zst" is appended to default file name
+    /// [default: gzip or zstd by ".gz" or ".zst" extension of output file, else none]
+    #[arg(long, value_enum, global=true)]
+    output_compression: Option<KsCompression>,
+
+    /// end each key of keys output by NUL instead of newline, so keys with newline are kept
+    #[arg(long, global=true)]
+    null_terminated: bool,
+
+    /// comma separated columns of csv output [default: key,size,last_modified,etag[,status]]
+    #[arg(long, global=true)]
+    columns: Option<String>,
+
+    /// comma separated columns kept in output of any format, e.g. key,size, names as of --columns
+    #[arg(long, global=true, conflicts_with = "columns")]
+    output_columns: Option<String>,
+
+    /// output file path [default: {region}_{bucket}_{datetime}.{parquet|ndjson}]
+    #[arg(long, global=true)]
+    output_parquet_file: Option<String>,
+
+    /// only count objects and bytes (per status in diff mode), no output or ks file is written
+    #[arg(long, global=true, conflicts_with_all = ["output_ks_file", "output_parquet_file", "columns"])]
+    count_only: bool,
+
+    /// write parquet files partitioned by first N segments of key, Hive style under --output-parquet-file as base directory
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global=true, value_name = "DEPTH")]
+    partition_by_prefix: Option<u32>,
+
+    /// roll output over to a new file {output}_part-NNNNN once this many objects are written to current one
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global=true, value_name = "ROWS")]
+    max_output_file_rows: Option<u64>,
+
+    /// roll output over to a new file {output}_part-NNNNN once current one reaches this size, e.g. "1GB"
+    #[arg(long, value_parser = utils::parse_size, global=true, value_name = "SIZE")]
+    max_output_file_bytes: Option<usize>,
+
+    /// write output in key order, each file of diff split sorted on its own; keys are sorted in memory before output
+    #[arg(long, global=true, conflicts_with = "count_only")]
+    sorted: bool,
+
+    /// write run metadata and output files as json next to output, {output}.manifest.json
+    #[arg(long, global=true, conflicts_with = "count_only")]
+    emit_manifest: bool,
+
+    /// print object count and bytes by size range, from 1 KiB up to 1 TiB by power of 4, with final summary
+    #[arg(long, global=true)]
+    size_histogram: bool,
+
+    /// write size histogram as csv file, implies --size-histogram
+    #[arg(long, global=true)]
+    size_histogram_file: Option<String>,
+
+    /// print object count and bytes by first <DEPTH> segments of key prefix, biggest first, with final summary
+    #[arg(long, global=true, value_name = "DEPTH", value_parser = clap::value_parser!(u32).range(1..))]
+    prefix_report: Option<u32>,
+
+    /// write prefix report as csv file, requires --prefix-report
+    #[arg(long, global=true, requires = "prefix_report")]
+    prefix_report_file: Option<String>,
+
+    /// export only objects of same content as others, as groups with id and member count
+    #[arg(long, global=true)]
+    find_duplicates: bool,
+
+    /// attributes telling objects of same content with --find-duplicates
+    #[arg(long, value_enum, default_value_t = DuplicateOn::Etag, global=true, requires = "find_duplicates")]
+    duplicates_on: DuplicateOn,
+
+    /// list only the lexicographically-first object under each common prefix of start prefix
+    #[arg(long, global=true)]
+    one_per_prefix: bool,
+
+    /// list all object versions and delete markers with ListObjectVersions
+    #[arg(long, global=true, conflicts_with = "one_per_prefix")]
+    versions: bool,
+
+    /// fetch tags of each listed object with GetObjectTagging and export them as "tags" column, one request per object
+    #[arg(long, global=true)]
+    with_tags: bool,
+
+    /// request owner of each object in list calls and export it as "owner_id" and "owner_display_name" columns
+    #[arg(long, global=true)]
+    with_owner: bool,
+
+    /// fetch checksum of this algorithm of each listed object with GetObjectAttributes and export it as "checksum" column,
+    /// one request per object
+    #[arg(long, value_enum, global=true)]
+    with_checksum: Option<ChecksumAlgorithm>,
+
+    /// fetch metadata of each listed object with HeadObject and export it as content type, encryption, user metadata
+    /// and other columns, one request per object, asks for confirmation with a cost estimate
+    #[arg(long, global=true)]
+    enrich_all: bool,
+
+    /// answer yes to confirmation of --enrich-all, for runs without a terminal
+    #[arg(long, global=true)]
+    yes: bool,
+
+    /// list with delimiter, common prefixes are exported as rows of type "prefix" instead of objects under them
+    #[arg(long, global=true, conflicts_with_all = ["one_per_prefix", "versions"])]
+    delimiter: Option<char>,
+
+    /// skip subtree under this prefix at request level, never listed nor exported, repeatable
+    #[arg(long, global=true, value_name = "PREFIX", conflicts_with_all = ["one_per_prefix", "delimiter"])]
+    exclude_prefix: Vec<String>,
+
+    /// drop zero-byte objects before they are counted or exported, on both sides in diff mode
+    #[arg(long, global=true)]
+    skip_empty: bool,
+
+    /// drop keys ending in "/", folder placeholders created by consoles, before they are counted or exported
+    #[arg(long, global=true)]
+    skip_dir_markers: bool,
+
+    /// split start prefixes without ks hints at first characters of keys after them, so list tasks start all over key space
+    #[arg(long, global=true, conflicts_with_all = ["one_per_prefix", "delimiter"])]
+    hash_prefix_seed: bool,
+
+    /// characters keys after start prefix start with, split at by --hash-prefix-seed [default: 0123456789abcdef]
+    #[arg(long, global=true, requires = "hash_prefix_seed", value_parser = utils::parse_hash_charset)]
+    hash_charset: Option<String>,
+
+    /// split key space partitions into N chunks of ks hints files, each to be listed on its own host with --ks-file,
+    /// nothing is listed
+    #[arg(long, value_name = "N", global=true, requires = "emit_chunks_file", conflicts_with_all = ["one_per_prefix", "delimiter"],
+        value_parser = clap::value_parser!(u32).range(2..))]
+    emit_chunks: Option<u32>,
+
+    /// file of --emit-chunks naming its chunk files one per line, each named after it, e.g. chunks_chunk-00000.input
+    #[arg(long, global=true, requires = "emit_chunks")]
+    emit_chunks_file: Option<String>,
+
+    /// levels of common prefixes listed under start prefix with --delimiter [default: 1]
+    #[arg(long, global=true, requires = "delimiter", value_parser = clap::value_parser!(u32).range(1..))]
+    max_depth: Option<u32>,
+
+    /// named profile of aws shared config and credentials files [default: AWS SDK defaults]
+    #[arg(long, global=true)]
+    profile: Option<String>,
+
+    /// assume this iam role with sts before listing, e.g. of another account
+    #[arg(long, global=true)]
+    assume_role_arn: Option<String>,
+
+    /// external id required by trust policy of assumed role
+    #[arg(long, global=true, requires = "assume_role_arn")]
+    external_id: Option<String>,
+
+    /// session name of assumed role [default: s3-fast-list]
+    #[arg(long, global=true, requires = "assume_role_arn")]
+    role_session_name: Option<String>,
+
+    /// base64 of 256 bits SSE-C key, sent with HEAD requests of --verify-sample on both buckets, never logged
+    #[arg(long, global=true, value_name = "BASE64")]
+    sse_customer_key: Option<String>,
+
+    /// never look up credentials or region from instance metadata service, also set by AWS_EC2_METADATA_DISABLED=true
+    #[arg(long, global=true)]
+    no_imds: bool,
+
+    /// skip sts GetCallerIdentity call resolving account of each bucket logged at startup
+    #[arg(long, global=true)]
+    no_identity_check: bool,
+
+    /// pay for list requests of requester pays bucket, source bucket only in diff mode
+    #[arg(long, global=true)]
+    request_payer: bool,
+
+    /// switch to the region where bucket is located on PermanentRedirect, at startup and during listing
+    #[arg(long, global=true)]
+    follow_redirects: bool,
+
+    /// unique id of this run for log correlation [default: random uuid]
+    #[arg(long, global=true)]
+    run_id: Option<String>,
+
+    /// write coverage of each listed key space partition to file
+    #[arg(long, global=true)]
+    coverage_file: Option<String>,
+
+    /// human readable numbers in log output, e.g. "1,234,567"
+    #[arg(long, global=true)]
+    humanize: bool,
+
+    /// tune concurrent list tasks by throttling, start at a quarter of --concurrency and grow up to it,
+    /// halve on 503 or 429 responses
+    #[arg(long, global=true)]
+    adaptive_concurrency: bool,
+
+    /// on ctrl-c or SIGTERM, max seconds to wait for output to be flushed before exit [default: no limit]
+    #[arg(long, global=true, value_parser = clap::value_parser!(u64).range(1..))]
+    shutdown_grace: Option<u64>,
+
+    /// print planned key space partitions and their list tasks, then exit without any S3 call or output file
+    #[arg(long, global=true)]
+    dry_run: bool,
+
+    /// serve prometheus metrics on http://{addr}/metrics while running, e.g. "127.0.0.1:9090"
+    #[arg(long, global=true)]
+    metrics_addr: Option<std::net::SocketAddr>,
+
+    /// take commands status, pause, resume and quit on this unix socket while running, one per line
+    #[cfg(unix)]
+    #[arg(long, global=true, value_name = "PATH")]
+    control_socket: Option<String>,
+
+    /// write plain text report of run to file at end, with buckets, options, totals, requests and errors
+    #[arg(long, global=true)]
+    report_file: Option<String>,
+
+    /// append json snapshot of progress counters to file at each stats interval
+    #[arg(long, global=true)]
+    stats_json: Option<String>,
+
+    /// interval in seconds of stats json snapshots
+    #[arg(long, global=true, requires = "stats_json", default_value_t = core::DEFAULT_STATS_INTERVAL_SECS,
+        value_parser = clap::value_parser!(u64).range(1..))]
+    stats_interval: u64,
+
+    /// best-effort cap of process memory, list tasks are paused while rss is over it, e.g. "8GB"
+    #[arg(long, value_parser = utils::parse_size, global=true)]
+    max_memory: Option<usize>,
+
+    /// cap of list requests per second across all concurrent tasks, shared by both sides in diff mode
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global=true)]
+    max_rps: Option<u32>,
+
+    /// timeout in seconds to establish connection to S3 endpoint [default: 60]
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global=true)]
+    http_connect_timeout: Option<u64>,
+
+    /// timeout in seconds to wait for first byte of S3 response, per response wait is raised to it if over 5s
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global=true)]
+    http_read_timeout: Option<u64>,
+
+    /// cap of connections to S3 endpoint of each bucket, requests over it wait for a free connection
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global=true)]
+    max_connections: Option<u32>,
+
+    /// keys per page of list calls, sent as MaxKeys, over 1000 is taken as 1000 [default: 1000 of S3]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), global=true)]
+    page_size: Option<u32>,
+
+    /// fail range of a truncated page without usable continuation token or short of page size, instead of listing on after it
+    #[arg(long, global=true, conflicts_with_all = ["one_per_prefix", "delimiter"])]
+    strict_pagination: bool,
+
+    /// exit at startup if open files needed by concurrency and output writers could exceed soft limit of open files,
+    /// instead of a warning
+    #[arg(long, global=true)]
+    strict_limits: bool,
+
+    /// raise soft limit of open files up to hard limit at startup
+    #[arg(long, global=true)]
+    raise_nofile: bool,
+
+    /// appended to user agent of requests, after SDK one and "s3-fast-list/{version}", e.g. to be identified by S3 gateway
+    #[arg(long, global=true, value_parser = utils::parse_user_agent)]
+    user_agent: Option<String>,
+
+    /// stop listing after about this many objects and write output of them, for a trial run on a subset
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), global=true)]
+    max_objects: Option<u64>,
+
+    /// exit with code 3 if over this percent of list requests failed, retried ones included
+    #[arg(long, value_name = "PERCENT", value_parser = utils::parse_percent, global=true)]
+    max_error_rate: Option<f64>,
+
+    /// after listing, HEAD a random sample of this percent of listed objects to confirm they are retrievable
+    #[arg(long, value_name = "PERCENT", value_parser = utils::parse_percent, global=true)]
+    verify_sample: Option<f64>,
+
+    /// retry list calls on throttling, 5xx and connection errors with exponential backoff, instead of SDK retry
+    #[arg(long, global=true)]
+    max_retries: Option<u32>,
+
+    /// base delay of retry backoff in milliseconds
+    #[arg(long, requires = "max_retries", default_value_t = core::DEFAULT_RETRY_BASE_DELAY_MS, global=true)]
+    retry_base_delay: u64,
+
+    /// what a key space range failed after retries does to the run
+    #[arg(long, value_enum, default_value_t = OnError::Continue, global=true)]
+    on_error: OnError,
+
+    /// what 503 or 429 responses of S3 do to list calls
+    #[arg(long, value_enum, default_value_t = OnThrottle::Backoff, global=true)]
+    on_throttle: OnThrottle,
+
+    /// abort if rss can't get under max memory in time, instead of giving up throttling
+    #[arg(long, requires = "max_memory", global=true)]
+    max_memory_abort: bool,
+}
+
+// parsed once per run, size of variants does not matter
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Commands {
+
+    /// fast list and export results
+    List {
+        /// source aws region (optional, uses AWS SDK defaults if not provided)
+        #[arg(long)]
+        region: Option<String>,
+
+        /// source bucket to list
+        #[arg(long)]
+        bucket: String,
+
+        /// append objects of complete partitions to output and checkpoint them every N seconds,
+        /// checkpoint file is named after output file with ".checkpoint"
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        checkpoint_interval: Option<u64>,
+
+        /// resume an interrupted run from checkpoint file, complete partitions are skipped
+        #[arg(long)]
+        resume: Option<String>,
+
+        /// write ks hints file of boundary prefixes splitting listed objects evenly into
+        /// 4 x --concurrency partitions, to feed back with --ks-file on next run
+        #[arg(long)]
+        emit_ks_hints: Option<String>,
+
+        /// re-list every N seconds after previous listing is done until ctrl-c, appending objects added,
+        /// deleted or changed since previous listing to output file
+        #[arg(long, value_name = "INTERVAL_SECS", value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with_all = ["checkpoint_interval", "resume", "emit_ks_hints"])]
+        watch: Option<u64>,
+    },
+
+    /// read objects from an S3 Inventory report instead of listing, and export results
+    FromInventory {
+        /// aws region of bucket holding inventory (optional, uses AWS SDK defaults if not provided)
+        #[arg(long)]
+        region: Option<String>,
+
+        /// manifest.json of inventory report, "s3://bucket/key" or local file
+        #[arg(long)]
+        manifest: String,
+
+        /// read data files from local directory standing for destination bucket, instead of S3
+        #[arg(long)]
+        data_dir: Option<String>,
+    },
+
+    /// bi-dir fast list and diff results
+    Diff {
+        /// source aws region (optional, uses AWS SDK defaults if not provided)
+        #[arg(long)]
+        region: Option<String>,
+
+        /// source bucket to list
+        #[arg(long)]
+        bucket: String,
+
+        /// target aws region (optional, uses AWS SDK defaults if not provided)
+        #[arg(long)]
+        target_region: Option<String>,
+
+        /// target bucket to list
+        #[arg(long)]
+        target_bucket: String,
+
+        /// custom S3 endpoint URL of target bucket, e.g. of another provider [default: --endpoint-url]
+        #[arg(long = "target-endpoint-url", value_parser = utils::parse_endpoint_url)]
+        target_endpoint: Option<String>,
+
+        /// force path-style addressing of target bucket (default when using --target-endpoint-url)
+        #[arg(long)]
+        target_force_path_style: bool,
+
+        /// named profile of target bucket, e.g. of another account [default: --profile]
+        #[arg(long)]
+        target_profile: Option<String>,
+
+        /// iam role assumed to list target bucket [default: --assume-role-arn]
+        #[arg(long)]
+        target_assume_role_arn: Option<String>,
+
+        /// external id of role assumed to list target bucket [default: --external-id]
+        #[arg(long, requires = "target_assume_role_arn")]
+        target_external_id: Option<String>,
+
+        /// only keep differences changed after this time, in unix seconds or RFC 3339
+        #[arg(long, value_parser = parse_timestamp)]
+        diff_changed_after: Option<u64>,
+
+        /// attributes compared for objects on both sides, separated by comma
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "size,etag")]
+        diff_on: Vec<DiffOn>,
+
+        /// last modified of both sides within this many seconds is equal, with "--diff-on mtime"
+        #[arg(long, value_name = "SECS")]
+        diff_lmtime_tolerance: Option<u64>,
+
+        /// write only left, only right and changed objects into three files, named after output file
+        #[arg(long)]
+        diff_split: bool,
+
+        /// only write differences of these statuses to output, separated by comma [default: all]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        diff_status: Vec<DiffStatus>,
+
+        /// only find differences of one direction, objects on both sides are not compared
+        #[arg(long, value_enum, default_value = "both")]
+        diff_direction: DiffDirection,
+
+        /// exit with code 2 if any difference found, 1 is kept for errors
+        #[arg(long)]
+        fail_on_diff: bool,
+
+        /// exit with code 2 if at least this many differences found, implies --fail-on-diff
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        fail_on_diff_count: Option<u64>,
+
+        /// pay for list requests of requester pays target bucket
+        #[arg(long)]
+        target_request_payer: bool,
+
+        /// cap of list requests per second of target bucket, --max-rps then applies to source bucket only
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        target_max_rps: Option<u32>,
+
+        /// read source side from manifest.json of S3 Inventory report instead of listing source bucket,
+        /// "s3://bucket/key" or local file
+        #[arg(long)]
+        manifest: Option<String>,
+
+        /// read inventory data files from local directory standing for destination bucket, instead of S3
+        #[arg(long, requires = "manifest")]
+        data_dir: Option<String>,
+
+        /// write shell script of aws cli commands syncing source to target, copying only left and changed objects
+        /// and removing only right ones
+        #[arg(long, value_name = "PATH")]
+        diff_emit_script: Option<String>,
+
+        /// write only left and changed objects as csv manifest of S3 Batch Operations, copying them from source
+        #[arg(long, value_name = "PATH")]
+        diff_emit_batch_manifest: Option<String>,
+    },
+
+    /// diff fast list of bucket against parquet output of a previous list run as left side
+    DiffAgainst {
+        /// aws region (optional, uses AWS SDK defaults if not provided)
+        #[arg(long)]
+        region: Option<String>,
+
+        /// bucket to list
+        #[arg(long)]
+        bucket: String,
+
+        /// parquet output of a previous list run of bucket, or base directory of partitioned output
+        #[arg(long)]
+        baseline: String,
+
+        /// only keep differences changed after this time, in unix seconds or RFC 3339
+        #[arg(long, value_parser = parse_timestamp)]
+        diff_changed_after: Option<u64>,
+
+        /// attributes compared for objects on both sides, separated by comma
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "size,etag")]
+        diff_on: Vec<DiffOn>,
+
+        /// last modified of both sides within this many seconds is equal, with "--diff-on mtime"
+        #[arg(long, value_name = "SECS")]
+        diff_lmtime_tolerance: Option<u64>,
+
+        /// write only left, only right and changed objects into three files, named after output file
+        #[arg(long)]
+        diff_split: bool,
+
+        /// only write differences of these statuses to output, separated by comma [default: all]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        diff_status: Vec<DiffStatus>,
+
+        /// only find differences of one direction, objects on both sides are not compared
+        #[arg(long, value_enum, default_value = "both")]
+        diff_direction: DiffDirection,
+
+        /// exit with code 2 if any difference found, 1 is kept for errors
+        #[arg(long)]
+        fail_on_diff: bool,
+
+        /// exit with code 2 if at least this many differences found, implies --fail-on-diff
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        fail_on_diff_count: Option<u64>,
+    },
+
+    /// fast list buckets of a targets file in one run, each exported to its own output file
+    Batch {
+        /// file of one target per line, "region,bucket[,endpoint]", empty region uses AWS SDK defaults,
+        /// endpoint defaults to --endpoint-url, blank lines and "#" comments skipped
+        #[arg(long)]
+        targets: String,
+
+        /// targets listed at the same time, each with up to --concurrency list tasks
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        batch_concurrency: u32,
+    },
+
+    /// verify a coverage file tiles the key space under prefix
+    VerifyCoverage {
+        /// coverage file exported with --coverage-file
+        #[arg(long)]
+        coverage_file: String,
+    },
+}
+
+// parse unix timestamp in seconds or RFC 3339 datetime
+fn parse_timestamp(s: &str) -> Result<u64, String> {
+    if let Ok(ts) = s.parse::<u64>() {
+        return Ok(ts);
+    }
+    let dt = DateTime::parse_from_rfc3339(s)
+        .map_err(|e| format!("expect unix seconds or RFC 3339 datetime: {e}"))?;
+    u64::try_from(dt.timestamp()).map_err(|_| "datetime before unix epoch".to_string())
+}
+
+// verify coverage file and return process exit code
+fn verify_coverage_main(coverage_file: &str, prefixes: &[String]) -> i32 {
+    let records = match coverage::load_coverage(coverage_file) {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("unable to load coverage file {}: {}", coverage_file, e);
+            return 2;
+        }
+    };
+
+    // partitions of each start prefix tile its own key space
+    let mut problems = Vec::new();
+    if prefixes.len() == 1 {
+        problems = coverage::verify_coverage(&records, &prefixes[0]);
+    } else {
+        for prefix in prefixes {
+            let of_prefix: Vec<_> = records.iter().filter(|r| r.prefix == *prefix).cloned().collect();
+            problems.extend(coverage::verify_coverage(&of_prefix, prefix).into_iter()
+                .map(|problem| format!("prefix \"{}\": {}", prefix, problem)));
+        }
+        for r in records.iter().filter(|r| !prefixes.contains(&r.prefix)) {
+            problems.push(format!("partition \"{}\" listed with prefix \"{}\", not one of start prefixes", r.start, r.prefix));
+        }
+    }
+    if problems.is_empty() {
+        let objects: usize = records.iter().map(|r| r.objects).sum();
+        println!("coverage OK: {} partitions, {} objects under prefix \"{}\"", records.len(), objects, prefixes.join("\", \""));
+        return 0;
+    }
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    println!("coverage FAILED: {} problems found in {} partitions", problems.len(), records.len());
+    1
+}
+
+// S3 Standard price of GET and HEAD requests in us-east-1, USD per 1,000 requests
+const HEAD_REQUEST_USD_PER_1000: f64 = 0.0004;
+
+// cost of one HeadObject request per object with --enrich-all, of --max-objects if given, else per million objects
+fn enrich_cost_estimate(max_objects: Option<usize>) -> String {
+    let cost = |objects: usize| objects as f64 * HEAD_REQUEST_USD_PER_1000 / 1000.0;
+    match max_objects {
+        Some(n) => format!("up to {} HeadObject requests of --max-objects, about ${:.2} at ${} per 1,000 requests", n, cost(n),
+            HEAD_REQUEST_USD_PER_1000),
+        None => format!("one HeadObject request per listed object, about ${:.2} per million objects at ${} per 1,000 requests",
+            cost(1_000_000), HEAD_REQUEST_USD_PER_1000),
+    }
+}
+
+// answer to a confirmation prompt, only "y" or "yes" goes on
+fn confirmed(input: impl std::io::BufRead) -> bool {
+    let mut lines = input.lines();
+    lines.next().and_then(|l| l.ok()).is_some_and(|l| matches!(l.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+}
+
+// files kept open besides connections and output writers: std streams, log, ks and hints files, runtime and metrics
+const NOFILE_RESERVED: u64 = 32;
+// soft limit raised to at most with --raise-nofile, an unlimited hard limit is not taken by setrlimit
+const NOFILE_RAISE_MAX: u64 = 1 << 20;
+
+// open files a run may need at once against soft limit, error has a concurrency of list tasks that fits
+fn check_open_files(soft: u64, buckets: u64, fetch_objects: bool, output_writers: u64, concurrency: usize,
+        max_connections: Option<usize>) -> Result<(), error::NofileLimitError> {
+    // a list task and its per object fetches hold each a connection to every listed bucket
+    let per_task = buckets * if fetch_objects { 2 } else { 1 };
+    let fixed = NOFILE_RESERVED + output_writers;
+    let connections = match max_connections {
+        Some(max_connections) => max_connections as u64 * buckets,
+        None => concurrency as u64 * per_task,
+    };
+    if fixed + connections > soft {
+        let concurrency = (soft.saturating_sub(fixed) / per_task).max(1) as usize;
+        return Err(error::NofileLimitError::new(fixed + connections, soft, concurrency));
+    }
+    Ok(())
+}
+
+// default name of output or ks file of one bucket, stamp is "_{datetime}" or empty with --no-timestamp
+fn list_filename(region: &Option<String>, bucket: &str, stamp: &str, extension: &str) -> String {
+    let region_prefix = region.as_ref().map(|r| format!("{}_", r)).unwrap_or_default();
+    format!("{}_{}{}.{}", region_prefix, bucket, stamp, extension)
+}
+
+// gzip or zstd stream by extension of output file given
+fn output_compression_of(output_file: Option<&str>) -> KsCompression {
+    [KsCompression::Gzip, KsCompression::Zstd].into_iter()
+        .find(|c| output_file.is_some_and(|f| f.ends_with(c.suffix())))
+        .unwrap_or(KsCompression::None)
+}
+
+// default file name put under --output-dir
+fn in_output_dir(output_dir: &Option<String>, filename: String) -> String {
+    match output_dir {
+        Some(dir) => std::path::Path::new(dir).join(filename).to_string_lossy().into_owned(),
+        None => filename,
+    }
+}
+
+// prefixes and failed ranges of all ks hints files, sorted and deduped, with count of prefixes before dedup.
+// gzip or zstd file is decompressed, only a missing default file is no hints
+// binary ks files of a previous run are split into "splits" hints each
+fn load_ks_files(filenames: &[String], given: bool, splits: usize) -> Result<(Vec<String>, Vec<data_map::KsRange>, usize), String> {
+    let mut ks_list = Vec::new();
+    let mut ks_ranges = Vec::new();
+    for ks_filename in filenames {
+        let input = match utils::open_decompressed(ks_filename) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !given => continue,
+            Ok(reader) => data_map::read_ks_hints(reader, splits),
+            Err(e) => Err(e),
+        };
+        let input = input.map_err(|e| format!("unable to read ks hints file {}: {}", ks_filename, e))?;
+        if let Some(entries) = input.ks_entries {
+            info!("  - {} prefixes of binary ks file {} split into {} ks hints", entries, ks_filename, input.hints.len());
+        }
+        if input.blank > 0 {
+            warn!("  - {} blank lines of ks hints file {} skipped", input.blank, ks_filename);
+        }
+        if !input.invalid.is_empty() {
+            let lines: Vec<String> = input.invalid.iter().take(10).map(|n| n.to_string()).collect();
+            warn!("  - {} invalid lines of ks hints file {} skipped, not utf-8 or with NUL, at line {}{}", input.invalid.len(),
+                ks_filename, lines.join(", "), if input.invalid.len() > lines.len() { ", ..." } else { "" });
+        }
+        if !input.hints.is_empty() && !input.ranges.is_empty() {
+            return Err(format!("ks hints file {} has both prefixes and tab separated failed ranges, one kind per file", ks_filename));
+        }
+        ks_list.extend(input.hints);
+        ks_ranges.extend(input.ranges);
+    }
+    if !ks_list.is_empty() && !ks_ranges.is_empty() {
+        return Err(format!("ks hints files {} mix prefixes and tab separated failed ranges, one kind in all of them", filenames.join(", ")));
+    }
+    // hints of all files before dedup
+    let merged = ks_list.len();
+
+    // sort input lexicographically, then dedup
+    ks_list.sort();
+    ks_list.dedup();
+    ks_ranges.sort();
+    ks_ranges.dedup();
+    Ok((ks_list, ks_ranges, merged))
+}
+
+// ks hints of each start prefix, ones under other prefixes are of no use to it,
+// failed ranges of ks hints file are listed under their own prefixes instead
+fn ks_hints_of_prefixes(prefixes: &[String], ks_list: &[String], ks_ranges: &[data_map::KsRange],
+        hints: data_map::KeySpaceHints) -> Vec<(String, data_map::KeySpaceHints)> {
+    if !ks_ranges.is_empty() {
+        let mut pairs: BTreeMap<&str, Vec<(String, Option<String>)>> = BTreeMap::new();
+        for range in ks_ranges {
+            pairs.entry(range.prefix.as_str()).or_default().push((range.start.clone(), range.end.clone()));
+        }
+        return pairs.into_iter()
+            .map(|(prefix, pairs)| (prefix.to_string(), data_map::KeySpaceHints::new_from_pairs(pairs.into_iter())))
+            .collect();
+    }
+    if prefixes.len() == 1 {
+        return vec![(prefixes[0].clone(), hints)];
+    }
+    prefixes.iter().map(|prefix| {
+        let ks_list: Vec<String> = ks_list.iter().filter(|k| k.starts_with(prefix.as_str())).cloned().collect();
+        (prefix.clone(), data_map::KeySpaceHints::new_from(&ks_list))
+    }).collect()
+}
+
+// print partitions of ks hints in the order list tasks take them, assuming partitions take equal time,
+// start prefixes are seeded as list tasks do with hash_seed
+fn print_list_plan(buckets: &[&str], start_prefixes: &[(String, data_map::KeySpaceHints)], concurrency: usize, files: &[(&str, &str)],
+        hash_seed: Option<&str>) {
+    let seeded;
+    let start_prefixes = match hash_seed {
+        Some(charset) => {
+            seeded = tasks_s3::seed_start_prefixes(start_prefixes.to_vec(), &charset.chars().collect::<Vec<char>>());
+            &seeded[..]
+        },
+        None => start_prefixes,
+    };
+    let pairs: usize = start_prefixes.iter().map(|(_, hints)| hints.len()).sum();
+    let streams = concurrency.min(pairs);
+    let mut i = 0;
+    for (prefix, hints) in start_prefixes {
+        println!("Plan - {} key space partitions under prefix \"{}\", concurrency {}", hints.len(), prefix, concurrency);
+        for pair in hints.unfinished() {
+            let (start, end) = pair.to_task_input();
+            let wave = if i < concurrency { "started at once" } else { "queued" };
+            println!("Plan - partition {:>4} - task {:>3} ({}) - after \"{}\" until {}", i, i % concurrency, wave, start,
+                end.map_or("end of key space".to_string(), |e| format!("\"{}\"", e)));
+            i += 1;
+        }
+    }
+    println!("Plan - {} parallel list streams per bucket, {} in total for {}", streams, streams * buckets.len(), buckets.join(", "));
+    for (kind, file) in files {
+        println!("Plan - {} file {} (not written by dry run)", kind, file);
+    }
+}
+
+// ranges left unlisted after run, to be listed again with --ks-file
+fn write_failed_ranges(g_state: &core::GlobalState, filename: &str) {
+    let failed_ranges = g_state.failed_ranges();
+    if !failed_ranges.is_empty() {
+        let content: String = failed_ranges.iter().map(|r| r.to_line()).collect();
+        match std::fs::write(filename, content) {
+            Ok(()) => warn!("!!! {} key space ranges failed, recorded to {}, rerun with --ks-file {} to list them again !!!",
+                failed_ranges.len(), filename, filename),
+            Err(e) => error!("unable to write failed ranges to {}: {}", filename, e),
+        }
+    }
+}
+
+// usage errors exit with 1 as other errors, clap's own 2 is the exit code of differences found by "--fail-on-diff",
+// help and version are printed to stdout with 0
+fn usage_exit_code(e: &clap::Error) -> i32 {
+    if e.use_stderr() { 1 } else { 0 }
+}
+
+fn exit_on_usage_error(e: clap::Error) -> ! {
+    let _ = e.print();
+    std::process::exit(usage_exit_code(&e))
+}
+
+// parse command line, with options of config file put before it as defaults, and names of those options
+fn parse_cli() -> (Cli, Vec<String>) {
+    let matches = Cli::command().try_get_matches().unwrap_or_else(|e| exit_on_usage_error(e));
+    let Some(filename) = matches.get_one::<String>("config") else {
+        return (Cli::from_arg_matches(&matches).unwrap_or_else(|e| exit_on_usage_error(e)), Vec::new());
+    };
+    let profile = matches.get_one::<String>("config_profile").map(|p| p.as_str());
+    let (config_args, names) = config::config_args(&Cli::command(), &matches, filename, profile).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut args = std::env::args_os();
+    let args = args.next().into_iter().chain(config_args.into_iter().map(std::ffi::OsString::from)).chain(args);
+    (Cli::try_parse_from(args).unwrap_or_else(|e| exit_on_usage_error(e)), names)
+}
+
+// subcommand of a run, batch lists each of its targets as list does
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RunCommand {
+    List,
+    FromInventory,
+    Diff,
+    DiffAgainst,
+    Batch,
+}
+
+impl RunCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::List => "list",
+            Self::FromInventory => "from-inventory",
+            Self::Diff => "diff",
+            Self::DiffAgainst => "diff-against",
+            Self::Batch => "batch",
+        }
+    }
+}
+
+// options of a run from command line and its subcommand, checked by validate_options before anything is loaded
+struct RunOptions {
+    command: RunCommand,
+    // names of options taken from config file
+    config_names: Vec<String>,
+    mode: RunMode,
+    region: Option<String>,
+    bucket: String,
+    target_region: Option<String>,
+    target_bucket: Option<String>,
+    diff_changed_after: Option<u64>,
+    diff_lmtime_tolerance: Option<u64>,
+    diff_on: Vec<DiffOn>,
+    diff_split: bool,
+    diff_status: Vec<DiffStatus>,
+    diff_direction: DiffDirection,
+    fail_on_diff: Option<u64>,
+    checkpoint_interval: Option<u64>,
+    resume: Option<String>,
+    emit_ks_hints: Option<String>,
+    watch: Option<u64>,
+    target_max_rps: Option<u32>,
+    diff_emit_script: Option<String>,
+    diff_emit_batch_manifest: Option<String>,
+    manifest: Option<String>,
+    inventory_data_dir: Option<String>,
+    baseline: Option<String>,
+    batch_targets_file: Option<String>,
+    batch_targets: Option<Vec<batch::BatchTarget>>,
+    batch_concurrency: usize,
+    profile: Option<String>,
+    target_profile: Option<String>,
+    assume_role: Option<core::AssumeRoleOptions>,
+    target_assume_role: Option<core::AssumeRoleOptions>,
+    request_payer: bool,
+    target_request_payer: bool,
+    sse_customer_key: Option<core::SseCustomerKey>,
+    endpoint: Option<String>,
+    force_path_style: bool,
+    // target side falls back to source endpoint and path style unless it has its own endpoint
+    target_endpoint: Option<String>,
+    target_force_path_style: bool,
+    tls_ca_cert: Option<String>,
+    tls_insecure: bool,
+    prefix_is_dir: bool,
+    prefixes: Vec<String>,
+    // prefixes given without trailing "/", logged once logger is up
+    prefixes_normalized: Vec<String>,
+    // single start prefix, only used where one is required
+    prefix: String,
+    threads: usize,
+    concurrency: usize,
+    adaptive_concurrency: bool,
+    filter: Vec<String>,
+    filter_combine: FilterCombine,
+    key_compare: KeyCompare,
+    one_per_prefix: bool,
+    shallow: Option<core::ShallowListOptions>,
+    exclude_prefix: Vec<String>,
+    skip_empty: bool,
+    skip_dir_markers: bool,
+    hash_seed: Option<String>,
+    versions: bool,
+    with_tags: bool,
+    with_owner: bool,
+    with_checksum: Option<ChecksumAlgorithm>,
+    enrich_all: bool,
+    yes: bool,
+    count_only: bool,
+    partition_depth: Option<usize>,
+    sorted: bool,
+    max_output_file_rows: Option<usize>,
+    max_output_file_bytes: Option<usize>,
+    emit_manifest: bool,
+    size_histogram: bool,
+    size_histogram_file: Option<String>,
+    prefix_report: Option<usize>,
+    prefix_report_file: Option<String>,
+    report_file: Option<String>,
+    follow_redirects: bool,
+    no_imds: bool,
+    no_identity_check: bool,
+    quiet: bool,
+    progress: bool,
+    coverage_file: Option<String>,
+    find_duplicates: Option<DuplicateOn>,
+    max_memory: Option<usize>,
+    max_memory_abort: bool,
+    max_rps: Option<u32>,
+    verify_sample: Option<f64>,
+    max_error_rate: Option<f64>,
+    max_objects: Option<usize>,
+    config: Option<String>,
+    config_profile: Option<String>,
+    user_agent: Option<String>,
+    http_connect_timeout: Option<u64>,
+    http_read_timeout: Option<u64>,
+    max_connections: Option<usize>,
+    // page size as given, and as capped at max of S3
+    page_size_given: Option<u32>,
+    page_size: Option<usize>,
+    strict_pagination: bool,
+    strict_limits: bool,
+    raise_nofile: bool,
+    metrics_addr: Option<std::net::SocketAddr>,
+    stats_json: Option<String>,
+    stats_interval: u64,
+    control_socket: Option<String>,
+    tuning_report: bool,
+    retry: Option<core::RetryPolicy>,
+    on_error: OnError,
+    on_throttle: OnThrottle,
+    shutdown_grace: Option<u64>,
+    dry_run: bool,
+    emit_chunks: Option<(u32, String)>,
+    since_ks: Option<String>,
+    ks_files: Vec<String>,
+    output_ks_file: Option<String>,
+    ks_format: KsFormat,
+    ks_compression: KsCompression,
+    output_format: OutputFormat,
+    time_format_given: bool,
+    time_format: TimeFormat,
+    columns: Option<String>,
+    output_columns: Option<String>,
+    parquet: utils::ParquetOptions,
+    arrow_compression: ArrowCompression,
+    null_terminated: bool,
+    output_parquet_file: Option<String>,
+    output_compression_given: bool,
+    output_compression: KsCompression,
+    // if output_log_file is set, it implies log=true
+    log: bool,
+    output_log_file: Option<String>,
+    log_format: LogFormat,
+    run_id: String,
+    no_timestamp: bool,
+    output_dir: Option<String>,
+    started_at: u64,
+}
+
+impl RunOptions {
+    fn checkpointed(&self) -> bool {
+        self.checkpoint_interval.is_some() || self.resume.is_some()
+    }
+
+    // main tasks to wait for, data map and mon task and list task of each side, watch task stands for list and data map task
+    fn tasks_count(&self) -> usize {
+        match (self.mode == RunMode::BiDir, self.watch) {
+            (true, _) => 4,
+            (false, Some(_)) => 2,
+            (false, None) => 3,
+        }
+    }
+}
+
+// start prefixes of command line, "/" for whole bucket, and those given without trailing "/" of --prefix-is-dir
+fn start_prefixes(prefix: Vec<String>, prefix_is_dir: bool) -> (Vec<String>, Vec<String>) {
+    let prefixes: Vec<String> = prefix.into_iter().map(|p| if p == "/" { "".to_string() } else { p }).collect();
+    let normalized = prefixes.iter()
+        .filter(|p| prefix_is_dir && core::dir_prefix(p) != **p)
+        .cloned()
+        .collect();
+    let prefixes = core::dedup_nested_prefixes(prefixes.into_iter()
+        .map(|p| if prefix_is_dir { core::dir_prefix(&p) } else { p }).collect());
+    (prefixes, normalized)
+}
+
+// options of a run from command line and its subcommand, errors are returned before logger is set up
+fn parse_options(cli: Cli, config_names: Vec<String>) -> Result<RunOptions, String> {
+    let assume_role = cli.assume_role_arn.map(|role_arn| core::AssumeRoleOptions {
+        role_arn,
+        external_id: cli.external_id.clone(),
+        session_name: cli.role_session_name.clone(),
+    });
+    let sse_customer_key = match cli.sse_customer_key.as_deref().map(core::SseCustomerKey::parse) {
+        Some(Err(e)) => return Err(error::OptionError::new("sse-customer-key", e).to_string()),
+        Some(Ok(key)) => Some(key),
+        None => None,
+    };
+    let (prefixes, prefixes_normalized) = start_prefixes(cli.prefix, cli.prefix_is_dir);
+    let prefix = prefixes[0].clone();
+    let output_compression = match (cli.output_compression, cli.output_format) {
+        (Some(compression), _) => compression,
+        (None, OutputFormat::Csv | OutputFormat::Ndjson) => output_compression_of(cli.output_parquet_file.as_deref()),
+        (None, _) => KsCompression::None,
+    };
+    #[cfg(unix)]
+    let control_socket = cli.control_socket;
+    #[cfg(not(unix))]
+    let control_socket: Option<String> = None;
+
+    let mut opts = RunOptions {
+        command: RunCommand::List,
+        config_names,
+        mode: RunMode::List,
+        region: None,
+        bucket: String::new(),
+        target_region: None,
+        target_bucket: None,
+        diff_changed_after: None,
+        diff_lmtime_tolerance: None,
+        diff_on: Vec::new(),
+        diff_split: false,
+        diff_status: Vec::new(),
+        diff_direction: DiffDirection::Both,
+        fail_on_diff: None,
+        checkpoint_interval: None,
+        resume: None,
+        emit_ks_hints: None,
+        watch: None,
+        target_max_rps: None,
+        diff_emit_script: None,
+        diff_emit_batch_manifest: None,
+        manifest: None,
+        inventory_data_dir: None,
+        baseline: None,
+        batch_targets_file: None,
+        batch_targets: None,
+        batch_concurrency: 1,
+        profile: cli.profile,
+        target_profile: None,
+        assume_role,
+        target_assume_role: None,
+        request_payer: cli.request_payer,
+        target_request_payer: false,
+        sse_customer_key,
+        // path-style addressing if explicitly requested or if a custom endpoint is provided
+        force_path_style: cli.force_path_style || cli.endpoint.is_some(),
+        endpoint: cli.endpoint,
+        target_endpoint: None,
+        target_force_path_style: false,
+        tls_ca_cert: cli.tls_ca_cert,
+        tls_insecure: cli.tls_insecure,
+        prefix_is_dir: cli.prefix_is_dir,
+        prefixes,
+        prefixes_normalized,
+        prefix,
+        threads: cli.threads,
+        concurrency: cli.concurrency,
+        adaptive_concurrency: cli.adaptive_concurrency,
+        filter: cli.filter,
+        filter_combine: cli.filter_combine,
+        key_compare: cli.key_compare,
+        one_per_prefix: cli.one_per_prefix,
+        shallow: cli.delimiter.map(|delimiter| core::ShallowListOptions {
+            delimiter: delimiter.to_string(),
+            max_depth: cli.max_depth.unwrap_or(1) as usize,
+        }),
+        exclude_prefix: cli.exclude_prefix,
+        skip_empty: cli.skip_empty,
+        skip_dir_markers: cli.skip_dir_markers,
+        hash_seed: cli.hash_prefix_seed.then(|| cli.hash_charset.unwrap_or(core::DEFAULT_HASH_CHARSET.to_string())),
+        versions: cli.versions,
+        with_tags: cli.with_tags,
+        with_owner: cli.with_owner,
+        with_checksum: cli.with_checksum,
+        enrich_all: cli.enrich_all,
+        yes: cli.yes,
+        count_only: cli.count_only,
+        partition_depth: cli.partition_by_prefix.map(|d| d as usize),
+        sorted: cli.sorted,
+        max_output_file_rows: cli.max_output_file_rows.map(|n| n as usize),
+        max_output_file_bytes: cli.max_output_file_bytes,
+        emit_manifest: cli.emit_manifest,
+        size_histogram: cli.size_histogram || cli.size_histogram_file.is_some(),
+        size_histogram_file: cli.size_histogram_file,
+        prefix_report: cli.prefix_report.map(|d| d as usize),
+        prefix_report_file: cli.prefix_report_file,
+        report_file: cli.report_file,
+        follow_redirects: cli.follow_redirects,
+        no_imds: cli.no_imds || credentials::imds_disabled_by_env(),
+        no_identity_check: cli.no_identity_check,
+        quiet: cli.quiet,
+        progress: cli.progress,
+        coverage_file: cli.coverage_file,
+        find_duplicates: cli.find_duplicates.then_some(cli.duplicates_on),
+        max_memory: cli.max_memory,
+        max_memory_abort: cli.max_memory_abort,
+        max_rps: cli.max_rps,
+        verify_sample: cli.verify_sample,
+        max_error_rate: cli.max_error_rate,
+        max_objects: cli.max_objects.map(|n| n as usize),
+        config: cli.config,
+        config_profile: cli.config_profile,
+        user_agent: cli.user_agent,
+        http_connect_timeout: cli.http_connect_timeout,
+        http_read_timeout: cli.http_read_timeout,
+        max_connections: cli.max_connections.map(|n| n as usize),
+        page_size_given: cli.page_size,
+        page_size: cli.page_size.map(|n| (n as usize).min(core::MAX_LIST_PAGE_SIZE)),
+        strict_pagination: cli.strict_pagination,
+        strict_limits: cli.strict_limits,
+        raise_nofile: cli.raise_nofile,
+        metrics_addr: cli.metrics_addr,
+        stats_json: cli.stats_json,
+        stats_interval: cli.stats_interval,
+        control_socket,
+        tuning_report: cli.tuning_report,
+        retry: cli.max_retries.map(|max_retries| core::RetryPolicy {
+            max_retries,
+            base_delay_ms: cli.retry_base_delay,
+        }),
+        on_error: cli.on_error,
+        on_throttle: cli.on_throttle,
+        shutdown_grace: cli.shutdown_grace,
+        dry_run: cli.dry_run,
+        emit_chunks: cli.emit_chunks.zip(cli.emit_chunks_file),
+        since_ks: cli.since_ks,
+        ks_files: cli.ks_file,
+        output_ks_file: cli.output_ks_file,
+        ks_format: cli.ks_format,
+        ks_compression: cli.ks_compression,
+        output_format: cli.output_format,
+        time_format_given: cli.time_format.is_some(),
+        time_format: cli.time_format.unwrap_or_default(),
+        columns: cli.columns,
+        output_columns: cli.output_columns,
+        parquet: utils::ParquetOptions {
+            compression: cli.parquet_compression,
+            row_group_size: cli.parquet_row_group_size.map(|n| n as usize),
+            max_row_group_bytes: cli.parquet_max_row_group_bytes,
+            sorted: cli.sorted,
+            bloom_filter_fpp: cli.parquet_bloom_filter.then_some(cli.parquet_bloom_fpp),
+        },
+        arrow_compression: cli.arrow_compression,
+        null_terminated: cli.null_terminated,
+        output_parquet_file: cli.output_parquet_file,
+        output_compression_given: cli.output_compression.is_some(),
+        output_compression,
+        log: cli.log || cli.output_log_file.is_some(),
+        output_log_file: cli.output_log_file,
+        log_format: cli.log_format,
+        run_id: cli.run_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+        no_timestamp: cli.no_timestamp,
+        output_dir: cli.output_dir,
+        started_at: provenance::now_secs(),
+    };
+
+    match cli.cmd {
+        Commands::List { region, bucket, checkpoint_interval, resume, emit_ks_hints, watch } => {
+            opts.region = region;
+            opts.bucket = bucket;
+            opts.checkpoint_interval = checkpoint_interval;
+            opts.resume = resume;
+            opts.emit_ks_hints = emit_ks_hints;
+            opts.watch = watch;
+        },
+        Commands::FromInventory { region, manifest, data_dir } => {
+            // data files are read from destination bucket, where manifest is as well
+            opts.bucket = match inventory::parse_s3_location(&manifest) {
+                Some((bucket, _)) => bucket.to_string(),
+                None => match inventory::Manifest::load(&manifest) {
+                    Ok(m) => m.destination_bucket_name().to_string(),
+                    Err(e) => return Err(error::InventoryError::new(&manifest, e).to_string()),
+                },
+            };
+            opts.command = RunCommand::FromInventory;
+            opts.region = region;
+            opts.manifest = Some(manifest);
+            opts.inventory_data_dir = data_dir;
+        },
+        Commands::Diff { region, bucket, target_region, target_bucket, target_endpoint, target_force_path_style,
+                target_profile, target_assume_role_arn, target_external_id, diff_changed_after, diff_on, diff_lmtime_tolerance, diff_split,
+                diff_status, diff_direction, fail_on_diff, fail_on_diff_count, target_request_payer, target_max_rps, manifest, data_dir, diff_emit_script,
+                diff_emit_batch_manifest } => {
+            opts.command = RunCommand::Diff;
+            opts.mode = RunMode::BiDir;
+            opts.region = region;
+            opts.bucket = bucket;
+            opts.target_region = target_region;
+            opts.target_bucket = Some(target_bucket);
+            opts.diff_changed_after = diff_changed_after;
+            opts.diff_on = diff_on;
+            opts.diff_lmtime_tolerance = diff_lmtime_tolerance;
+            opts.diff_split = diff_split;
+            opts.diff_status = diff_status;
+            opts.diff_direction = diff_direction;
+            opts.fail_on_diff = fail_on_diff_count.or(if fail_on_diff { Some(1) } else { None });
+            opts.target_max_rps = target_max_rps;
+            opts.diff_emit_script = diff_emit_script;
+            opts.diff_emit_batch_manifest = diff_emit_batch_manifest;
+            opts.manifest = manifest;
+            opts.inventory_data_dir = data_dir;
+            opts.target_request_payer = target_request_payer;
+            opts.target_endpoint = target_endpoint;
+            opts.target_force_path_style = target_force_path_style;
+            opts.target_profile = target_profile.or(opts.profile.clone());
+            opts.target_assume_role = match target_assume_role_arn {
+                Some(role_arn) => Some(core::AssumeRoleOptions {
+                    role_arn,
+                    external_id: target_external_id.or(cli.external_id),
+                    session_name: cli.role_session_name,
+                }),
+                None => opts.assume_role.clone(),
+            };
+        },
+        Commands::DiffAgainst { region, bucket, baseline, diff_changed_after, diff_on, diff_lmtime_tolerance, diff_split,
+                diff_status, diff_direction, fail_on_diff, fail_on_diff_count } => {
+            // baseline is left side, bucket listed now is right side
+            opts.command = RunCommand::DiffAgainst;
+            opts.mode = RunMode::BiDir;
+            opts.target_region = region.clone();
+            opts.target_bucket = Some(bucket.clone());
+            opts.region = region;
+            opts.bucket = bucket;
+            opts.baseline = Some(baseline);
+            opts.diff_changed_after = diff_changed_after;
+            opts.diff_on = diff_on;
+            opts.diff_lmtime_tolerance = diff_lmtime_tolerance;
+            opts.diff_split = diff_split;
+            opts.diff_status = diff_status;
+            opts.diff_direction = diff_direction;
+            opts.fail_on_diff = fail_on_diff_count.or(if fail_on_diff { Some(1) } else { None });
+            opts.target_request_payer = opts.request_payer;
+            opts.target_profile = opts.profile.clone();
+            opts.target_assume_role = opts.assume_role.clone();
+        },
+        Commands::Batch { targets, batch_concurrency } => {
+            let batch_targets = batch::read_targets(&targets).map_err(|e| e.to_string())?;
+            // first target stands for all of them until each is run on its own
+            opts.command = RunCommand::Batch;
+            opts.region = batch_targets[0].region.clone();
+            opts.bucket = batch_targets[0].bucket.clone();
+            opts.batch_targets_file = Some(targets);
+            opts.batch_targets = Some(batch_targets);
+            opts.batch_concurrency = batch_concurrency as usize;
+        },
+        Commands::VerifyCoverage { .. } => unreachable!("verify-coverage lists nothing, run before options of a run are taken"),
+    }
+
+    if opts.target_endpoint.is_some() {
+        opts.target_force_path_style = true;
+    } else {
+        opts.target_endpoint = opts.endpoint.clone();
+        opts.target_force_path_style |= opts.force_path_style;
+    }
+    Ok(opts)
+}
+
+// name of first option of a conflict set
+fn conflict(set: &[(&'static str, bool)]) -> Option<&'static str> {
+    set.iter().find(|(_, set)| *set).map(|(name, _)| *name)
+}
+
+// reason of an option not supported with another one, or at all in diff mode
+fn conflict_reason(conflict: &str) -> String {
+    if conflict == "diff" { "not supported in diff mode".to_string() } else { format!("can not be used with --{}", conflict) }
+}
+
+// options that can't be used together or need another one, checked before any file is read or request is sent
+fn validate_options(opts: &RunOptions) -> Result<(), error::OptionError> {
+    let diff = opts.mode == RunMode::BiDir;
+    let checkpointed = opts.checkpointed();
+    if opts.prefix_is_dir && opts.shallow.as_ref().is_some_and(|shallow| shallow.delimiter != "/") {
+        return Err(error::OptionError::new("prefix-is-dir", "appends \"/\" to prefix, only with --delimiter /".to_string()));
+    }
+
+    // options naming one file or port of a run, targets of batch would all share it
+    if opts.batch_targets.is_some() {
+        if let Some(conflict) = conflict(&[("ks-file", !opts.ks_files.is_empty()), ("output-parquet-file", opts.output_parquet_file.is_some()),
+                ("output-ks-file", opts.output_ks_file.is_some()), ("coverage-file", opts.coverage_file.is_some()),
+                ("metrics-addr", opts.metrics_addr.is_some()), ("stats-json", opts.stats_json.is_some()), ("tuning-report", opts.tuning_report),
+                ("size-histogram-file", opts.size_histogram_file.is_some()), ("prefix-report-file", opts.prefix_report_file.is_some()),
+                ("report-file", opts.report_file.is_some()), ("control-socket", opts.control_socket.is_some()), ("progress", opts.progress)]) {
+            return Err(error::OptionError::new("targets", format!("can not be used with --{}, not shared by targets of batch", conflict)));
+        }
+    }
+
+    // excluded prefixes only matter inside start prefixes
+    for excluded in opts.exclude_prefix.iter() {
+        if let Some(prefix) = opts.prefixes.iter().find(|p| excluded.is_empty() || p.starts_with(excluded.as_str())) {
+            return Err(error::OptionError::new("exclude-prefix", format!("\"{}\" excludes whole start prefix \"{}\"", excluded, prefix)));
+        }
+    }
+
+    if opts.manifest.is_some() || opts.baseline.is_some() {
+        let opt_name = if opts.manifest.is_some() { "manifest" } else { "baseline" };
+        if let Some(conflict) = conflict(&[("one-per-prefix", opts.one_per_prefix), ("delimiter", opts.shallow.is_some()),
+                ("versions", opts.versions)]) {
+            return Err(error::OptionError::new(opt_name, format!("rows are read as objects, can not be used with --{}", conflict)));
+        }
+    }
+
+    if opts.parquet.bloom_filter_fpp.is_some() && opts.output_format != OutputFormat::Parquet {
+        return Err(error::OptionError::new("parquet-bloom-filter", "requires --output-format parquet".to_string()));
+    }
+    if opts.arrow_compression != ArrowCompression::None && opts.output_format != OutputFormat::Arrow {
+        return Err(error::OptionError::new("arrow-compression", "requires --output-format arrow".to_string()));
+    }
+    if opts.output_compression != KsCompression::None {
+        if !matches!(opts.output_format, OutputFormat::Csv | OutputFormat::Ndjson) {
+            return Err(error::OptionError::new("output-compression", "requires --output-format csv or ndjson, parquet and arrow have their own compression".to_string()));
+        }
+        if opts.watch.is_some() {
+            return Err(error::OptionError::new("output-compression", "can not be used with --watch, changes are appended to a plain file".to_string()));
+        }
+    }
+    if opts.time_format_given && !matches!(opts.output_format, OutputFormat::Csv | OutputFormat::Ndjson) {
+        return Err(error::OptionError::new("time-format", "requires --output-format csv or ndjson, parquet and arrow keep a timestamp column, keys have none".to_string()));
+    }
+    if opts.null_terminated && opts.output_format != OutputFormat::Keys {
+        return Err(error::OptionError::new("null-terminated", "requires --output-format keys".to_string()));
+    }
+    if opts.partition_depth.is_some() {
+        if opts.output_format != OutputFormat::Parquet {
+            return Err(error::OptionError::new("partition-by-prefix", "requires --output-format parquet".to_string()));
+        }
+        if let Some(conflict) = conflict(&[("diff-split", opts.diff_split), ("count-only", opts.count_only)]) {
+            return Err(error::OptionError::new("partition-by-prefix", format!("can not be used with --{}", conflict)));
+        }
+    }
+    if opts.shallow.is_some() && opts.emit_ks_hints.is_some() {
+        return Err(error::OptionError::new("emit-ks-hints", "needs a full list, not supported with --delimiter".to_string()));
+    }
+    if opts.count_only && opts.diff_split {
+        return Err(error::OptionError::new("count-only", "no output file to split with --diff-split".to_string()));
+    }
+    if opts.count_only && checkpointed {
+        return Err(error::OptionError::new("count-only", "no output file to checkpoint into".to_string()));
+    }
+
+    if checkpointed {
+        let opt_name = if opts.resume.is_some() { "resume" } else { "checkpoint-interval" };
+        if matches!(opts.output_format, OutputFormat::Parquet | OutputFormat::Arrow) {
+            return Err(error::OptionError::new(opt_name, "requires --output-format ndjson, csv or keys, parquet or arrow file can not be appended".to_string()));
+        }
+        if opts.one_per_prefix || opts.shallow.is_some() {
+            let mode = if opts.one_per_prefix { "one-per-prefix" } else { "delimiter" };
+            return Err(error::OptionError::new(opt_name, format!("not supported in {} mode", mode)));
+        }
+        if opts.hash_seed.is_some() {
+            return Err(error::OptionError::new(opt_name, "can not be used with --hash-prefix-seed, partitions are seeded after checkpoint is made".to_string()));
+        }
+        if opts.prefixes.len() > 1 {
+            return Err(error::OptionError::new(opt_name, "supports one start prefix only".to_string()));
+        }
+    }
+
+    if opts.find_duplicates.is_some() {
+        if let Some(conflict) = conflict(&[("diff", diff), ("versions", opts.versions), ("delimiter", opts.shallow.is_some()),
+                ("count-only", opts.count_only), ("partition-by-prefix", opts.partition_depth.is_some()),
+                ("checkpoint-interval", checkpointed), ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new("find-duplicates", conflict_reason(conflict)));
+        }
+    }
+    if opts.sorted {
+        if let Some(conflict) = conflict(&[("find-duplicates", opts.find_duplicates.is_some()), ("checkpoint-interval", checkpointed),
+                ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new("sorted", format!("can not be used with --{}", conflict)));
+        }
+    }
+    if opts.max_output_file_rows.is_some() || opts.max_output_file_bytes.is_some() {
+        let opt_name = if opts.max_output_file_rows.is_some() { "max-output-file-rows" } else { "max-output-file-bytes" };
+        if let Some(conflict) = conflict(&[("count-only", opts.count_only), ("partition-by-prefix", opts.partition_depth.is_some()),
+                ("find-duplicates", opts.find_duplicates.is_some()), ("checkpoint-interval", checkpointed), ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new(opt_name, format!("can not be used with --{}", conflict)));
+        }
+    }
+    // manifest is written next to output of final dump
+    if opts.emit_manifest {
+        if let Some(conflict) = conflict(&[("checkpoint-interval", checkpointed), ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new("emit-manifest", format!("can not be used with --{}", conflict)));
+        }
+    }
+    // sync script addresses each bucket the way it is listed
+    if opts.diff_emit_script.is_some() {
+        if let Some(conflict) = conflict(&[("versions", opts.versions), ("delimiter", opts.shallow.is_some()),
+                ("one-per-prefix", opts.one_per_prefix), ("count-only", opts.count_only)]) {
+            return Err(error::OptionError::new("diff-emit-script", format!("can not be used with --{}", conflict)));
+        }
+    }
+    // manifest entries are copied from source, with version id if listing versions
+    if opts.diff_emit_batch_manifest.is_some() {
+        if let Some(conflict) = conflict(&[("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix),
+                ("count-only", opts.count_only)]) {
+            return Err(error::OptionError::new("diff-emit-batch-manifest", format!("can not be used with --{}", conflict)));
+        }
+    }
+
+    // tags, and metadata of HeadObject, are fetched by flat list tasks of list mode only
+    for (opt_name, set) in [("with-tags", opts.with_tags), ("enrich-all", opts.enrich_all)] {
+        if !set {
+            continue;
+        }
+        if let Some(conflict) = conflict(&[("diff", diff), ("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix),
+                ("watch", opts.watch.is_some()), ("manifest", opts.manifest.is_some())]) {
+            return Err(error::OptionError::new(opt_name, conflict_reason(conflict)));
+        }
+    }
+    // owner is only in list responses of objects, not of common prefixes, inventory or baseline,
+    // checksum is fetched by flat list tasks of both sides, baseline and inventory have none
+    for (opt_name, set) in [("with-owner", opts.with_owner), ("with-checksum", opts.with_checksum.is_some())] {
+        if !set {
+            continue;
+        }
+        if let Some(conflict) = conflict(&[("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix),
+                ("watch", opts.watch.is_some()), ("manifest", opts.manifest.is_some()), ("baseline", opts.baseline.is_some())]) {
+            return Err(error::OptionError::new(opt_name, format!("can not be used with --{}", conflict)));
+        }
+    }
+    if opts.with_checksum.is_none() && opts.diff_on.contains(&DiffOn::Checksum) {
+        return Err(error::OptionError::new("diff-on", "checksum requires --with-checksum".to_string()));
+    }
+    if opts.diff_lmtime_tolerance.is_some() && !opts.diff_on.contains(&DiffOn::Mtime) {
+        return Err(error::OptionError::new("diff-lmtime-tolerance", "requires mtime in --diff-on".to_string()));
+    }
+
+    // changed objects are not looked for in one direction
+    if let Some(status) = opts.diff_direction.status() {
+        if let Some(other) = opts.diff_status.iter().find(|s| **s != status) {
+            return Err(error::OptionError::new("diff-status", format!("{} is not found with --diff-direction {}", other.name(), opts.diff_direction.name())));
+        }
+    }
+
+    // objects are counted by flat list of one bucket only
+    if opts.max_objects.is_some() {
+        if let Some(conflict) = conflict(&[("diff", diff), ("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix),
+                ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new("max-objects", conflict_reason(conflict)));
+        }
+    }
+
+    // adaptive controller halves on throttling, other policies keep concurrency as given
+    if opts.adaptive_concurrency && opts.on_throttle != OnThrottle::Backoff {
+        return Err(error::OptionError::new("adaptive-concurrency", format!("requires --on-throttle backoff, not {}", opts.on_throttle.name())));
+    }
+
+    // only pages of flat list of objects are checked
+    if opts.strict_pagination && opts.versions {
+        return Err(error::OptionError::new("strict-pagination", "can not be used with --versions".to_string()));
+    }
+
+    // only flat list of whole key space is sampled
+    if opts.verify_sample.is_some() {
+        if let Some(conflict) = conflict(&[("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix),
+                ("watch", opts.watch.is_some())]) {
+            return Err(error::OptionError::new("verify-sample", format!("can not be used with --{}", conflict)));
+        }
+    }
+
+    if opts.watch.is_some() {
+        if matches!(opts.output_format, OutputFormat::Parquet | OutputFormat::Arrow | OutputFormat::Keys) {
+            return Err(error::OptionError::new("watch", "requires --output-format ndjson or csv, changes are appended to output file".to_string()));
+        }
+        if let Some(conflict) = conflict(&[("filter", !opts.filter.is_empty()), ("versions", opts.versions), ("one-per-prefix", opts.one_per_prefix),
+                ("delimiter", opts.shallow.is_some()), ("count-only", opts.count_only), ("columns", opts.columns.is_some()),
+                ("output-columns", opts.output_columns.is_some()), ("size-histogram", opts.size_histogram), ("progress", opts.progress),
+                ("skip-empty", opts.skip_empty), ("skip-dir-markers", opts.skip_dir_markers), ("time-format", opts.time_format_given),
+                ("key-compare", opts.key_compare != KeyCompare::Exact)]) {
+            return Err(error::OptionError::new("watch", format!("can not be used with --{}", conflict)));
+        }
+    }
+
+    // prefixes of one listed bucket are compared to ks file of previous run
+    if opts.since_ks.is_some() {
+        if let Some(conflict) = conflict(&[("diff", diff), ("watch", opts.watch.is_some()), ("batch", opts.batch_targets.is_some()),
+                ("checkpoint-interval", checkpointed), ("count-only", opts.count_only), ("find-duplicates", opts.find_duplicates.is_some()),
+                ("delimiter", opts.shallow.is_some()), ("one-per-prefix", opts.one_per_prefix)]) {
+            return Err(error::OptionError::new("since-ks", conflict_reason(conflict)));
+        }
+    }
+
+    // groups are summed up while objects are exported or counted, list mode only
+    if opts.prefix_report.is_some() {
+        if let Some(conflict) = conflict(&[("diff", diff), ("watch", opts.watch.is_some()), ("checkpoint-interval", checkpointed),
+                ("find-duplicates", opts.find_duplicates.is_some()), ("delimiter", opts.shallow.is_some())]) {
+            return Err(error::OptionError::new("prefix-report", conflict_reason(conflict)));
+        }
+    }
+
+    // fail fast on csv columns we are not able to output
+    if opts.columns.is_some() && opts.output_format != OutputFormat::Csv {
+        return Err(error::OptionError::new("columns", "only applies to --output-format csv".to_string()));
+    }
+    // columns left out of output, whatever fields are listed or fetched for filter
+    if opts.output_columns.is_some() && opts.output_format == OutputFormat::Keys {
+        return Err(error::OptionError::new("output-columns", "not supported with --output-format keys, only key is written".to_string()));
+    }
+
+    // tls options only apply to custom endpoints, never to aws one
+    let batch_endpoint = opts.batch_targets.as_ref().is_some_and(|targets| targets.iter().any(|t| t.endpoint.is_some()));
+    if (opts.tls_ca_cert.is_some() || opts.tls_insecure) && opts.endpoint.is_none() && opts.target_endpoint.is_none() && !batch_endpoint {
+        let opt_name = if opts.tls_insecure { "tls-insecure" } else { "tls-ca-cert" };
+        return Err(error::OptionError::new(opt_name, "requires --endpoint-url, --target-endpoint-url or endpoint of --targets".to_string()));
+    }
+
+    // partitions of ks hints are written out in chunks instead of listed
+    if opts.emit_chunks.is_some() {
+        if let Some(conflict) = conflict(&[("batch", opts.batch_targets.is_some()), ("manifest", opts.manifest.is_some()),
+                ("watch", opts.watch.is_some()), ("checkpoint-interval", checkpointed)]) {
+            return Err(error::OptionError::new("emit-chunks", format!("can not be used with --{}", conflict)));
+        }
+    }
+    Ok(())
+}
+
+// options taken as given but worth a warning, and those costing one request per object
+fn warn_options(opts: &RunOptions) {
+    for excluded in opts.exclude_prefix.iter().filter(|excluded| !opts.prefixes.iter().any(|p| excluded.starts_with(p.as_str()))) {
+        warn!("exclude prefix \"{}\" is not under any start prefix, ignored", excluded);
+    }
+    if opts.coverage_file.is_some() && opts.manifest.is_some() {
+        warn!("coverage file is not written for inventory, no key space is listed");
+    }
+    if opts.diff_emit_script.is_some() && (opts.assume_role.is_some() || opts.target_assume_role.is_some()) {
+        warn!("sync script uses credentials of aws cli, roles assumed by this run are not carried over");
+    }
+    if opts.with_tags {
+        warn!("!!! --with-tags sends one GetObjectTagging request per listed object, billed as GET requests and slower than listing !!!");
+    }
+    if opts.enrich_all {
+        warn!("!!! --enrich-all sends one HeadObject request per listed object, billed as GET requests and slower than listing !!!");
+    }
+    if let Some(algorithm) = opts.with_checksum {
+        warn!("!!! --with-checksum {} sends one GetObjectAttributes request per listed object, billed as GET requests and slower than listing !!!",
+            algorithm.name());
+    }
+    if opts.watch.is_some() && opts.coverage_file.is_some() {
+        warn!("coverage file is not written in watch mode");
+    }
+}
+
+// exit on error of startup, logged as any other error
+fn exit_on_error<T>(result: Result<T, impl std::fmt::Display>) -> T {
+    result.unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1)
+    })
+}
+
+// log to stderr or log file, every line tagged with run id
+fn init_logger(opts: &RunOptions) {
+    let package_name = env!("CARGO_PKG_NAME").replace("-", "_");
+    let loglevel_s = format!("{}={}", package_name, if opts.quiet { "warn" } else { "info" });
+    let loglevel = std::env::var("RUST_LOG").unwrap_or(loglevel_s);
+
+    let log_run_id = opts.run_id.clone();
+    let log_format = opts.log_format;
+    let mut logger = env_logger::Builder::new();
+    logger.parse_filters(&loglevel)
+        .format(move |buf, record| match log_format {
+            LogFormat::Text => writeln!(buf, "[{} {:<5} {} {}] {}",
+                buf.timestamp(), record.level(), record.target(), log_run_id, record.args()),
+            LogFormat::Json => writeln!(buf, "{}", serde_json::json!({
+                "timestamp": buf.timestamp().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "run_id": log_run_id,
+                "message": record.args().to_string(),
+            })),
+        });
+
+    if opts.log {
+        // Use specified log file path or generate default with timestamp
+        let logfile_s = match &opts.output_log_file {
+            Some(path) => path.clone(),
+            None => format!("fastlist_{}.log", Local::now().format("%Y%m%d%H%M%S"))
+        };
+
+        let logfile = std::fs::OpenOptions::new()
+                                .create(true)
+                                .append(true)
+                                .open(&logfile_s)
+                                .expect("unable to open log file");
+        logger.target(env_logger::Target::Pipe(Box::new(logfile)));
+    }
+    logger.init();
+}
+
+// ks and output file of a run, default ones named after region and bucket
+struct OutputFiles {
+    name_stamp: String,
+    ks_extension: String,
+    output_extension: String,
+    ks: String,
+    output: String,
+}
+
+impl OutputFiles {
+    // ranges left unlisted are written next to output, to be listed again with --ks-file
+    fn failed_ranges(&self) -> String {
+        format!("{}.failed-ranges", self.output)
+    }
+
+    // output and ks file of each target of batch, named as in list mode
+    fn of_batch_target(&self, opts: &RunOptions, target: &batch::BatchTarget) -> (String, String) {
+        let mut filename_output = in_output_dir(&opts.output_dir,
+            list_filename(&target.region, &target.bucket, &self.name_stamp, &self.output_extension));
+        if opts.partition_depth.is_some() {
+            filename_output.truncate(filename_output.len() - opts.output_format.extension().len() - 1);
+        }
+        (filename_output, in_output_dir(&opts.output_dir, list_filename(&target.region, &target.bucket, &self.name_stamp, &self.ks_extension)))
+    }
+}
+
+fn output_files(opts: &RunOptions) -> OutputFiles {
+    let dt_str = Local::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let name_stamp = if opts.no_timestamp { String::new() } else { format!("_{}", dt_str) };
+    let ks_extension = format!("{}{}", opts.ks_format.extension(), opts.ks_compression.suffix());
+    let output_extension = format!("{}{}", opts.output_format.extension(), opts.output_compression.suffix());
+
+    // Use custom KS file path if provided, otherwise generate default
+    let ks = match &opts.output_ks_file {
+        Some(path) => path.clone(),
+        None => in_output_dir(&opts.output_dir, list_filename(&opts.region, &opts.bucket, &name_stamp, &ks_extension))
+    };
+
+    // Use custom parquet file path if provided, otherwise generate default
+    let mut output = match &opts.output_parquet_file {
+        Some(path) => path.clone(),
+        None => {
+            if opts.mode == RunMode::List {
+                in_output_dir(&opts.output_dir, list_filename(&opts.region, &opts.bucket, &name_stamp, &output_extension))
+            } else {
+                let region_prefix = opts.region.as_ref().map(|region| format!("{}_", region)).unwrap_or_default();
+                let target_region_prefix = opts.target_region.as_ref().map(|region| format!("{}_", region)).unwrap_or_default();
+                in_output_dir(&opts.output_dir, format!("{}_{}_{}_{}{}.{}", region_prefix, opts.bucket,
+                    target_region_prefix, opts.target_bucket.as_ref().unwrap(), name_stamp, output_extension))
+            }
+        }
+    };
+    // output file of partitions is the base directory, default one without extension
+    if opts.partition_depth.is_some() && opts.output_parquet_file.is_none() {
+        if let Some(base) = output.strip_suffix(&format!(".{}", opts.output_format.extension())) {
+            output = base.to_string();
+        }
+    }
+
+    OutputFiles {
+        name_stamp,
+        ks_extension,
+        output_extension,
+        ks,
+        output,
+    }
+}
+
+// ks hints of a run, from ks files or what is left to list of a checkpoint
+struct KsInput {
+    filenames: Vec<String>,
+    list: Vec<String>,
+    ranges: Vec<data_map::KsRange>,
+    merged_len: usize,
+    hints: data_map::KeySpaceHints,
+    hints_pairs_len: usize,
+}
+
+fn load_ks_input(opts: &RunOptions) -> Result<KsInput, String> {
+    let given = !opts.ks_files.is_empty();
+    let filenames = if given {
+        opts.ks_files.clone()
+    } else if opts.batch_targets.is_some() {
+        Vec::new()
+    } else {
+        // default ks hints input filename - include region if provided
+        if let Some(region) = &opts.region {
+            vec![format!("{}_{}_{}", region, opts.bucket, "ks_hints.input")]
+        } else {
+            vec![format!("{}_{}", opts.bucket, "ks_hints.input")]
+        }
+    };
+
+    // load ks hints if exists
+    let (list, ranges, merged_len) = load_ks_files(&filenames, given, opts.concurrency * core::DEFAULT_KS_HINTS_SPLITS_PER_TASK)?;
+    let hints = data_map::KeySpaceHints::new_from(&list);
+    let hints_pairs_len = hints.len();
+    Ok(KsInput {
+        filenames,
+        list,
+        ranges,
+        merged_len,
+        hints,
+        hints_pairs_len,
+    })
+}
+
+// checkpoint of complete partitions, on resume partitions left in it replace ks hints and its output file is appended to
+fn load_checkpointer(opts: &mut RunOptions, files: &mut OutputFiles, ks: &mut KsInput) -> Result<Option<checkpoint::Checkpointer>, String> {
+    if !opts.checkpointed() {
+        return Ok(None);
+    }
+    let opt_name = if opts.resume.is_some() { "resume" } else { "checkpoint-interval" };
+    if !ks.ranges.is_empty() {
+        return Err(error::OptionError::new(opt_name, "not supported with failed ranges of --ks-file".to_string()).to_string());
+    }
+    let interval = opts.checkpoint_interval.unwrap_or(core::DEFAULT_CHECKPOINT_INTERVAL_SECS);
+    let (filename, checkpoint) = match opts.resume.clone() {
+        Some(filename) => {
+            let checkpoint = checkpoint::Checkpoint::load(&filename)
+                .map_err(|e| format!("unable to load checkpoint file {}: {}", filename, e))?;
+            let mismatch = if checkpoint.bucket != opts.bucket {
+                Some(format!("bucket {}", checkpoint.bucket))
+            } else if checkpoint.prefix != opts.prefix {
+                Some(format!("prefix \"{}\"", checkpoint.prefix))
+            } else if checkpoint.output_format != opts.output_format.extension() {
+                Some(format!("output format {}", checkpoint.output_format))
+            } else if checkpoint.versions != opts.versions {
+                Some(format!("versions {}", checkpoint.versions))
+            } else if opts.output_parquet_file.as_ref().is_some_and(|f| *f != checkpoint.output_file) {
+                Some(format!("output file {}", checkpoint.output_file))
+            } else {
+                None
+            };
+            if let Some(mismatch) = mismatch {
+                return Err(error::OptionError::new("resume", format!("checkpoint {} was taken with {}", filename, mismatch)).to_string());
+            }
+            ks.hints = checkpoint.hints();
+            ks.hints_pairs_len = ks.hints.len();
+            files.output = checkpoint.output_file.clone();
+            // resumed output is appended as another gzip member or zstd frame
+            if !opts.output_compression_given && opts.output_parquet_file.is_none() {
+                opts.output_compression = output_compression_of(Some(&files.output));
+            }
+            (filename, checkpoint)
+        },
+        None => {
+            let checkpoint = checkpoint::Checkpoint::new(&opts.bucket, &opts.prefix, &files.output,
+                opts.output_format.extension(), opts.versions, &ks.hints);
+            (format!("{}.checkpoint", files.output), checkpoint)
+        },
+    };
+    Ok(Some(checkpoint::Checkpointer::new(&filename, interval, checkpoint, opts.resume.is_some())))
+}
+
+// ks file of previous run for --since-ks, key names are compared by digest file of the run if it left one
+fn load_since_ks(filename: &str) -> Result<data_map::SinceKs, String> {
+    let since = utils::open_decompressed(filename).and_then(data_map::SinceKs::read)
+        .map_err(|e| format!("unable to read ks file {} of --since-ks: {}", filename, e))?;
+    // ks file of an older run only has counts
+    let filename_digest = format!("{}{}", filename, data_map::KS_DIGEST_SUFFIX);
+    match std::fs::File::open(&filename_digest) {
+        Ok(file) => since.with_digests(std::io::BufReader::new(file))
+            .map_err(|e| format!("unable to read digest file {} of --since-ks: {}", filename_digest, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("!!! no digest file {} of --since-ks, prefixes are compared by object count only !!!", filename_digest);
+            Ok(since)
+        },
+        Err(e) => Err(format!("unable to read digest file {} of --since-ks: {}", filename_digest, e)),
+    }
+}
+
+// columns of csv output, and those kept in other formats with --output-columns
+fn output_columns(opts: &RunOptions) -> Result<(Vec<utils::OutputColumn>, Option<Vec<utils::OutputColumn>>), error::OptionError> {
+    let optional_columns = utils::OptionalColumns {
+        diff_mode: opts.mode == RunMode::BiDir,
+        versions: opts.versions,
+        shallow: opts.shallow.is_some(),
+        duplicates: opts.find_duplicates.is_some(),
+        tags: opts.with_tags,
+        owner: opts.with_owner,
+        checksum: opts.with_checksum.is_some(),
+        enrich: opts.enrich_all,
+    };
+    let columns = utils::parse_columns(opts.columns.as_deref(), &optional_columns)?;
+    let output_columns = opts.output_columns.as_deref().map(|c| utils::parse_output_columns(c, &optional_columns)).transpose()?;
+    // same as --columns for csv output
+    let columns = match (&output_columns, opts.output_format) {
+        (Some(columns), OutputFormat::Csv) => columns.clone(),
+        _ => columns,
+    };
+    Ok((columns, output_columns))
+}
+
+fn tls_verify(opts: &RunOptions) -> Result<Option<core::TlsVerify>, error::OptionError> {
+    match &opts.tls_ca_cert {
+        Some(path) => core::TlsVerify::from_ca_cert(path).map(Some)
+            .map_err(|e| error::OptionError::new("tls-ca-cert", format!("unable to load {}: {}", path, e))),
+        None => Ok(opts.tls_insecure.then_some(core::TlsVerify::Insecure)),
+    }
+}
+
+// fail fast on profile not found, SDK would only fall back to other credential providers
+fn check_profiles(opts: &RunOptions) -> Result<(), error::OptionError> {
+    for (opt_name, profile) in [("profile", &opts.profile), ("target-profile", &opts.target_profile)] {
+        if let Some(profile) = profile.as_ref().filter(|p| !utils::aws_profile_exists(p)) {
+            return Err(error::OptionError::new(opt_name, format!("profile {} not found in aws config or credentials file", profile)));
+        }
+    }
+    Ok(())
+}
+
+// connections and output writers checked against soft limit of open files upfront, not to fail mid-run
+fn check_nofile_limit(opts: &RunOptions) -> Result<(), error::NofileLimitError> {
+    let Some((mut soft, hard)) = utils::nofile_limit() else {
+        return Ok(());
+    };
+    if opts.raise_nofile && soft < hard.min(NOFILE_RAISE_MAX) {
+        let raised = hard.min(NOFILE_RAISE_MAX);
+        match utils::set_nofile_soft_limit(raised) {
+            Ok(_) => {
+                info!("  - soft limit of open files raised from {} to {}", soft, raised);
+                soft = raised;
+            },
+            Err(e) => warn!("unable to raise soft limit of open files to {}: {}", raised, e),
+        }
+    }
+    let buckets = if opts.mode == RunMode::BiDir { 2 } else { 1 } * opts.batch_concurrency as u64;
+    let writers = if opts.count_only { 0 } else if opts.partition_depth.is_some() { core::DEFAULT_PARTITION_MAX_OPEN_WRITERS as u64 } else { 4 };
+    check_open_files(soft, buckets, opts.with_tags || opts.with_checksum.is_some() || opts.enrich_all,
+        writers * opts.batch_concurrency as u64, opts.concurrency, opts.max_connections)
+}
+
+// startup log of options a run is taken with
+fn log_options(opts: &RunOptions, files: &OutputFiles, ks: &KsInput, checkpointer: Option<&checkpoint::Checkpointer>,
+        since_ks: Option<&data_map::SinceKs>) {
+    info!("fast list tools v{} starting:", env!("CARGO_PKG_VERSION"));
+    info!("  - run id {}", opts.run_id);
+    if let Some(filename) = &opts.config {
+        let profile = opts.config_profile.as_ref().map(|p| format!(" profile {}", p)).unwrap_or_default();
+        info!("  - config file {}{}, defaults of {}", filename, profile,
+            if opts.config_names.is_empty() { "no option".to_string() } else { opts.config_names.join(", ") });
+    }
+    info!("  - mode {:?}, threads {}, concurrent tasks {}", opts.mode, opts.threads, opts.concurrency);
+    info!("  - start prefix {}", opts.prefixes.join(", "));
+    for prefix in &opts.prefixes_normalized {
+        info!("  - prefix \"{}\" taken as directory \"{}\" with --prefix-is-dir", prefix, core::dir_prefix(prefix));
+    }
+    if let (Some(targets), Some(filename)) = (&opts.batch_targets, &opts.batch_targets_file) {
+        info!("  - batch of {} targets of {}, {} listed at a time", targets.len(), filename, opts.batch_concurrency);
+    } else if let Some(region) = &opts.region {
+        info!("  - region {}", region);
+    }
+    if let Some(profile) = &opts.profile {
+        info!("  - profile {}", profile);
+    }
+    if opts.target_profile.is_some() && opts.target_profile != opts.profile {
+        info!("  - target profile {}", opts.target_profile.as_deref().unwrap());
+    }
+    if let Some(role) = &opts.assume_role {
+        info!("  - assume role {}", role.role_arn);
+    }
+    if let Some(role) = opts.target_assume_role.as_ref().filter(|r| Some(&r.role_arn) != opts.assume_role.as_ref().map(|r| &r.role_arn)) {
+        info!("  - target assume role {}", role.role_arn);
+    }
+    if let Some(wm) = opts.diff_changed_after {
+        info!("  - only differences changed after {}", wm);
+    }
+    if opts.mode == RunMode::BiDir {
+        match opts.diff_direction.status() {
+            Some(status) => info!("  - diff direction {}, only {} objects, attributes not compared", opts.diff_direction.name(), status.name()),
+            None => info!("  - diff on {:?}", opts.diff_on),
+        }
+        if let Some(tolerance) = opts.diff_lmtime_tolerance {
+            info!("  - last modified within {}s taken as equal", tolerance);
+        }
+    }
+    if let Some(threshold) = opts.fail_on_diff {
+        info!("  - fail if {} or more differences found", threshold);
+    }
+    for filter in &opts.filter {
+        info!("  - filter \"{}\"", filter);
+    }
+    if opts.filter.len() > 1 {
+        info!("  - filters combined with {:?}", opts.filter_combine);
+    }
+    if opts.key_compare != KeyCompare::Exact {
+        info!("  - keys compared {:?}, output keeps keys as listed", opts.key_compare);
+    }
+    if opts.mode == RunMode::BiDir && (opts.target_endpoint != opts.endpoint || opts.target_force_path_style != opts.force_path_style) {
+        let endpoint = |e: &Option<String>| e.clone().unwrap_or_else(|| "<aws default>".to_string());
+        info!("  - using source endpoint-url: {}{}", endpoint(&opts.endpoint),
+            if opts.force_path_style { ", path-style addressing" } else { "" });
+        info!("  - using target endpoint-url: {}{}", endpoint(&opts.target_endpoint),
+            if opts.target_force_path_style { ", path-style addressing" } else { "" });
+    } else {
+        if let Some(endpoint) = &opts.endpoint {
+            info!("  - using custom endpoint-url: {}", endpoint);
+        }
+        if opts.force_path_style {
+            info!("  - using path-style addressing");
+        }
+    }
+    if let Some(max_memory) = opts.max_memory {
+        if utils::current_rss().is_none() {
+            warn!("  - max memory {} set, but rss is not available on this platform", max_memory);
+        } else {
+            info!("  - max memory {}{}", utils::fmt_bytes(max_memory as u64),
+                if opts.max_memory_abort { ", abort if exceeded" } else { "" });
+        }
+    }
+    match (opts.request_payer, opts.target_request_payer) {
+        (true, true) => info!("  - pay for list requests of both buckets"),
+        (true, false) => info!("  - pay for list requests of {}bucket", if opts.mode == RunMode::BiDir { "source " } else { "" }),
+        (false, true) => info!("  - pay for list requests of target bucket"),
+        (false, false) => {},
+    }
+    if let Some(stats_json) = &opts.stats_json {
+        info!("  - stats json snapshots to {} every {}s", stats_json, opts.stats_interval);
+    }
+    if let Some(path) = &opts.control_socket {
+        info!("  - control socket {}, commands status, pause, resume and quit", path);
+    }
+    if let Some(grace) = opts.shutdown_grace {
+        info!("  - shutdown grace {}s", grace);
+    }
+    if opts.adaptive_concurrency {
+        info!("  - adaptive concurrency up to {} list tasks", opts.concurrency);
+    }
+    match opts.on_throttle {
+        OnThrottle::Wait => info!("  - on throttle wait, throttled list calls are retried until they go through"),
+        OnThrottle::Abort => info!("  - on throttle abort run after {}s throttled without a success", core::DEFAULT_THROTTLE_ABORT_SECS),
+        OnThrottle::Backoff => {},
+    }
+    if let Some(path) = &opts.tls_ca_cert {
+        info!("  - trust CA certificates of {} for custom endpoint", path);
+    }
+    if opts.tls_insecure {
+        warn!("!!! TLS certificate verification of custom endpoint is DISABLED, connections are open to interception !!!");
+    }
+    if let Some(timeout) = opts.http_connect_timeout {
+        info!("  - http connect timeout {}s", timeout);
+    }
+    if let Some(timeout) = opts.http_read_timeout {
+        info!("  - http read timeout {}s", timeout);
+    }
+    if let Some(max_connections) = opts.max_connections {
+        info!("  - max {} connections per bucket", max_connections);
+        if max_connections < opts.concurrency {
+            warn!("!!! max connections {} below concurrency {}, list tasks will wait for connections !!!", max_connections, opts.concurrency);
+        }
+    }
+    match (opts.page_size_given, opts.page_size) {
+        (Some(given), Some(page_size)) if given as usize > page_size => {
+            warn!("  - list page size {} over max {} of S3, {} keys per page", given, core::MAX_LIST_PAGE_SIZE, page_size);
+        },
+        (_, Some(page_size)) => info!("  - list page size {} keys", page_size),
+        _ => info!("  - list page size {} keys of S3 default", core::DEFAULT_LIST_PAGE_SIZE),
+    }
+    if opts.strict_pagination {
+        info!("  - strict pagination, range after a suspect page fails");
+    }
+    if let Some(retry) = &opts.retry {
+        info!("  - retry list calls up to {} times, base delay {}ms", retry.max_retries, retry.base_delay_ms);
+    }
+    match opts.on_error {
+        OnError::Continue => info!("  - on list error continue, failed ranges are recorded to {}", files.failed_ranges()),
+        OnError::Abort => info!("  - on list error abort run"),
+    }
+    match (opts.max_rps, opts.target_max_rps) {
+        (Some(max_rps), None) => info!("  - max {} list requests per second", max_rps),
+        (max_rps, Some(target_max_rps)) => {
+            if let Some(max_rps) = max_rps {
+                info!("  - max {} list requests per second of source bucket", max_rps);
+            }
+            info!("  - max {} list requests per second of target bucket", target_max_rps);
+        },
+        (None, None) => {},
+    }
+    if let Some(coverage_file) = &opts.coverage_file {
+        if opts.one_per_prefix || opts.shallow.is_some() {
+            warn!("  - coverage file {} is not written in {} mode", coverage_file,
+                if opts.one_per_prefix { "one-per-prefix" } else { "delimiter" });
+        } else {
+            info!("  - coverage file {}", coverage_file);
+        }
+    }
+    if opts.versions {
+        info!("  - list all object versions and delete markers");
+    }
+    if opts.with_tags {
+        info!("  - fetch tags of each listed object");
+    }
+    if opts.with_owner {
+        info!("  - list owner of each object");
+    }
+    if let Some(algorithm) = opts.with_checksum {
+        info!("  - fetch {} checksum of each listed object", algorithm.name());
+    }
+    if opts.enrich_all {
+        info!("  - fetch metadata of each listed object with HeadObject, {}", enrich_cost_estimate(opts.max_objects));
+    }
+    if opts.count_only {
+        info!("  - count only, no output file");
+    }
+    if let Some(depth) = opts.prefix_report {
+        info!("  - report objects and bytes by first {} segments of prefix", depth);
+    }
+    if let (Some(filename), Some(since)) = (&opts.since_ks, since_ks) {
+        info!("  - only objects of prefixes added or changed since ks file {} of {} prefixes exported, compared by {}", filename, since.len(),
+            if since.has_digests() { "object count and key names" } else { "object count" });
+    }
+    if let Some(dir) = &opts.output_dir {
+        info!("  - default output files under {}", dir);
+    }
+    if opts.no_timestamp {
+        info!("  - default output file names without datetime, those of previous runs are overwritten");
+    }
+    if let Some(filename) = &opts.diff_emit_script {
+        info!("  - sync script of differences to {}", filename);
+    }
+    if let Some(n) = opts.max_objects {
+        info!("  - stop listing after {} objects", n);
+    }
+    if let Some(percent) = opts.verify_sample {
+        info!("  - verify {}% of listed objects with HEAD requests after listing", percent);
+    }
+    if let Some(filename) = &opts.diff_emit_batch_manifest {
+        info!("  - batch operations manifest of objects to copy to {}", filename);
+    }
+    if let Some(on) = opts.find_duplicates {
+        info!("  - find duplicates by {}, only objects of same content as others are exported", if on == DuplicateOn::SizeEtag { "size and etag" } else { "etag" });
+    }
+    if let Some(hints_file) = &opts.emit_ks_hints {
+        info!("  - ks hints of {} partitions emitted to {}", opts.concurrency * core::DEFAULT_KS_HINTS_SPLITS_PER_TASK, hints_file);
+    }
+    if let Some(depth) = opts.partition_depth {
+        info!("  - parquet files partitioned by first {} key prefix segments under {}/", depth, files.output);
+    }
+    if opts.output_compression != KsCompression::None {
+        info!("  - {} output compressed into a {} stream", opts.output_format.extension(), &opts.output_compression.suffix()[1..]);
+    }
+    match (opts.max_output_file_rows, opts.max_output_file_bytes) {
+        (Some(rows), Some(bytes)) => info!("  - output rolled over to next file every {} objects or {}", rows, utils::fmt_bytes_human(bytes as u64)),
+        (Some(rows), None) => info!("  - output rolled over to next file every {} objects", rows),
+        (None, Some(bytes)) => info!("  - output rolled over to next file every {}", utils::fmt_bytes_human(bytes as u64)),
+        (None, None) => {},
+    }
+    if let Some(key) = &opts.sse_customer_key {
+        info!("  - SSE-C key of md5 {} sent with HEAD requests", key.key_md5());
+        if opts.verify_sample.is_none() {
+            warn!("SSE-C key is only sent with HEAD requests of --verify-sample, list and tag requests do not need it");
+        }
+    }
+    if let Some(fpp) = opts.parquet.bloom_filter_fpp {
+        info!("  - bloom filter of key in parquet row groups, false positive probability {}", fpp);
+    }
+    if opts.emit_manifest {
+        info!("  - run manifest written to {}", provenance::manifest_filename(&files.output, &files.output_extension));
+    }
+    if opts.sorted {
+        info!("  - output sorted by key, keys of all exported objects are held in memory to sort");
+    }
+    if let Some(checkpointer) = checkpointer {
+        let (complete, total, objects) = checkpointer.progress();
+        if checkpointer.is_resumed() {
+            info!("  - resume from checkpoint {}, {}/{} partitions complete, {} objects exported to {}",
+                checkpointer.filename(), complete, total, utils::fmt_count(objects), files.output);
+        } else {
+            info!("  - checkpoint to {} every {}s", checkpointer.filename(),
+                opts.checkpoint_interval.unwrap_or(core::DEFAULT_CHECKPOINT_INTERVAL_SECS));
+        }
+    }
+    let exclude_prefix = listed_exclude_prefixes(opts);
+    if !exclude_prefix.is_empty() {
+        info!("  - exclude prefixes {}", exclude_prefix.join(", "));
+    }
+    if opts.skip_empty || opts.skip_dir_markers {
+        let skipped: Vec<&str> = [("zero-byte objects", opts.skip_empty), ("directory markers", opts.skip_dir_markers)].into_iter()
+            .filter(|(_, set)| *set).map(|(name, _)| name).collect();
+        info!("  - skip {} as listed", skipped.join(" and "));
+    }
+    if let Some(interval) = opts.watch {
+        info!("  - watch, re-list every {}s until ctrl-c, changes appended to {}, no ks file", interval, files.output);
+    }
+    if let Some(manifest) = &opts.manifest {
+        info!("  - source objects read from inventory manifest {}, ks hints ignored", manifest);
+    }
+    if let Some(baseline) = &opts.baseline {
+        info!("  - left side read from baseline {}, only_left is deleted since, only_right is added", baseline);
+    }
+    let ks_filename = ks.filenames.join(", ");
+    if opts.one_per_prefix {
+        info!("  - list first object per common prefix only, ks hints ignored");
+    } else if let Some(shallow) = &opts.shallow {
+        info!("  - list with delimiter \"{}\" down to {} levels of common prefixes, ks hints ignored", shallow.delimiter, shallow.max_depth);
+    } else if opts.batch_targets.is_some() {
+        info!("  - ks hints ignored in batch, each target is listed from start prefix");
+    } else if opts.resume.is_some() {
+        info!("  - {} ks hints pairs left to list from checkpoint, ks hints file ignored", ks.hints_pairs_len);
+    } else if !ks.ranges.is_empty() {
+        info!("  - loaded {} failed ranges from input file {}, listed under their own prefix instead of --prefix", ks.ranges.len(), ks_filename);
+    } else if ks.list.is_empty() && opts.hash_seed.is_some() {
+        info!("  - NO ks hints found, start prefix split at first characters \"{}\" of keys after it", opts.hash_seed.as_deref().unwrap());
+    } else if ks.list.is_empty() {
+        info!("  - NO ks hints found");
+    } else {
+        if ks.filenames.len() > 1 {
+            info!("  - merged {} prefix of {} ks hints files, {} left after dedup", ks.merged_len, ks.filenames.len(), ks.list.len());
+        }
+        info!("  - loaded {} prefix from input file {}, assembly into {} of ks hints pairs", ks.list.len(), ks_filename, ks.hints_pairs_len);
+    }
+}
+
+// excluded prefixes under a start prefix, others are ignored with a warning
+fn listed_exclude_prefixes(opts: &RunOptions) -> Vec<String> {
+    opts.exclude_prefix.iter().filter(|excluded| opts.prefixes.iter().any(|p| excluded.starts_with(p.as_str()))).cloned().collect()
+}
+
+// list plan of each target of batch, nothing is listed
+fn print_batch_plan(opts: &RunOptions, files: &OutputFiles, targets: &[batch::BatchTarget]) {
+    for target in targets {
+        let (filename_output, filename_ks) = files.of_batch_target(opts, target);
+        println!("Plan - target s3://{} region {} endpoint {}", target.bucket, target.region.as_deref().unwrap_or("<default>"),
+            target.endpoint.as_deref().or(opts.endpoint.as_deref()).unwrap_or("<aws default>"));
+        let mut files = vec![("ks", filename_ks.as_str())];
+        if !opts.count_only {
+            files.insert(0, ("output", filename_output.as_str()));
+        }
+        let start_prefixes = ks_hints_of_prefixes(&opts.prefixes, &[], &[], data_map::KeySpaceHints::new_from(&Vec::new()));
+        print_list_plan(&[target.bucket.as_str()], &start_prefixes, opts.concurrency, &files, opts.hash_seed.as_deref());
+    }
+}
+
+// list plan of dry run, nothing is listed
+fn print_plan(opts: &RunOptions, files: &OutputFiles, ks: KsInput) {
+    let mut buckets = vec![opts.bucket.as_str()];
+    buckets.extend(opts.target_bucket.as_deref());
+    let start_prefixes = || ks_hints_of_prefixes(&opts.prefixes, &ks.list, &ks.ranges, ks.hints.clone());
+    if let Some(manifest) = &opts.manifest {
+        println!("Plan - objects of {} read from inventory manifest {}, no list request", opts.bucket, manifest);
+    } else if let Some(baseline) = &opts.baseline {
+        println!("Plan - left side read from baseline {}, right side is listed", baseline);
+        print_list_plan(&[opts.bucket.as_str()], &start_prefixes(), opts.concurrency, &[], opts.hash_seed.as_deref());
+    } else if opts.one_per_prefix || opts.shallow.is_some() {
+        println!("Plan - common prefixes are listed as found, partitions are not known before listing");
+        println!("Plan - up to {} parallel list streams per bucket, {} in total for {}", opts.concurrency,
+            opts.concurrency * buckets.len(), buckets.join(", "));
+    } else if let Some(interval) = opts.watch {
+        println!("Plan - re-listed every {}s until ctrl-c, changes appended to {}", interval, files.output);
+        print_list_plan(&buckets, &start_prefixes(), opts.concurrency, &[], opts.hash_seed.as_deref());
+    } else {
+        let mut output_files = vec![("ks", files.ks.as_str())];
+        if !opts.count_only {
+            output_files.insert(0, ("output", files.output.as_str()));
+        }
+        print_list_plan(&buckets, &start_prefixes(), opts.concurrency, &output_files, opts.hash_seed.as_deref());
+    }
+    if opts.enrich_all {
+        println!("Plan - {}", enrich_cost_estimate(opts.max_objects));
+    }
+}
+
+// partitions of ks hints are written out in chunks instead of listed, exit code of run
+fn emit_chunks(opts: &RunOptions, ks: KsInput, n: u32, filename: &str) -> i32 {
+    let mut start_prefixes = ks_hints_of_prefixes(&opts.prefixes, &ks.list, &ks.ranges, ks.hints);
+    if let Some(charset) = &opts.hash_seed {
+        start_prefixes = tasks_s3::seed_start_prefixes(start_prefixes, &charset.chars().collect::<Vec<char>>());
+    }
+    let chunks = data_map::split_chunks(&start_prefixes, n as usize);
+    if chunks.len() < n as usize {
+        let pairs: usize = chunks.iter().map(|c| c.len()).sum();
+        let more = if opts.hash_seed.is_some() { "a longer --hash-charset" } else { "--hash-prefix-seed" };
+        error!("{}", error::OptionError::new("emit-chunks", format!("only {} key space partitions to split into {} chunks, \
+            give more ks hints or {}", pairs, n, more)));
+        return 1;
+    }
+    match data_map::write_chunks(filename, &chunks) {
+        Ok(filenames) => {
+            for (i, (ranges, chunk_file)) in chunks.iter().zip(&filenames).enumerate() {
+                let (first, last) = (&ranges[0], &ranges[ranges.len() - 1]);
+                info!("Emit Chunks - chunk {} - {} partitions, after \"{}\" of prefix \"{}\" until {} of prefix \"{}\", to {}", i,
+                    ranges.len(), first.start, first.prefix, last.end.as_ref().map(|e| format!("\"{}\"", e)).as_deref()
+                    .unwrap_or("end of key space"), last.prefix, chunk_file);
+            }
+            info!("Emit Chunks - {} chunk files listed in {}, list each with --ks-file", filenames.len(), filename);
+            0
+        },
+        Err(e) => {
+            error!("unable to write chunks file {}: {}", filename, e);
+            1
+        },
+    }
+}
+
+// one request per object is confirmed before any is sent
+fn confirm_enrich_all(max_objects: Option<usize>) -> Result<(), String> {
+    if !std::io::stdin().is_terminal() {
+        return Err(error::OptionError::new("enrich-all", "needs confirmation, pass --yes to run without a terminal".to_string()).to_string());
+    }
+    eprint!("--enrich-all sends {}, continue? [y/N] ", enrich_cost_estimate(max_objects));
+    if !confirmed(std::io::stdin().lock()) {
+        return Err("--enrich-all not confirmed, nothing listed".to_string());
+    }
+    Ok(())
+}
+
+// ctrl-c, and SIGTERM or SIGHUP of orchestrators on unix, all stop the run the same way
+fn set_quit_handler(shutdown_grace: Option<u64>) -> Arc<AtomicBool> {
+    let quit = Arc::new(AtomicBool::new(false));
+    let q = quit.clone();
+    ctrlc::set_handler(move || {
+        if q.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // bound the wait for output to be flushed, exit without it if not done in time
+        if let Some(grace) = shutdown_grace {
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_secs(grace));
+                eprintln!("shutdown grace of {}s exceeded, exit before output is flushed", grace);
+                std::process::exit(1);
+            });
+        }
+    }).expect("failed to setting ctrl-c signal handler");
+    quit
+}
+
+// s3 client of source side, each target of batch has its own region and endpoint
+fn source_client_options(opts: &RunOptions, tls: &Option<core::TlsVerify>) -> core::S3ClientOptions {
+    core::S3ClientOptions {
+        region: opts.region.clone(),
+        profile: opts.profile.clone(),
+        assume_role: opts.assume_role.clone(),
+        endpoint: opts.endpoint.clone(),
+        force_path_style: opts.force_path_style,
+        follow_redirects: opts.follow_redirects,
+        retry: opts.retry,
+        user_agent: opts.user_agent.clone(),
+        connect_timeout: opts.http_connect_timeout,
+        read_timeout: opts.http_read_timeout,
+        max_connections: opts.max_connections,
+        tls: tls.clone().filter(|_| opts.endpoint.is_some()),
+        no_imds: opts.no_imds,
+    }
+}
+
+// s3 client of target side of diff, with its own region, profile, role and endpoint
+fn target_client_options(opts: &RunOptions, tls: &Option<core::TlsVerify>) -> core::S3ClientOptions {
+    core::S3ClientOptions {
+        region: opts.target_region.clone(),
+        profile: opts.target_profile.clone(),
+        assume_role: opts.target_assume_role.clone(),
+        endpoint: opts.target_endpoint.clone(),
+        force_path_style: opts.target_force_path_style,
+        tls: tls.clone().filter(|_| opts.target_endpoint.is_some()),
+        ..source_client_options(opts, tls)
+    }
+}
+
+// options of data map task
+fn data_map_options(opts: &RunOptions, files: &OutputFiles, columns: Vec<utils::OutputColumn>, output_columns: Option<Vec<utils::OutputColumn>>,
+        since_ks: Option<Arc<data_map::SinceKs>>) -> core::DataMapOptions {
+    let run_metadata = provenance::RunMetadata {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        run_id: opts.run_id.clone(),
+        mode: opts.command.name().to_string(),
+        bucket: opts.bucket.clone(),
+        region: opts.region.clone(),
+        endpoint: opts.endpoint.clone(),
+        prefixes: opts.prefixes.clone(),
+        target_bucket: opts.target_bucket.clone(),
+        target_region: opts.target_region.clone(),
+        target_endpoint: opts.target_bucket.as_ref().and(opts.target_endpoint.clone()),
+        filters: opts.filter.clone(),
+        started_at: opts.started_at,
+    };
+    let diff_script = opts.diff_emit_script.as_ref().map(|filename| script::DiffScriptOptions {
+        filename: filename.clone(),
+        source: script::ScriptSide {
+            bucket: opts.bucket.clone(),
+            region: opts.region.clone(),
+            endpoint: opts.endpoint.clone(),
+            profile: opts.profile.clone(),
+            request_payer: opts.request_payer,
+        },
+        target: script::ScriptSide {
+            bucket: opts.target_bucket.clone().unwrap_or_default(),
+            region: opts.target_region.clone(),
+            endpoint: opts.target_endpoint.clone(),
+            profile: opts.target_profile.clone(),
+            request_payer: opts.target_request_payer,
+        },
+        comment: format!("sync s3://{} to s3://{}, from diff of s3-fast-list v{} run {}", opts.bucket,
+            opts.target_bucket.as_deref().unwrap_or_default(), env!("CARGO_PKG_VERSION"), opts.run_id),
+    });
+    let diff_batch_manifest = opts.diff_emit_batch_manifest.as_ref().map(|filename| script::BatchManifestOptions {
+        filename: filename.clone(),
+        bucket: opts.bucket.clone(),
+        versions: opts.versions,
+    });
+
+    core::DataMapOptions {
+        filters: opts.filter.clone(),
+        filter_combine: opts.filter_combine,
+        mode: opts.mode.clone(),
+        ks_format: opts.ks_format,
+        ks_compression: opts.ks_compression,
+        output_format: opts.output_format,
+        columns,
+        output_columns,
+        parquet: opts.parquet.clone(),
+        arrow_compression: opts.arrow_compression,
+        output_compression: opts.output_compression,
+        diff_changed_after: opts.diff_changed_after,
+        diff_lmtime_tolerance: opts.diff_lmtime_tolerance.unwrap_or(0),
+        diff_on: opts.diff_on.clone(),
+        diff_direction: opts.diff_direction,
+        diff_split: opts.diff_split,
+        diff_status: opts.diff_status.clone(),
+        null_terminated: opts.null_terminated,
+        versions: opts.versions,
+        shallow: opts.shallow.is_some(),
+        count_only: opts.count_only,
+        log_to_file: opts.log,
+        size_histogram: opts.size_histogram,
+        size_histogram_file: opts.size_histogram_file.clone(),
+        prefix_report: opts.prefix_report,
+        prefix_report_file: opts.prefix_report_file.clone(),
+        report_file: opts.report_file.clone(),
+        time_format: opts.time_format,
+        key_compare: opts.key_compare,
+        skip_empty: opts.skip_empty,
+        skip_dir_markers: opts.skip_dir_markers,
+        partition_depth: opts.partition_depth,
+        sorted: opts.sorted,
+        max_output_file_rows: opts.max_output_file_rows,
+        max_output_file_bytes: opts.max_output_file_bytes,
+        emit_ks_hints: opts.emit_ks_hints.clone(),
+        ks_hints_splits: opts.concurrency * core::DEFAULT_KS_HINTS_SPLITS_PER_TASK,
+        find_duplicates: opts.find_duplicates,
+        diff_script,
+        diff_batch_manifest,
+        with_tags: opts.with_tags,
+        with_owner: opts.with_owner,
+        with_checksum: opts.with_checksum.is_some(),
+        enrich_all: opts.enrich_all,
+        run_metadata,
+        emit_manifest: opts.emit_manifest.then(|| provenance::manifest_filename(&files.output, &files.output_extension)),
+        since_ks,
+    }
+}
+
+// global state of one listing, each target of batch has its own
+fn global_state(opts: &RunOptions, quit: Arc<AtomicBool>) -> core::GlobalState {
+    let mem_governor = core::MemoryGovernor::new(opts.max_memory.unwrap_or(0), opts.max_memory_abort);
+    let g_state = core::GlobalState::new(quit, opts.tasks_count(), 0, opts.run_id.clone())
+        .with_memory_governor(mem_governor)
+        .with_max_objects(opts.max_objects)
+        .with_page_size(opts.page_size).with_strict_pagination(opts.strict_pagination)
+        .with_on_error(opts.on_error).with_on_throttle(opts.on_throttle);
+    if opts.adaptive_concurrency {
+        return g_state.with_adaptive_concurrency(core::AdaptiveConcurrency::new(opts.concurrency));
+    }
+    g_state
+}
+
+fn runtime(threads: usize) -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(threads)
+        .build()
+        .unwrap()
+}
+
+// everything a run needs once options are checked and its input is loaded
+struct RunSetup {
+    files: OutputFiles,
+    ks: KsInput,
+    checkpointer: Option<checkpoint::Checkpointer>,
+    tls: Option<core::TlsVerify>,
+    data_map_opts: core::DataMapOptions,
+    quit: Arc<AtomicBool>,
+}
+
+// left side of a run, listed from bucket, or read from inventory or baseline
+enum Source {
+    Bucket,
+    Watch(u64),
+    Inventory { manifest: String, data_dir: Option<String> },
+    Baseline(String),
+}
+
+// result of a run as told by global state once all tasks quit
+#[derive(Debug, Default)]
+struct RunOutcome {
+    diff_count: Option<usize>,
+    list_failed: bool,
+    error_rate: f64,
+    suspect_pages: usize,
+    throttle_aborted: bool,
+}
+
+fn run_list(opts: &RunOptions, setup: RunSetup) -> i32 {
+    let source = match opts.watch {
+        Some(interval) => Source::Watch(interval),
+        None => Source::Bucket,
+    };
+    exit_code(opts, &run_tasks(opts, setup, source))
+}
+
+fn run_from_inventory(opts: &RunOptions, setup: RunSetup) -> i32 {
+    let source = Source::Inventory {
+        manifest: opts.manifest.clone().expect("manifest of from-inventory"),
+        data_dir: opts.inventory_data_dir.clone(),
+    };
+    exit_code(opts, &run_tasks(opts, setup, source))
+}
+
+fn run_diff(opts: &RunOptions, setup: RunSetup) -> i32 {
+    // source side of diff can be read from inventory, target side is always listed
+    let source = match &opts.manifest {
+        Some(manifest) => Source::Inventory { manifest: manifest.clone(), data_dir: opts.inventory_data_dir.clone() },
+        None => Source::Bucket,
+    };
+    exit_code(opts, &run_tasks(opts, setup, source))
+}
+
+fn run_diff_against(opts: &RunOptions, setup: RunSetup) -> i32 {
+    let source = Source::Baseline(opts.baseline.clone().expect("baseline of diff-against"));
+    exit_code(opts, &run_tasks(opts, setup, source))
+}
+
+// list tasks of one or both sides, data map and mon task, until all of them quit
+fn run_tasks(opts: &RunOptions, setup: RunSetup, source: Source) -> RunOutcome {
+    let RunSetup { files, ks, checkpointer, tls, data_map_opts, quit } = setup;
+    let (concurrency, threads, one_per_prefix) = (opts.concurrency, opts.threads, opts.one_per_prefix);
+    let exclude_prefix = listed_exclude_prefixes(opts);
+    let rt = runtime(opts.threads);
+    let outcome = rt.block_on(async {
+        let mut g_state = global_state(opts, quit)
+            .with_progress_bar(opts.progress && !opts.quiet && std::io::stdout().is_terminal());
+        if let Some(checkpointer) = checkpointer {
+            g_state = g_state.with_checkpointer(checkpointer);
+        }
+        // source limiter is shared with target side unless target has its own
+        let label = if opts.mode == RunMode::BiDir && opts.target_max_rps.is_none() { "all buckets" } else { "source bucket" };
+        let rate_limiter = opts.max_rps.map(|rps| Arc::new(core::RateLimiter::new(label, rps)));
+        let target_rate_limiter = match opts.target_max_rps {
+            Some(rps) => Some(Arc::new(core::RateLimiter::new("target bucket", rps))),
+            None => rate_limiter.clone(),
+        };
+        if let Some(limiter) = &rate_limiter {
+            g_state = g_state.with_rate_limiter(limiter.clone());
+        }
+        if let (Some(limiter), Some(_)) = (&target_rate_limiter, opts.target_max_rps) {
+            g_state = g_state.with_rate_limiter(limiter.clone());
+        }
+        let mut set = tokio::task::JoinSet::new();
+
+        let (data_map_channel, data_map_channel_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // both sides of diff sample the first object of the same common prefixes
+        let shared_splits = (opts.one_per_prefix && opts.mode == RunMode::BiDir).then(|| Arc::new(core::SharedSplits::new(2)));
+
+        // init left task
+        let start_prefixes = ks_hints_of_prefixes(&opts.prefixes, &ks.list, &ks.ranges, ks.hints);
+        let dir = if opts.mode == RunMode::BiDir {
+            core::S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE
+        } else {
+            core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE
+        };
+        let client_opts = source_client_options(opts, &tls);
+        let task_ctx = core::S3TaskContext::new(&opts.bucket, &client_opts,
+            data_map_channel.clone(), dir, g_state.clone()
+        ).probe(&client_opts).await.with_versions(opts.versions).with_request_payer(opts.request_payer).with_rate_limiter(rate_limiter)
+        .with_exclude_prefixes(&exclude_prefix).with_owner(opts.with_owner).with_sse_customer_key(opts.sse_customer_key.clone())
+        .with_hash_seed(opts.hash_seed.as_deref()).with_shared_splits(shared_splits.clone());
+        task_ctx.check_clock_skew(!opts.filter.is_empty());
+        let source_identity = task_ctx.identity(opts.endpoint.as_deref(), !opts.no_identity_check);
+        info!("  - source {}", source_identity);
+        let coverage_file = opts.coverage_file.clone();
+        let shallow = opts.shallow.clone();
+        let prefixes = opts.prefixes.clone();
+        match source {
+            Source::Baseline(baseline) => {
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        baseline::baseline_main_task(&task_ctx, &baseline, prefixes, threads).await
+                    })
+                });
+            },
+            Source::Inventory { manifest, data_dir } => {
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        inventory::inventory_main_task(&task_ctx, &manifest, data_dir, prefixes, concurrency, threads).await
+                    })
+                });
+            },
+            Source::Watch(interval) => {
+                let (output_format, filename) = (opts.output_format, files.output.clone());
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        watch::watch_main_task(&task_ctx, start_prefixes, concurrency, interval, output_format, &filename).await
+                    })
+                });
+            },
+            Source::Bucket => {
+                let task_ctx = task_ctx.with_verify_sample(opts.verify_sample).with_tags(opts.with_tags.then_some(concurrency))
+                    .with_checksum(opts.with_checksum, concurrency).with_enrich(opts.enrich_all.then_some(concurrency));
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        tasks_s3::flat_list_main_task(&task_ctx, start_prefixes, concurrency, one_per_prefix, shallow, coverage_file).await
+                    })
+                });
+            },
+        }
+
+        // init right task if bidir mode
+        if opts.mode == RunMode::BiDir {
+            let client_opts = target_client_options(opts, &tls);
+            let task_ctx = core::S3TaskContext::new(opts.target_bucket.as_ref().unwrap(), &client_opts,
+                data_map_channel, core::S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, g_state.clone()
+            ).probe(&client_opts).await.with_versions(opts.versions).with_request_payer(opts.target_request_payer).with_rate_limiter(target_rate_limiter)
+            .with_exclude_prefixes(&exclude_prefix).with_verify_sample(opts.verify_sample).with_owner(opts.with_owner)
+            .with_checksum(opts.with_checksum, concurrency).with_sse_customer_key(opts.sse_customer_key.clone())
+            .with_hash_seed(opts.hash_seed.as_deref()).with_shared_splits(shared_splits.clone());
+            task_ctx.check_clock_skew(!opts.filter.is_empty());
+            let target_identity = task_ctx.identity(opts.target_endpoint.as_deref(), !opts.no_identity_check);
+            info!("  - target {}", target_identity);
+            // with unknown accounts on both sides, same bucket at same region and endpoint is still the same one
+            if target_identity == source_identity {
+                warn!("!!! source and target are the same bucket {}, all objects compare equal !!!", source_identity.bucket);
+            }
+            let start_prefixes = ks_hints_of_prefixes(&opts.prefixes, &ks.list, &ks.ranges, data_map::KeySpaceHints::new_from(&ks.list));
+            // coverage of target bucket goes to a sibling file
+            let coverage_file = opts.coverage_file.as_ref().map(|f| format!("{}.target", f));
+            let shallow = opts.shallow.clone();
+            set.spawn_blocking(move || {
+                tokio::runtime::Handle::current().block_on(async move {
+                    tasks_s3::flat_list_main_task(&task_ctx, start_prefixes, concurrency, one_per_prefix, shallow, coverage_file).await
+                })
+            });
+        }
+
+        // watch task stands for data map task
+        if opts.watch.is_none() {
+            let data_map_ctx = core::DataMapContext::new(data_map_channel_rx, g_state.clone(), data_map_opts);
+            let (filename_ks, filename_output) = (files.ks.clone(), files.output.clone());
+            set.spawn_blocking(move || {
+                tokio::runtime::Handle::current().block_on(async move {
+                    data_map::data_map_task(data_map_ctx, filename_ks, filename_output).await
+                })
+            });
+        }
+
+        // init mon task
+        let mon_ctx = core::MonContext::new(g_state.clone()).with_metrics_addr(opts.metrics_addr)
+            .with_stats_json(opts.stats_json.as_deref(), opts.stats_interval).with_control_socket(opts.control_socket.as_deref())
+            .with_tuning_report(opts.tuning_report.then_some(core::TuningReportOptions { concurrency, log_to_file: opts.log }));
+        set.spawn_blocking(move || {
+            tokio::runtime::Handle::current().block_on(async move {
+                mon::mon_task(mon_ctx).await
+            })
+        });
+
+        while set.join_next().await.is_some() {
+        }
+        info!("All Tasks quit");
+        write_failed_ranges(&g_state, &files.failed_ranges());
+        RunOutcome {
+            diff_count: g_state.read_diff_count(),
+            list_failed: g_state.is_list_failed(),
+            error_rate: g_state.errors().rate(g_state.read_list_request()),
+            suspect_pages: g_state.read_suspect_pages(),
+            throttle_aborted: g_state.is_throttle_aborted(),
+        }
+    });
+    rt.shutdown_background();
+    outcome
+}
+
+// exit code of a finished run, 2 only for differences found with --fail-on-diff
+fn exit_code(opts: &RunOptions, outcome: &RunOutcome) -> i32 {
+    if outcome.throttle_aborted {
+        error!("list throttled for {}s without a success, run aborted with --on-throttle abort", core::DEFAULT_THROTTLE_ABORT_SECS);
+        return 4;
+    }
+
+    if outcome.list_failed && opts.on_error == OnError::Abort {
+        error!("list failed, run aborted");
+        return 1;
+    }
+    if opts.strict_pagination && outcome.suspect_pages > 0 {
+        error!("{} suspect pages with --strict-pagination, ranges after them failed", outcome.suspect_pages);
+        return 1;
+    }
+
+    // run degraded by errors, even though retries got listing through
+    if let Some(max_error_rate) = opts.max_error_rate.filter(|max| outcome.error_rate > *max) {
+        warn!("!!! {:.2}% of list requests failed, over max error rate {}%, see error breakdown of mon task !!!", outcome.error_rate, max_error_rate);
+        return 3;
+    }
+
+    if let Some(threshold) = opts.fail_on_diff {
+        match outcome.diff_count {
+            _ if outcome.list_failed => {
+                error!("list failed, unable to tell differences");
+                return 1;
+            },
+            Some(count) if count as u64 >= threshold => {
+                warn!("{} differences found, fail on {} or more", count, threshold);
+                return 2;
+            },
+            Some(_) => {},
+            None => {
+                error!("run not completed, unable to tell differences");
+                return 1;
+            },
+        }
+    }
+    0
+}
+
+// each target of batch listed as list mode, up to --batch-concurrency at a time
+fn run_batch(opts: &RunOptions, setup: RunSetup) -> i32 {
+    let targets = opts.batch_targets.as_deref().unwrap_or_default();
+    let client_opts = source_client_options(opts, &setup.tls);
+    let exclude_prefix = listed_exclude_prefixes(opts);
+    let (concurrency, one_per_prefix) = (opts.concurrency, opts.one_per_prefix);
+    let rt = runtime(opts.threads);
+    let results = rt.block_on(async {
+        // with --on-error abort, targets not started yet are skipped once one failed
+        let failed = Arc::new(AtomicBool::new(false));
+        let run_target = |target: batch::BatchTarget| {
+            let (quit, failed) = (setup.quit.clone(), failed.clone());
+            let (filename_output, filename_ks) = setup.files.of_batch_target(opts, &target);
+            let (client_opts, data_map_opts, tls, exclude_prefix) = (&client_opts, &setup.data_map_opts, &setup.tls, &exclude_prefix);
+            let output_extension = setup.files.output_extension.as_str();
+            async move {
+                let mut result = batch::BatchResult {
+                    target: target.clone(),
+                    status: batch::BatchStatus::Skipped,
+                    objects: 0,
+                    bytes: 0,
+                    error_rate: 0.0,
+                    output_file: None,
+                };
+                if quit.load(Ordering::SeqCst) || (opts.on_error == OnError::Abort && failed.load(Ordering::SeqCst)) {
+                    return result;
+                }
+                info!("Batch - start target s3://{}{}", target.bucket, target.region.as_ref().map(|r| format!(" of {}", r)).unwrap_or_default());
+
+                // data map task quits its own target, ctrl-c is passed on to the one listed now
+                let target_quit = Arc::new(AtomicBool::new(false));
+                let (q, tq) = (quit.clone(), target_quit.clone());
+                tokio::spawn(async move {
+                    while !tq.load(Ordering::SeqCst) {
+                        if q.load(Ordering::SeqCst) {
+                            tq.store(true, Ordering::SeqCst);
+                        }
+                        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    }
+                });
+                let mut g_state = global_state(opts, target_quit);
+                let rate_limiter = opts.max_rps.map(|rps| Arc::new(core::RateLimiter::new("source bucket", rps)));
+                if let Some(limiter) = &rate_limiter {
+                    g_state = g_state.with_rate_limiter(limiter.clone());
+                }
+                let mut set = tokio::task::JoinSet::new();
+                let (data_map_channel, data_map_channel_rx) = tokio::sync::mpsc::unbounded_channel();
+
+                // endpoint of target takes path style addressing, as --endpoint-url does
+                let endpoint = target.endpoint.clone().or(opts.endpoint.clone());
+                let client_opts = core::S3ClientOptions {
+                    region: target.region.clone(),
+                    force_path_style: client_opts.force_path_style || target.endpoint.is_some(),
+                    tls: tls.clone().filter(|_| endpoint.is_some()),
+                    endpoint: endpoint.clone(),
+                    ..client_opts.clone()
+                };
+                let task_ctx = core::S3TaskContext::new(&target.bucket, &client_opts,
+                    data_map_channel, core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE, g_state.clone()
+                ).probe(&client_opts).await.with_versions(opts.versions).with_request_payer(opts.request_payer).with_rate_limiter(rate_limiter)
+                .with_exclude_prefixes(exclude_prefix).with_owner(opts.with_owner).with_sse_customer_key(opts.sse_customer_key.clone())
+                .with_verify_sample(opts.verify_sample).with_tags(opts.with_tags.then_some(concurrency))
+                .with_checksum(opts.with_checksum, concurrency).with_enrich(opts.enrich_all.then_some(concurrency))
+                .with_hash_seed(opts.hash_seed.as_deref());
+                task_ctx.check_clock_skew(!opts.filter.is_empty());
+                info!("  - source {}", task_ctx.identity(endpoint.as_deref(), !opts.no_identity_check));
+                let start_prefixes = ks_hints_of_prefixes(&opts.prefixes, &[], &[], data_map::KeySpaceHints::new_from(&Vec::new()));
+                let shallow = opts.shallow.clone();
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        tasks_s3::flat_list_main_task(&task_ctx, start_prefixes, concurrency, one_per_prefix, shallow, None).await
+                    })
+                });
+
+                let mut data_map_opts = data_map_opts.clone();
+                data_map_opts.run_metadata.bucket = target.bucket.clone();
+                data_map_opts.run_metadata.region = target.region.clone();
+                data_map_opts.run_metadata.endpoint = endpoint.clone();
+                if data_map_opts.emit_manifest.is_some() {
+                    data_map_opts.emit_manifest = Some(provenance::manifest_filename(&filename_output, output_extension));
+                }
+                let data_map_ctx = core::DataMapContext::new(data_map_channel_rx, g_state.clone(), data_map_opts);
+                let output_file = filename_output.clone();
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        data_map::data_map_task(data_map_ctx, filename_ks, output_file).await
+                    })
+                });
+                let mon_ctx = core::MonContext::new(g_state.clone());
+                set.spawn_blocking(move || {
+                    tokio::runtime::Handle::current().block_on(async move {
+                        mon::mon_task(mon_ctx).await
+                    })
+                });
+
+                while set.join_next().await.is_some() {
+                }
+                write_failed_ranges(&g_state, &format!("{}.failed-ranges", filename_output));
+                result.status = if quit.load(Ordering::SeqCst) {
+                    batch::BatchStatus::Interrupted
+                } else if g_state.is_list_failed() || g_state.is_throttle_aborted() {
+                    failed.store(true, Ordering::SeqCst);
+                    batch::BatchStatus::Failed
+                } else {
+                    batch::BatchStatus::Ok
+                };
+                result.objects = g_state.read_listed_objects();
+                result.bytes = g_state.read_listed_bytes();
+                result.error_rate = g_state.errors().rate(g_state.read_list_request());
+                result.output_file = (!opts.count_only).then_some(filename_output);
+                result
+            }
+        };
+        futures::stream::iter(targets.iter().cloned()).map(run_target).buffered(opts.batch_concurrency).collect::<Vec<_>>().await
+    });
+    rt.shutdown_background();
+
+    for line in batch::summary_table(&results) {
+        println!("{}", line);
+        if opts.log {
+            info!("{}", line);
+        }
+    }
+    if let Some(r) = results.iter().find(|r| r.status != batch::BatchStatus::Ok) {
+        error!("batch not completed, target s3://{} {}", r.target.bucket,
+            if r.status == batch::BatchStatus::Failed { "failed" } else { "not listed to end" });
+        return 1;
+    }
+    if let Some(max_error_rate) = opts.max_error_rate {
+        if let Some(r) = results.iter().find(|r| r.error_rate > max_error_rate) {
+            warn!("!!! {:.2}% of list requests of s3://{} failed, over max error rate {}% !!!", r.error_rate, r.target.bucket, max_error_rate);
+            return 3;
+        }
+    }
+    0
+}
+
+// entry of the s3-fast-list binary, parse command line and run to exit
+pub fn main() {
+    let (cli, config_names) = parse_cli();
+    // coverage file is checked on its own, nothing is listed
+    if let Commands::VerifyCoverage { coverage_file } = &cli.cmd {
+        let (prefixes, _) = start_prefixes(cli.prefix.clone(), cli.prefix_is_dir);
+        std::process::exit(verify_coverage_main(coverage_file, &prefixes));
+    }
+    utils::set_humanize(cli.humanize);
+    let mut opts = parse_options(cli, config_names).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    init_logger(&opts);
+    exit_on_error(validate_options(&opts));
+    warn_options(&opts);
+
+    let mut files = output_files(&opts);
+    let mut ks = exit_on_error(load_ks_input(&opts));
+    let checkpointer = exit_on_error(load_checkpointer(&mut opts, &mut files, &mut ks));
+    let since_ks = exit_on_error(opts.since_ks.as_deref().map(load_since_ks).transpose()).map(Arc::new);
+    let (columns, output_columns) = exit_on_error(output_columns(&opts));
+    let tls = exit_on_error(tls_verify(&opts));
+    exit_on_error(check_profiles(&opts));
+
+    log_options(&opts, &files, &ks, checkpointer.as_ref(), since_ks.as_deref());
+    if let Err(e) = check_nofile_limit(&opts) {
+        if opts.strict_limits {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+        warn!("!!! {} !!!", e);
+    }
+
+    if let Some(targets) = opts.batch_targets.as_ref().filter(|_| opts.dry_run) {
+        print_batch_plan(&opts, &files, targets);
+        std::process::exit(0);
+    }
+    if let Some((n, filename)) = &opts.emit_chunks {
+        std::process::exit(emit_chunks(&opts, ks, *n, filename));
+    }
+    if opts.dry_run {
+        print_plan(&opts, &files, ks);
+        std::process::exit(0);
+    }
+    if opts.enrich_all && !opts.yes {
+        exit_on_error(confirm_enrich_all(opts.max_objects));
+    }
+    if let Some(dir) = opts.output_dir.as_ref().filter(|_| !opts.count_only) {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            error!("{}", error::OptionError::new("output-dir", format!("unable to create {}: {}", dir, e)));
+            std::process::exit(1);
+        }
+    }
+
+    let data_map_opts = data_map_options(&opts, &files, columns, output_columns, since_ks);
+    let setup = RunSetup {
+        files,
+        ks,
+        checkpointer,
+        tls,
+        data_map_opts,
+        quit: set_quit_handler(opts.shutdown_grace),
+    };
+    let code = match opts.command {
+        RunCommand::List => run_list(&opts, setup),
+        RunCommand::FromInventory => run_from_inventory(&opts, setup),
+        RunCommand::Diff => run_diff(&opts, setup),
+        RunCommand::DiffAgainst => run_diff_against(&opts, setup),
+        RunCommand::Batch => run_batch(&opts, setup),
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_exit_code() {
+        // bad arguments never exit with 2 of differences found
+        for args in [&["s3-fast-list", "list", "--no-such-option"][..], &["s3-fast-list", "diff", "--bucket", "b"],
+                &["s3-fast-list", "list", "--concurrency", "x"], &["s3-fast-list"]] {
+            let e = Cli::try_parse_from(args).err().unwrap();
+            assert_eq!(usage_exit_code(&e), 1, "{:?}", args);
+        }
+        for args in [["s3-fast-list", "--help"], ["s3-fast-list", "--version"]] {
+            let e = Cli::try_parse_from(args).err().unwrap();
+            assert_eq!(usage_exit_code(&e), 0, "{:?}", args);
+        }
+        assert!(Cli::try_parse_from(["s3-fast-list", "diff", "--bucket", "a", "--target-bucket", "b", "--fail-on-diff"]).is_ok());
+    }
+
+    fn run_options(args: &[&str]) -> RunOptions {
+        let cli = Cli::try_parse_from(["s3-fast-list"].iter().chain(args)).unwrap();
+        parse_options(cli, vec![]).unwrap()
+    }
+
+    #[test]
+    fn test_validate_options() {
+        assert!(validate_options(&run_options(&["list", "--bucket", "b"])).is_ok());
+        let e = validate_options(&run_options(&["--partition-by-prefix", "1", "--output-format", "csv", "list", "--bucket", "b"])).unwrap_err();
+        assert!(e.to_string().starts_with("invalid option --partition-by-prefix:"), "{}", e);
+        let e = validate_options(&run_options(&["--null-terminated", "list", "--bucket", "b"])).unwrap_err();
+        assert!(e.to_string().starts_with("invalid option --null-terminated:"), "{}", e);
+        let e = validate_options(&run_options(&["--prefix", "dir1/", "--exclude-prefix", "dir1", "list", "--bucket", "b"])).unwrap_err();
+        assert!(e.to_string().starts_with("invalid option --exclude-prefix:"), "{}", e);
+        // excluded prefix outside start prefixes is only ignored
+        assert!(validate_options(&run_options(&["--prefix", "dir1/", "--exclude-prefix", "dir2/", "list", "--bucket", "b"])).is_ok());
+    }
+
+    #[test]
+    fn test_output_files() {
+        let opts = run_options(&["--no-timestamp", "list", "--bucket", "b", "--region", "us-east-1"]);
+        let files = output_files(&opts);
+        assert_eq!(files.output, "us-east-1__b.parquet");
+        assert_eq!(files.failed_ranges(), "us-east-1__b.parquet.failed-ranges");
+        assert_eq!(opts.tasks_count(), 3);
+
+        // output of partitions is a directory without extension
+        let opts = run_options(&["--no-timestamp", "--partition-by-prefix", "1", "list", "--bucket", "b"]);
+        assert_eq!(output_files(&opts).output, "_b");
+
+        let opts = run_options(&["--no-timestamp", "diff", "--bucket", "a", "--target-bucket", "b"]);
+        assert_eq!(output_files(&opts).output, "_a__b.parquet");
+        assert_eq!(opts.tasks_count(), 4);
+        assert_eq!(run_options(&["list", "--bucket", "b", "--watch", "10"]).tasks_count(), 2);
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let opts = run_options(&["diff", "--bucket", "a", "--target-bucket", "b", "--fail-on-diff"]);
+        assert_eq!(exit_code(&opts, &RunOutcome { diff_count: Some(0), ..Default::default() }), 0);
+        assert_eq!(exit_code(&opts, &RunOutcome { diff_count: Some(1), ..Default::default() }), 2);
+        // differences can not be told of a run not listed to end
+        assert_eq!(exit_code(&opts, &RunOutcome { diff_count: None, ..Default::default() }), 1);
+        assert_eq!(exit_code(&opts, &RunOutcome { diff_count: Some(1), list_failed: true, ..Default::default() }), 1);
+        assert_eq!(exit_code(&opts, &RunOutcome { throttle_aborted: true, ..Default::default() }), 4);
+
+        let opts = run_options(&["--max-error-rate", "1", "list", "--bucket", "b"]);
+        assert_eq!(exit_code(&opts, &RunOutcome { error_rate: 0.5, ..Default::default() }), 0);
+        assert_eq!(exit_code(&opts, &RunOutcome { error_rate: 2.0, ..Default::default() }), 3);
+        let opts = run_options(&["--on-error", "abort", "list", "--bucket", "b"]);
+        assert_eq!(exit_code(&opts, &RunOutcome { list_failed: true, ..Default::default() }), 1);
+    }
+
+    #[test]
+    fn test_load_ks_files() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("ks-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        // overlapping hints of two teams, one of them gzipped
+        std::fs::write(path("a.input"), "dir2/x\ndir0/a\ndir1/b\n").unwrap();
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(b"dir1/b\ndir0/a\ndir3\n").unwrap();
+        std::fs::write(path("b.input.gz"), gz.finish().unwrap()).unwrap();
+
+        let (hints, ranges, merged) = load_ks_files(&[path("a.input"), path("b.input.gz")], true, 4).unwrap();
+        assert_eq!(hints, ["dir0/a", "dir1/b", "dir2/x", "dir3"]);
+        assert!(ranges.is_empty());
+        assert_eq!(merged, 6);
+
+        // only a missing default file is no hints
+        assert_eq!(load_ks_files(&[path("none.input")], false, 4).unwrap(), (vec![], vec![], 0));
+        assert!(load_ks_files(&[path("a.input"), path("none.input")], true, 4).is_err());
+
+        // one kind in all of them
+        std::fs::write(path("c.input"), "dir1/\tdir1/a\tdir1/b\n").unwrap();
+        assert!(load_ks_files(&[path("a.input"), path("c.input")], true, 4).is_err());
+        let (_, ranges, _) = load_ks_files(&[path("c.input"), path("c.input")], true, 4).unwrap();
+        assert_eq!(ranges.len(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_enrich_confirmation() {
+        assert_eq!(enrich_cost_estimate(Some(5_000_000)),
+            "up to 5000000 HeadObject requests of --max-objects, about $2.00 at $0.0004 per 1,000 requests");
+        assert!(enrich_cost_estimate(None).contains("about $0.40 per million objects"));
+
+        assert!(confirmed(std::io::Cursor::new("y\n")));
+        assert!(confirmed(std::io::Cursor::new(" YES \n")));
+        assert!(!confirmed(std::io::Cursor::new("\n")));
+        assert!(!confirmed(std::io::Cursor::new("no\ny\n")));
+        // closed stdin is no answer
+        assert!(!confirmed(std::io::Cursor::new("")));
+    }
+
+    #[test]
+    fn test_check_open_files() {
+        // 32 reserved and 4 writers, one connection of each list task
+        assert!(check_open_files(1024, 1, false, 4, 988, None).is_ok());
+        let e = check_open_files(1024, 1, false, 4, 989, None).unwrap_err();
+        assert_eq!(e, error::NofileLimitError::new(1025, 1024, 988));
+
+        // two buckets in diff mode, fetch of tags or checksum doubles connections of each task
+        let e = check_open_files(1024, 2, true, 4, 300, None).unwrap_err();
+        assert_eq!(e, error::NofileLimitError::new(1236, 1024, 247));
+        assert!(check_open_files(1024, 2, true, 4, 300, Some(400)).is_ok());
+
+        // writers alone over limit still suggest one task
+        let e = check_open_files(64, 1, false, 100, 10, None).unwrap_err();
+        assert_eq!(e, error::NofileLimitError::new(142, 64, 1));
+        assert!(check_open_files(u64::MAX, 1, false, 4, 10000, None).is_ok());
+    }
+}
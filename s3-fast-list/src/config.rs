@@ -0,0 +1,112 @@
+use clap::{ArgMatches, Command};
+use clap::parser::ValueSource;
+use toml::{Table, Value};
+use crate::error::ConfigError;
+
+// options selecting the config itself, not taken from config file
+const CONFIG_OPTIONS: [&str; 2] = ["config", "config-profile"];
+
+// options of config file, top-level keys overlaid by those of "[profiles.<name>]"
+fn config_table(content: &str, profile: Option<&str>) -> Result<Table, String> {
+    let mut table: Table = content.parse().map_err(|e: toml::de::Error| e.message().to_string())?;
+    let profiles = match table.remove("profiles") {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => return Err("profiles should be a table of [profiles.<name>]".to_string()),
+        None => Table::new(),
+    };
+    if let Some(name) = profile {
+        match profiles.get(name) {
+            Some(Value::Table(options)) => table.extend(options.clone()),
+            Some(_) => return Err(format!("profiles.{} should be a table", name)),
+            None => return Err(format!("profile {} not found", name)),
+        }
+    }
+    Ok(table)
+}
+
+// "--name=value" args of one config entry, a flag is "--name" if true and none if false
+fn entry_args(name: &str, value: &Value, flag: bool) -> Result<Vec<String>, String> {
+    match value {
+        Value::Boolean(on) if flag => Ok(if *on { vec![format!("--{}", name)] } else { vec![] }),
+        _ if flag => Err(format!("{} is a flag, should be true or false", name)),
+        Value::String(s) => Ok(vec![format!("--{}={}", name, s)]),
+        Value::Integer(n) => Ok(vec![format!("--{}={}", name, n)]),
+        Value::Float(f) => Ok(vec![format!("--{}={}", name, f)]),
+        Value::Array(values) => values.iter().try_fold(Vec::new(), |mut args, v| match v {
+            Value::Array(_) | Value::Table(_) => Err(format!("{} should be an array of values", name)),
+            v => {
+                args.extend(entry_args(name, v, false)?);
+                Ok(args)
+            },
+        }),
+        _ => Err(format!("{} of {} not supported", value.type_str(), name)),
+    }
+}
+
+// args of config file options, global ones only, converted in order of key
+fn table_args(command: &Command, table: &Table, skip: impl Fn(&str) -> bool) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut args = Vec::new();
+    for (key, value) in table {
+        // keys are long option names, in snake case as well
+        let name = key.replace('_', "-");
+        let arg = command.get_arguments()
+            .find(|a| a.is_global_set() && a.get_long() == Some(name.as_str()) && !CONFIG_OPTIONS.contains(&name.as_str()))
+            .ok_or(format!("{} is not a global option", key))?;
+        if skip(arg.get_id().as_str()) {
+            continue;
+        }
+        let flag = !arg.get_action().takes_values();
+        let entry = entry_args(&name, value, flag)?;
+        args.push((name, entry));
+    }
+    Ok(args)
+}
+
+// args of config file for global options not given on command line, to be put before args of command line,
+// with names of options taken from config
+pub fn config_args(command: &Command, matches: &ArgMatches, filename: &str, profile: Option<&str>) -> Result<(Vec<String>, Vec<String>), ConfigError> {
+    let content = std::fs::read_to_string(filename).map_err(|e| ConfigError::new(filename, e.to_string()))?;
+    let table = config_table(&content, profile).map_err(|e| ConfigError::new(filename, e))?;
+    let on_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let entries = table_args(command, &table, on_command_line).map_err(|e| ConfigError::new(filename, e))?;
+    let names = entries.iter().map(|(name, _)| name.clone()).collect();
+    let args = entries.into_iter().flat_map(|(_, args)| args).collect();
+    Ok((args, names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn test_config_args() {
+        let command = Command::new("t")
+            .arg(Arg::new("concurrency").long("concurrency").global(true))
+            .arg(Arg::new("filter").long("filter").global(true).action(clap::ArgAction::Append))
+            .arg(Arg::new("humanize").long("humanize").global(true).action(clap::ArgAction::SetTrue))
+            .arg(Arg::new("bucket").long("bucket"));
+
+        let content = r#"
+            concurrency = 8
+            filter = ["size > 0", "key.ends_with('.gz')"]
+            humanize = true
+            [profiles.prod]
+            concurrency = 64
+            humanize = false
+        "#;
+        let args = |profile: Option<&str>, skip: fn(&str) -> bool| -> Vec<String> {
+            table_args(&command, &config_table(content, profile).unwrap(), skip).unwrap()
+                .into_iter().flat_map(|(_, a)| a).collect()
+        };
+        assert_eq!(args(None, |_| false),
+            vec!["--concurrency=8", "--filter=size > 0", "--filter=key.ends_with('.gz')", "--humanize"]);
+        assert_eq!(args(Some("prod"), |id| id == "filter"), vec!["--concurrency=64"]);
+        assert!(config_table(content, Some("dev")).is_err());
+
+        let table = config_table("bucket = \"b\"", None).unwrap();
+        assert!(table_args(&command, &table, |_| false).is_err());
+        let table = config_table("humanize = \"yes\"", None).unwrap();
+        assert!(table_args(&command, &table, |_| false).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use log::{info, warn, error};
+use crate::core::GlobalState;
+use crate::stats::StatsSampler;
+
+// connection without a command for this long is closed
+const CONTROL_IDLE_TIMEOUT_SECS: u64 = 60;
+// longest command line read, a longer one closes the connection
+const CONTROL_MAX_LINE_BYTES: usize = 1024;
+
+// path is taken over only if it is a socket nobody listens on, e.g. left behind by a killed run
+fn remove_stale_socket(path: &str) -> Result<(), String> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.to_string()),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err("exists and is not a socket".to_string());
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err("in use by another run".to_string());
+    }
+    std::fs::remove_file(path).map_err(|e| e.to_string())
+}
+
+// socket is bound in a new dir of mode 0700 next to path, only then made 0600 and moved to path,
+// so nobody else can connect before permissions are restricted, whatever the umask
+fn bind_private(path: &str) -> std::io::Result<UnixListener> {
+    let target = Path::new(path);
+    let name = target.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+    let dir = target.with_file_name(format!(".{}.{}.tmp", name.to_string_lossy(), std::process::id()));
+    std::fs::DirBuilder::new().mode(0o700).create(&dir)?;
+    let bound = dir.join(name);
+    let result = UnixListener::bind(&bound).and_then(|listener| {
+        std::fs::set_permissions(&bound, std::fs::Permissions::from_mode(0o600))?;
+        std::fs::rename(&bound, target)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&bound);
+    let _ = std::fs::remove_dir(&dir);
+    result
+}
+
+// anyone could connect to a socket writable by others, commands are refused then
+fn is_world_writable(path: &str) -> bool {
+    std::fs::metadata(path).map_or(true, |m| m.permissions().mode() & 0o002 != 0)
+}
+
+// one reply line to a command line
+fn handle_command(command: &str, g_state: &GlobalState, sampler: &StatsSampler) -> String {
+    match command {
+        "status" => sampler.snapshot_json(g_state, false).unwrap_or_else(|e| format!("error: {}", e)),
+        "pause" => {
+            if g_state.pause() {
+                info!("Control Socket - list tasks paused before their next page");
+                "ok paused".to_string()
+            } else {
+                "ok already paused".to_string()
+            }
+        },
+        "resume" => {
+            if g_state.resume() {
+                info!("Control Socket - list tasks resumed");
+                "ok resumed".to_string()
+            } else {
+                "ok not paused".to_string()
+            }
+        },
+        "quit" => {
+            info!("Control Socket - quit requested");
+            g_state.quit();
+            "ok quitting".to_string()
+        },
+        _ => format!("error: unknown command \"{}\", expect status, pause, resume or quit", command),
+    }
+}
+
+// take commands on unix socket at path, one per line with one reply line each,
+// runs until aborted by mon task on quit
+pub async fn control_server(path: String, g_state: GlobalState) {
+
+    if let Err(e) = remove_stale_socket(&path) {
+        error!("Control Socket - unable to listen on {}: {}", path, e);
+        return;
+    }
+    let listener = match bind_private(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Control Socket - unable to listen on {}: {}", path, e);
+            return;
+        }
+    };
+    info!("Control Socket - listening on {}", path);
+
+    let path = Arc::new(path);
+    let sampler = Arc::new(StatsSampler::new());
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Control Socket - accept error: {}", e);
+                continue;
+            }
+        };
+        let (path, g_state, sampler) = (path.clone(), g_state.clone(), sampler.clone());
+        tokio::spawn(async move {
+            let _ = handle_connection(stream, &path, &g_state, &sampler).await;
+        });
+    }
+}
+
+async fn handle_connection(stream: UnixStream, path: &str, g_state: &GlobalState, sampler: &StatsSampler) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let timeout = tokio::time::Duration::from_secs(CONTROL_IDLE_TIMEOUT_SECS);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        // max bytes of command and newline after it, more without newline is a line too long
+        let mut line = (&mut reader).take(CONTROL_MAX_LINE_BYTES as u64 + 1);
+        match tokio::time::timeout(timeout, line.read_until(b'\n', &mut buf)).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(_)) => {},
+            Ok(Err(e)) => return Err(e),
+        }
+        if buf.len() > CONTROL_MAX_LINE_BYTES && buf.last() != Some(&b'\n') {
+            warn!("Control Socket - command line over {} bytes, connection closed", CONTROL_MAX_LINE_BYTES);
+            writer.write_all(format!("error: command line over {} bytes\n", CONTROL_MAX_LINE_BYTES).as_bytes()).await?;
+            break;
+        }
+        let line = String::from_utf8_lossy(&buf);
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+        let reply = if is_world_writable(path) {
+            warn!("Control Socket - {} is world-writable, command \"{}\" refused", path, command);
+            format!("error: socket {} is world-writable, command refused", path)
+        } else {
+            handle_command(command, g_state, sampler)
+        };
+        writer.write_all(format!("{}\n", reply).as_bytes()).await?;
+    }
+    writer.shutdown().await
+}
+
+// socket file is removed on quit, so next run can listen on same path
+pub fn remove_socket(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("Control Socket - unable to remove {}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_handle_command() {
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "run".to_string());
+        let sampler = StatsSampler::new();
+
+        assert_eq!(handle_command("pause", &g_state, &sampler), "ok paused");
+        assert_eq!(handle_command("pause", &g_state, &sampler), "ok already paused");
+        let status: serde_json::Value = serde_json::from_str(&handle_command("status", &g_state, &sampler)).unwrap();
+        assert_eq!(status["run_id"], "run");
+        assert_eq!(status["paused"], true);
+        assert_eq!(handle_command("resume", &g_state, &sampler), "ok resumed");
+        assert!(!g_state.is_paused());
+        assert!(handle_command("stop", &g_state, &sampler).starts_with("error: unknown command"));
+        assert_eq!(handle_command("quit", &g_state, &sampler), "ok quitting");
+        assert!(g_state.is_quit());
+    }
+
+    #[tokio::test]
+    async fn test_control_server() {
+        let dir = std::env::temp_dir().join(format!("control-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("run.sock").to_str().unwrap().to_string();
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "run".to_string());
+        let server = tokio::spawn(control_server(path.clone(), g_state.clone()));
+        let mut stream = loop {
+            match UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(tokio::time::Duration::from_millis(10)).await,
+            }
+        };
+        // owner only from the start, no dir of binding left behind
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut reply = String::new();
+        stream.write_all(format!("{}\npause\n", "x".repeat(CONTROL_MAX_LINE_BYTES)).as_bytes()).await.unwrap();
+        let mut reader = BufReader::new(&mut stream);
+        reader.read_line(&mut reply).await.unwrap();
+        assert!(reply.starts_with("error: unknown command"), "{}", reply);
+        reply.clear();
+        reader.read_line(&mut reply).await.unwrap();
+        assert_eq!(reply, "ok paused\n");
+
+        // a line too long closes connection before any other command is taken
+        stream.write_all(format!("{}resume\n", "x".repeat(CONTROL_MAX_LINE_BYTES)).as_bytes()).await.unwrap();
+        let mut rest = String::new();
+        stream.read_to_string(&mut rest).await.unwrap();
+        assert_eq!(rest, format!("error: command line over {} bytes\n", CONTROL_MAX_LINE_BYTES));
+        assert!(g_state.is_paused());
+
+        server.abort();
+        remove_socket(&path);
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
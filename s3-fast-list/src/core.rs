@@ -1,25 +1,59 @@
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, AtomicBool, Ordering};
+use std::sync::atomic::{AtomicUsize, AtomicU64, AtomicBool, Ordering};
 use std::sync::OnceLock;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use tokio::sync::Barrier;
 use tokio::sync::mpsc::{UnboundedSender, UnboundedReceiver};
-use rhai::{Engine, AST, Scope};
-use rhai::serde::to_dynamic;
 use serde::{Deserialize, Serialize};
-use log::{warn, error};
-use crate::stats::HttpStatusCodeTracker;
+use log::{debug, info, warn, error};
+use aws_smithy_runtime_api::client::http::{HttpClient, HttpConnector, HttpConnectorFuture, HttpConnectorSettings, SharedHttpClient, SharedHttpConnector};
+use aws_smithy_runtime_api::client::orchestrator::HttpRequest;
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_sdk_s3::config::SharedCredentialsProvider;
+use crate::stats::{ErrorCounters, HttpStatusCodeTracker, StatsJsonWriter};
+use crate::mon::ProgressBar;
+use crate::tasks_s3;
+use crate::filter;
+use crate::error::AssumeRoleError;
+use crate::checkpoint::Checkpointer;
+use crate::data_map::{KsRange, SinceKs};
+use crate::utils;
+use crate::script;
+use crate::provenance;
+use crate::credentials;
 
-#[allow(dead_code)]
 pub(crate) const KB: usize = 1024;
 pub(crate) const MB: usize = 1_048_576;
-#[allow(dead_code)]
 pub(crate) const GB: usize = 1_073_741_824;
 
 pub(crate) const DEFAULT_TASK_HEARTBEAT_INTERVAL_SECS: u64 = 5;
 pub(crate) const DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS: u64 = 1;
+pub(crate) const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 60;
+pub(crate) const DEFAULT_STATS_INTERVAL_SECS: u64 = 10;
+pub(crate) const DEFAULT_PARTITION_MAX_OPEN_WRITERS: usize = 64;
+// partitions per concurrent task of emitted ks hints, so tasks done early pick up more
+pub(crate) const DEFAULT_KS_HINTS_SPLITS_PER_TASK: usize = 4;
+// first characters of keys after start prefix seeded by --hash-prefix-seed, as of hex hashes or ids
+pub(crate) const DEFAULT_HASH_CHARSET: &str = "0123456789abcdef";
+pub(crate) const MAX_HASH_CHARSET_LEN: usize = 256;
+// MaxKeys of list calls, S3 returns up to 1000 keys per page and 1000 is the most it takes
+pub(crate) const DEFAULT_LIST_PAGE_SIZE: usize = 1000;
+pub(crate) const MAX_LIST_PAGE_SIZE: usize = 1000;
+const DEFAULT_ADAPTIVE_CONCURRENCY_COOLDOWN_MS: u64 = 1000;
 
 pub(crate) const DEFAULT_S3_CLIENT_TIMEOUT: u64 = 5;
+pub(crate) const DEFAULT_CLOCK_SKEW_WARN_SECS: i64 = 60;
+pub(crate) const DEFAULT_MEMORY_SAMPLE_INTERVAL_MS: u64 = 250;
+pub(crate) const DEFAULT_MEMORY_THROTTLE_MAX_WAIT_SECS: u64 = 30;
+// batches of list results in channel to data map, about one page of 1000 objects each, list tasks pause issuing
+// pages over high watermark until data map drains channel down to low watermark
+pub(crate) const DEFAULT_CHANNEL_HIGH_WATERMARK: usize = 256;
+pub(crate) const DEFAULT_CHANNEL_LOW_WATERMARK: usize = 128;
+// watermarks while rss is over cap of "--max-memory", fewer results held in flight
+pub(crate) const DEFAULT_CHANNEL_CAPPED_HIGH_WATERMARK: usize = 16;
+pub(crate) const DEFAULT_CHANNEL_CAPPED_LOW_WATERMARK: usize = 8;
+// resolved objects are evicted from data map at most this often while rss is over cap
+pub(crate) const DEFAULT_MEMORY_EVICT_INTERVAL_SECS: u64 = 5;
 
 pub(crate) const S3_TASK_CONTEXT_DIR_LEFT: u8 = OBJECT_PROPS_FLAG_DIR_LEFT;
 pub(crate) const S3_TASK_CONTEXT_DIR_RIGHT: u8 = OBJECT_PROPS_FLAG_DIR_RIGHT;
@@ -30,9 +64,17 @@ pub(crate) const S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE: u8 = OBJECT_PROPS_FLAG_DIR
 const S3_CLIENT_MAX_ATTEMPTS: u32 = 10;
 const S3_CLIENT_INITIAL_BACKOFF: u64 = 30;
 const S3_CLIENT_CONNECT_TIMEOUT: u64 = 60;
+pub(crate) const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 200;
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 20_000;
+// throttle responses without a success in between for this long stop the run with "--on-throttle abort"
+pub(crate) const DEFAULT_THROTTLE_ABORT_SECS: u64 = 30;
+const DEFAULT_ROLE_SESSION_NAME: &str = "s3-fast-list";
 
 const OBJECT_PROPS_FLAG_S3_GP_BUCKET: u8 = 0b1;    // general purpose bucket
 const OBJECT_PROPS_FLAG_S3_DIR_BUCKET: u8 = 0b10;   // directory bucket
+const OBJECT_PROPS_FLAG_IS_LATEST: u8 = 0b100;      // latest version, always set if not listing versions
+const OBJECT_PROPS_FLAG_DELETE_MARKER: u8 = 0b1000; // delete marker of versioned bucket
+const OBJECT_PROPS_FLAG_COMMON_PREFIX: u8 = 0b1_0000; // common prefix of delimiter list, not an object
 
 const OBJECT_PROPS_FLAG_DIR_LEFT: u8 = 0b1000_0000;
 const OBJECT_PROPS_FLAG_DIR_RIGHT: u8 = 0b0100_0000;
@@ -45,95 +87,357 @@ const OBJECT_PROPS_STATUS_SIZE_NOT_MATCH: u8 = 1;
 const OBJECT_PROPS_STATUS_ETAG_NOT_AVAIL: u8 = 2;
 const OBJECT_PROPS_STATUS_ETAG_NOT_MATCH: u8 = 3;
 const OBJECT_PROPS_STATUS_FILTER_OUT: u8 = 4;
+const OBJECT_PROPS_STATUS_MTIME_NOT_MATCH: u8 = 5;
+const OBJECT_PROPS_STATUS_CHECKSUM_NOT_MATCH: u8 = 6;
+
+// attributes found different for object seen on both sides
+const OBJECT_PROPS_DIFF_ATTR_SIZE: u8 = 0b1;
+const OBJECT_PROPS_DIFF_ATTR_ETAG: u8 = 0b10;
+const OBJECT_PROPS_DIFF_ATTR_MTIME: u8 = 0b100;
+const OBJECT_PROPS_DIFF_ATTR_CHECKSUM: u8 = 0b1000;
+const OBJECT_PROPS_DIFF_ATTR_NAME: [(u8, &str); 4] = [
+    (OBJECT_PROPS_DIFF_ATTR_SIZE, "size"),
+    (OBJECT_PROPS_DIFF_ATTR_ETAG, "etag"),
+    (OBJECT_PROPS_DIFF_ATTR_MTIME, "mtime"),
+    (OBJECT_PROPS_DIFF_ATTR_CHECKSUM, "checksum"),
+];
+
+pub(crate) static OBJECT_FILTER: OnceLock<filter::Filter> = OnceLock::new();
+// how objects are compared in diff mode, default ones until data map sets them once
+pub(crate) static DIFF_SETTINGS: OnceLock<DiffSettings> = OnceLock::new();
+const DIFF_ON_DEFAULT: [DiffOn; 2] = [DiffOn::Size, DiffOn::Etag];
+static DIFF_SETTINGS_DEFAULT: DiffSettings = DiffSettings { on: Vec::new(), direction: DiffDirection::Both, changed_after: None, lmtime_tolerance: 0 };
+// rendering of last modified in text outputs, parquet and arrow keep a timestamp column regardless
+pub(crate) static TIME_FORMAT: OnceLock<TimeFormat> = OnceLock::new();
 
-const OBJECT_FILTER_ALLOWED_VARIABLE: [&str; 2] = ["SOURCE", "TARGET"];
-const OBJECT_FILTER_ALLOWED_PROPERTY: [&str; 2] = ["size", "last_modified"];
-pub(crate) static OBJECT_FILTER: OnceLock<ObjectFilter> = OnceLock::new();
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum RunMode {
+    List,
+    BiDir,
+}
 
-#[derive(Debug)]
-pub(crate) struct ObjectFilter {
-    engine: Engine,
-    ast: AST,
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum FilterCombine {
+    /// object is kept only if all filters matched
+    And,
+    /// object is kept if any of filters matched
+    Or,
 }
 
-impl ObjectFilter {
-    // check any invalidation of input filter experssion
-    fn check_expr(&self, mode: RunMode) -> bool {
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum OnError {
+    /// failed key space range is logged and recorded to failed ranges file, listing goes on
+    Continue,
+    /// first failed key space range stops the run with exit code 1
+    Abort,
+}
 
-        let mut check_failed = false;
-        /*
-         * since rhai only check object map existence when the expression is executed to specific condition,
-         * go through AST to check all variable's props to ensure only allowed object props is used.
-        */
-        self.ast.walk(&mut|nodes| {
-            let mut cont = true;
-            for node in nodes {
-                match node {
-                    rhai::ASTNode::Expr(expr) => {
-                        match expr {
-                            // Property(Box<((getter, hash), (setter, hash), prop)>, position)
-                            rhai::Expr::Property(props, _) => {
-                                let prop = props.2.as_str();
-                                if !OBJECT_FILTER_ALLOWED_PROPERTY.contains(&prop) {
-                                    error!("object property \"{prop}\" not allowed");
-                                    cont = false;
-                                    check_failed = true;
-                                }
-                            },
-                            rhai::Expr::Variable(names, _, _) => {
-                                let name = names.1.as_str();
-                                if !OBJECT_FILTER_ALLOWED_VARIABLE.contains(&name) {
-                                    error!("variable \"{name}\" not allowed");
-                                    cont = false;
-                                    check_failed = true;
-                                }
-                            },
-                            _ => {},
-                        }
-                    }
-                    _ => {},
-                }
-            }
-            cont
-        });
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum OnThrottle {
+    /// throttled requests are retried until they go through, each waiting for the rate limiter and a backoff delay
+    Wait,
+    /// run stops with exit code 4 once list clients are throttled for 30s without a success
+    Abort,
+    /// throttled requests are retried as other errors, and halve the limit of --adaptive-concurrency
+    Backoff,
+}
 
-        if check_failed {
-            return false;
+impl OnThrottle {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Wait => "wait",
+            Self::Abort => "abort",
+            Self::Backoff => "backoff",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum DiffOn {
+    /// key presence only, object seen on both sides is equal
+    Key,
+    /// object size
+    Size,
+    /// etag, heuristic for multipart uploads since their etag depends on part size
+    Etag,
+    /// last modified time
+    Mtime,
+    /// additional checksum fetched with "--with-checksum", missing one on either side is a difference
+    Checksum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum ChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Crc64nvme,
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Crc32 => "crc32",
+            ChecksumAlgorithm::Crc32c => "crc32c",
+            ChecksumAlgorithm::Crc64nvme => "crc64nvme",
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    // checksum of this algorithm in GetObjectAttributes response, none if object is uploaded without it
+    pub fn value<'a>(&self, checksum: &'a aws_sdk_s3::types::Checksum) -> Option<&'a str> {
+        match self {
+            ChecksumAlgorithm::Crc32 => checksum.checksum_crc32(),
+            ChecksumAlgorithm::Crc32c => checksum.checksum_crc32_c(),
+            ChecksumAlgorithm::Crc64nvme => checksum.checksum_crc64_nvme(),
+            ChecksumAlgorithm::Sha1 => checksum.checksum_sha1(),
+            ChecksumAlgorithm::Sha256 => checksum.checksum_sha256(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum DiffStatus {
+    /// object only in source bucket
+    #[value(name = "only_left")]
+    OnlyLeft,
+    /// object only in target bucket
+    #[value(name = "only_right")]
+    OnlyRight,
+    /// object in both buckets with compared attributes differing
+    Changed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub(crate) enum DiffDirection {
+    /// objects only in source, only in target and changed ones
+    #[default]
+    Both,
+    /// only objects in source but not in target, e.g. for a one-way backfill
+    LeftToRight,
+    /// only objects in target but not in source
+    RightToLeft,
+}
+
+impl DiffStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffStatus::OnlyLeft => "only_left",
+            DiffStatus::OnlyRight => "only_right",
+            DiffStatus::Changed => "changed",
+        }
+    }
+}
+
+// options of diff mode read by matching, passed in rather than read from globals so they could be tested one by one
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct DiffSettings {
+    // attributes compared for object seen on both sides, size and etag if empty
+    pub on: Vec<DiffOn>,
+    // differences of one direction only, objects on both sides are taken as equal without comparing attributes
+    pub direction: DiffDirection,
+    // unix timestamp watermark, only differences changed after it are kept
+    pub changed_after: Option<u64>,
+    // seconds last modified of both sides could differ by and still be equal, e.g. after replication
+    pub lmtime_tolerance: u64,
+}
+
+impl DiffSettings {
+    pub fn current() -> &'static Self {
+        DIFF_SETTINGS.get().unwrap_or(&DIFF_SETTINGS_DEFAULT)
+    }
+
+    // "key" alone compares nothing but key presence, as does a diff of one direction
+    fn compared(&self) -> &[DiffOn] {
+        if self.direction != DiffDirection::Both {
+            &[]
+        } else if self.on.is_empty() {
+            &DIFF_ON_DEFAULT
+        } else {
+            &self.on
         }
+    }
+}
 
-        // then we build fake ObjectProps to test AST to see
-        // any other invalidation exist in expression
-        let mut scope = Scope::new();
-        let source = to_dynamic(ObjectProps::default()).unwrap();
-        scope.push_constant_dynamic("SOURCE", source.into_read_only());
+impl DiffDirection {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffDirection::Both => "both",
+            DiffDirection::LeftToRight => "left-to-right",
+            DiffDirection::RightToLeft => "right-to-left",
+        }
+    }
 
-        if mode == RunMode::BiDir {
-            let target = to_dynamic(ObjectProps::default()).unwrap();
-            scope.push_constant_dynamic("TARGET", target.into_read_only());
+    // only status of differences found in one direction, none for both
+    pub fn status(&self) -> Option<DiffStatus> {
+        match self {
+            DiffDirection::Both => None,
+            DiffDirection::LeftToRight => Some(DiffStatus::OnlyLeft),
+            DiffDirection::RightToLeft => Some(DiffStatus::OnlyRight),
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum DuplicateOn {
+    /// etag only, multipart uploads of different content could share one
+    Etag,
+    /// size and etag, fewer false positives of multipart uploads
+    SizeEtag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum KsFormat {
+    /// csv lines of "prefix","count", for human inspection
+    Text,
+    /// length-prefixed binary, faster to reload
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum KsCompression {
+    /// plain file
+    None,
+    /// gzip, readable by zcat
+    Gzip,
+    /// zstd, faster and smaller than gzip
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum ArrowCompression {
+    /// uncompressed record batches
+    None,
+    /// lz4 frame, fast to read
+    Lz4,
+    /// zstd, smaller than lz4
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum LogFormat {
+    /// "[timestamp level target run_id] message" lines
+    Text,
+    /// one json object per line with timestamp, level, target, run_id and message
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// object metadata in parquet
+    Parquet,
+    /// arrow ipc stream of record batches, same columns as parquet
+    Arrow,
+    /// one json object per line
+    Ndjson,
+    /// csv with header row, columns selected by "--columns"
+    Csv,
+    /// object keys only, one per line with no header
+    Keys,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub(crate) enum TimeFormat {
+    /// UTC datetime in seconds, e.g. "2024-01-01T00:00:00Z"
+    #[default]
+    Rfc3339,
+    /// unix milliseconds
+    EpochMillis,
+    /// unix seconds
+    EpochSecs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub(crate) enum KeyCompare {
+    /// byte-wise, the same as S3
+    #[default]
+    Exact,
+    /// keys folded to lowercase
+    CaseInsensitive,
+    /// keys in unicode normalization form C
+    Nfc,
+}
 
-        match self.engine.eval_ast_with_scope::<bool>(&mut scope, &self.ast) {
-            Ok(_) => {
-                return true;
+impl KeyCompare {
+    // key in form it is compared in, borrowed if already so
+    pub fn normalize<'a>(&self, key: &'a str) -> std::borrow::Cow<'a, str> {
+        use unicode_normalization::UnicodeNormalization;
+        match self {
+            KeyCompare::Exact => key.into(),
+            KeyCompare::CaseInsensitive => {
+                let folded = key.to_lowercase();
+                if folded == key { key.into() } else { folded.into() }
             },
-            Err(e) => {
-                error!("validation filter expression failed: {e:?}");
-                return false;
-            }
+            KeyCompare::Nfc if unicode_normalization::is_nfc(key) => key.into(),
+            KeyCompare::Nfc => key.nfc().collect::<String>().into(),
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub(crate) enum RunMode {
-    List,
-    BiDir,
+impl TimeFormat {
+    pub fn current() -> Self {
+        TIME_FORMAT.get().copied().unwrap_or_default()
+    }
+
+    // field of csv output
+    pub fn render_text(&self, secs: u64) -> String {
+        match self {
+            TimeFormat::Rfc3339 => utils::fmt_rfc3339(secs),
+            TimeFormat::EpochMillis => (secs * 1000).to_string(),
+            TimeFormat::EpochSecs => secs.to_string(),
+        }
+    }
+
+    // value of ndjson output, string of datetime or number of epoch ones
+    pub fn render_json(&self, secs: u64) -> serde_json::Value {
+        match self {
+            TimeFormat::Rfc3339 => utils::fmt_rfc3339(secs).into(),
+            TimeFormat::EpochMillis => (secs * 1000).into(),
+            TimeFormat::EpochSecs => secs.into(),
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrows",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Keys => "txt",
+        }
+    }
+}
+
+impl KsFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            KsFormat::Text => "ks",
+            KsFormat::Binary => "ksb",
+        }
+    }
+}
+
+impl KsCompression {
+    // appended to extension of ks file
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            KsCompression::None => "",
+            KsCompression::Gzip => ".gz",
+            KsCompression::Zstd => ".zst",
+        }
+    }
 }
 
 #[repr(transparent)]
 #[derive(Debug)]
 pub struct ObjectKey(String);
 pub type ObjectName = String;
+// object version is kept in object name, so each (key, version id) is a separate entry in data map
+const OBJECT_VERSION_SEPARATOR: char = '\0';
 pub type ObjectPrefix = String;
 
 impl ObjectKey {
@@ -155,7 +459,7 @@ impl ObjectKey {
             );
         }
         Self (
-            vec![prefix.as_str(), name.as_str()].join("/")
+            [prefix.as_str(), name.as_str()].join("/")
         )
     }
 
@@ -167,13 +471,28 @@ impl ObjectKey {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    // split key and version id, name of object is suffixed by version id when listing versions
+    pub fn split_version(&self) -> (&str, Option<&str>) {
+        match self.0.split_once(OBJECT_VERSION_SEPARATOR) {
+            Some((key, version_id)) => (key, Some(version_id)),
+            None => (self.0.as_str(), None),
+        }
+    }
+
+    // zero-byte placeholder of a "folder" created by consoles, key ends with "/"
+    pub fn is_dir_marker(&self) -> bool {
+        self.split_version().0.ends_with('/')
+    }
+
+    pub fn with_version(key: &str, version_id: &str) -> Self {
+        Self(format!("{key}{OBJECT_VERSION_SEPARATOR}{version_id}"))
+    }
 }
 
 impl From<&str> for ObjectKey {
     fn from(item: &str) -> Self {
-        Self {
-            0: item.to_string()
-        }
+        Self(item.to_string())
     }
 }
 
@@ -187,6 +506,58 @@ pub enum MatchResult {
     Ignore = 5,
 }
 
+// tags of object in order of GetObjectTagging response
+pub type ObjectTags = Vec<(String, String)>;
+
+// owner of object in list response, display name is not returned in every region
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectOwner {
+    pub id: Option<String>,
+    pub display_name: Option<String>,
+}
+
+// metadata of object in HeadObject response with "--enrich-all", none of a header not set
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectHead {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub content_language: Option<String>,
+    pub content_disposition: Option<String>,
+    pub cache_control: Option<String>,
+    pub server_side_encryption: Option<String>,
+    pub sse_kms_key_id: Option<String>,
+    // user metadata of "x-amz-meta-" headers, ordered by name
+    pub metadata: std::collections::BTreeMap<String, String>,
+    // only of a multipart upload, returned as part number 1 is asked for
+    pub parts_count: Option<u32>,
+    pub tag_count: Option<u32>,
+    pub replication_status: Option<String>,
+    pub object_lock_mode: Option<String>,
+    // "x-amz-restore" of archived object being or been restored
+    pub restore: Option<String>,
+}
+
+impl From<&aws_sdk_s3::operation::head_object::HeadObjectOutput> for ObjectHead {
+    fn from(item: &aws_sdk_s3::operation::head_object::HeadObjectOutput) -> Self {
+        let string = |s: Option<&str>| s.filter(|s| !s.is_empty()).map(|s| s.to_string());
+        Self {
+            content_type: string(item.content_type()),
+            content_encoding: string(item.content_encoding()),
+            content_language: string(item.content_language()),
+            content_disposition: string(item.content_disposition()),
+            cache_control: string(item.cache_control()),
+            server_side_encryption: string(item.server_side_encryption().map(|s| s.as_str())),
+            sse_kms_key_id: string(item.ssekms_key_id()),
+            metadata: item.metadata().map(|m| m.iter().map(|(k, v)| (k.clone(), v.clone())).collect()).unwrap_or_default(),
+            parts_count: item.parts_count().map(|n| n as u32),
+            tag_count: item.tag_count().map(|n| n as u32),
+            replication_status: string(item.replication_status().map(|s| s.as_str())),
+            object_lock_mode: string(item.object_lock_mode().map(|s| s.as_str())),
+            restore: string(item.restore()),
+        }
+    }
+}
+
 #[repr(align(8))]
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct ObjectProps {
@@ -194,15 +565,31 @@ pub struct ObjectProps {
     flags: u8,
     #[serde(skip)]
     status: u8,
-    #[allow(dead_code)]
     #[serde(skip)]
-    pad: u16,
+    diff_attrs: u8,
+    #[serde(serialize_with = "serialize_storage_class", deserialize_with = "deserialize_storage_class")]
+    storage_class: u8,
     #[serde(skip)]
     etag_parts: u32,
     last_modified: u64,
     size: u64,
     #[serde(skip)]
     etag_md5: [u8; 16],
+    // only fetched with "--with-tags", none if not fetched or failed to
+    #[serde(skip)]
+    tags: Option<Arc<ObjectTags>>,
+    // only listed with "--with-owner", none if not listed
+    #[serde(skip)]
+    owner: Option<Arc<ObjectOwner>>,
+    // only fetched with "--with-checksum", base64 as in response, none if not fetched or uploaded without one
+    #[serde(skip)]
+    checksum: Option<Arc<String>>,
+    // only fetched with "--enrich-all", none if not fetched or failed to
+    #[serde(skip)]
+    head: Option<Arc<ObjectHead>>,
+    // key as listed, only kept if it differs from key of object map normalized by "--key-compare"
+    #[serde(skip)]
+    listed_key: Option<Arc<String>>,
 }
 
 impl ObjectProps {
@@ -215,6 +602,12 @@ impl ObjectProps {
         (self.flags & OBJECT_PROPS_FLAG_DIFF_MODE) == OBJECT_PROPS_FLAG_DIFF_MODE
     }
 
+    // seen on both sides and not exported, equal or filtered out, later props of it are only duplicates
+    pub fn is_resolved(&self) -> bool {
+        (self.flags & OBJECT_PROPS_FLAG_DIR_BOTH) == OBJECT_PROPS_FLAG_DIR_BOTH &&
+            (self.status == OBJECT_PROPS_STATUS_MATCH || self.status == OBJECT_PROPS_STATUS_FILTER_OUT)
+    }
+
     #[allow(dead_code)]
     pub fn set_bucket_type_gp(&mut self) {
         self.flags |= OBJECT_PROPS_FLAG_S3_GP_BUCKET;
@@ -225,6 +618,83 @@ impl ObjectProps {
         self.flags |= OBJECT_PROPS_FLAG_S3_DIR_BUCKET;
     }
 
+    pub fn is_latest(&self) -> bool {
+        (self.flags & OBJECT_PROPS_FLAG_IS_LATEST) == OBJECT_PROPS_FLAG_IS_LATEST
+    }
+
+    pub fn is_delete_marker(&self) -> bool {
+        (self.flags & OBJECT_PROPS_FLAG_DELETE_MARKER) == OBJECT_PROPS_FLAG_DELETE_MARKER
+    }
+
+    // row of a common prefix, with no size, mtime, etag or storage class
+    pub fn new_common_prefix() -> Self {
+        Self {
+            flags: OBJECT_PROPS_FLAG_S3_GP_BUCKET | OBJECT_PROPS_FLAG_IS_LATEST | OBJECT_PROPS_FLAG_COMMON_PREFIX,
+            status: OBJECT_PROPS_STATUS_OPEN,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_common_prefix(&self) -> bool {
+        (self.flags & OBJECT_PROPS_FLAG_COMMON_PREFIX) == OBJECT_PROPS_FLAG_COMMON_PREFIX
+    }
+
+    pub fn set_tags(&mut self, tags: ObjectTags) {
+        self.tags = Some(Arc::new(tags));
+    }
+
+    pub fn set_owner(&mut self, owner: Option<&aws_sdk_s3::types::Owner>) {
+        self.owner = owner.map(|o| Arc::new(ObjectOwner {
+            id: o.id().map(|s| s.to_string()),
+            display_name: o.display_name().map(|s| s.to_string()),
+        }));
+    }
+
+    pub fn owner(&self) -> Option<&ObjectOwner> {
+        self.owner.as_deref()
+    }
+
+    pub fn set_checksum(&mut self, checksum: Option<&str>) {
+        self.checksum = checksum.map(|c| Arc::new(c.to_string()));
+    }
+
+    pub fn checksum(&self) -> Option<&str> {
+        self.checksum.as_deref().map(|c| c.as_str())
+    }
+
+    pub fn set_head(&mut self, head: ObjectHead) {
+        self.head = Some(Arc::new(head));
+    }
+
+    pub fn head(&self) -> Option<&ObjectHead> {
+        self.head.as_deref()
+    }
+
+    pub fn set_listed_key(&mut self, key: &str) {
+        self.listed_key = Some(Arc::new(key.to_string()));
+    }
+
+    // key of output, the one listed even if object is kept in map under its normalized key
+    pub fn listed_key(&self, prefix: &ObjectPrefix, name: &ObjectName) -> ObjectKey {
+        self.listed_key.as_deref().map_or_else(|| ObjectKey::encode(prefix, name), |key| ObjectKey::from(key.as_str()))
+    }
+
+    // tags in json object of tag key to value, none if tags are not fetched
+    pub fn tags(&self) -> Option<Arc<ObjectTags>> {
+        self.tags.clone()
+    }
+
+    pub fn tags_json(&self) -> Option<serde_json::Map<String, serde_json::Value>> {
+        self.tags.as_ref().map(|tags| tags.iter()
+            .map(|(k, v)| (k.clone(), serde_json::Value::String(v.clone())))
+            .collect())
+    }
+
+    // "type" column of delimiter list output
+    pub fn object_type(&self) -> &'static str {
+        if self.is_common_prefix() { "prefix" } else { "object" }
+    }
+
     pub fn is_left(&self) -> bool {
         (self.flags & OBJECT_PROPS_FLAG_DIR_LEFT) == OBJECT_PROPS_FLAG_DIR_LEFT
     }
@@ -235,10 +705,10 @@ impl ObjectProps {
 
     pub fn is_etag_avail(&self) -> bool {
         let (prefix, aligned, suffix) = unsafe { self.etag_md5.align_to::<u128>() };
-        return prefix.iter().all(|&x| x == 0)
+        prefix.iter().all(|&x| x == 0)
             && suffix.iter().all(|&x| x == 0)
             && aligned.iter().all(|&x| x == 0)
-            && self.etag_parts == 0;
+            && self.etag_parts == 0
     }
 
     pub fn etag(&self) -> ([u8; 16], u32) {
@@ -253,10 +723,28 @@ impl ObjectProps {
         self.last_modified
     }
 
+    pub fn storage_class(&self) -> &'static str {
+        if self.is_common_prefix() {
+            return "";
+        }
+        storage_class_name(self.storage_class)
+    }
+
+    // names of attributes found different against the other side, empty if none
+    pub fn diff_attrs(&self) -> Vec<&'static str> {
+        OBJECT_PROPS_DIFF_ATTR_NAME.iter()
+            .filter(|(attr, _)| self.diff_attrs & attr != 0)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
     pub fn etag_string(&self) -> String {
 
+        if self.is_common_prefix() {
+            return String::new();
+        }
         let s = if self.etag_parts == 0 {
-            format!("{}", hex::encode(self.etag_md5))
+            hex::encode(self.etag_md5)
         } else {
             format!("{}-{}", hex::encode(self.etag_md5), self.etag_parts)
         };
@@ -264,22 +752,24 @@ impl ObjectProps {
     }
 
     // used only in final dump stage
-    pub fn final_status_check(&self) -> MatchResult {
+    pub fn final_status_check(&self, prefix: &ObjectPrefix, name: &ObjectName) -> MatchResult {
+        self.final_status_check_with(prefix, name, DiffSettings::current())
+    }
+
+    fn final_status_check_with(&self, prefix: &ObjectPrefix, name: &ObjectName, settings: &DiffSettings) -> MatchResult {
 
         // if we are in list mode and we have filter set, do apply here
         if !self.is_diff_mode() {
             if let Some(filter) = OBJECT_FILTER.get() {
-                let mut scope = Scope::new();
-                let source = to_dynamic(self).unwrap();
-                scope.push_constant_dynamic("SOURCE", source.into_read_only());
-                match filter.engine.eval_ast_with_scope(&mut scope, &filter.ast) {
+                let mut scope = filter.scope(prefix, name, self, None);
+                match filter.eval(&mut scope) {
                     Ok(false) => {
                         return MatchResult::Ignore;
                     },
                     Ok(true) => {
                         // if filter condition matched, just continue
                     },
-                    e @ _ => {
+                    e => {
                         // if apply filter error, just continue
                         warn!("failed to apply filter for {self:?}, error: {e:?}");
                     }
@@ -293,7 +783,9 @@ impl ObjectProps {
 
         if self.status == OBJECT_PROPS_STATUS_SIZE_NOT_MATCH ||
             self.status == OBJECT_PROPS_STATUS_ETAG_NOT_AVAIL ||
-            self.status == OBJECT_PROPS_STATUS_ETAG_NOT_MATCH {
+            self.status == OBJECT_PROPS_STATUS_ETAG_NOT_MATCH ||
+            self.status == OBJECT_PROPS_STATUS_MTIME_NOT_MATCH ||
+            self.status == OBJECT_PROPS_STATUS_CHECKSUM_NOT_MATCH {
 
             return MatchResult::Astrisk;
         }
@@ -308,6 +800,17 @@ impl ObjectProps {
             // if status is in OPEN, only one side set the flag
             assert!((self.flags & OBJECT_PROPS_FLAG_DIR_BOTH) != OBJECT_PROPS_FLAG_DIR_BOTH);
 
+            // one side only object, use its own last_modified against watermark
+            if settings.changed_after.is_some_and(|wm| self.last_modified <= wm) {
+                return MatchResult::Ignore;
+            }
+            // one side only objects of the other direction are not differences asked for
+            match settings.direction {
+                DiffDirection::LeftToRight if self.is_right() => return MatchResult::Ignore,
+                DiffDirection::RightToLeft if self.is_left() => return MatchResult::Ignore,
+                _ => {},
+            }
+
             if self.is_left() {
                 return MatchResult::Plus;
             } else if self.is_right() {
@@ -321,7 +824,11 @@ impl ObjectProps {
         panic!("object props flags {} status {}, why ?", self.flags, self.status);
     }
 
-    pub fn r#match(&mut self, other: &ObjectProps) -> MatchResult {
+    pub fn r#match(&mut self, other: &ObjectProps, prefix: &ObjectPrefix, name: &ObjectName) -> MatchResult {
+        self.match_with(other, prefix, name, DiffSettings::current())
+    }
+
+    fn match_with(&mut self, other: &ObjectProps, prefix: &ObjectPrefix, name: &ObjectName, settings: &DiffSettings) -> MatchResult {
 
         /*
          *  NOTE:
@@ -356,12 +863,8 @@ impl ObjectProps {
         if let Some(filter) = OBJECT_FILTER.get() {
             // should be in diff mode
             assert!(left.is_diff_mode() && right.is_diff_mode());
-            let mut scope = Scope::new();
-            let source = to_dynamic(left).unwrap();
-            let target = to_dynamic(right).unwrap();
-            scope.push_constant_dynamic("SOURCE", source.into_read_only());
-            scope.push_constant_dynamic("TARGET", target.into_read_only());
-            match filter.engine.eval_ast_with_scope(&mut scope, &filter.ast) {
+            let mut scope = filter.scope(prefix, name, left, Some(right));
+            match filter.eval(&mut scope) {
                 Ok(false) => {
                     *self = left.clone();
                     self.flags |= OBJECT_PROPS_FLAG_DIR_BOTH;
@@ -371,83 +874,230 @@ impl ObjectProps {
                 Ok(true) => {
                     // if filter condition matched, just continue
                 },
-                e @ _ => {
+                e => {
                     // if apply filter error, just continue
                     warn!("failed to apply filter for {left:?} and {right:?}, error: {e:?}");
                 }
             }
         }
 
-        // if size not match, override the entry with left's data
-        if left.size != right.size {
-            *self = left.clone();
-            self.flags |= OBJECT_PROPS_FLAG_DIR_BOTH;
-            self.status = OBJECT_PROPS_STATUS_SIZE_NOT_MATCH;
-            return MatchResult::Astrisk;
+        let diff_on = settings.compared();
+        // delete markers and common prefixes have neither size nor etag
+        let both_without_etag = (left.is_delete_marker() && right.is_delete_marker())
+            || (left.is_common_prefix() && right.is_common_prefix());
+        let etag_not_avail = !both_without_etag && (left.is_etag_avail() || right.is_etag_avail());
+        let mut diff_attrs = 0;
+        if diff_on.contains(&DiffOn::Size) && left.size != right.size {
+            diff_attrs |= OBJECT_PROPS_DIFF_ATTR_SIZE;
         }
-
-        // if size eq but missing md5 value on either side
-        // mark this *
-        if left.is_etag_avail() || right.is_etag_avail() {
-            *self = left.clone();
-            self.flags |= OBJECT_PROPS_FLAG_DIR_BOTH;
-            self.status = OBJECT_PROPS_STATUS_ETAG_NOT_AVAIL;
-            return MatchResult::Astrisk;
+        // missing md5 value on either side is treated as not match
+        if diff_on.contains(&DiffOn::Etag) && (etag_not_avail || left.etag() != right.etag()) {
+            diff_attrs |= OBJECT_PROPS_DIFF_ATTR_ETAG;
         }
-
-        // if we have md5 value on both side
-        if left.etag() != right.etag() {
-            *self = left.clone();
-            self.flags |= OBJECT_PROPS_FLAG_DIR_BOTH;
-            self.status = OBJECT_PROPS_STATUS_ETAG_NOT_MATCH;
-            return MatchResult::Astrisk;
+        if diff_on.contains(&DiffOn::Mtime) && left.last_modified.abs_diff(right.last_modified) > settings.lmtime_tolerance {
+            diff_attrs |= OBJECT_PROPS_DIFF_ATTR_MTIME;
         }
+        // as etag, missing checksum on either side is treated as not match
+        if diff_on.contains(&DiffOn::Checksum) && !both_without_etag && (left.checksum.is_none() || left.checksum != right.checksum) {
+            diff_attrs |= OBJECT_PROPS_DIFF_ATTR_CHECKSUM;
+        }
+
+        let status = if diff_attrs & OBJECT_PROPS_DIFF_ATTR_SIZE != 0 {
+            // if size not match, override the entry with left's data
+            OBJECT_PROPS_STATUS_SIZE_NOT_MATCH
+        } else if diff_attrs & OBJECT_PROPS_DIFF_ATTR_ETAG != 0 && etag_not_avail {
+            // if size eq but missing md5 value on either side
+            // mark this *
+            OBJECT_PROPS_STATUS_ETAG_NOT_AVAIL
+        } else if diff_attrs & OBJECT_PROPS_DIFF_ATTR_ETAG != 0 {
+            // if we have md5 value on both side
+            OBJECT_PROPS_STATUS_ETAG_NOT_MATCH
+        } else if diff_attrs & OBJECT_PROPS_DIFF_ATTR_MTIME != 0 {
+            OBJECT_PROPS_STATUS_MTIME_NOT_MATCH
+        } else if diff_attrs & OBJECT_PROPS_DIFF_ATTR_CHECKSUM != 0 {
+            OBJECT_PROPS_STATUS_CHECKSUM_NOT_MATCH
+        } else {
+            OBJECT_PROPS_STATUS_MATCH
+        };
+
+        // difference is kept only if either side changed after watermark
+        let stale = settings.changed_after
+            .is_some_and(|wm| left.last_modified <= wm && right.last_modified <= wm);
 
         *self = left.clone();
         self.flags |= OBJECT_PROPS_FLAG_DIR_BOTH;
-        self.status = OBJECT_PROPS_STATUS_MATCH;
-        return MatchResult::Equal;
+        self.diff_attrs = diff_attrs;
+
+        if status == OBJECT_PROPS_STATUS_MATCH {
+            self.status = status;
+            return MatchResult::Equal;
+        }
+
+        if stale {
+            self.status = OBJECT_PROPS_STATUS_FILTER_OUT;
+            return MatchResult::Ignore;
+        }
+
+        self.status = status;
+        MatchResult::Astrisk
     }
 }
 
+// decode etag into (md5, parts), parts is 0 if not multipart upload
+fn parse_etag(e_tag: Option<&str>) -> ([u8; 16], u32) {
+    let mut md5 = [0u8; 16];
+    e_tag.map_or((md5, 0), |x| {
+        if x.len() == 34 {
+            // etag string in decoded like:
+            // "d41d8cd98f00b204e9800998ecf8427e"
+            if x.get(1..33).is_some_and(|h| hex::decode_to_slice(h, &mut md5).is_ok()) {
+                return (md5, 0);
+            }
+        } else if x.len() >= 36 && x.chars().nth(33) == Some('-') {
+            //  md5 string like:
+            // "c8af37b371ec442ad415feeb87d83246-186"
+            if x.get(1..33).is_some_and(|h| hex::decode_to_slice(h, &mut md5).is_ok()) {
+                if let Some(parts) = x.get(34..x.len()-1).and_then(|p| p.parse::<u32>().ok()) {
+                    return (md5, parts);
+                }
+            }
+        }
+        // kept as an object without etag rather than failing the whole listing
+        warn!("unhandled etag format {}, taken as no etag", x);
+        ([0u8; 16], 0)
+    })
+}
+
+// unquoted etag of an inventory or output file, md5 hex and parts if multipart upload
+pub(crate) fn is_etag(e_tag: &str) -> bool {
+    let (md5, parts) = e_tag.split_once('-').map_or((e_tag, None), |(md5, parts)| (md5, Some(parts)));
+    md5.len() == 32 && md5.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+        && parts.map_or(true, |p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+// storage classes kept in one byte, index 0 is the default if absent in list response
+const STORAGE_CLASS_NAME: [&str; 16] = [
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER",
+    "DEEP_ARCHIVE",
+    "OUTPOSTS",
+    "GLACIER_IR",
+    "SNOW",
+    "EXPRESS_ONEZONE",
+    "FSX_OPENZFS",
+    "FSX_ONTAP",
+    "AWS_BACKUP_WARM",
+    "AWS_BACKUP_LOW_COST_WARM",
+    "UNKNOWN",
+];
+const STORAGE_CLASS_UNKNOWN: u8 = STORAGE_CLASS_NAME.len() as u8 - 1;
+
+fn storage_class_code(name: Option<&str>) -> u8 {
+    let Some(name) = name else {
+        return 0;
+    };
+    STORAGE_CLASS_NAME.iter()
+        .position(|x| *x == name)
+        .map_or_else(|| {
+            debug!("unknown storage class {}", name);
+            STORAGE_CLASS_UNKNOWN
+        }, |x| x as u8)
+}
+
+fn storage_class_name(code: u8) -> &'static str {
+    STORAGE_CLASS_NAME.get(code as usize).copied().unwrap_or("UNKNOWN")
+}
+
+fn serialize_storage_class<S: serde::Serializer>(code: &u8, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(storage_class_name(*code))
+}
+
+fn deserialize_storage_class<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u8, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Ok(storage_class_code(Some(&name)))
+}
+
 impl From<&aws_sdk_s3::types::Object> for ObjectProps {
     fn from(item: &aws_sdk_s3::types::Object) -> Self {
-        let mut md5 = [0u8; 16];
-        let (etag_md5, etag_parts) = item.e_tag()
-                                        .map_or((md5, 0), |x| {
-                                            if x.len() == 34 {
-                                                // etag string in decoded like:
-                                                // "d41d8cd98f00b204e9800998ecf8427e"
-                                                if let Ok(_) = hex::decode_to_slice(&x[1..33], &mut md5) {
-                                                    return (md5, 0);
-                                                }
-                                            } else if x.len() >= 36 {
-                                                //  md5 string like:
-                                                // "c8af37b371ec442ad415feeb87d83246-186"
-                                                // format check
-                                                if x.chars().nth(33) != Some('-') {
-                                                    panic!("unhandled etag format {}", x);
-                                                }
-                                                if let Ok(_) = hex::decode_to_slice(&x[1..33], &mut md5) {
-                                                    if let Ok(parts) = &x[34..x.len()-1].parse::<usize>() {
-                                                        return (md5, *parts as u32);
-                                                    }
-                                                }
-                                            }
-                                            panic!("unhandled etag format {}", x);
-                                        });
+        let (etag_md5, etag_parts) = parse_etag(item.e_tag());
+        Self {
+            flags: OBJECT_PROPS_FLAG_S3_GP_BUCKET | OBJECT_PROPS_FLAG_IS_LATEST,
+            status: OBJECT_PROPS_STATUS_OPEN,
+            diff_attrs: 0,
+            storage_class: storage_class_code(item.storage_class().map(|x| x.as_str())),
+            etag_parts,
+            last_modified: item
+                .last_modified()
+                .map_or(0, |x| x.secs() as u64),
+            size: item
+                .size()
+                .map_or(0, |x| x as u64),
+            etag_md5,
+            tags: None,
+            owner: None,
+            checksum: None,
+            head: None,
+            listed_key: None,
+        }
+    }
+}
+
+// one listed object, its key and props that filters are evaluated on
+#[derive(Debug)]
+pub struct ObjectRecord {
+    pub key: ObjectKey,
+    pub props: ObjectProps,
+}
+
+impl From<&aws_sdk_s3::types::Object> for ObjectRecord {
+    fn from(item: &aws_sdk_s3::types::Object) -> Self {
+        Self {
+            key: ObjectKey::from(item.key().unwrap_or_default()),
+            props: ObjectProps::from(item),
+        }
+    }
+}
+
+impl From<&aws_sdk_s3::types::ObjectVersion> for ObjectProps {
+    fn from(item: &aws_sdk_s3::types::ObjectVersion) -> Self {
+        let (etag_md5, etag_parts) = parse_etag(item.e_tag());
+        let latest = if item.is_latest().unwrap_or(false) { OBJECT_PROPS_FLAG_IS_LATEST } else { 0 };
         Self {
-            flags: OBJECT_PROPS_FLAG_S3_GP_BUCKET,
+            flags: OBJECT_PROPS_FLAG_S3_GP_BUCKET | latest,
             status: OBJECT_PROPS_STATUS_OPEN,
-            pad: 0,
-            etag_parts: etag_parts,
+            diff_attrs: 0,
+            storage_class: storage_class_code(item.storage_class().map(|x| x.as_str())),
+            etag_parts,
             last_modified: item
                 .last_modified()
                 .map_or(0, |x| x.secs() as u64),
             size: item
                 .size()
                 .map_or(0, |x| x as u64),
-            etag_md5: etag_md5,
+            etag_md5,
+            tags: None,
+            owner: None,
+            checksum: None,
+            head: None,
+            listed_key: None,
+        }
+    }
+}
+
+impl From<&aws_sdk_s3::types::DeleteMarkerEntry> for ObjectProps {
+    fn from(item: &aws_sdk_s3::types::DeleteMarkerEntry) -> Self {
+        let latest = if item.is_latest().unwrap_or(false) { OBJECT_PROPS_FLAG_IS_LATEST } else { 0 };
+        Self {
+            flags: OBJECT_PROPS_FLAG_S3_GP_BUCKET | OBJECT_PROPS_FLAG_DELETE_MARKER | latest,
+            status: OBJECT_PROPS_STATUS_OPEN,
+            last_modified: item
+                .last_modified()
+                .map_or(0, |x| x.secs() as u64),
+            ..Default::default()
         }
     }
 }
@@ -462,7 +1112,7 @@ impl TaskRendezvous {
     fn new(tasks_count: usize, warmup_secs: u64) -> Self {
         Self {
             barrier: Arc::new(Barrier::new(tasks_count)),
-            warmup_secs: warmup_secs,
+            warmup_secs,
         }
     }
 
@@ -477,66 +1127,1185 @@ impl TaskRendezvous {
     }
 }
 
-const TASK_STATUS_BIT_LEFT: usize = 0x1;
-const TASK_STATUS_BIT_RIGHT: usize = 0x2;
-const TASK_STATUS_BIT_DATA_MAP: usize = 0x4;
-const TASK_STATUS_BIT_MON: usize = 0x8;
-
-#[derive(Clone)]
-pub struct GlobalState {
-    state: Arc<AtomicUsize>,
-    quit: Arc<AtomicBool>,
-    tracker: Arc<HttpStatusCodeTracker>,
-    task_next_stream_timeout_count: Arc<AtomicUsize>,
-    s3_client_timeout_count: Arc<AtomicUsize>,
-    s3_client_generic_error_count: Arc<AtomicUsize>,
-    task_rendez: TaskRendezvous,
+// keep process rss under cap by pausing list tasks, sampled via /proc at most every interval
+pub(crate) struct MemoryGovernor {
+    max_bytes: usize, // 0 for unlimited
+    abort: bool,
+    last_sample_ms: AtomicU64,
+    rss: AtomicUsize,
+    over_cap: AtomicBool,
+    given_up: AtomicBool,
+    throttled_count: AtomicUsize,
+    // list tasks paused on channel under capped watermark, over the one without cap
+    channel_capped_count: AtomicUsize,
 }
 
-impl GlobalState {
-    pub fn new(quit: Arc<AtomicBool>, tasks_count: usize, warmup_secs: u64) -> Self {
+impl MemoryGovernor {
+    pub fn new(max_bytes: usize, abort: bool) -> Self {
         Self {
-            state: Arc::new(AtomicUsize::new(0)),
-            quit: quit,
-            tracker: Arc::new(HttpStatusCodeTracker::new()),
-            task_next_stream_timeout_count: Arc::new(AtomicUsize::new(0)),
-            s3_client_timeout_count: Arc::new(AtomicUsize::new(0)),
-            s3_client_generic_error_count: Arc::new(AtomicUsize::new(0)),
-            task_rendez: TaskRendezvous::new(tasks_count, warmup_secs),
+            max_bytes,
+            abort,
+            last_sample_ms: AtomicU64::new(0),
+            rss: AtomicUsize::new(0),
+            over_cap: AtomicBool::new(false),
+            given_up: AtomicBool::new(false),
+            throttled_count: AtomicUsize::new(0),
+            channel_capped_count: AtomicUsize::new(0),
         }
     }
 
-    pub async fn wait_to_start(&self) {
-        self.task_rendez.wait().await;
+    pub fn unlimited() -> Self {
+        Self::new(0, false)
     }
 
-    #[allow(dead_code)]
-    pub fn get_warmup_secs(&self) -> u64 {
-        self.task_rendez.get_warmup_secs()
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes > 0
     }
 
-    pub fn inc_task_next_stream_timeout(&self) {
-        self.task_next_stream_timeout_count.fetch_add(1, Ordering::SeqCst);
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn read_rss(&self) -> usize {
+        self.rss.load(Ordering::Relaxed)
+    }
+
+    pub fn read_throttled(&self) -> usize {
+        self.throttled_count.load(Ordering::Relaxed)
+    }
+
+    pub fn read_channel_capped(&self) -> usize {
+        self.channel_capped_count.load(Ordering::Relaxed)
+    }
+
+    // high and low watermark of channel to data map, lowered while rss is over cap
+    fn channel_watermarks(&self) -> (usize, usize) {
+        if self.is_over_cap() {
+            (DEFAULT_CHANNEL_CAPPED_HIGH_WATERMARK, DEFAULT_CHANNEL_CAPPED_LOW_WATERMARK)
+        } else {
+            (DEFAULT_CHANNEL_HIGH_WATERMARK, DEFAULT_CHANNEL_LOW_WATERMARK)
+        }
+    }
+
+    pub fn is_over_cap(&self) -> bool {
+        self.is_enabled() && self.sample()
+    }
+
+    // return true if rss is over cap, rss is re-sampled if last sample is stale
+    fn sample(&self) -> bool {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let last = self.last_sample_ms.load(Ordering::Relaxed);
+        if now.saturating_sub(last) >= DEFAULT_MEMORY_SAMPLE_INTERVAL_MS &&
+            self.last_sample_ms.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            if let Some(rss) = utils::current_rss() {
+                self.rss.store(rss, Ordering::Relaxed);
+                self.over_cap.store(rss > self.max_bytes, Ordering::Relaxed);
+            }
+        }
+        self.over_cap.load(Ordering::Relaxed)
+    }
+}
+
+// retry of list calls on retryable errors, replaces retry of SDK if given
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+}
+
+impl RetryPolicy {
+    // exponential backoff with equal jitter, capped at max delay
+    pub fn backoff(&self, retry: u32) -> std::time::Duration {
+        let exp = self.base_delay_ms.saturating_mul(1 << retry.min(16)).min(DEFAULT_RETRY_MAX_DELAY_MS);
+        std::time::Duration::from_millis(exp / 2 + fastrand::u64(0..=exp / 2))
+    }
+}
+
+// list with delimiter, common prefixes down to max depth under start prefix are rows instead of their objects
+#[derive(Debug, Clone)]
+pub(crate) struct ShallowListOptions {
+    pub delimiter: String,
+    pub max_depth: usize,
+}
+
+// common prefixes of "--one-per-prefix" listed by each side of diff, merged into one set of split points,
+// so both sides fetch the first object of the same prefixes
+#[derive(Debug)]
+pub(crate) struct SharedSplits {
+    sides: usize,
+    // count of sides merged and prefixes of all of them
+    merged: std::sync::Mutex<(usize, BTreeSet<String>)>,
+    done: tokio::sync::Notify,
+}
+
+impl SharedSplits {
+    pub fn new(sides: usize) -> Self {
+        Self { sides, merged: std::sync::Mutex::new((0, BTreeSet::new())), done: tokio::sync::Notify::new() }
+    }
+
+    // add prefixes of one side and wait for the others, all sides get the sorted union.
+    // a side failed to list merges none, so the others don't wait forever
+    pub async fn merge(&self, prefixes: Vec<String>) -> Vec<String> {
+        let notified = self.done.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        {
+            let mut merged = self.merged.lock().unwrap();
+            merged.0 += 1;
+            merged.1.extend(prefixes);
+            if merged.0 >= self.sides {
+                self.done.notify_waiters();
+                return merged.1.iter().cloned().collect();
+            }
+        }
+        notified.await;
+        self.merged.lock().unwrap().1.iter().cloned().collect()
+    }
+}
+
+// sorted prefixes without the ones nested in another, which are covered already
+pub(crate) fn dedup_nested_prefixes(mut prefixes: Vec<String>) -> Vec<String> {
+    prefixes.sort();
+    prefixes.dedup();
+    let mut kept: Vec<String> = Vec::new();
+    for p in prefixes {
+        if !kept.last().is_some_and(|last| p.starts_with(last.as_str())) {
+            kept.push(p);
+        }
+    }
+    kept
+}
+
+// prefix as a directory, "foo" to "foo/" so it no longer covers "foobar/", root "" is kept
+pub(crate) fn dir_prefix(prefix: &str) -> String {
+    if prefix.is_empty() || prefix.ends_with('/') {
+        prefix.to_string()
+    } else {
+        format!("{}/", prefix)
+    }
+}
+
+// largest char, keys under a prefix all sort before prefix followed by it
+const EXCLUDE_PREFIX_SKIP_CHAR: char = '\u{10FFFF}';
+
+/*
+ * subtrees never listed, a list task jumps over one when it lands in it,
+ * starting next request after the last possible key under it instead of paginating through.
+ * the landing page is filtered, so at most one page per excluded prefix and partition is wasted.
+ */
+#[derive(Debug)]
+pub(crate) struct ExcludePrefixes {
+    prefixes: Vec<String>,
+    pruned: AtomicUsize,
+}
+
+impl ExcludePrefixes {
+    pub fn new(prefixes: Vec<String>) -> Self {
+        Self {
+            prefixes: dedup_nested_prefixes(prefixes),
+            pruned: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn prefixes(&self) -> &[String] {
+        &self.prefixes
+    }
+
+    // excluded prefix the key is under
+    pub fn matching(&self, key: &str) -> Option<&str> {
+        let i = self.prefixes.partition_point(|p| p.as_str() <= key);
+        if i == 0 {
+            return None;
+        }
+        let p = self.prefixes[i - 1].as_str();
+        key.starts_with(p).then_some(p)
+    }
+
+    // start after for next request to skip the excluded prefix key is under,
+    // None if key is not excluded or already at end of the subtree
+    pub fn skip_from(&self, key: &str) -> Option<String> {
+        let skip = format!("{}{}", self.matching(key)?, EXCLUDE_PREFIX_SKIP_CHAR);
+        if skip.as_str() <= key {
+            return None;
+        }
+        self.pruned.fetch_add(1, Ordering::Relaxed);
+        Some(skip)
+    }
+
+    pub fn read_pruned(&self) -> usize {
+        self.pruned.load(Ordering::Relaxed)
+    }
+}
+
+// random sample of listed keys, checked with HEAD requests once listing of its side completes
+#[derive(Debug)]
+pub(crate) struct VerifySampler {
+    percent: f64,
+    // key and version id
+    keys: std::sync::Mutex<Vec<(String, Option<String>)>>,
+}
+
+impl VerifySampler {
+    pub fn new(percent: f64) -> Self {
+        Self {
+            percent,
+            keys: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    // common prefixes and delete markers can not be fetched, so never sampled
+    pub fn sample(&self, output: &HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>) {
+        let picked: Vec<_> = output.iter()
+            .flat_map(|(prefix, objects)| objects.iter().map(move |(name, props)| (prefix, name, props)))
+            .filter(|(_, _, props)| !props.is_common_prefix() && !props.is_delete_marker() && fastrand::f64() * 100.0 < self.percent)
+            .map(|(prefix, name, _)| {
+                let key = ObjectKey::encode(prefix, name);
+                let (key, version_id) = key.split_version();
+                (key.to_string(), version_id.map(|v| v.to_string()))
+            })
+            .collect();
+        if !picked.is_empty() {
+            self.keys.lock().unwrap().extend(picked);
+        }
+    }
+
+    pub fn take(&self) -> Vec<(String, Option<String>)> {
+        std::mem::take(&mut *self.keys.lock().unwrap())
+    }
+}
+
+// per object requests of listed objects, GetObjectTagging, GetObjectAttributes or HeadObject,
+// requests in flight of all list tasks of one side are capped by concurrency
+#[derive(Debug)]
+pub(crate) struct ObjectFetcher {
+    pub permits: tokio::sync::Semaphore,
+    pub concurrency: usize,
+    fetched: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+impl ObjectFetcher {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            permits: tokio::sync::Semaphore::new(concurrency),
+            concurrency,
+            fetched: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inc_fetched(&self) {
+        self.fetched.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn inc_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // objects fetched and failed to
+    pub fn read_counts(&self) -> (usize, usize) {
+        (self.fetched.load(Ordering::Relaxed), self.failed.load(Ordering::Relaxed))
+    }
+}
+
+// outcome of HEAD requests on sampled keys of one bucket
+#[derive(Debug, Clone, Default)]
+pub struct VerifyResult {
+    pub bucket: String,
+    pub checked: usize,
+    // responded 404
+    pub missing: usize,
+    // failed with any other error, not telling if key exists
+    pub failed: usize,
+}
+
+// cap list requests per second shared by all list tasks of one or both sides,
+// each request reserves the next free slot so callers are spaced evenly without burst
+pub(crate) struct RateLimiter {
+    label: &'static str,
+    max_rps: u32,
+    next_slot: std::sync::Mutex<tokio::time::Instant>,
+    acquired: AtomicUsize,
+}
+
+impl RateLimiter {
+    pub fn new(label: &'static str, max_rps: u32) -> Self {
+        Self {
+            label,
+            max_rps,
+            next_slot: std::sync::Mutex::new(tokio::time::Instant::now()),
+            acquired: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    pub fn max_rps(&self) -> u32 {
+        self.max_rps
+    }
+
+    // requests let through so far
+    pub fn read_acquired(&self) -> usize {
+        self.acquired.load(Ordering::Relaxed)
+    }
+
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let slot = (*next_slot).max(tokio::time::Instant::now());
+            *next_slot = slot + std::time::Duration::from_secs(1) / self.max_rps;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/*
+ * limit of concurrent list tasks tuned AIMD style, shared by all sides:
+ *   - grows by one after as many successful list requests in a row as the current limit
+ *   - halves on throttle response (503 or 429), at most once per cooldown as requests in flight
+ *     are throttled together
+ * starts at a quarter of max, never goes above max or below one
+ */
+pub(crate) struct AdaptiveConcurrency {
+    max: usize,
+    limit: AtomicUsize,
+    successes: AtomicUsize,
+    throttled: AtomicUsize,
+    last_decrease: std::sync::Mutex<Option<std::time::Instant>>,
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            limit: AtomicUsize::new((max / 4).max(1)),
+            successes: AtomicUsize::new(0),
+            throttled: AtomicUsize::new(0),
+            last_decrease: std::sync::Mutex::new(None),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn read_throttled(&self) -> usize {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    pub fn on_success(&self) {
+        let limit = self.limit();
+        if self.successes.fetch_add(1, Ordering::Relaxed) + 1 >= limit && limit < self.max {
+            self.successes.store(0, Ordering::Relaxed);
+            let _ = self.limit.compare_exchange(limit, limit + 1, Ordering::Relaxed, Ordering::Relaxed);
+        }
+    }
+
+    pub fn on_throttle(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        let mut last_decrease = self.last_decrease.lock().unwrap();
+        let cooldown = std::time::Duration::from_millis(DEFAULT_ADAPTIVE_CONCURRENCY_COOLDOWN_MS);
+        if last_decrease.is_some_and(|ts| ts.elapsed() < cooldown) {
+            return;
+        }
+        *last_decrease = Some(std::time::Instant::now());
+        self.successes.store(0, Ordering::Relaxed);
+        let _ = self.limit.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |limit| Some((limit / 2).max(1)));
+    }
+}
+
+// throttle responses of list clients of all sides, and the streak of them not broken by a success
+pub(crate) struct ThrottleTracker {
+    started: std::time::Instant,
+    throttled: AtomicUsize,
+    // millis since started of first throttle response after last success, 0 if none
+    streak_since_ms: AtomicU64,
+    streak_throttled: AtomicUsize,
+}
+
+impl ThrottleTracker {
+    pub fn new() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            throttled: AtomicUsize::new(0),
+            streak_since_ms: AtomicU64::new(0),
+            streak_throttled: AtomicUsize::new(0),
+        }
+    }
+
+    // millis since started, off by one not to be taken for no streak
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64 + 1
+    }
+
+    pub fn on_throttle(&self) {
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        self.streak_throttled.fetch_add(1, Ordering::Relaxed);
+        let _ = self.streak_since_ms.compare_exchange(0, self.elapsed_ms(), Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub fn on_success(&self) {
+        if self.streak_since_ms.load(Ordering::Relaxed) != 0 {
+            self.streak_since_ms.store(0, Ordering::Relaxed);
+            self.streak_throttled.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn read_throttled(&self) -> usize {
+        self.throttled.load(Ordering::Relaxed)
+    }
+
+    // how long and how many responses have been throttled since last success, none if last one succeeded
+    pub fn streak(&self) -> Option<(std::time::Duration, usize)> {
+        let since = self.streak_since_ms.load(Ordering::Relaxed);
+        (since != 0).then(|| (std::time::Duration::from_millis(self.elapsed_ms().saturating_sub(since)),
+            self.streak_throttled.load(Ordering::Relaxed)))
+    }
+}
+
+// feed status of every http response of list client to throttle tracker and adaptive concurrency, SDK retries included
+#[derive(Clone)]
+struct ThrottleInterceptor {
+    tracker: Arc<ThrottleTracker>,
+    // only lowered on throttle with "--on-throttle backoff"
+    adaptive: Option<Arc<AdaptiveConcurrency>>,
+}
+
+impl std::fmt::Debug for ThrottleInterceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ThrottleInterceptor")
+    }
+}
+
+impl aws_sdk_s3::config::Intercept for ThrottleInterceptor {
+    fn name(&self) -> &'static str {
+        "ThrottleInterceptor"
+    }
+
+    fn read_after_transmit(&self, context: &aws_sdk_s3::config::interceptors::BeforeDeserializationInterceptorContextRef<'_>,
+            _runtime_components: &aws_sdk_s3::config::RuntimeComponents, _cfg: &mut aws_sdk_s3::config::ConfigBag) -> Result<(), aws_sdk_s3::error::BoxError> {
+        let code = context.response().status().as_u16();
+        if crate::error::is_throttle_status(code) {
+            self.tracker.on_throttle();
+            if let Some(adaptive) = &self.adaptive {
+                adaptive.on_throttle();
+            }
+        } else if (200..300).contains(&code) {
+            self.tracker.on_success();
+            if let Some(adaptive) = &self.adaptive {
+                adaptive.on_success();
+            }
+        }
+        Ok(())
+    }
+}
+
+// product token of this tool, always in user agent so it is told apart from other SDK clients
+pub(crate) const USER_AGENT_PRODUCT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// append product token and custom string to user agent set by SDK, the header is not signed,
+// effective one is logged once on first request, i.e. bucket probe at startup
+static USER_AGENT_LOGGED: AtomicBool = AtomicBool::new(false);
+
+struct UserAgentInterceptor {
+    suffix: String,
+}
+
+impl std::fmt::Debug for UserAgentInterceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "UserAgentInterceptor")
+    }
+}
+
+impl aws_sdk_s3::config::Intercept for UserAgentInterceptor {
+    fn name(&self) -> &'static str {
+        "UserAgentInterceptor"
+    }
+
+    fn modify_before_transmit(&self, context: &mut aws_sdk_s3::config::interceptors::BeforeTransmitInterceptorContextMut<'_>,
+            _runtime_components: &aws_sdk_s3::config::RuntimeComponents, _cfg: &mut aws_sdk_s3::config::ConfigBag) -> Result<(), aws_sdk_s3::error::BoxError> {
+        let headers = context.request_mut().headers_mut();
+        let user_agent = match headers.get("user-agent") {
+            Some(sdk) => format!("{} {}", sdk, self.suffix),
+            None => self.suffix.clone(),
+        };
+        if !USER_AGENT_LOGGED.swap(true, Ordering::Relaxed) {
+            info!("  - user agent: {}", user_agent);
+        }
+        headers.insert("user-agent", user_agent);
+        Ok(())
+    }
+}
+
+// http client capping requests in flight, and so connections it keeps open, with --max-connections,
+// list tasks over the cap wait for a free connection instead of opening more of them
+#[derive(Debug)]
+struct BoundedHttpClient {
+    inner: SharedHttpClient,
+    permits: Arc<tokio::sync::Semaphore>,
+}
+
+impl HttpClient for BoundedHttpClient {
+    fn http_connector(&self, settings: &HttpConnectorSettings, components: &RuntimeComponents) -> SharedHttpConnector {
+        SharedHttpConnector::new(BoundedHttpConnector {
+            inner: self.inner.http_connector(settings, components),
+            permits: self.permits.clone(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct BoundedHttpConnector {
+    inner: SharedHttpConnector,
+    permits: Arc<tokio::sync::Semaphore>,
+}
+
+impl HttpConnector for BoundedHttpConnector {
+    fn call(&self, request: HttpRequest) -> HttpConnectorFuture {
+        let inner = self.inner.clone();
+        let permits = self.permits.clone();
+        HttpConnectorFuture::new(async move {
+            // semaphore is never closed
+            let _permit = permits.acquire_owned().await.expect("connection permits closed");
+            inner.call(request).await
+        })
+    }
+}
+
+// tls certificate verification of custom endpoint, system trust store is used if None
+#[derive(Debug, Clone)]
+pub(crate) enum TlsVerify {
+    // trusted in addition to system roots
+    CaCerts(Vec<rustls::Certificate>),
+    // accept any certificate, e.g. self-signed one of internal endpoint
+    Insecure,
+}
+
+impl TlsVerify {
+    // load all certificates of pem file, fail on none found or invalid one
+    pub fn from_ca_cert(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        let certs: Vec<_> = rustls_pemfile::certs(&mut std::io::BufReader::new(file)).map_err(|e| e.to_string())?
+            .into_iter().map(rustls::Certificate).collect();
+        if certs.is_empty() {
+            return Err("no certificate found in pem file".to_string());
+        }
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in certs.iter() {
+            roots.add(cert).map_err(|e| e.to_string())?;
+        }
+        Ok(TlsVerify::CaCerts(certs))
+    }
+
+    fn client_config(&self) -> rustls::ClientConfig {
+        let builder = rustls::ClientConfig::builder().with_safe_defaults();
+        match self {
+            TlsVerify::CaCerts(certs) => {
+                let mut roots = rustls::RootCertStore::empty();
+                for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+                    // system roots rustls can't parse are skipped, as hyper-rustls does
+                    let _ = roots.add(&rustls::Certificate(cert.0));
+                }
+                for cert in certs.iter() {
+                    // validated on load
+                    let _ = roots.add(cert);
+                }
+                builder.with_root_certificates(roots).with_no_client_auth()
+            },
+            TlsVerify::Insecure => builder.with_custom_certificate_verifier(Arc::new(NoCertVerifier)).with_no_client_auth(),
+        }
+    }
+}
+
+// certificate verifier of --tls-insecure, handshake signatures are still checked against presented certificate
+struct NoCertVerifier;
+
+impl rustls::client::ServerCertVerifier for NoCertVerifier {
+    fn verify_server_cert(&self, _end_entity: &rustls::Certificate, _intermediates: &[rustls::Certificate],
+            _server_name: &rustls::ServerName, _scts: &mut dyn Iterator<Item = &[u8]>, _ocsp_response: &[u8],
+            _now: std::time::SystemTime) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+// http client with pool of at most max connections or custom tls verification, None to keep default client of SDK
+#[allow(deprecated)]
+fn tuned_http_client(opts: &S3ClientOptions) -> Option<SharedHttpClient> {
+    if opts.max_connections.is_none() && opts.tls.is_none() {
+        return None;
+    }
+    let tls_builder = hyper_rustls::HttpsConnectorBuilder::new();
+    let tls_builder = match &opts.tls {
+        Some(verify) => tls_builder.with_tls_config(verify.client_config()),
+        None => tls_builder.with_native_roots(),
+    };
+    let https_connector = tls_builder
+        .https_or_http()
+        .enable_http1()
+        .build();
+    let mut hyper_builder = hyper_0_14::Client::builder();
+    if let Some(max_connections) = opts.max_connections {
+        hyper_builder.pool_max_idle_per_host(max_connections);
+    }
+    let inner = aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder::new()
+        .hyper_builder(hyper_builder)
+        .build(https_connector);
+    match opts.max_connections {
+        Some(max_connections) => Some(SharedHttpClient::new(BoundedHttpClient {
+            inner,
+            permits: Arc::new(tokio::sync::Semaphore::new(max_connections)),
+        })),
+        None => Some(inner),
+    }
+}
+
+const TASK_STATUS_BIT_LEFT: usize = 0x1;
+const TASK_STATUS_BIT_RIGHT: usize = 0x2;
+const TASK_STATUS_BIT_DATA_MAP: usize = 0x4;
+const TASK_STATUS_BIT_MON: usize = 0x8;
+
+// region of bucket by endpoint and bucket name from GetBucketLocation, None if it failed
+type BucketRegions = HashMap<(Option<String>, String), Option<String>>;
+
+#[derive(Clone)]
+pub struct GlobalState {
+    state: Arc<AtomicUsize>,
+    quit: Arc<AtomicBool>,
+    tracker: Arc<HttpStatusCodeTracker>,
+    task_next_stream_timeout_count: Arc<AtomicUsize>,
+    s3_client_timeout_count: Arc<AtomicUsize>,
+    s3_client_generic_error_count: Arc<AtomicUsize>,
+    s3_redirect_count: Arc<AtomicUsize>,
+    task_rendez: TaskRendezvous,
+    run_id: Arc<String>,
+    mem_governor: Arc<MemoryGovernor>,
+    // differences exported in diff mode, only set if run completed and dumped
+    diff_count: Arc<OnceLock<usize>>,
+    // any list task quit on unrecoverable error
+    list_failed: Arc<AtomicBool>,
+    checkpointer: Option<Arc<Checkpointer>>,
+    // rate limiters of list requests, one shared or one per side
+    rate_limiters: Vec<Arc<RateLimiter>>,
+    list_retry_count: Arc<AtomicUsize>,
+    list_request_count: Arc<AtomicUsize>,
+    // objects and bytes received from list calls of all sides, before filter and diff
+    listed_objects_count: Arc<AtomicUsize>,
+    listed_bytes: Arc<AtomicU64>,
+    // flat list tasks of key space pairs in flight
+    active_list_tasks: Arc<AtomicUsize>,
+    // limit of flat list tasks with adaptive concurrency
+    adaptive_concurrency: Option<Arc<AdaptiveConcurrency>>,
+    // verify sample results of each side, in order of completion
+    verify_results: Arc<std::sync::Mutex<Vec<VerifyResult>>>,
+    bucket_regions: Arc<std::sync::Mutex<BucketRegions>>,
+    errors: Arc<ErrorCounters>,
+    // stop listing once this many objects listed
+    max_objects: Option<usize>,
+    // key space partitions of all sides to list and done so far, for eta of progress bar
+    partitions_total: Arc<AtomicUsize>,
+    partitions_done: Arc<AtomicUsize>,
+    // drawn by mon task in place of heartbeat lines
+    progress_bar: Option<Arc<ProgressBar>>,
+    on_error: OnError,
+    on_throttle: OnThrottle,
+    throttle: Arc<ThrottleTracker>,
+    throttle_abort_after: std::time::Duration,
+    // run stopped by "--on-throttle abort"
+    throttle_aborted: Arc<AtomicBool>,
+    // batches waiting in channel to data map, as seen by data map task, and the most of them so far
+    queued_batches: Arc<AtomicUsize>,
+    peak_queued_batches: Arc<AtomicUsize>,
+    // times list tasks paused over high watermark of channel
+    backpressure_count: Arc<AtomicUsize>,
+    // list tasks hold before next page while set, toggled by --control-socket
+    paused: Arc<AtomicBool>,
+    // ranges left unlisted after retries with --on-error continue, of all sides
+    failed_ranges: Arc<std::sync::Mutex<Vec<KsRange>>>,
+    // when list tasks of all sides completed
+    list_ended: Arc<std::sync::Mutex<Option<std::time::Instant>>>,
+    // MaxKeys sent on list calls, none leaves it to S3 default
+    page_size: Option<usize>,
+    // most keys of a page that was truncated, below page size if endpoint caps pages lower
+    peak_truncated_page: Arc<AtomicUsize>,
+    // truncated pages without a usable continuation token or short of page size, keys may be missing around them
+    suspect_pages: Arc<AtomicUsize>,
+    // suspect page fails its partition instead of listing on
+    strict_pagination: bool,
+}
+
+impl GlobalState {
+    pub fn new(quit: Arc<AtomicBool>, tasks_count: usize, warmup_secs: u64, run_id: String) -> Self {
+        Self {
+            state: Arc::new(AtomicUsize::new(0)),
+            quit,
+            tracker: Arc::new(HttpStatusCodeTracker::new()),
+            task_next_stream_timeout_count: Arc::new(AtomicUsize::new(0)),
+            s3_client_timeout_count: Arc::new(AtomicUsize::new(0)),
+            s3_client_generic_error_count: Arc::new(AtomicUsize::new(0)),
+            s3_redirect_count: Arc::new(AtomicUsize::new(0)),
+            task_rendez: TaskRendezvous::new(tasks_count, warmup_secs),
+            run_id: Arc::new(run_id),
+            mem_governor: Arc::new(MemoryGovernor::unlimited()),
+            diff_count: Arc::new(OnceLock::new()),
+            list_failed: Arc::new(AtomicBool::new(false)),
+            checkpointer: None,
+            rate_limiters: Vec::new(),
+            list_retry_count: Arc::new(AtomicUsize::new(0)),
+            list_request_count: Arc::new(AtomicUsize::new(0)),
+            listed_objects_count: Arc::new(AtomicUsize::new(0)),
+            listed_bytes: Arc::new(AtomicU64::new(0)),
+            active_list_tasks: Arc::new(AtomicUsize::new(0)),
+            adaptive_concurrency: None,
+            verify_results: Arc::new(std::sync::Mutex::new(Vec::new())),
+            bucket_regions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            errors: Arc::new(ErrorCounters::default()),
+            max_objects: None,
+            partitions_total: Arc::new(AtomicUsize::new(0)),
+            partitions_done: Arc::new(AtomicUsize::new(0)),
+            progress_bar: None,
+            on_error: OnError::Continue,
+            on_throttle: OnThrottle::Backoff,
+            throttle: Arc::new(ThrottleTracker::new()),
+            throttle_abort_after: std::time::Duration::from_secs(DEFAULT_THROTTLE_ABORT_SECS),
+            throttle_aborted: Arc::new(AtomicBool::new(false)),
+            queued_batches: Arc::new(AtomicUsize::new(0)),
+            peak_queued_batches: Arc::new(AtomicUsize::new(0)),
+            backpressure_count: Arc::new(AtomicUsize::new(0)),
+            paused: Arc::new(AtomicBool::new(false)),
+            failed_ranges: Arc::new(std::sync::Mutex::new(Vec::new())),
+            list_ended: Arc::new(std::sync::Mutex::new(None)),
+            page_size: None,
+            peak_truncated_page: Arc::new(AtomicUsize::new(0)),
+            suspect_pages: Arc::new(AtomicUsize::new(0)),
+            strict_pagination: false,
+        }
+    }
+
+    pub fn with_memory_governor(mut self, governor: MemoryGovernor) -> Self {
+        self.mem_governor = Arc::new(governor);
+        self
+    }
+
+    pub fn with_max_objects(mut self, max_objects: Option<usize>) -> Self {
+        self.max_objects = max_objects;
+        self
+    }
+
+    pub fn with_progress_bar(mut self, progress: bool) -> Self {
+        self.progress_bar = progress.then(|| Arc::new(ProgressBar::new()));
+        self
+    }
+
+    pub fn with_on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    pub fn with_on_throttle(mut self, on_throttle: OnThrottle) -> Self {
+        self.on_throttle = on_throttle;
+        self
+    }
+
+    pub fn on_throttle(&self) -> OnThrottle {
+        self.on_throttle
+    }
+
+    pub fn throttle(&self) -> &ThrottleTracker {
+        &self.throttle
+    }
+
+    // how long a streak of throttle responses lasts before "--on-throttle abort" stops the run
+    pub fn throttle_abort_after(&self) -> std::time::Duration {
+        self.throttle_abort_after
+    }
+
+    #[cfg(test)]
+    pub fn with_throttle_abort_after(mut self, after: std::time::Duration) -> Self {
+        self.throttle_abort_after = after;
+        self
+    }
+
+    // interceptor of list clients feeding throttle tracker, and adaptive concurrency with "--on-throttle backoff"
+    pub(crate) fn throttle_interceptor(&self) -> impl aws_sdk_s3::config::Intercept + 'static {
+        ThrottleInterceptor {
+            tracker: self.throttle.clone(),
+            adaptive: self.adaptive_concurrency.clone().filter(|_| self.on_throttle == OnThrottle::Backoff),
+        }
+    }
+
+    // stop all tasks on sustained throttling, return false if stopped already
+    pub fn abort_on_throttle(&self) -> bool {
+        let first = !self.throttle_aborted.swap(true, Ordering::Relaxed);
+        self.quit();
+        first
+    }
+
+    pub fn is_throttle_aborted(&self) -> bool {
+        self.throttle_aborted.load(Ordering::Relaxed)
+    }
+
+    pub fn with_page_size(mut self, page_size: Option<usize>) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    // MaxKeys to send on list calls
+    pub fn max_keys(&self) -> Option<i32> {
+        self.page_size.map(|n| n as i32)
+    }
+
+    // keys per page asked for, S3 default without --page-size
+    pub fn page_size(&self) -> usize {
+        self.page_size.unwrap_or(DEFAULT_LIST_PAGE_SIZE)
+    }
+
+    // keys of a page with more pages after it
+    pub fn add_truncated_page(&self, keys: usize) {
+        self.peak_truncated_page.fetch_max(keys, Ordering::Relaxed);
+    }
+
+    pub fn read_peak_truncated_page(&self) -> usize {
+        self.peak_truncated_page.load(Ordering::Relaxed)
+    }
+
+    pub fn with_strict_pagination(mut self, strict: bool) -> Self {
+        self.strict_pagination = strict;
+        self
+    }
+
+    pub fn is_strict_pagination(&self) -> bool {
+        self.strict_pagination
+    }
+
+    // suspect pages so far including this one
+    pub fn inc_suspect_page(&self) -> usize {
+        self.suspect_pages.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    pub fn read_suspect_pages(&self) -> usize {
+        self.suspect_pages.load(Ordering::Relaxed)
+    }
+
+    pub fn on_error(&self) -> OnError {
+        self.on_error
+    }
+
+    pub fn set_queued_batches(&self, batches: usize) {
+        self.queued_batches.store(batches, Ordering::Relaxed);
+        self.peak_queued_batches.fetch_max(batches, Ordering::Relaxed);
+    }
+
+    pub fn read_queued_batches(&self) -> usize {
+        self.queued_batches.load(Ordering::Relaxed)
+    }
+
+    pub fn read_peak_queued_batches(&self) -> usize {
+        self.peak_queued_batches.load(Ordering::Relaxed)
+    }
+
+    pub fn read_backpressure(&self) -> usize {
+        self.backpressure_count.load(Ordering::Relaxed)
+    }
+
+    // list tasks outrunning data map pile up results in channel, pause over high watermark until drained to low one,
+    // both are lowered while rss is over cap, also once memory throttling is given up
+    pub async fn wait_for_channel(&self) {
+        let (high, low) = self.mem_governor.channel_watermarks();
+        let queued = self.read_queued_batches();
+        if queued <= high {
+            return;
+        }
+        self.backpressure_count.fetch_add(1, Ordering::Relaxed);
+        if queued <= DEFAULT_CHANNEL_HIGH_WATERMARK {
+            self.mem_governor.channel_capped_count.fetch_add(1, Ordering::Relaxed);
+        }
+        while self.read_queued_batches() > low && !self.is_quit() {
+            tokio::time::sleep(tokio::time::Duration::from_millis(DEFAULT_MEMORY_SAMPLE_INTERVAL_MS)).await;
+        }
+    }
+
+    pub fn add_failed_range(&self, range: KsRange) {
+        self.failed_ranges.lock().unwrap().push(range);
+    }
+
+    // sorted and deduplicated, the same range may fail on both sides
+    pub fn failed_ranges(&self) -> Vec<KsRange> {
+        let mut ranges = self.failed_ranges.lock().unwrap().clone();
+        ranges.sort();
+        ranges.dedup();
+        ranges
+    }
+
+    pub fn progress_bar(&self) -> Option<&ProgressBar> {
+        self.progress_bar.as_deref()
+    }
+
+    // bar line is ended before other lines go to stdout
+    pub fn finish_progress_bar(&self) {
+        if let Some(bar) = &self.progress_bar {
+            bar.finish(self);
+        }
+    }
+
+    pub fn with_checkpointer(mut self, checkpointer: Checkpointer) -> Self {
+        self.checkpointer = Some(Arc::new(checkpointer));
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiters.push(limiter);
+        self
+    }
+
+    pub fn rate_limiters(&self) -> &[Arc<RateLimiter>] {
+        &self.rate_limiters
+    }
+
+    pub fn with_adaptive_concurrency(mut self, adaptive: AdaptiveConcurrency) -> Self {
+        self.adaptive_concurrency = Some(Arc::new(adaptive));
+        self
+    }
+
+    pub fn adaptive_concurrency(&self) -> Option<&AdaptiveConcurrency> {
+        self.adaptive_concurrency.as_deref()
+    }
+
+    // concurrent flat list tasks allowed now, fixed one if not adaptive
+    pub fn list_concurrency(&self, max: usize) -> usize {
+        self.adaptive_concurrency.as_ref().map_or(max, |a| a.limit().min(max))
+    }
+
+    // only set if run is checkpointed
+    pub fn checkpointer(&self) -> Option<&Checkpointer> {
+        self.checkpointer.as_deref()
+    }
+
+    pub fn run_id(&self) -> &str {
+        self.run_id.as_str()
+    }
+
+    pub fn memory_governor(&self) -> &MemoryGovernor {
+        &self.mem_governor
+    }
+
+    // list tasks hold before their next page until resumed, by commands of --control-socket
+    pub fn pause(&self) -> bool {
+        !self.paused.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn resume(&self) -> bool {
+        self.paused.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    async fn wait_while_paused(&self) {
+        while self.is_paused() && !self.is_quit() {
+            tokio::time::sleep(tokio::time::Duration::from_millis(DEFAULT_MEMORY_SAMPLE_INTERVAL_MS)).await;
+        }
+    }
+
+    // pause caller while paused by control socket, then while channel to data map is backed up, then while rss is over cap,
+    // if it can't get under cap in time, either quit or stop throttling for the rest of run
+    pub async fn wait_for_memory(&self) {
+        self.wait_while_paused().await;
+        self.wait_for_channel().await;
+
+        let gov = &self.mem_governor;
+        if !gov.is_enabled() {
+            return;
+        }
+
+        if gov.given_up.load(Ordering::Relaxed) || !gov.sample() {
+            return;
+        }
+
+        gov.throttled_count.fetch_add(1, Ordering::Relaxed);
+        let started = tokio::time::Instant::now();
+        while gov.sample() && !self.is_quit() {
+            if started.elapsed().as_secs() >= DEFAULT_MEMORY_THROTTLE_MAX_WAIT_SECS {
+                if gov.given_up.swap(true, Ordering::Relaxed) {
+                    return;
+                }
+                if gov.abort {
+                    error!("rss {} still over max memory {} after {}s, abort",
+                        gov.read_rss(), gov.max_bytes, DEFAULT_MEMORY_THROTTLE_MAX_WAIT_SECS);
+                    self.quit();
+                } else {
+                    warn!("rss {} still over max memory {} after {}s, stop throttling list tasks",
+                        gov.read_rss(), gov.max_bytes, DEFAULT_MEMORY_THROTTLE_MAX_WAIT_SECS);
+                }
+                return;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(DEFAULT_MEMORY_SAMPLE_INTERVAL_MS)).await;
+        }
+    }
+
+    pub async fn wait_to_start(&self) {
+        self.task_rendez.wait().await;
+    }
+
+    #[allow(dead_code)]
+    pub fn get_warmup_secs(&self) -> u64 {
+        self.task_rendez.get_warmup_secs()
+    }
+
+    pub fn inc_task_next_stream_timeout(&self) {
+        self.task_next_stream_timeout_count.fetch_add(1, Ordering::SeqCst);
     }
 
     pub fn read_task_next_stream_timeout(&self) -> usize {
         self.task_next_stream_timeout_count.load(Ordering::SeqCst)
     }
 
-    pub fn inc_s3_client_timeout(&self) {
-        self.s3_client_timeout_count.fetch_add(1, Ordering::SeqCst);
+    pub fn inc_s3_client_timeout(&self) {
+        self.s3_client_timeout_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn read_s3_client_timeout(&self) -> usize {
+        self.s3_client_timeout_count.load(Ordering::SeqCst)
+    }
+
+    pub fn inc_s3_client_generic_error(&self) {
+        self.s3_client_generic_error_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn read_s3_client_generic_error(&self) -> usize {
+        self.s3_client_generic_error_count.load(Ordering::SeqCst)
+    }
+
+    pub fn inc_s3_redirect(&self) {
+        self.s3_redirect_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn read_s3_redirect(&self) -> usize {
+        self.s3_redirect_count.load(Ordering::SeqCst)
+    }
+
+    pub fn inc_list_retry(&self) {
+        self.list_retry_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn read_list_retry(&self) -> usize {
+        self.list_retry_count.load(Ordering::SeqCst)
+    }
+
+    pub fn inc_list_request(&self) {
+        self.list_request_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read_list_request(&self) -> usize {
+        self.list_request_count.load(Ordering::Relaxed)
+    }
+
+    pub fn errors(&self) -> &ErrorCounters {
+        &self.errors
+    }
+
+    pub fn add_listed(&self, objects: usize, bytes: u64) {
+        self.listed_objects_count.fetch_add(objects, Ordering::Relaxed);
+        self.listed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    // true once objects listed reach --max-objects, first one to see it logs and quits as ctrl-c does,
+    // pages already in flight of other tasks are still taken so it may overshoot
+    pub fn max_objects_reached(&self) -> bool {
+        let Some(max_objects) = self.max_objects else {
+            return false;
+        };
+        if self.read_listed_objects() < max_objects {
+            return false;
+        }
+        if !self.quit.swap(true, Ordering::SeqCst) {
+            info!("max objects {} reached with {} objects listed, stop listing", utils::fmt_count(max_objects),
+                utils::fmt_count(self.read_listed_objects()));
+        }
+        true
+    }
+
+    pub fn read_listed_objects(&self) -> usize {
+        self.listed_objects_count.load(Ordering::Relaxed)
+    }
+
+    pub fn read_listed_bytes(&self) -> u64 {
+        self.listed_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn inc_active_list_tasks(&self) {
+        self.active_list_tasks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec_active_list_tasks(&self) {
+        self.active_list_tasks.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn read_active_list_tasks(&self) -> usize {
+        self.active_list_tasks.load(Ordering::Relaxed)
     }
 
-    pub fn read_s3_client_timeout(&self) -> usize {
-        self.s3_client_timeout_count.load(Ordering::SeqCst)
+    pub fn add_partitions(&self, count: usize) {
+        self.partitions_total.fetch_add(count, Ordering::Relaxed);
     }
 
-    pub fn inc_s3_client_generic_error(&self) {
-        self.s3_client_generic_error_count.fetch_add(1, Ordering::SeqCst);
+    pub fn inc_partitions_done(&self) {
+        self.partitions_done.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn read_s3_client_generic_error(&self) -> usize {
-        self.s3_client_generic_error_count.load(Ordering::SeqCst)
+    // partitions done and in total
+    pub fn read_partitions(&self) -> (usize, usize) {
+        (self.partitions_done.load(Ordering::Relaxed), self.partitions_total.load(Ordering::Relaxed))
+    }
+
+    pub fn set_diff_count(&self, count: usize) {
+        let _ = self.diff_count.set(count);
+    }
+
+    pub fn read_diff_count(&self) -> Option<usize> {
+        self.diff_count.get().copied()
+    }
+
+    pub fn add_verify_result(&self, result: VerifyResult) {
+        self.verify_results.lock().unwrap().push(result);
+    }
+
+    pub fn read_verify_results(&self) -> Vec<VerifyResult> {
+        self.verify_results.lock().unwrap().clone()
+    }
+
+    // GetBucketLocation is sent once per bucket and endpoint in a run
+    async fn bucket_region(&self, client: &aws_sdk_s3::Client, endpoint: Option<&str>, bucket: &str) -> Option<String> {
+        let cache_key = (endpoint.map(|e| e.to_string()), bucket.to_string());
+        if let Some(region) = self.bucket_regions.lock().unwrap().get(&cache_key) {
+            return region.clone();
+        }
+        let region = match tasks_s3::get_bucket_region(client, bucket).await {
+            Ok(region) => Some(region),
+            Err(e) => {
+                info!("  - unable to get location of bucket {}, {}, keep region {}", bucket, e,
+                    client.config().region().map(|r| r.to_string()).as_deref().unwrap_or("<none>"));
+                None
+            },
+        };
+        self.bucket_regions.lock().unwrap().insert(cache_key, region.clone());
+        region
+    }
+
+    pub fn set_list_failed(&self) {
+        self.list_failed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_list_failed(&self) -> bool {
+        self.list_failed.load(Ordering::SeqCst)
     }
 
     pub fn get_tracker(&self) -> Arc<HttpStatusCodeTracker> {
@@ -589,15 +2358,22 @@ impl GlobalState {
                 panic!("unkown dir {} for list task", dir);
             }
         }
+        if !self.all_list_tasks_is_running() {
+            self.list_ended.lock().unwrap().get_or_insert(std::time::Instant::now());
+        }
+    }
+
+    pub fn read_list_ended(&self) -> Option<std::time::Instant> {
+        *self.list_ended.lock().unwrap()
     }
 
     pub fn list_task_is_running(&self, dir: u8) -> bool {
         match dir {
             S3_TASK_CONTEXT_DIR_LEFT | S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE => {
-                return self.is_running(TASK_STATUS_BIT_LEFT);
+                self.is_running(TASK_STATUS_BIT_LEFT)
             },
-            S3_TASK_CONTEXT_DIR_RIGHT => {
-                return self.is_running(TASK_STATUS_BIT_RIGHT);
+            S3_TASK_CONTEXT_DIR_RIGHT | S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE => {
+                self.is_running(TASK_STATUS_BIT_RIGHT)
             },
             _ => {
                 panic!("unkown dir {} for list task", dir);
@@ -630,32 +2406,173 @@ impl GlobalState {
     }
 }
 
+// customer provided key of SSE-C objects, sent with requests on objects that need it, never logged
+#[derive(Clone)]
+pub(crate) struct SseCustomerKey {
+    // base64 of 256 bits key
+    key: String,
+    // base64 of md5 digest of key
+    key_md5: String,
+}
+
+impl SseCustomerKey {
+    // error does not echo the key
+    pub fn parse(key: &str) -> Result<Self, String> {
+        use base64::Engine;
+        let engine = base64::engine::general_purpose::STANDARD;
+        let raw = engine.decode(key.trim()).map_err(|_| "key should be base64 encoded".to_string())?;
+        if raw.len() != 32 {
+            return Err(format!("key should be 256 bits for AES256, got {} bits", raw.len() * 8));
+        }
+        Ok(Self {
+            key: engine.encode(&raw),
+            key_md5: engine.encode(md5::compute(&raw).0),
+        })
+    }
+
+    pub fn key_md5(&self) -> &str {
+        &self.key_md5
+    }
+}
+
+impl std::fmt::Debug for SseCustomerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SseCustomerKey(md5 {})", self.key_md5)
+    }
+}
+
+// options to build s3 client for one side of list
+#[derive(Debug, Clone)]
+pub(crate) struct AssumeRoleOptions {
+    pub role_arn: String,
+    pub external_id: Option<String>,
+    pub session_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct S3ClientOptions {
+    pub region: Option<String>,
+    // named profile of aws shared config to resolve credentials and region from
+    pub profile: Option<String>,
+    // credentials of profile or env are used to assume this role
+    pub assume_role: Option<AssumeRoleOptions>,
+    pub endpoint: Option<String>,
+    pub force_path_style: bool,
+    pub follow_redirects: bool,
+    pub retry: Option<RetryPolicy>,
+    // appended to user agent after product token of this tool
+    pub user_agent: Option<String>,
+    // seconds, SDK connect timeout is kept as S3_CLIENT_CONNECT_TIMEOUT and no read timeout if None
+    pub connect_timeout: Option<u64>,
+    pub read_timeout: Option<u64>,
+    pub max_connections: Option<usize>,
+    // only set with custom endpoint
+    pub tls: Option<TlsVerify>,
+    // credentials and region are never looked up from instance metadata service
+    pub no_imds: bool,
+}
+
 #[derive(Clone)]
 pub(crate) struct S3TaskContext {
     pub s3_bucket_name: String,
-    pub s3_client: aws_sdk_s3::Client,
+    // shared by all tasks of the bucket, replaced once a PermanentRedirect is followed to bucket region
+    s3_client: Arc<std::sync::RwLock<aws_sdk_s3::Client>>,
     pub data_map_channel: UnboundedSender<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>,
     pub dir: u8,
     pub g_state: GlobalState,
+    // local clock minus S3 server clock in secs, None if probe failed
+    pub clock_skew: Option<i64>,
+    // list all object versions and delete markers
+    pub versions: bool,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    pub retry: Option<RetryPolicy>,
+    // send x-amz-request-payer on list calls of requester pays bucket
+    pub request_payer: Option<aws_sdk_s3::types::RequestPayer>,
+    pub exclude: Option<Arc<ExcludePrefixes>>,
+    pub verify_sample: Option<Arc<VerifySampler>>,
+    pub tags: Option<Arc<ObjectFetcher>>,
+    // checksum of this algorithm fetched for listed objects
+    pub checksum: Option<(ChecksumAlgorithm, Arc<ObjectFetcher>)>,
+    // metadata of listed objects fetched with HeadObject
+    pub enrich: Option<Arc<ObjectFetcher>>,
+    // request owner of objects in list calls
+    pub with_owner: bool,
+    // SSE-C key sent with HEAD of verify pass
+    pub sse_customer_key: Option<SseCustomerKey>,
+    // max wait of one s3 response, raised over DEFAULT_S3_CLIENT_TIMEOUT by a longer read timeout
+    pub response_timeout: std::time::Duration,
+    // start prefixes without ks hints are split at these first characters of keys after them
+    pub hash_seed: Option<Vec<char>>,
+    // same credentials as s3 client, for sts call of identity check
+    credentials_provider: Option<SharedCredentialsProvider>,
+    // split points of "--one-per-prefix" shared with the other side of diff
+    pub shared_splits: Option<Arc<SharedSplits>>,
+    // PermanentRedirect of list calls switches client to bucket region instead of failing
+    follow_redirects: bool,
+}
+
+// bucket region of probe against region of client
+#[derive(Debug, PartialEq)]
+enum BucketRegion {
+    Same,
+    // no region configured, the one of SDK defaults is replaced silently
+    Detected(String),
+    // bucket lives in another region than configured
+    Redirect(String),
+}
+
+fn check_bucket_region(probed: Option<String>, client_region: Option<&str>, configured: bool) -> BucketRegion {
+    match probed {
+        Some(region) if Some(region.as_str()) != client_region => match configured {
+            true => BucketRegion::Redirect(region),
+            false => BucketRegion::Detected(region),
+        },
+        _ => BucketRegion::Same,
+    }
+}
+
+// same client config in another region
+fn client_in_region(s3_client: &aws_sdk_s3::Client, region: String) -> aws_sdk_s3::Client {
+    let s3_config = s3_client.config().to_builder()
+        .region(aws_sdk_s3::config::Region::new(region))
+        .build();
+    aws_sdk_s3::Client::from_conf(s3_config)
 }
 
 impl S3TaskContext {
-    pub fn new(bucket: &str, region: Option<&str>, endpoint: Option<&str>, force_path_style: bool,
+    pub fn new(bucket: &str, opts: &S3ClientOptions,
             data_map_channel: UnboundedSender<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>,
             dir: u8, g_state: GlobalState) -> Self {
 
-        // create and config s3 client
-        let loader = aws_config::from_env()
-            .retry_config(
-                aws_config::retry::RetryConfig::standard()
-                    .with_max_attempts(S3_CLIENT_MAX_ATTEMPTS)
-                    .with_initial_backoff(std::time::Duration::from_secs(S3_CLIENT_INITIAL_BACKOFF))
-            )
-            .timeout_config(
-                aws_config::timeout::TimeoutConfigBuilder::new()
-                    .connect_timeout(std::time::Duration::from_secs(S3_CLIENT_CONNECT_TIMEOUT))
-                    .build()
-            );
+        // create and config s3 client, list calls are retried by us instead of SDK with retry policy
+        let retry_config = if opts.retry.is_some() {
+            aws_config::retry::RetryConfig::disabled()
+        } else {
+            aws_config::retry::RetryConfig::standard()
+                .with_max_attempts(S3_CLIENT_MAX_ATTEMPTS)
+                .with_initial_backoff(std::time::Duration::from_secs(S3_CLIENT_INITIAL_BACKOFF))
+        };
+        let mut timeout_config = aws_config::timeout::TimeoutConfigBuilder::new()
+            .connect_timeout(std::time::Duration::from_secs(opts.connect_timeout.unwrap_or(S3_CLIENT_CONNECT_TIMEOUT)));
+        if let Some(read_timeout) = opts.read_timeout {
+            timeout_config = timeout_config.read_timeout(std::time::Duration::from_secs(read_timeout));
+        }
+        let mut loader = aws_config::from_env()
+            .retry_config(retry_config)
+            .timeout_config(timeout_config.build());
+        if let Some(profile) = &opts.profile {
+            loader = loader.profile_name(profile);
+        }
+
+        // credentials chain of SDK defaults is rebuilt here, to tell which source of it is used
+        let region = tokio::task::block_in_place(move || {
+            tokio::runtime::Handle::current()
+                .block_on(async move {
+                    credentials::default_region(opts.region.as_deref(), opts.profile.as_deref(), opts.no_imds).await
+                })
+            });
+        let (credentials_chain, credentials_source) = credentials::credentials_chain(region.clone(), opts.profile.as_deref(), opts.no_imds);
+        loader = loader.region(region).credentials_provider(credentials_chain);
 
         let config = tokio::task::block_in_place(move || {
             tokio::runtime::Handle::current()
@@ -667,36 +2584,358 @@ impl S3TaskContext {
         // Build S3 config with optional region and endpoint
         let mut s3_config_builder = aws_sdk_s3::config::Builder::from(&config);
 
-        if let Some(region_str) = region {
+        if let Some(region_str) = &opts.region {
             s3_config_builder = s3_config_builder.region(
                 aws_sdk_s3::config::Region::new(region_str.to_owned())
             );
         }
 
-        if let Some(endpoint_url) = endpoint {
+        // temporary credentials of assumed role are cached by client and refreshed before they expire
+        let credentials_provider = if let Some(role) = &opts.assume_role {
+            let provider = SharedCredentialsProvider::new(assume_role_provider(role, opts.region.as_deref(), &config));
+            s3_config_builder = s3_config_builder.credentials_provider(provider.clone());
+            Some(provider)
+        } else {
+            config.credentials_provider()
+        };
+        if let Some(provider) = credentials_provider.clone().filter(|_| opts.assume_role.is_none()) {
+            // resolved once up front only to log its source, client resolves and caches its own
+            let res = tokio::task::block_in_place(move || {
+                tokio::runtime::Handle::current()
+                    .block_on(async move {
+                        use aws_sdk_s3::config::ProvideCredentials;
+                        provider.provide_credentials().await.map(|_| ())
+                    })
+                });
+            if let Err(e) = res {
+                warn!("no credentials resolved for bucket {}: {}", bucket, aws_sdk_s3::error::DisplayErrorContext(&e));
+            }
+        }
+        let imds = if opts.no_imds { ", instance metadata disabled" } else { "" };
+        match *credentials_source.lock().unwrap() {
+            Some(source) if opts.assume_role.is_some() => info!("  - credentials of bucket {} from assumed role, source credentials from {}{}",
+                bucket, source, imds),
+            Some(source) => info!("  - credentials of bucket {} from {}{}", bucket, source, imds),
+            None => info!("  - credentials of bucket {} not resolved{}", bucket, imds),
+        }
+
+        if let Some(endpoint_url) = &opts.endpoint {
             s3_config_builder = s3_config_builder.endpoint_url(endpoint_url.to_owned());
         }
 
         // Set force-path-style addressing if requested
-        if force_path_style {
+        if opts.force_path_style {
             s3_config_builder = s3_config_builder.force_path_style(true);
         }
 
+        s3_config_builder = s3_config_builder.interceptor(g_state.throttle_interceptor());
+        if let Some(http_client) = tuned_http_client(opts) {
+            s3_config_builder = s3_config_builder.http_client(http_client);
+        }
+        let suffix = match &opts.user_agent {
+            Some(custom) => format!("{} {}", USER_AGENT_PRODUCT, custom),
+            None => USER_AGENT_PRODUCT.to_string(),
+        };
+        s3_config_builder = s3_config_builder.interceptor(UserAgentInterceptor { suffix });
+
         let s3_client = aws_sdk_s3::Client::from_conf(s3_config_builder.build());
 
         Self {
             s3_bucket_name: bucket.to_string(),
-            s3_client: s3_client,
-            data_map_channel: data_map_channel,
-            dir: dir,
-            g_state: g_state,
+            s3_client: Arc::new(std::sync::RwLock::new(s3_client)),
+            data_map_channel,
+            dir,
+            g_state,
+            clock_skew: None,
+            versions: false,
+            rate_limiter: None,
+            retry: opts.retry,
+            request_payer: None,
+            exclude: None,
+            verify_sample: None,
+            tags: None,
+            checksum: None,
+            enrich: None,
+            with_owner: false,
+            sse_customer_key: None,
+            response_timeout: std::time::Duration::from_secs(DEFAULT_S3_CLIENT_TIMEOUT.max(opts.read_timeout.unwrap_or(0))),
+            hash_seed: None,
+            credentials_provider,
+            shared_splits: None,
+            follow_redirects: opts.follow_redirects,
+        }
+    }
+
+    // context of a client built by caller, no credentials lookup or probe of bucket, for tests against a local endpoint
+    #[cfg(test)]
+    pub fn with_client(bucket: &str, s3_client: aws_sdk_s3::Client,
+            data_map_channel: UnboundedSender<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>,
+            dir: u8, g_state: GlobalState) -> Self {
+        Self {
+            s3_bucket_name: bucket.to_string(),
+            s3_client: Arc::new(std::sync::RwLock::new(s3_client)),
+            data_map_channel,
+            dir,
+            g_state,
+            clock_skew: None,
+            versions: false,
+            rate_limiter: None,
+            retry: None,
+            request_payer: None,
+            exclude: None,
+            verify_sample: None,
+            tags: None,
+            checksum: None,
+            enrich: None,
+            with_owner: false,
+            sse_customer_key: None,
+            response_timeout: std::time::Duration::from_secs(DEFAULT_S3_CLIENT_TIMEOUT),
+            hash_seed: None,
+            credentials_provider: None,
+            shared_splits: None,
+            follow_redirects: false,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_follow_redirects(mut self, follow_redirects: bool) -> Self {
+        self.follow_redirects = follow_redirects;
+        self
+    }
+
+    pub fn s3_client(&self) -> aws_sdk_s3::Client {
+        self.s3_client.read().unwrap().clone()
+    }
+
+    pub fn follow_redirects(&self) -> bool {
+        self.follow_redirects
+    }
+
+    // switch client of all tasks to bucket region of PermanentRedirect, false if it is there already,
+    // a redirect from bucket region itself can't be followed
+    pub fn follow_redirect(&self, bucket_region: &str) -> bool {
+        let mut s3_client = self.s3_client.write().unwrap();
+        let client_region = s3_client.config().region().map(|r| r.to_string());
+        if client_region.as_deref() == Some(bucket_region) {
+            return false;
+        }
+        info!("bucket {} is located in region {}, follow redirect from region {}", self.s3_bucket_name, bucket_region,
+            client_region.as_deref().unwrap_or("<none>"));
+        *s3_client = client_in_region(&s3_client, bucket_region.to_string());
+        true
+    }
+
+    pub fn with_versions(mut self, versions: bool) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    pub fn with_request_payer(mut self, request_payer: bool) -> Self {
+        self.request_payer = request_payer.then_some(aws_sdk_s3::types::RequestPayer::Requester);
+        self
+    }
+
+    pub fn with_rate_limiter(mut self, limiter: Option<Arc<RateLimiter>>) -> Self {
+        self.rate_limiter = limiter;
+        self
+    }
+
+    // each side counts its own pruned prefixes
+    pub fn with_exclude_prefixes(mut self, prefixes: &[String]) -> Self {
+        self.exclude = (!prefixes.is_empty()).then(|| Arc::new(ExcludePrefixes::new(prefixes.to_vec())));
+        self
+    }
+
+    // percent of listed objects to check with HEAD after listing
+    pub fn with_verify_sample(mut self, percent: Option<f64>) -> Self {
+        self.verify_sample = percent.map(|p| Arc::new(VerifySampler::new(p)));
+        self
+    }
+
+    // fetch tags of listed objects with this many requests in flight
+    pub fn with_tags(mut self, concurrency: Option<usize>) -> Self {
+        self.tags = concurrency.map(|c| Arc::new(ObjectFetcher::new(c)));
+        self
+    }
+
+    // fetch checksum of listed objects with GetObjectAttributes, with this many requests in flight
+    pub fn with_checksum(mut self, algorithm: Option<ChecksumAlgorithm>, concurrency: usize) -> Self {
+        self.checksum = algorithm.map(|a| (a, Arc::new(ObjectFetcher::new(concurrency))));
+        self
+    }
+
+    // fetch metadata of listed objects with HeadObject, with this many requests in flight
+    pub fn with_enrich(mut self, concurrency: Option<usize>) -> Self {
+        self.enrich = concurrency.map(|c| Arc::new(ObjectFetcher::new(c)));
+        self
+    }
+
+    pub fn with_owner(mut self, with_owner: bool) -> Self {
+        self.with_owner = with_owner;
+        self
+    }
+
+    pub fn with_sse_customer_key(mut self, key: Option<SseCustomerKey>) -> Self {
+        self.sse_customer_key = key;
+        self
+    }
+
+    pub fn with_shared_splits(mut self, splits: Option<Arc<SharedSplits>>) -> Self {
+        self.shared_splits = splits;
+        self
+    }
+
+    // sorted characters of charset, see utils::parse_hash_charset
+    pub fn with_hash_seed(mut self, charset: Option<&str>) -> Self {
+        self.hash_seed = charset.map(|c| c.chars().collect());
+        self
+    }
+
+    // (algorithm, key, key md5) headers of SSE-C, none without key
+    pub fn sse_customer_headers(&self) -> (Option<String>, Option<String>, Option<String>) {
+        match &self.sse_customer_key {
+            Some(k) => (Some("AES256".to_string()), Some(k.key.clone()), Some(k.key_md5.clone())),
+            None => (None, None, None),
+        }
+    }
+
+    pub fn is_excluded(&self, key: &str) -> bool {
+        self.exclude.as_ref().is_some_and(|e| e.matching(key).is_some())
+    }
+
+    pub fn skip_excluded(&self, key: &str) -> Option<String> {
+        self.exclude.as_ref().and_then(|e| e.skip_from(key))
+    }
+
+    // hold on until next list request is allowed by rate limiter,
+    // called right before each list request, so it is counted here as well
+    pub async fn wait_for_rate_limit(&self) {
+        if let Some(limiter) = &self.rate_limiter {
+            limiter.acquire().await;
         }
+        self.g_state.inc_list_request();
     }
 
     pub fn get_tracker(&self) -> Arc<HttpStatusCodeTracker> {
         self.g_state.get_tracker()
     }
 
+    // probe bucket once before it is listed, client of all tasks is switched to region where bucket located,
+    // and local clock skew is taken from S3 response headers
+    pub async fn probe(mut self, opts: &S3ClientOptions) -> Self {
+        let bucket = self.s3_bucket_name.clone();
+        let mut s3_client = self.s3_client();
+
+        // without --region, list calls go to region where bucket located instead of one of SDK defaults
+        if opts.region.is_none() {
+            let bucket_region = self.g_state.bucket_region(&s3_client, opts.endpoint.as_deref(), &bucket).await;
+            let configured_region = s3_client.config().region().map(|r| r.to_string());
+            if let Some(bucket_region) = bucket_region.filter(|r| Some(r) != configured_region.as_ref()) {
+                info!("  - bucket {} is located in region {}, detected by GetBucketLocation", bucket, bucket_region);
+                s3_client = client_in_region(&s3_client, bucket_region);
+            }
+        }
+
+        let probe = tasks_s3::probe_bucket(&s3_client, &bucket).await;
+
+        // SDK keeps retrying requests rejected by tls handshake, fail here instead of listing nothing forever
+        if probe.tls_rejected {
+            error!("TLS certificate of endpoint {} is not trusted, {}", opts.endpoint.as_deref().unwrap_or("<aws default>"),
+                crate::error::HINT_TLS_CERTIFICATE);
+            std::process::exit(1);
+        }
+
+        // bucket lives in another region than configured, S3 will respond PermanentRedirect
+        let client_region = s3_client.config().region().map(|r| r.to_string());
+        match check_bucket_region(probe.region, client_region.as_deref(), opts.region.is_some()) {
+            BucketRegion::Same => {},
+            BucketRegion::Detected(bucket_region) => {
+                info!("  - bucket {} is located in region {}, detected by HeadBucket", bucket, bucket_region);
+                s3_client = client_in_region(&s3_client, bucket_region);
+            },
+            BucketRegion::Redirect(bucket_region) => {
+                self.g_state.inc_s3_redirect();
+                let configured_region = client_region.unwrap_or_default();
+                if opts.follow_redirects {
+                    info!("  - bucket {} is located in region {}, follow redirect from region {}", bucket, bucket_region, configured_region);
+                    s3_client = client_in_region(&s3_client, bucket_region);
+                } else {
+                    warn!("!!! bucket {} is located in region {} but configured region is {} !!!", bucket, bucket_region, configured_region);
+                    warn!("!!! please rerun with --region {} or --follow-redirects !!!", bucket_region);
+                }
+            },
+        }
+        self.s3_client = Arc::new(std::sync::RwLock::new(s3_client));
+        self.clock_skew = probe.clock_skew;
+        self
+    }
+
+    // warn if local clock skew exceeds threshold, since last_modified
+    // based filter compares object timestamps against local time
+    pub fn check_clock_skew(&self, has_filter: bool) {
+        match self.clock_skew {
+            Some(skew) if skew.abs() > DEFAULT_CLOCK_SKEW_WARN_SECS => {
+                warn!("!!! local clock is {} by {}s against S3 server time of bucket {} !!!",
+                    if skew > 0 { "ahead" } else { "behind" }, skew.abs(), self.s3_bucket_name);
+                if has_filter {
+                    warn!("!!! date based filter results may be inaccurate, please fix local clock !!!");
+                }
+            },
+            Some(skew) => {
+                info!("  - clock skew {}s against S3 server time of bucket {}", skew, self.s3_bucket_name);
+            },
+            None => {
+                warn!("unable to detect clock skew against S3 server time of bucket {}", self.s3_bucket_name);
+            },
+        }
+    }
+
+    // who lists this bucket where, account is resolved with one sts GetCallerIdentity call if check is set
+    pub fn identity(&self, endpoint: Option<&str>, check: bool) -> BucketIdentity {
+        let s3_client = self.s3_client();
+        let config = s3_client.config();
+        let region = config.region().map(|r| r.to_string());
+        let account = match self.credentials_provider.clone().filter(|_| check) {
+            Some(credentials) => {
+                let mut sts_config = aws_sdk_sts::Config::builder()
+                    .behavior_version(aws_config::BehaviorVersion::latest())
+                    .credentials_provider(credentials)
+                    .region(config.region().cloned());
+                if let Some(endpoint) = endpoint {
+                    sts_config = sts_config.endpoint_url(endpoint);
+                }
+                if let Some(http_client) = config.http_client() {
+                    sts_config = sts_config.http_client(http_client);
+                }
+                let sts_client = aws_sdk_sts::Client::from_conf(sts_config.build());
+                let timeout = self.response_timeout;
+                let res = tokio::task::block_in_place(move || {
+                    tokio::runtime::Handle::current()
+                        .block_on(async move {
+                            match tokio::time::timeout(timeout, sts_client.get_caller_identity().send()).await {
+                                Ok(res) => res.map(|output| output.account).map_err(|e| aws_sdk_s3::error::DisplayErrorContext(e).to_string()),
+                                Err(_) => Err(format!("no response in {}s", timeout.as_secs())),
+                            }
+                        })
+                    });
+                match res {
+                    Ok(account) => account,
+                    Err(e) => {
+                        warn!("unable to resolve account of bucket {} with sts GetCallerIdentity: {}", self.s3_bucket_name, e);
+                        None
+                    },
+                }
+            },
+            None => None,
+        };
+        BucketIdentity {
+            bucket: self.s3_bucket_name.clone(),
+            account,
+            region,
+            endpoint: endpoint.map(|e| e.to_string()),
+        }
+    }
+
     pub fn start(&self) {
         self.g_state.list_task_start(self.dir);
     }
@@ -714,33 +2953,198 @@ impl S3TaskContext {
     }
 }
 
+// options of data map task from cli
+#[derive(Debug, Clone)]
+pub(crate) struct DataMapOptions {
+    pub filters: Vec<String>,
+    pub filter_combine: FilterCombine,
+    pub mode: RunMode,
+    pub ks_format: KsFormat,
+    pub ks_compression: KsCompression,
+    pub output_format: OutputFormat,
+    pub columns: Vec<utils::OutputColumn>,
+    // columns kept in parquet, arrow ipc and ndjson output with --output-columns, those of csv are in columns
+    pub output_columns: Option<Vec<utils::OutputColumn>>,
+    pub parquet: utils::ParquetOptions,
+    pub arrow_compression: ArrowCompression,
+    // csv and ndjson output written as gzip or zstd stream
+    pub output_compression: KsCompression,
+    pub diff_changed_after: Option<u64>,
+    // seconds of last modified difference taken as equal with "--diff-on mtime"
+    pub diff_lmtime_tolerance: u64,
+    pub diff_on: Vec<DiffOn>,
+    pub diff_direction: DiffDirection,
+    pub diff_split: bool,
+    // statuses of differences written to output, all if empty
+    pub diff_status: Vec<DiffStatus>,
+    // keys output ends each key by NUL instead of newline
+    pub null_terminated: bool,
+    pub versions: bool,
+    // delimiter list, output has type column of prefix or object
+    pub shallow: bool,
+    // only count objects, no output or ks file is written
+    pub count_only: bool,
+    // log goes to file, so final summary is logged as well besides stdout
+    pub log_to_file: bool,
+    // print size histogram with summary, and write it as csv file if given
+    pub size_histogram: bool,
+    pub size_histogram_file: Option<String>,
+    // print object count and bytes by first segments of prefix up to depth, and write it as csv file if given
+    pub prefix_report: Option<usize>,
+    pub prefix_report_file: Option<String>,
+    // plain text report of run written at end
+    pub report_file: Option<String>,
+    // last modified in csv and ndjson output
+    pub time_format: TimeFormat,
+    // how keys of both sides are matched in diff mode and compared by filter
+    pub key_compare: KeyCompare,
+    // drop zero-byte objects and keys ending in "/" as they come from list tasks, before counted or written
+    pub skip_empty: bool,
+    pub skip_dir_markers: bool,
+    // write parquet files partitioned by first segments of key prefix, output file is the base directory
+    pub partition_depth: Option<usize>,
+    // write output in key order, objects to export are sorted as a whole before written
+    pub sorted: bool,
+    // roll output over to a new shard file once current one reaches either
+    pub max_output_file_rows: Option<usize>,
+    pub max_output_file_bytes: Option<usize>,
+    // write ks hints splitting listed objects into this many partitions
+    pub emit_ks_hints: Option<String>,
+    pub ks_hints_splits: usize,
+    // only export objects sharing content with others, grouped by etag or size and etag
+    pub find_duplicates: Option<DuplicateOn>,
+    // shell script of aws cli commands syncing source to target from diff result
+    pub diff_script: Option<script::DiffScriptOptions>,
+    // s3 batch operations manifest of objects to copy from source, from diff result
+    pub diff_batch_manifest: Option<script::BatchManifestOptions>,
+    // tags of objects are fetched and exported
+    pub with_tags: bool,
+    // owner of objects is listed and exported
+    pub with_owner: bool,
+    // checksum of objects is fetched and exported
+    pub with_checksum: bool,
+    // metadata of objects of HeadObject is fetched and exported
+    pub enrich_all: bool,
+    // how output is produced, in footer of parquet output
+    pub run_metadata: provenance::RunMetadata,
+    // sidecar json of run metadata and output files
+    pub emit_manifest: Option<String>,
+    // ks file of a previous run, only objects of prefixes new or changed in count since are exported
+    pub since_ks: Option<Arc<SinceKs>>,
+}
+
+impl DataMapOptions {
+    pub fn optional_columns(&self) -> utils::OptionalColumns {
+        utils::OptionalColumns {
+            diff_mode: self.mode == RunMode::BiDir,
+            versions: self.versions,
+            shallow: self.shallow,
+            duplicates: self.find_duplicates.is_some(),
+            tags: self.with_tags,
+            owner: self.with_owner,
+            checksum: self.with_checksum,
+            enrich: self.enrich_all,
+        }
+    }
+}
+
+// bucket of one side with account and region it is listed in, logged before listing starts
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BucketIdentity {
+    pub bucket: String,
+    // none if not checked or sts call failed
+    pub account: Option<String>,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+}
+
+impl std::fmt::Display for BucketIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "bucket {}, account {}, region {}, endpoint {}", self.bucket, self.account.as_deref().unwrap_or("<unknown>"),
+            self.region.as_deref().unwrap_or("<none>"), self.endpoint.as_deref().unwrap_or("<aws default>"))
+    }
+}
+
+// build assume role provider on top of loaded credentials and try it once,
+// exit early with role arn if it fails instead of failing every list call
+fn assume_role_provider(role: &AssumeRoleOptions, region: Option<&str>, config: &aws_config::SdkConfig) -> aws_config::sts::AssumeRoleProvider {
+    use aws_sdk_s3::config::ProvideCredentials;
+
+    let mut builder = aws_config::sts::AssumeRoleProvider::builder(&role.role_arn)
+        .configure(config)
+        .session_name(role.session_name.as_deref().unwrap_or(DEFAULT_ROLE_SESSION_NAME));
+    if let Some(external_id) = &role.external_id {
+        builder = builder.external_id(external_id);
+    }
+    if let Some(region) = region {
+        builder = builder.region(aws_sdk_s3::config::Region::new(region.to_owned()));
+    }
+
+    let res = tokio::task::block_in_place(move || {
+        tokio::runtime::Handle::current()
+            .block_on(async move {
+                let provider = builder.build().await;
+                provider.provide_credentials().await.map(|_| provider)
+            })
+        });
+    match res {
+        Ok(provider) => provider,
+        Err(e) => {
+            error!("{}", AssumeRoleError::new(&role.role_arn, aws_sdk_s3::error::DisplayErrorContext(&e).to_string()));
+            std::process::exit(1);
+        }
+    }
+}
+
+// objects dropped by --skip-empty and --skip-dir-markers, of both sides in diff mode
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SkippedObjects {
+    pub empty: usize,
+    pub dir_markers: usize,
+}
+
 pub(crate) struct DataMapContext {
     pub data_map_channel: UnboundedReceiver<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>,
     pub g_state: GlobalState,
+    pub opts: DataMapOptions,
+    pub skipped: SkippedObjects,
 }
 
 impl DataMapContext {
-    pub fn new(data_map_channel: UnboundedReceiver<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>, g_state: GlobalState, opt_filter: Option<String>, opt_mode: RunMode) -> Self {
+    pub fn new(data_map_channel: UnboundedReceiver<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>, g_state: GlobalState,
+            opts: DataMapOptions) -> Self {
+        let _ = DIFF_SETTINGS.set(DiffSettings {
+            on: opts.diff_on.clone(),
+            direction: opts.diff_direction,
+            changed_after: opts.diff_changed_after,
+            lmtime_tolerance: opts.diff_lmtime_tolerance,
+        });
+        let _ = TIME_FORMAT.set(opts.time_format);
+
         // init filter if we got from cli
-        if let Some(expr) = opt_filter {
-            let mut engine = Engine::new();
-            engine.set_fail_on_invalid_map_property(true)
-                .set_max_variables(2) // only SOURCE and TARGET
-                .set_max_map_size(2); // only size and last_modified
-
-            let ast = engine.compile_expression(expr);
-            if ast.is_err() {
-                error!("unable to compile object filter expression: {ast:?}");
+        if !opts.filters.is_empty() {
+            let filter = match filter::Filter::compile(&opts.filters, opts.filter_combine, opts.key_compare) {
+                Ok(filter) => filter,
+                Err(e) => {
+                    error!("unable to compile object filter expression: {e}");
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = filter.check_expr(opts.mode.clone()) {
+                error!("invalid object filter expression: {e}");
                 std::process::exit(1);
             }
-            let filter = ObjectFilter {
-                engine: engine,
-                ast: ast.unwrap(),
-            };
-            let res = filter.check_expr(opt_mode);
-            if res != true {
+            if filter.uses_tags && !opts.with_tags {
+                error!("filter on \"tag:\" requires --with-tags");
+                std::process::exit(1);
+            }
+            if filter.uses_owner && !opts.with_owner {
+                error!("filter on owner_id or owner_display_name requires --with-owner");
                 std::process::exit(1);
             }
+            if filter.uses_anchored {
+                info!("  - key startswith / endswith of filter are anchored byte comparisons, no regex");
+            }
             let res = OBJECT_FILTER.set(filter);
             if res.is_err() {
                 error!("unable to set value to global object filter: {res:?}");
@@ -749,8 +3153,10 @@ impl DataMapContext {
         }
 
         Self {
-            data_map_channel: data_map_channel,
-            g_state: g_state,
+            data_map_channel,
+            g_state,
+            opts,
+            skipped: SkippedObjects::default(),
         }
     }
 
@@ -782,15 +3188,55 @@ impl DataMapContext {
 
 pub(crate) struct MonContext {
     pub g_state: GlobalState,
+    // serve prometheus metrics on this address while running
+    pub metrics_addr: Option<std::net::SocketAddr>,
+    // append json snapshot of counters to file at each interval
+    pub stats_json: Option<(Arc<StatsJsonWriter>, u64)>,
+    // unix socket taking status, pause, resume and quit commands while running
+    pub control_socket: Option<String>,
+    // print tuning report at end of run
+    pub tuning_report: Option<TuningReportOptions>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TuningReportOptions {
+    // --concurrency of run, busy list tasks are compared to it
+    pub concurrency: usize,
+    // log goes to file, so report is logged as well besides stdout
+    pub log_to_file: bool,
 }
 
 impl MonContext {
     pub fn new(g_state: GlobalState) -> Self {
         Self {
-            g_state: g_state,
+            g_state,
+            metrics_addr: None,
+            stats_json: None,
+            control_socket: None,
+            tuning_report: None,
         }
     }
 
+    pub fn with_tuning_report(mut self, report: Option<TuningReportOptions>) -> Self {
+        self.tuning_report = report;
+        self
+    }
+
+    pub fn with_metrics_addr(mut self, addr: Option<std::net::SocketAddr>) -> Self {
+        self.metrics_addr = addr;
+        self
+    }
+
+    pub fn with_stats_json(mut self, filename: Option<&str>, interval_secs: u64) -> Self {
+        self.stats_json = filename.map(|f| (Arc::new(StatsJsonWriter::new(f)), interval_secs));
+        self
+    }
+
+    pub fn with_control_socket(mut self, path: Option<&str>) -> Self {
+        self.control_socket = path.map(|p| p.to_string());
+        self
+    }
+
     pub fn get_tracker(&self) -> Arc<HttpStatusCodeTracker> {
         self.g_state.get_tracker()
     }
@@ -830,4 +3276,312 @@ mod tests {
         let key = ObjectKey::encode(&prefix, &name);
         assert_eq!(key.as_str(), "a/b/c/test.jpg");
     }
+
+    #[test]
+    fn test_time_format() {
+        assert_eq!(TimeFormat::Rfc3339.render_text(1704067200), "2024-01-01T00:00:00Z");
+        assert_eq!(TimeFormat::EpochMillis.render_text(1704067200), "1704067200000");
+        assert_eq!(TimeFormat::EpochSecs.render_text(1704067200), "1704067200");
+        assert_eq!(TimeFormat::Rfc3339.render_json(0), serde_json::json!("1970-01-01T00:00:00Z"));
+        assert_eq!(TimeFormat::EpochMillis.render_json(1), serde_json::json!(1000));
+    }
+
+    #[test]
+    fn test_key_compare() {
+        assert!(matches!(KeyCompare::Exact.normalize("Data/A.txt"), std::borrow::Cow::Borrowed("Data/A.txt")));
+        assert!(matches!(KeyCompare::CaseInsensitive.normalize("data/a.txt"), std::borrow::Cow::Borrowed(_)));
+        assert_eq!(KeyCompare::CaseInsensitive.normalize("Data/ÄB.txt"), "data/äb.txt");
+        // "e" followed by combining acute accent composed into one char
+        assert_eq!(KeyCompare::Nfc.normalize("caf\u{65}\u{301}.txt"), "caf\u{e9}.txt");
+        assert!(matches!(KeyCompare::Nfc.normalize("caf\u{e9}.txt"), std::borrow::Cow::Borrowed(_)));
+        assert_eq!(KeyCompare::Nfc.normalize("Data/A.txt"), "Data/A.txt");
+
+        let mut props = ObjectProps::default();
+        let (prefix, name) = ("data".to_string(), "a.txt".to_string());
+        assert_eq!(props.listed_key(&prefix, &name).as_str(), "data/a.txt");
+        props.set_listed_key("Data/A.txt");
+        assert_eq!(props.listed_key(&prefix, &name).as_str(), "Data/A.txt");
+    }
+
+    // open object of one side, same etag on both sides
+    fn props(dir: u8, size: u64, last_modified: u64) -> ObjectProps {
+        ObjectProps { flags: dir, status: OBJECT_PROPS_STATUS_OPEN, size, last_modified, etag_md5: [1; 16], ..Default::default() }
+    }
+
+    #[test]
+    fn test_object_props_diff_attrs() {
+        let (prefix, name) = ("data".to_string(), "obj.bin".to_string());
+
+        // default compares size and etag, mtime is ignored
+        let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 1000);
+        assert_eq!(left.r#match(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 100, 2000), &prefix, &name), MatchResult::Equal);
+        assert!(left.diff_attrs().is_empty());
+
+        let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 1000);
+        let mut right = props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 200, 1000);
+        right.etag_md5 = [2; 16];
+        assert_eq!(left.r#match(&right, &prefix, &name), MatchResult::Astrisk);
+        assert_eq!(left.diff_attrs(), vec!["size", "etag"]);
+        assert_eq!(left.final_status_check(&prefix, &name), MatchResult::Astrisk);
+    }
+
+    #[test]
+    fn test_object_props_changed_after() {
+        let (prefix, name) = ("data".to_string(), "obj.bin".to_string());
+        let settings = DiffSettings { changed_after: Some(1000), ..Default::default() };
+
+        // changed on both sides before watermark is ignored, either side after it is kept
+        let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 900);
+        assert_eq!(left.match_with(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 200, 1000), &prefix, &name, &settings), MatchResult::Ignore);
+        assert_eq!(left.final_status_check_with(&prefix, &name, &settings), MatchResult::Ignore);
+        let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 900);
+        assert_eq!(left.match_with(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 200, 1001), &prefix, &name, &settings), MatchResult::Astrisk);
+        assert_eq!(left.final_status_check_with(&prefix, &name, &settings), MatchResult::Astrisk);
+
+        // one side only object by its own last modified
+        let left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 1000);
+        assert_eq!(left.final_status_check_with(&prefix, &name, &settings), MatchResult::Ignore);
+        let right = props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 100, 1001);
+        assert_eq!(right.final_status_check_with(&prefix, &name, &settings), MatchResult::Minus);
+        assert_eq!(right.final_status_check_with(&prefix, &name, &DiffSettings::default()), MatchResult::Minus);
+        assert_eq!(left.final_status_check_with(&prefix, &name, &DiffSettings::default()), MatchResult::Plus);
+    }
+
+    #[test]
+    fn test_object_props_lmtime_tolerance() {
+        let (prefix, name) = ("data".to_string(), "obj.bin".to_string());
+        let settings = DiffSettings { on: vec![DiffOn::Size, DiffOn::Etag, DiffOn::Mtime], lmtime_tolerance: 60, ..Default::default() };
+
+        // just inside tolerance on either side is equal, just outside is changed on mtime only
+        for (left_mtime, right_mtime, res) in [(1000, 1060, MatchResult::Equal), (1060, 1000, MatchResult::Equal),
+                (1000, 1061, MatchResult::Astrisk), (1061, 1000, MatchResult::Astrisk)] {
+            let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, left_mtime);
+            assert_eq!(left.match_with(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 100, right_mtime), &prefix, &name, &settings), res);
+            let attrs: Vec<&str> = if res == MatchResult::Equal { vec![] } else { vec!["mtime"] };
+            assert_eq!(left.diff_attrs(), attrs);
+        }
+
+        // no tolerance, any difference counts
+        let settings = DiffSettings { lmtime_tolerance: 0, ..settings };
+        let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 1000);
+        assert_eq!(left.match_with(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 100, 1001), &prefix, &name, &settings), MatchResult::Astrisk);
+    }
+
+    #[test]
+    fn test_object_props_diff_direction() {
+        let (prefix, name) = ("data".to_string(), "obj.bin".to_string());
+        let left_only = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 0);
+        let right_only = props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 100, 0);
+
+        let settings = DiffSettings { direction: DiffDirection::LeftToRight, ..Default::default() };
+        assert_eq!(left_only.final_status_check_with(&prefix, &name, &settings), MatchResult::Plus);
+        assert_eq!(right_only.final_status_check_with(&prefix, &name, &settings), MatchResult::Ignore);
+
+        let settings = DiffSettings { direction: DiffDirection::RightToLeft, ..Default::default() };
+        assert_eq!(left_only.final_status_check_with(&prefix, &name, &settings), MatchResult::Ignore);
+        assert_eq!(right_only.final_status_check_with(&prefix, &name, &settings), MatchResult::Minus);
+
+        // object on both sides with another size is changed only in both directions, equal otherwise
+        for (direction, res) in [(DiffDirection::Both, MatchResult::Astrisk), (DiffDirection::LeftToRight, MatchResult::Equal),
+                (DiffDirection::RightToLeft, MatchResult::Equal)] {
+            let settings = DiffSettings { direction, ..Default::default() };
+            let mut left = props(S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, 100, 0);
+            assert_eq!(left.match_with(&props(S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE, 200, 0), &prefix, &name, &settings), res);
+            assert_eq!(left.final_status_check_with(&prefix, &name, &settings), res);
+        }
+    }
+
+    #[test]
+    fn test_object_props_storage_class() {
+        assert_eq!(storage_class_code(None), 0);
+        assert_eq!(storage_class_name(storage_class_code(Some("GLACIER"))), "GLACIER");
+        assert_eq!(storage_class_name(storage_class_code(Some("FUTURE_CLASS"))), "UNKNOWN");
+
+        let props = ObjectProps { storage_class: storage_class_code(Some("DEEP_ARCHIVE")), ..Default::default() };
+        let map = rhai::serde::to_dynamic(&props).unwrap().cast::<rhai::Map>();
+        assert_eq!(map["storage_class"].clone().into_string().unwrap(), "DEEP_ARCHIVE");
+        assert_eq!(ObjectProps::default().storage_class(), "STANDARD");
+    }
+
+    #[test]
+    fn test_parse_etag() {
+        let md5 = [0xc8, 0xaf, 0x37, 0xb3, 0x71, 0xec, 0x44, 0x2a, 0xd4, 0x15, 0xfe, 0xeb, 0x87, 0xd8, 0x32, 0x46];
+        assert_eq!(parse_etag(Some("\"c8af37b371ec442ad415feeb87d83246\"")), (md5, 0));
+        assert_eq!(parse_etag(Some("\"c8af37b371ec442ad415feeb87d83246-186\"")), (md5, 186));
+        for malformed in ["\"c8af37b371ec442ad415feeb87d83246-abc\"", "\"c8af37b371ec442ad415feeb87d83246x\"", "\"c8af37b371ec442ad415feeb87d8324é\""] {
+            assert_eq!(parse_etag(Some(malformed)), ([0; 16], 0), "{}", malformed);
+        }
+        assert!(is_etag("c8af37b371ec442ad415feeb87d83246") && is_etag("c8af37b371ec442ad415feeb87d83246-2"));
+        assert!(!is_etag("c8af37b371ec442ad415feeb87d83246-") && !is_etag("c8af37b371ec442ad415feeb87d83246x"));
+        assert!(!is_etag("C8AF37B371EC442AD415FEEB87D83246") && !is_etag(""));
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter() {
+        // 10ms apart, the first one is not delayed
+        let limiter = Arc::new(RateLimiter::new("test", 100));
+        let started = tokio::time::Instant::now();
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..11 {
+            let limiter = limiter.clone();
+            set.spawn(async move { limiter.acquire().await });
+        }
+        while set.join_next().await.is_some() {}
+        assert!(started.elapsed() >= std::time::Duration::from_millis(100));
+        assert_eq!(limiter.read_acquired(), 11);
+    }
+
+    #[test]
+    fn test_adaptive_concurrency() {
+        let adaptive = AdaptiveConcurrency::new(8);
+        assert_eq!(adaptive.limit(), 2);
+        // one more after as many successes as the limit
+        adaptive.on_success();
+        assert_eq!(adaptive.limit(), 2);
+        adaptive.on_success();
+        assert_eq!(adaptive.limit(), 3);
+        for _ in 0..100 {
+            adaptive.on_success();
+        }
+        assert_eq!(adaptive.limit(), 8);
+
+        // halved once within cooldown
+        adaptive.on_throttle();
+        adaptive.on_throttle();
+        assert_eq!(adaptive.limit(), 4);
+        assert_eq!(adaptive.read_throttled(), 2);
+
+        let adaptive = AdaptiveConcurrency::new(1);
+        adaptive.on_throttle();
+        assert_eq!(adaptive.limit(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_channel_watermarks() {
+        let quit = Arc::new(AtomicBool::new(false));
+        // no cap, queued under high watermark goes on
+        let g_state = GlobalState::new(quit.clone(), 1, 0, "test".to_string());
+        g_state.set_queued_batches(DEFAULT_CHANNEL_CAPPED_HIGH_WATERMARK + 1);
+        g_state.wait_for_channel().await;
+        assert_eq!(g_state.read_backpressure(), 0);
+
+        // rss is over a cap of one byte, capped watermarks apply
+        if utils::current_rss().is_none() {
+            return;
+        }
+        let g_state = GlobalState::new(quit, 1, 0, "test".to_string()).with_memory_governor(MemoryGovernor::new(1, false));
+        g_state.set_queued_batches(DEFAULT_CHANNEL_CAPPED_HIGH_WATERMARK + 1);
+        let drained = g_state.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+            drained.set_queued_batches(DEFAULT_CHANNEL_CAPPED_LOW_WATERMARK);
+        });
+        g_state.wait_for_channel().await;
+        assert_eq!(g_state.read_queued_batches(), DEFAULT_CHANNEL_CAPPED_LOW_WATERMARK);
+        assert_eq!(g_state.read_backpressure(), 1);
+        assert_eq!(g_state.memory_governor().read_channel_capped(), 1);
+    }
+
+    #[test]
+    fn test_throttle_tracker() {
+        let tracker = ThrottleTracker::new();
+        assert!(tracker.streak().is_none());
+        tracker.on_throttle();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        tracker.on_throttle();
+        let (streak, throttled) = tracker.streak().unwrap();
+        assert!(streak >= std::time::Duration::from_millis(20));
+        assert_eq!(throttled, 2);
+
+        // success breaks streak, total is kept
+        tracker.on_success();
+        assert!(tracker.streak().is_none());
+        tracker.on_throttle();
+        assert_eq!(tracker.streak().unwrap().1, 1);
+        assert_eq!(tracker.read_throttled(), 3);
+    }
+
+    #[test]
+    fn test_exclude_prefixes() {
+        let exclude = ExcludePrefixes::new(vec!["tmp/".to_string(), "logs/".to_string(), "tmp/a/".to_string()]);
+        assert_eq!(exclude.prefixes(), ["logs/", "tmp/"]);
+        assert_eq!(dedup_nested_prefixes(vec!["b/".to_string(), "".to_string(), "a/".to_string()]), [""]);
+        assert_eq!(dedup_nested_prefixes(["foo", "foobar"].iter().map(|p| dir_prefix(p)).collect()), ["foo/", "foobar/"]);
+        assert_eq!(dir_prefix(""), "");
+        assert_eq!(dir_prefix("a/"), "a/");
+        assert_eq!(exclude.matching("tmp/a/b"), Some("tmp/"));
+        assert_eq!(exclude.matching("logs/"), Some("logs/"));
+        assert_eq!(exclude.matching("tmp"), None);
+        assert_eq!(exclude.matching("tmq"), None);
+        assert_eq!(exclude.matching("a"), None);
+
+        let skip = exclude.skip_from("tmp/x").unwrap();
+        assert!(skip.as_str() > "tmp/zzzz" && skip.as_str() < "tmp0");
+        // no jump back from the end of subtree
+        assert_eq!(exclude.skip_from(&skip), None);
+        assert_eq!(exclude.skip_from("data/1"), None);
+        assert_eq!(exclude.read_pruned(), 1);
+    }
+
+    #[test]
+    fn test_sse_customer_key() {
+        // key of 32 zero bytes
+        let key = SseCustomerKey::parse("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap();
+        assert_eq!(key.key_md5(), "cLyPS3KoaSFGi/joRB3OUQ==");
+        assert!(!format!("{:?}", key).contains("AAAA"));
+        assert!(SseCustomerKey::parse("AAAA").is_err());
+        assert!(SseCustomerKey::parse("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        use crate::error::*;
+
+        let policy = RetryPolicy { max_retries: 5, base_delay_ms: 100 };
+        for retry in 0..4 {
+            let delay = policy.backoff(retry).as_millis() as u64;
+            assert!((50 << retry..=100 << retry).contains(&delay));
+        }
+        assert!(policy.backoff(30).as_millis() as u64 <= DEFAULT_RETRY_MAX_DELAY_MS);
+
+        let err = |errno: u8, code: u16| FlatRuntimeError::new(errno, "".to_string(), "".to_string()).with_http_status_code(code);
+        assert!(err(ERROR_S3_NEXT_STREAM_TIMEOUT, 0).is_retryable());
+        assert!(err(ERROR_S3_CLIENT_GENERIC, 0).is_retryable());
+        assert!(err(ERROR_S3_UNKOWN, 503).is_retryable());
+        assert!(err(ERROR_S3_UNKOWN, 429).is_retryable());
+        assert!(!err(ERROR_S3_UNKOWN, 404).is_retryable());
+        assert!(!err(ERROR_S3_ACCESS_DENIED, 403).is_retryable());
+        assert!(!err(ERROR_S3_NO_BUCKET, 404).is_retryable());
+    }
+
+    #[test]
+    fn test_max_objects_reached() {
+        let quit = Arc::new(AtomicBool::new(false));
+        let g_state = GlobalState::new(quit.clone(), 1, 0, "test".to_string()).with_max_objects(Some(10));
+        g_state.add_listed(9, 100);
+        assert!(!g_state.max_objects_reached());
+        assert!(!g_state.is_quit());
+
+        // page of another task in flight overshoots, limit is still taken as reached
+        g_state.add_listed(5, 50);
+        assert!(g_state.max_objects_reached());
+        assert!(quit.load(Ordering::SeqCst));
+        assert!(g_state.max_objects_reached());
+
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "test".to_string()).with_max_objects(None);
+        g_state.add_listed(usize::MAX / 2, 0);
+        assert!(!g_state.max_objects_reached());
+        assert!(!g_state.is_quit());
+    }
+
+    #[test]
+    fn test_check_bucket_region() {
+        let probed = |r: &str| Some(r.to_string());
+        assert_eq!(check_bucket_region(probed("us-east-1"), Some("us-east-1"), true), BucketRegion::Same);
+        assert_eq!(check_bucket_region(None, Some("us-east-1"), true), BucketRegion::Same);
+        // only a region given by user is a redirect, a default of SDK is replaced without warning
+        assert_eq!(check_bucket_region(probed("eu-west-1"), Some("us-east-1"), true), BucketRegion::Redirect("eu-west-1".to_string()));
+        assert_eq!(check_bucket_region(probed("eu-west-1"), Some("us-east-1"), false), BucketRegion::Detected("eu-west-1".to_string()));
+        assert_eq!(check_bucket_region(probed("eu-west-1"), None, false), BucketRegion::Detected("eu-west-1".to_string()));
+    }
 }
@@ -0,0 +1,157 @@
+use std::io::{BufRead, BufReader};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PartitionStatus {
+    Complete,
+    Incomplete,
+}
+
+// one listed key space partition (start_after, end), end is None for the last one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionCoverage {
+    pub bucket: String,
+    pub prefix: String,
+    pub start: String,
+    pub end: Option<String>,
+    pub objects: usize,
+    pub status: PartitionStatus,
+}
+
+// write coverage records as json lines, sorted by start prefix and partition start
+pub async fn write_coverage(filename: &str, records: &mut [PartitionCoverage]) -> tokio::io::Result<()> {
+
+    records.sort_by(|a, b| (&a.prefix, &a.start).cmp(&(&b.prefix, &b.start)));
+
+    let inner = tokio::fs::File::create(filename).await?;
+    let mut writer = tokio::io::BufWriter::new(inner);
+    for record in records.iter() {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await?;
+    }
+    writer.flush().await?;
+
+    Ok(())
+}
+
+pub fn load_coverage(filename: &str) -> std::io::Result<Vec<PartitionCoverage>> {
+
+    let file = std::fs::File::open(filename)?;
+    let mut records = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+
+    Ok(records)
+}
+
+// check partitions tile the whole key space under prefix,
+// return list of problems found, empty if coverage is complete
+pub fn verify_coverage(records: &[PartitionCoverage], prefix: &str) -> Vec<String> {
+
+    let mut problems = Vec::new();
+    if records.is_empty() {
+        problems.push("no partition found".to_string());
+        return problems;
+    }
+
+    for r in records {
+        if r.prefix != prefix {
+            problems.push(format!("partition \"{}\" listed with prefix \"{}\", expect \"{}\"", r.start, r.prefix, prefix));
+        }
+        if r.bucket != records[0].bucket {
+            problems.push(format!("partition \"{}\" from bucket {}, expect {}", r.start, r.bucket, records[0].bucket));
+        }
+        if r.status != PartitionStatus::Complete {
+            problems.push(format!("partition \"{}\" - {:?} is incomplete", r.start, r.end));
+        }
+    }
+
+    let mut sorted: Vec<&PartitionCoverage> = records.iter().collect();
+    sorted.sort_by(|a, b| a.start.cmp(&b.start));
+
+    if !sorted[0].start.is_empty() {
+        problems.push(format!("gap at head of key space, first partition starts after \"{}\"", sorted[0].start));
+    }
+
+    for pair in sorted.windows(2) {
+        let (prev, next) = (pair[0], pair[1]);
+        match &prev.end {
+            None => {
+                problems.push(format!("overlap between partition \"{}\" - end of key space and \"{}\"", prev.start, next.start));
+            },
+            Some(end) if *end < next.start => {
+                problems.push(format!("gap between \"{}\" and \"{}\"", end, next.start));
+            },
+            Some(end) if *end > next.start => {
+                problems.push(format!("overlap between \"{}\" and \"{}\"", next.start, end));
+            },
+            _ => {},
+        }
+    }
+
+    let last = sorted[sorted.len() - 1];
+    if let Some(end) = &last.end {
+        problems.push(format!("gap at tail of key space, last partition ends at \"{}\"", end));
+    }
+
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(start: &str, end: Option<&str>, status: PartitionStatus) -> PartitionCoverage {
+        PartitionCoverage {
+            bucket: "bucket".to_string(),
+            prefix: "".to_string(),
+            start: start.to_string(),
+            end: end.map(|s| s.to_string()),
+            objects: 1,
+            status,
+        }
+    }
+
+    #[test]
+    fn test_verify_coverage() {
+        use PartitionStatus::*;
+
+        let records = vec![
+            partition("b", Some("c"), Complete),
+            partition("", Some("b"), Complete),
+            partition("c", None, Complete),
+        ];
+        assert!(verify_coverage(&records, "").is_empty());
+        assert_eq!(verify_coverage(&records, "data/").len(), 3);
+
+        // gap in the middle and at tail
+        let records = vec![
+            partition("", Some("b"), Complete),
+            partition("c", Some("d"), Complete),
+        ];
+        assert_eq!(verify_coverage(&records, "").len(), 2);
+
+        // overlap and incomplete
+        let records = vec![
+            partition("", Some("c"), Complete),
+            partition("b", None, Incomplete),
+        ];
+        assert_eq!(verify_coverage(&records, "").len(), 2);
+
+        // gap at head
+        let records = vec![
+            partition("a", None, Complete),
+        ];
+        assert_eq!(verify_coverage(&records, "").len(), 1);
+
+        assert_eq!(verify_coverage(&[], "").len(), 1);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Mutex};
+use aws_config::ecs::EcsCredentialsProvider;
+use aws_config::environment::{EnvironmentVariableCredentialsProvider, EnvironmentVariableRegionProvider};
+use aws_config::imds::credentials::ImdsCredentialsProvider;
+use aws_config::imds::region::ImdsRegionProvider;
+use aws_config::meta::credentials::CredentialsProviderChain;
+use aws_config::meta::region::RegionProviderChain;
+use aws_config::profile::{ProfileFileCredentialsProvider, ProfileFileRegionProvider};
+use aws_config::provider_config::ProviderConfig;
+use aws_config::web_identity_token::WebIdentityTokenCredentialsProvider;
+use aws_credential_types::provider::{future, ProvideCredentials, SharedCredentialsProvider};
+use aws_sdk_s3::config::Region;
+
+// env var of SDK to turn off lookups of instance metadata service, same as --no-imds if "true"
+pub const ENV_EC2_METADATA_DISABLED: &str = "AWS_EC2_METADATA_DISABLED";
+
+pub fn imds_disabled_by_env() -> bool {
+    std::env::var(ENV_EC2_METADATA_DISABLED).is_ok_and(|v| v.eq_ignore_ascii_case("true"))
+}
+
+// name of credentials source that returned credentials last, shared by all sources of one chain
+pub type ResolvedSource = Arc<Mutex<Option<&'static str>>>;
+
+// one source of credentials chain, records its name once it returns credentials
+#[derive(Debug)]
+struct NamedSource {
+    name: &'static str,
+    provider: SharedCredentialsProvider,
+    resolved: ResolvedSource,
+}
+
+impl ProvideCredentials for NamedSource {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            let credentials = self.provider.provide_credentials().await?;
+            *self.resolved.lock().unwrap() = Some(self.name);
+            Ok(credentials)
+        })
+    }
+}
+
+// region to resolve credentials with, as default region chain of SDK but without instance metadata with no_imds
+pub async fn default_region(region: Option<&str>, profile: Option<&str>, no_imds: bool) -> Option<Region> {
+    let mut profile_region = ProfileFileRegionProvider::builder();
+    if let Some(profile) = profile {
+        profile_region = profile_region.profile_name(profile);
+    }
+    let mut chain = RegionProviderChain::first_try(region.map(|r| Region::new(r.to_owned())))
+        .or_else(EnvironmentVariableRegionProvider::new())
+        .or_else(profile_region.build());
+    if !no_imds {
+        chain = chain.or_else(ImdsRegionProvider::builder().build());
+    }
+    chain.region().await
+}
+
+// sources of default credentials chain of SDK in same order, instance metadata is the last one and left out with no_imds
+pub fn credentials_chain(region: Option<Region>, profile: Option<&str>, no_imds: bool) -> (CredentialsProviderChain, ResolvedSource) {
+    let conf = ProviderConfig::without_region().with_region(region);
+    let mut profile_provider = ProfileFileCredentialsProvider::builder().configure(&conf);
+    if let Some(profile) = profile {
+        profile_provider = profile_provider.profile_name(profile);
+    }
+
+    let mut sources: Vec<(&'static str, SharedCredentialsProvider)> = vec![
+        ("Environment", SharedCredentialsProvider::new(EnvironmentVariableCredentialsProvider::new())),
+        ("Profile", SharedCredentialsProvider::new(profile_provider.build())),
+        ("WebIdentityToken", SharedCredentialsProvider::new(WebIdentityTokenCredentialsProvider::builder().configure(&conf).build())),
+        ("EcsContainer", SharedCredentialsProvider::new(EcsCredentialsProvider::builder().configure(&conf).build())),
+    ];
+    if !no_imds {
+        sources.push(("Ec2InstanceMetadata", SharedCredentialsProvider::new(ImdsCredentialsProvider::builder().configure(&conf).build())));
+    }
+
+    let resolved = ResolvedSource::default();
+    let mut sources = sources.into_iter()
+        .map(|(name, provider)| (name, NamedSource { name, provider, resolved: resolved.clone() }));
+    let (name, first) = sources.next().unwrap();
+    let chain = sources.fold(CredentialsProviderChain::first_try(name, first), |chain, (name, source)| chain.or_else(name, source));
+    (chain, resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_credentials_chain() {
+        let region = default_region(Some("eu-west-1"), None, true).await;
+        assert_eq!(region.as_ref().map(|r| r.as_ref()), Some("eu-west-1"));
+
+        let (chain, resolved) = credentials_chain(region, None, true);
+        let names = format!("{:?}", chain);
+        assert!(names.contains("EcsContainer"));
+        assert!(!names.contains("Ec2InstanceMetadata"));
+        assert!(resolved.lock().unwrap().is_none());
+    }
+}
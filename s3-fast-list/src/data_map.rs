@@ -1,23 +1,40 @@
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet, VecDeque};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::{RwLock, Mutex};
-use log::{info, warn, debug};
+use log::{info, warn, error, debug};
 use crate::utils;
 use crate::core;
 use crate::core::MB;
-use crate::core::{DataMapContext, ObjectKey, ObjectPrefix, ObjectName, ObjectProps, MatchResult};
+use crate::checkpoint::{self, Checkpointer};
+use crate::provenance;
+use crate::report;
+use crate::coverage::PartitionCoverage;
+use crate::script::{self, BatchManifest, DiffScript};
+use crate::core::{DataMapContext, DataMapOptions, SkippedObjects, ObjectKey, ObjectPrefix, ObjectName, ObjectProps, MatchResult, KsFormat, KsCompression, OutputFormat, RunMode, DuplicateOn, DiffStatus, KeyCompare};
 
 const OUTPUT_DIR_FLAG_EQUAL: u8 = 0;
 const OUTPUT_DIR_FLAG_PLUS: u8 = 1;
 const OUTPUT_DIR_FLAG_MINUS: u8 = 2;
 const OUTPUT_DIR_FLAG_ASTRISK: u8 = 3;
 
+// file suffix of only left, only right and changed objects with "--diff-split"
+const DIFF_SPLIT_SUFFIX: [&str; 3] = ["only_left", "only_right", "changed"];
+
+const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+const KS_BINARY_MAGIC: &[u8; 4] = b"KSB\0";
+const KS_BINARY_VERSION: u16 = 1;
+// digests of key names per prefix written next to ks file, for "--since-ks" of next run
+pub(crate) const KS_DIGEST_SUFFIX: &str = ".digest";
+
 struct PrefixMap {
     inner: Arc<RwLock<HashMap<ObjectPrefix, ObjectMap>>>,
     count: Arc<AtomicUsize>,
+    // summary of objects evicted once resolved, added to the one of objects left at dump
+    resolved: Arc<Mutex<ObjectSummary>>,
 }
 
 impl PrefixMap {
@@ -25,6 +42,7 @@ impl PrefixMap {
         Self {
             inner: Arc::new(RwLock::new(HashMap::new())),
             count: Arc::new(AtomicUsize::new(0)),
+            resolved: Arc::new(Mutex::new(ObjectSummary::default())),
         }
     }
 
@@ -75,11 +93,11 @@ impl PrefixMap {
     }
 
     // sync file op in async fn
-    pub async fn dump_ks(&self, filename: &str) -> tokio::io::Result<()> {
+    pub async fn dump_ks(&self, filename: &str, format: KsFormat, compression: KsCompression) -> tokio::io::Result<()> {
 
         let buffer_size = 10 * MB;
         let inner = tokio::fs::File::create(filename).await?;
-        let mut writer = tokio::io::BufWriter::with_capacity(buffer_size, inner);
+        let mut writer = utils::CompressedWriter::new(tokio::io::BufWriter::with_capacity(buffer_size, inner), compression)?;
 
         // btree to sort key in lex
         let mut btree = std::collections::BTreeMap::<ObjectPrefix, usize>::new();
@@ -90,292 +108,1876 @@ impl PrefixMap {
         }
         drop(hash);
 
-        // output key, count in sorted order
-        for (prefix, count) in btree.iter() {
-            let line = format!("\"{}\",\"{}\"\n", prefix, count);
-            writer.write(line.as_bytes()).await?;
+        match format {
+            KsFormat::Text => {
+                // output key, count in sorted order
+                for (prefix, count) in btree.iter() {
+                    let line = format!("\"{}\",\"{}\"\n", prefix, count);
+                    writer.write_all(line.as_bytes()).await?;
+                }
+            },
+            KsFormat::Binary => {
+                /*
+                 * binary ks layout, all integers in little endian:
+                 *   header: magic "KSB\0", version u16, reserved u16, count of entries u64
+                 *   entry:  length of prefix u32, prefix in utf-8, count of objects u64
+                 */
+                writer.write_all(KS_BINARY_MAGIC).await?;
+                writer.write_all(&KS_BINARY_VERSION.to_le_bytes()).await?;
+                writer.write_all(&0u16.to_le_bytes()).await?;
+                writer.write_all(&(btree.len() as u64).to_le_bytes()).await?;
+                for (prefix, count) in btree.iter() {
+                    writer.write_all(&(prefix.len() as u32).to_le_bytes()).await?;
+                    writer.write_all(prefix.as_bytes()).await?;
+                    writer.write_all(&(*count as u64).to_le_bytes()).await?;
+                }
+            },
         }
 
-        writer.flush().await?;
+        writer.finish().await?;
         Ok(())
     }
 
+    // digest of key names of each prefix as "prefix","digest in hex", sorted as ks file
+    pub async fn dump_ks_digest(&self, filename: &str) -> std::io::Result<()> {
+        let hash = self.inner.read().await;
+        let btree: std::collections::BTreeMap<&str, u64> = hash.iter().map(|(prefix, obj_map)| (prefix.as_str(), obj_map.get_digest())).collect();
+        let mut wtr = csv::WriterBuilder::new().has_headers(false).quote_style(csv::QuoteStyle::Always)
+            .from_writer(std::io::BufWriter::new(std::fs::File::create(filename)?));
+        for (prefix, digest) in btree {
+            wtr.write_record([prefix, &format!("{:016x}", digest)])?;
+        }
+        wtr.flush()
+    }
+
+    // boundary prefixes splitting objects of map into partitions of about even count
+    pub async fn ks_hints(&self, splits: usize) -> Vec<String> {
+        let hash = self.inner.read().await;
+        let mut counts: Vec<(&str, usize)> = hash.iter().map(|(prefix, obj_map)| (prefix.as_str(), obj_map.get_count())).collect();
+        counts.sort();
+        split_ks(&counts, splits)
+    }
+
     // sync file op in async fn
-    pub async fn dump(&self, output_file: &str, include_equal: bool) -> tokio::io::Result<()> {
+    // return count of objects exported, summary of all but filtered out ones and output files written
+    // sync script and batch manifest are only written if diff is complete
+    async fn dump(&self, output_file: &str, include_equal: bool, opts: &DataMapOptions,
+            diff_complete: bool) -> tokio::io::Result<(usize, ObjectSummary, Vec<String>)> {
+
+        // with diff split, outputs are only left, only right and changed, otherwise all in one
+        let split = opts.diff_split && opts.mode == RunMode::BiDir;
+        let outputs = match opts.partition_depth {
+            Some(depth) => DumpOutput::Partitioned(PartitionedOutput::new(output_file, depth, opts)),
+            None if split => {
+                let mut outputs = Vec::new();
+                for filename in diff_split_filenames(output_file) {
+                    outputs.push(ShardedOutput::open(&filename, opts).await?);
+                }
+                DumpOutput::Files(outputs)
+            },
+            None => DumpOutput::Files(vec![ShardedOutput::open(output_file, opts).await?]),
+        };
+
+        // sync script is written from the same differences as output, failing it leaves output as is
+        let script_opts = opts.diff_script.as_ref().filter(|_| diff_complete);
+        let mut script = None;
+        if let Some(script_opts) = script_opts {
+            match DiffScript::create(script_opts).await {
+                Ok(s) => script = Some(s),
+                Err(e) => warn!("Data Map Task - unable to create sync script {}: {}", script_opts.filename, e),
+            }
+        }
+        let manifest_opts = opts.diff_batch_manifest.as_ref().filter(|_| diff_complete);
+        let mut manifest = None;
+        if let Some(manifest_opts) = manifest_opts {
+            match BatchManifest::create(manifest_opts).await {
+                Ok(m) => manifest = Some(m),
+                Err(e) => warn!("Data Map Task - unable to create batch manifest {}: {}", manifest_opts.filename, e),
+            }
+        }
 
-        let buffer_size = 100 * MB;
-        let f = tokio::fs::File::create(output_file).await?;
-        let writer = tokio::io::BufWriter::with_capacity(buffer_size, f);
-        let mut parquet = utils::AsyncParquetOutput::new(writer);
+        let mut sinks = DumpSinks {
+            outputs,
+            script,
+            script_opts,
+            manifest,
+            manifest_opts,
+            split,
+            include_equal,
+            diff_status: &opts.diff_status,
+            exported: 0,
+        };
 
         let hash = self.inner.read().await;
+        let mut summary = self.resolved.lock().await.clone().with_prefix_report(opts.prefix_report);
+        if opts.sorted {
+            // all maps are locked at once, so objects are sorted by reference with only their keys copied
+            let mut maps = Vec::with_capacity(hash.len());
+            for (prefix, obj_map) in hash.iter().filter(|(prefix, obj_map)| !is_since_unchanged(opts, prefix, obj_map)) {
+                maps.push((prefix, obj_map.inner.lock().await));
+            }
+            let mut entries = Vec::new();
+            for (prefix, map) in maps.iter() {
+                for (name, props) in map.iter() {
+                    if let Some(flag) = export_flag(prefix, name, props, include_equal, &mut summary) {
+                        entries.push((props.listed_key(prefix, name), prefix.as_str(), props, flag));
+                    }
+                }
+            }
+            entries.sort_unstable_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
 
-        for (prefix, obj_map) in hash.iter() {
+            // consecutive objects of one flag, and of one partition if partitioned, are written as one batch
+            let partition = |prefix: &str| opts.partition_depth.map(|depth| partition_dir(prefix, depth));
+            let mut batch: [Vec<(ObjectKey, ObjectProps)>; 4] = Default::default();
+            let mut run: Option<(&str, u8)> = None;
+            for (key, prefix, props, flag) in entries {
+                let same_run = run.is_some_and(|(run_prefix, run_flag)| run_flag == flag
+                    && batch[flag as usize].len() < SORTED_BATCH_SIZE
+                    && (run_prefix == prefix || partition(run_prefix) == partition(prefix)));
+                if !same_run {
+                    if let Some((run_prefix, _)) = run {
+                        sinks.write(run_prefix, std::mem::take(&mut batch)).await;
+                    }
+                    run = Some((prefix, flag));
+                }
+                batch[flag as usize].push((key, props.to_owned()));
+            }
+            if let Some((run_prefix, _)) = run {
+                sinks.write(run_prefix, batch).await;
+            }
+        } else {
+            // prefixes of one partition are next to each other in lex order, so each partition is written at once
+            let mut prefixes: Vec<_> = hash.iter().filter(|(prefix, obj_map)| !is_since_unchanged(opts, prefix, obj_map)).collect();
+            if opts.partition_depth.is_some() {
+                prefixes.sort_by(|a, b| a.0.cmp(b.0));
+            }
 
-            let mut plus = Vec::new();
-            let mut minus = Vec::new();
-            let mut astrisk = Vec::new();
-            let mut equal = Vec::new();
+            for (prefix, obj_map) in prefixes {
+                let mut batch: [Vec<(ObjectKey, ObjectProps)>; 4] = Default::default();
+                let map = obj_map.inner.lock().await;
+                for (name, props) in map.iter() {
+                    if let Some(flag) = export_flag(prefix, name, props, include_equal, &mut summary) {
+                        batch[flag as usize].push((props.listed_key(prefix, name), props.to_owned()));
+                    }
+                }
+                drop(map);
+                sinks.write(prefix, batch).await;
+            }
+        }
+        drop(hash);
+
+        let (exported, files) = sinks.close().await;
+        Ok((exported, summary, files))
+    }
+
+    /*
+     * export only objects sharing etag (and size) with others. first pass counts objects of each etag,
+     * second one collects members of etags seen more than once, so memory beyond the map is one entry
+     * per distinct etag plus copies of duplicated objects. groups are numbered from the one of most
+     * bytes reclaimable, members of a group are in key order
+     */
+    async fn dump_duplicates(&self, output_file: &str, on: DuplicateOn, opts: &DataMapOptions) -> tokio::io::Result<(DuplicateSummary, ObjectSummary)> {
 
+        let mut summary = ObjectSummary::default();
+        let mut counts: HashMap<DupKey, u64> = HashMap::new();
+        let hash = self.inner.read().await;
+        for (prefix, obj_map) in hash.iter() {
             let map = obj_map.inner.lock().await;
             for (name, props) in map.iter() {
-                match props.final_status_check() {
-                    MatchResult::Plus => {
-                        let key = ObjectKey::encode(prefix, &name);
-                        plus.push((key, props.to_owned()));
-                    },
-                    MatchResult::Minus => {
-                        let key = ObjectKey::encode(prefix, &name);
-                        minus.push((key, props.to_owned()));
-                    },
-                    MatchResult::Astrisk => {
-                        let key = ObjectKey::encode(prefix, &name);
-                        astrisk.push((key, props.to_owned()));
-                    },
-                    MatchResult::Equal => {
-                        if include_equal {
-                            let key = ObjectKey::encode(prefix, &name);
-                            equal.push((key, props.to_owned()));
-                        }
-                    },
-                    MatchResult::Ignore => {
-                        // object is filtered out
-                    },
-                    result @ _ => {
-                        panic!("{:?} should not occurs here", result);
-                    }
+                if props.final_status_check(prefix, name) != MatchResult::Plus {
+                    continue;
+                }
+                summary.add(OUTPUT_DIR_FLAG_PLUS, props.size());
+                if let Some(key) = dup_key(props, on) {
+                    *counts.entry(key).or_default() += 1;
                 }
             }
-            drop(map);
+        }
+        counts.retain(|_, count| *count > 1);
 
-            let _ = parquet.write(plus, OUTPUT_DIR_FLAG_PLUS).await;
-            let _ = parquet.write(minus, OUTPUT_DIR_FLAG_MINUS).await;
-            let _ = parquet.write(astrisk, OUTPUT_DIR_FLAG_ASTRISK).await;
-            if include_equal {
-                let _ = parquet.write(equal, OUTPUT_DIR_FLAG_EQUAL).await;
+        let mut groups: HashMap<DupKey, Vec<(ObjectKey, ObjectProps)>> = HashMap::new();
+        for (prefix, obj_map) in hash.iter() {
+            let map = obj_map.inner.lock().await;
+            for (name, props) in map.iter() {
+                let Some(key) = dup_key(props, on).filter(|key| counts.contains_key(key)) else {
+                    continue;
+                };
+                if props.final_status_check(prefix, name) == MatchResult::Plus {
+                    groups.entry(key).or_default().push((ObjectKey::encode(prefix, name), props.to_owned()));
+                }
             }
         }
         drop(hash);
+        drop(counts);
 
-        let _ = parquet.close().await;
+        let mut groups: Vec<Vec<(ObjectKey, ObjectProps)>> = groups.into_values().collect();
+        for members in groups.iter_mut() {
+            members.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+        }
+        // members of one group may differ in size if only etag is compared, first one stands for the group
+        let reclaimable = |members: &Vec<(ObjectKey, ObjectProps)>| members[0].1.size() * (members.len() as u64 - 1);
+        groups.sort_by(|a, b| reclaimable(b).cmp(&reclaimable(a)).then_with(|| a[0].0.as_str().cmp(b[0].0.as_str())));
 
-        Ok(())
-    }
-}
+        let mut output = open_output(output_file, opts, false).await?;
+        let mut dup_summary = DuplicateSummary::default();
+        for (i, members) in groups.into_iter().enumerate() {
+            dup_summary.groups += 1;
+            dup_summary.objects += members.len();
+            dup_summary.reclaimable += reclaimable(&members);
+            let dups = vec![(i as u64 + 1, members.len() as u64); members.len()];
+            output.write_duplicates(members, OUTPUT_DIR_FLAG_PLUS, &dups).await?;
+        }
+        close_output(output, opts).await?;
 
-impl std::fmt::Display for PrefixMap {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let (prefix, object) = self.get_stats();
-        write!(f, "prefix count {}, object count {}", prefix, object)
+        Ok((dup_summary, summary))
     }
-}
 
-#[derive(Clone)]
-pub struct ObjectMap {
-    inner: Arc<Mutex<HashMap<ObjectName, ObjectProps>>>,
-    count: Arc<AtomicUsize>,
-}
+    // count objects and bytes by final status instead of dumping them, filtered out ones are skipped
+    async fn count(&self, prefix_report: Option<usize>) -> ObjectSummary {
 
-impl ObjectMap {
-    pub fn new() -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(HashMap::new())),
-            count: Arc::new(AtomicUsize::new(0)),
+        let mut count = self.resolved.lock().await.clone().with_prefix_report(prefix_report);
+        let hash = self.inner.read().await;
+        for (prefix, obj_map) in hash.iter() {
+            let map = obj_map.inner.lock().await;
+            for (name, props) in map.iter() {
+                let flag = match props.final_status_check(prefix, name) {
+                    MatchResult::Equal => OUTPUT_DIR_FLAG_EQUAL,
+                    MatchResult::Plus => OUTPUT_DIR_FLAG_PLUS,
+                    MatchResult::Minus => OUTPUT_DIR_FLAG_MINUS,
+                    MatchResult::Astrisk => OUTPUT_DIR_FLAG_ASTRISK,
+                    MatchResult::Ignore => continue,
+                    result => panic!("{:?} should not occurs here", result),
+                };
+                count.add(flag, props.size());
+                count.add_to_prefix_report(prefix, props.size());
+            }
         }
-    }
 
-    pub fn get_count(&self) -> usize {
-        self.count.load(Ordering::SeqCst)
+        count
     }
 
-    pub fn inc_count(&self) {
-        let _ = self.count.fetch_add(1, Ordering::SeqCst);
-    }
+    // remove objects of complete partitions from map and return the ones to export,
+    // object count of prefix is kept for ks file
+    async fn take_completed(&self, done: &[PartitionCoverage]) -> Vec<(ObjectKey, ObjectProps)> {
 
-    #[allow(dead_code)]
-    pub fn dec_count(&self) {
-        let _ = self.count.fetch_sub(1, Ordering::SeqCst);
-    }
+        let mut v = Vec::new();
+        let hash = self.inner.read().await;
+        for (prefix, obj_map) in hash.iter() {
+            let mut map = obj_map.inner.lock().await;
+            map.retain(|name, props| {
+                let key = ObjectKey::encode(prefix, name);
+                if !checkpoint::in_partitions(done, key.split_version().0) {
+                    return true;
+                }
+                if props.final_status_check(prefix, name) == MatchResult::Plus {
+                    v.push((key, props.to_owned()));
+                }
+                false
+            });
+        }
 
-    /*
-     * return:
-     *   - (vec for mtime check, vec for astrisk)
-     */
-    pub async fn bulk_insert(&self, prefix: &str, items: Vec<(ObjectName, ObjectProps)>) -> Vec<ObjectKey> {
+        v
+    }
 
-        let mut astrisk = Vec::new();
-        let _prefix = prefix.to_string();
-        let mut hash = self.inner.lock().await;
+    // drop objects of diff seen on both sides and not to export from map, only their summary and name
+    // are kept, return count of objects evicted
+    async fn evict_resolved(&self) -> usize {
 
-        for item in items {
-            let name = item.0;
-            let props = item.1;
-            if let Some(exist_props) = hash.get_mut(&name) {
-                // compare and trigger next flow
-                match exist_props.r#match(&props) {
-                    MatchResult::Astrisk => {
-                        // collect the confirmed astrisk one
-                        let key = ObjectKey::encode(&_prefix, &name);
-                        astrisk.push(key);
-                    },
-                    MatchResult::Dup => {
-                        // if this is a duplicated props, just ignore it
-                    },
-                    MatchResult::Equal => {
-                    },
-                    MatchResult::Ignore => {
-                    },
-                    res @ _ => {
-                        // we only care about the one need to do mtime check
-                        // ignore other match result
-                        // do nothing for time based equal here
-                        debug!("match results {} {} {:?}", prefix, name.as_str(), res);
-                    },
+        let mut evicted = 0;
+        let mut resolved = self.resolved.lock().await;
+        let hash = self.inner.read().await;
+        for (prefix, obj_map) in hash.iter() {
+            let mut map = obj_map.inner.lock().await;
+            let mut names = obj_map.evicted.lock().await;
+            map.retain(|name, props| {
+                if !props.is_resolved() {
+                    return true;
                 }
-            } else {
-                hash.insert(name, props);
-                self.inc_count();
-            }
+                export_flag(prefix, name, props, false, &mut resolved);
+                names.insert(name.as_str().into());
+                evicted += 1;
+                false
+            });
+            map.shrink_to_fit();
         }
 
-        astrisk
+        evicted
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct KeySpacePair {
-    index: usize,
-    start: String,
-    end: Option<String>,
+    async fn since_delta(&self, since: &SinceKs) -> SinceDelta {
+        let hash = self.inner.read().await;
+        let current: HashMap<&str, (usize, u64)> = hash.iter()
+            .map(|(prefix, obj_map)| (prefix.as_str(), (obj_map.get_count(), obj_map.get_digest()))).collect();
+        since.delta(&current)
+    }
+
+    // objects still held in map
+    async fn remaining(&self) -> usize {
+        let hash = self.inner.read().await;
+        let mut total = 0;
+        for obj_map in hash.values() {
+            total += obj_map.inner.lock().await.len();
+        }
+        total
+    }
 }
 
-impl KeySpacePair {
-    pub fn new(index: usize, start: String, end: String) -> Self {
+// upper bounds of size histogram buckets, from 1 KiB up to 1 TiB by power of 4, last bucket is over 1 TiB
+const SIZE_HISTOGRAM_BUCKETS: usize = 17;
 
-        let e = if end == "" { None } else { Some(end) };
+fn size_histogram_bound(bucket: usize) -> Option<u64> {
+    (bucket < SIZE_HISTOGRAM_BUCKETS - 1).then(|| 1024u64 << (2 * bucket))
+}
 
-        Self {
-            index: index,
-            start: start,
-            end: e,
-        }
+fn size_histogram_bucket(size: u64) -> usize {
+    (0..SIZE_HISTOGRAM_BUCKETS - 1).find(|b| size < size_histogram_bound(*b).unwrap()).unwrap_or(SIZE_HISTOGRAM_BUCKETS - 1)
+}
+
+// first segments of prefix up to depth, e.g. "logs/2024" of "logs/2024/01" at depth 2,
+// shorter prefix is a group of its own and "/" is the one of objects at top level
+fn prefix_group(prefix: &str, depth: usize) -> &str {
+    match prefix.match_indices('/').nth(depth.saturating_sub(1)) {
+        Some((end, _)) if end > 0 => &prefix[..end],
+        _ => prefix,
     }
+}
 
-    pub fn index(&self) -> usize {
-        self.index
+// group of prefix report as key prefix, e.g. "logs/2024/"
+fn fmt_prefix_group(group: &str) -> String {
+    if group == "/" { group.to_string() } else { format!("{}/", group) }
+}
+
+// bound in whole binary units, e.g. "256 KiB"
+fn fmt_size_bound(bound: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut unit = 0;
+    let mut value = bound;
+    while value >= 1024 && value % 1024 == 0 && unit < UNITS.len() - 1 {
+        value /= 1024;
+        unit += 1;
     }
+    format!("{} {}", value, UNITS[unit])
+}
 
-    pub fn to_task_input(&self) -> (&str, Option<&str>) {
-        (&self.start, self.end.as_ref().map(|x| x.as_str()))
+// content identity of object to find duplicates, size is 0 if only etag is compared
+type DupKey = (u64, [u8; 16], u32);
+
+fn dup_key(props: &ObjectProps, on: DuplicateOn) -> Option<DupKey> {
+    let (md5, parts) = props.etag();
+    // empty objects take no storage, and objects without etag can't be told apart
+    if props.size() == 0 || (md5 == [0u8; 16] && parts == 0) {
+        return None;
     }
+    let size = if on == DuplicateOn::SizeEtag { props.size() } else { 0 };
+    Some((size, md5, parts))
 }
 
-pub struct KeySpaceHints {
-    inner: VecDeque<KeySpacePair>,
-    inflight: HashMap<usize, KeySpacePair>,
-    done: Vec<KeySpacePair>,
+#[derive(Debug, Default)]
+struct DuplicateSummary {
+    groups: usize,
+    objects: usize,
+    // bytes of all but one member of each group
+    reclaimable: u64,
 }
 
-impl KeySpaceHints {
-    pub fn new_from(hints: &Vec<String>) -> Self {
+// objects and bytes by output dir flag, for --count-only and final summary
+#[derive(Debug, Default, Clone)]
+struct ObjectSummary {
+    objects: [usize; 4],
+    bytes: [u64; 4],
+    min_size: [u64; 4],
+    max_size: [u64; 4],
+    // (objects, bytes) of each size bucket, all statuses together
+    histogram: [(usize, u64); SIZE_HISTOGRAM_BUCKETS],
+    // depth and (objects, bytes) of each group of first segments of prefix with --prefix-report
+    prefix_report: Option<(usize, PrefixGroups)>,
+}
 
-        let mut v = VecDeque::new();
+type PrefixGroups = HashMap<String, (usize, u64)>;
 
-        let mut index = 0;
-        let last = "".to_string();
-        // start from top level
-        let mut start = "".to_string();
-        for key in hints {
-            v.push_back(KeySpacePair::new(index, start.clone(), key.to_string()));
-            start = key.to_string();
-            index += 1;
-        }
-        // push last
-        v.push_back(KeySpacePair::new(index, start, last));
+impl ObjectSummary {
+    fn add(&mut self, flag: u8, size: u64) {
+        let bucket = &mut self.histogram[size_histogram_bucket(size)];
+        bucket.0 += 1;
+        bucket.1 += size;
 
-        Self {
-            inner: v,
-            inflight: HashMap::new(),
-            done: Vec::new(),
-        }
+        let i = flag as usize;
+        self.min_size[i] = if self.objects[i] == 0 { size } else { self.min_size[i].min(size) };
+        self.max_size[i] = self.max_size[i].max(size);
+        self.objects[i] += 1;
+        self.bytes[i] += size;
     }
 
-    pub fn next(&mut self) -> Option<KeySpacePair> {
-        if let Some(pair) = self.inner.pop_front() {
-            self.inflight.insert(pair.index(), pair.clone());
-            return Some(pair);
+    fn with_prefix_report(mut self, depth: Option<usize>) -> Self {
+        if self.prefix_report.is_none() {
+            self.prefix_report = depth.map(|depth| (depth, HashMap::new()));
         }
-        None
+        self
     }
 
-    pub fn finish(&mut self, index: usize) {
-        if let Some(pair) = self.inflight.remove(&index) {
-            self.done.push(pair);
+    // group is looked up by slice of prefix, so only a new group allocates
+    fn add_to_prefix_report(&mut self, prefix: &str, size: u64) {
+        let Some((depth, groups)) = self.prefix_report.as_mut() else {
             return;
+        };
+        let group = prefix_group(prefix, *depth);
+        match groups.get_mut(group) {
+            Some(entry) => {
+                entry.0 += 1;
+                entry.1 += size;
+            },
+            None => {
+                groups.insert(group.to_string(), (1, size));
+            },
         }
-        panic!("index {} not in inflight list", index);
     }
 
-    pub fn len(&self) -> usize {
-        self.inner.len()
+    // (group, objects, bytes) by bytes descending, then by group
+    fn prefix_report(&self) -> Vec<(String, usize, u64)> {
+        let Some((_, groups)) = self.prefix_report.as_ref() else {
+            return Vec::new();
+        };
+        let mut rows: Vec<_> = groups.iter()
+            .map(|(group, (objects, bytes))| (fmt_prefix_group(group), *objects, *bytes)).collect();
+        rows.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(&b.0)));
+        rows
+    }
+
+    fn write_prefix_report_csv(&self, filename: &str) -> std::io::Result<()> {
+        let mut csv = String::from("prefix,objects,bytes\r\n");
+        for (group, objects, bytes) in self.prefix_report() {
+            csv.push_str(&format!("\"{}\",{},{}\r\n", group.replace('"', "\"\""), objects, bytes));
+        }
+        std::fs::write(filename, csv)
+    }
+
+    // differences of statuses selected by --diff-status, all of them if none, as exported by dump
+    fn differences(&self, diff_status: &[DiffStatus]) -> usize {
+        [OUTPUT_DIR_FLAG_PLUS, OUTPUT_DIR_FLAG_MINUS, OUTPUT_DIR_FLAG_ASTRISK].into_iter()
+            .filter(|flag| diff_status.is_empty() || diff_status.iter().any(|status| status_flag(*status) == *flag))
+            .map(|flag| self.objects[flag as usize]).sum()
+    }
+
+    // e.g. "301 objects, 43.8 KiB (44850 bytes), size min 0 B, max 1.2 KiB, avg 149 B"
+    fn describe(&self, flag: u8) -> String {
+        let i = flag as usize;
+        let avg = if self.objects[i] > 0 { self.bytes[i] / self.objects[i] as u64 } else { 0 };
+        format!("{} objects, {} ({} bytes), size min {}, max {}, avg {}",
+            utils::fmt_count(self.objects[i]), utils::fmt_bytes_human(self.bytes[i]), self.bytes[i],
+            utils::fmt_bytes_human(self.min_size[i]), utils::fmt_bytes_human(self.max_size[i]), utils::fmt_bytes_human(avg))
+    }
+
+    // (lower bound, upper bound if any, objects, bytes) of each bucket
+    fn histogram(&self) -> impl Iterator<Item = (u64, Option<u64>, usize, u64)> + '_ {
+        self.histogram.iter().enumerate().map(|(bucket, (objects, bytes))| {
+            let lower = if bucket == 0 { 0 } else { size_histogram_bound(bucket - 1).unwrap() };
+            (lower, size_histogram_bound(bucket), *objects, *bytes)
+        })
+    }
+
+    fn write_histogram_csv(&self, filename: &str) -> std::io::Result<()> {
+        let mut csv = String::from("min_size,max_size,objects,bytes\r\n");
+        for (lower, upper, objects, bytes) in self.histogram() {
+            csv.push_str(&format!("{},{},{},{}\r\n", lower, upper.map(|u| u.to_string()).unwrap_or_default(), objects, bytes));
+        }
+        std::fs::write(filename, csv)
     }
 }
 
-async fn do_dump(map: &PrefixMap, filename_ks: &str, filename_output: &str) {
+type ObjectOutput = utils::AsyncObjectOutput<utils::CompressedWriter<tokio::io::BufWriter<tokio::fs::File>>>;
 
-    let include_eq = false;
-    info!("Data Map Task - final map stats {}", map);
-    info!("Data Map Task - dumping all object keys to {}, include_eq: {}", filename_output, include_eq);
-    let _ = map.dump(&filename_output, include_eq).await;
-    info!("Data Map Task - dumping ks to {}", filename_ks);
-    let _ = map.dump_ks(&filename_ks).await;
-    info!("Data Map Task - quit");
+// open object output file in chosen format, appended to if exists with append
+async fn open_output(output_file: &str, opts: &DataMapOptions, append: bool) -> tokio::io::Result<ObjectOutput> {
+    open_output_with_buffer(output_file, opts, append, 100 * MB).await
+}
 
-    return ();
+async fn open_output_with_buffer(output_file: &str, opts: &DataMapOptions, append: bool, buffer_size: usize) -> tokio::io::Result<ObjectOutput> {
+
+    let f = if append {
+        tokio::fs::OpenOptions::new().create(true).append(true).open(output_file).await?
+    } else {
+        tokio::fs::File::create(output_file).await?
+    };
+    // header row only for new or empty file
+    let header = f.metadata().await?.len() == 0;
+    let writer = utils::CompressedWriter::new(tokio::io::BufWriter::with_capacity(buffer_size, f), opts.output_compression)?;
+    let optional = opts.optional_columns();
+    let projection = opts.output_columns.as_deref();
+    let output = match opts.output_format {
+        OutputFormat::Parquet => utils::AsyncObjectOutput::Parquet(Box::new(utils::AsyncParquetOutput::new(writer, &opts.parquet, optional, projection))),
+        OutputFormat::Arrow => utils::AsyncObjectOutput::Arrow(Box::new(utils::AsyncArrowOutput::new(writer, opts.arrow_compression, optional, projection)?)),
+        OutputFormat::Ndjson => utils::AsyncObjectOutput::Ndjson(utils::AsyncNdjsonOutput::new(writer, optional, projection)),
+        OutputFormat::Csv => utils::AsyncObjectOutput::Csv(utils::AsyncCsvOutput::new(writer, &opts.columns, header).await?),
+        OutputFormat::Keys => utils::AsyncObjectOutput::Keys(utils::AsyncKeysOutput::new(writer, opts.null_terminated)),
+    };
+
+    Ok(output)
 }
 
-pub async fn data_map_task(mut ctx: DataMapContext, filename_ks: String, filename_output: String) -> () {
+// run metadata goes to footer of parquet output before closed
+async fn close_output(mut output: ObjectOutput, opts: &DataMapOptions) -> tokio::io::Result<()> {
+    output.append_footer(|objects| opts.run_metadata.footer(objects));
+    output.close().await
+}
 
-    ctx.start();
-    ctx.g_state.wait_to_start().await;
+// objects of one batch of sorted output at most
+const SORTED_BATCH_SIZE: usize = 64 * 1024;
 
-    info!("Data Map Task - started");
+// output dir flag of object to export, none if filtered out or an equal one not exported,
+// summary counts all but filtered out ones
+fn export_flag(prefix: &ObjectPrefix, name: &ObjectName, props: &ObjectProps, include_equal: bool, summary: &mut ObjectSummary) -> Option<u8> {
+    let flag = match props.final_status_check(prefix, name) {
+        MatchResult::Plus => OUTPUT_DIR_FLAG_PLUS,
+        MatchResult::Minus => OUTPUT_DIR_FLAG_MINUS,
+        MatchResult::Astrisk => OUTPUT_DIR_FLAG_ASTRISK,
+        MatchResult::Equal => OUTPUT_DIR_FLAG_EQUAL,
+        // object is filtered out
+        MatchResult::Ignore => return None,
+        result => panic!("{:?} should not occurs here", result),
+    };
+    summary.add(flag, props.size());
+    summary.add_to_prefix_report(prefix, props.size());
+    (flag != OUTPUT_DIR_FLAG_EQUAL || include_equal).then_some(flag)
+}
 
-    let map = PrefixMap::new();
-    let mut has_more_in_queue;
+// prefix of same object count and key names as in ks file of "--since-ks" is taken as unchanged and not exported
+fn is_since_unchanged(opts: &DataMapOptions, prefix: &str, obj_map: &ObjectMap) -> bool {
+    opts.since_ks.as_ref().is_some_and(|since| since.is_unchanged(prefix, obj_map.get_count(), obj_map.get_digest()))
+}
 
-    let mut last_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+fn status_flag(status: DiffStatus) -> u8 {
+    match status {
+        DiffStatus::OnlyLeft => OUTPUT_DIR_FLAG_PLUS,
+        DiffStatus::OnlyRight => OUTPUT_DIR_FLAG_MINUS,
+        DiffStatus::Changed => OUTPUT_DIR_FLAG_ASTRISK,
+    }
+}
 
-    loop {
-        match ctx.data_map_channel.try_recv() {
-            Ok(hash) => {
-                for (prefix, items) in hash.into_iter() {
-                    let object_hash = map.get_object_hash(&prefix).await;
-                    let _ = object_hash.bulk_insert(&prefix, items).await;
+// output of dump with sync script and batch manifest written from the same batches
+struct DumpSinks<'a> {
+    outputs: DumpOutput<'a>,
+    script: Option<DiffScript>,
+    script_opts: Option<&'a script::DiffScriptOptions>,
+    manifest: Option<BatchManifest>,
+    manifest_opts: Option<&'a script::BatchManifestOptions>,
+    split: bool,
+    include_equal: bool,
+    // statuses of differences written to output, all if empty, sync script and batch manifest get all of them
+    diff_status: &'a [DiffStatus],
+    exported: usize,
+}
+
+impl DumpSinks<'_> {
+    // objects of one prefix, or of one partition, indexed by output dir flag
+    async fn write(&mut self, prefix: &str, batch: [Vec<(ObjectKey, ObjectProps)>; 4]) {
+        let [equal, plus, minus, astrisk] = batch;
+
+        self.exported += equal.len() + [(&plus, OUTPUT_DIR_FLAG_PLUS), (&minus, OUTPUT_DIR_FLAG_MINUS), (&astrisk, OUTPUT_DIR_FLAG_ASTRISK)]
+            .into_iter().filter(|(_, flag)| self.selected(*flag)).map(|(v, _)| v.len()).sum::<usize>();
+        if let Some(s) = self.script.as_mut() {
+            if let Err(e) = s.write_diff(&plus, &astrisk, &minus).await {
+                warn!("Data Map Task - failed to write sync script, removed: {}", e);
+                self.script = None;
+                if let Some(script_opts) = self.script_opts {
+                    let _ = tokio::fs::remove_file(&script_opts.filename).await;
                 }
-                has_more_in_queue = true;
-            },
-            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
-                has_more_in_queue = false;
-            },
-            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
-                warn!("Data Map Task - consumer recv channel disconnected in data map task");
-                break;
             }
         }
-
-        if ctx.is_quit() {
-            info!("Data Map Task - force quit, dump file *MAY INCONSISTENT*");
-            do_dump(&map, &filename_ks, &filename_output).await;
-            ctx.complete();
-            return ();
-        } else if !ctx.all_list_tasks_is_running() && !has_more_in_queue {
-            do_dump(&map, &filename_ks, &filename_output).await;
-            ctx.complete();
-            ctx.quit();
-            return ();
+        if let Some(m) = self.manifest.as_mut() {
+            if let Err(e) = m.write_diff(&plus, &astrisk).await {
+                warn!("Data Map Task - failed to write batch manifest, removed: {}", e);
+                self.manifest = None;
+                if let Some(manifest_opts) = self.manifest_opts {
+                    let _ = tokio::fs::remove_file(&manifest_opts.filename).await;
+                }
+            }
         }
-
+        for (v, flag) in [(plus, OUTPUT_DIR_FLAG_PLUS), (minus, OUTPUT_DIR_FLAG_MINUS), (astrisk, OUTPUT_DIR_FLAG_ASTRISK)] {
+            if self.selected(flag) {
+                let _ = self.outputs.write(prefix, v, flag).await;
+            }
+        }
+        // no split file for equal ones
+        if self.include_equal && !self.split {
+            let _ = self.outputs.write(prefix, equal, OUTPUT_DIR_FLAG_EQUAL).await;
+        }
+    }
+
+    fn selected(&self, diff_flag: u8) -> bool {
+        self.diff_status.is_empty() || self.diff_status.iter().any(|status| status_flag(*status) == diff_flag)
+    }
+
+    // return count of objects exported and output files
+    async fn close(self) -> (usize, Vec<String>) {
+        let files = self.outputs.close().await;
+        if let (Some(script), Some(script_opts)) = (self.script, self.script_opts) {
+            match script.close().await {
+                Ok((copies, deletes)) => info!("Data Map Task - sync script of {} copies and {} deletes written to {}",
+                    utils::fmt_count(copies), utils::fmt_count(deletes), script_opts.filename),
+                Err(e) => warn!("Data Map Task - failed to write sync script {}: {}", script_opts.filename, e),
+            }
+        }
+        if let (Some(manifest), Some(manifest_opts)) = (self.manifest, self.manifest_opts) {
+            match manifest.close().await {
+                Ok((entries, 0)) => info!("Data Map Task - batch manifest of {} objects written to {}",
+                    utils::fmt_count(entries), manifest_opts.filename),
+                Ok((entries, skipped)) => info!("Data Map Task - batch manifest of {} objects written to {}, {} delete markers skipped",
+                    utils::fmt_count(entries), manifest_opts.filename, utils::fmt_count(skipped)),
+                Err(e) => warn!("Data Map Task - failed to write batch manifest {}: {}", manifest_opts.filename, e),
+            }
+        }
+        (self.exported, files)
+    }
+}
+
+// outputs of final dump: one file, three files of diff split, or one file per key prefix partition
+enum DumpOutput<'a> {
+    Files(Vec<ShardedOutput<'a>>),
+    Partitioned(PartitionedOutput<'a>),
+}
+
+impl DumpOutput<'_> {
+    async fn write(&mut self, prefix: &str, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        match self {
+            // with diff split, flags of plus, minus and astrisk go to their own file
+            Self::Files(outputs) if outputs.len() > 1 => outputs[diff_flag as usize - 1].write(v, diff_flag).await,
+            Self::Files(outputs) => outputs[0].write(v, diff_flag).await,
+            Self::Partitioned(output) => output.write(prefix, v, diff_flag).await,
+        }
+    }
+
+    // output files written, base directory of partitioned output
+    async fn close(self) -> Vec<String> {
+        match self {
+            Self::Files(outputs) => {
+                let mut files = Vec::new();
+                for output in outputs {
+                    files.extend(output.close().await);
+                }
+                files
+            },
+            Self::Partitioned(output) => {
+                let base_dir = output.base_dir.clone();
+                output.close().await;
+                vec![base_dir]
+            },
+        }
+    }
+}
+
+/*
+ * output of one file, or rolled over to the next shard "{stem}_part-00000{ext}" once the current one
+ * reaches max rows or bytes of output file. bytes are checked before each batch, so a shard may exceed
+ * the limit by one batch.
+ */
+struct ShardedOutput<'a> {
+    output_file: String,
+    opts: &'a DataMapOptions,
+    // output open and rows written to it
+    current: Option<(ObjectOutput, usize)>,
+    files: Vec<String>,
+}
+
+impl<'a> ShardedOutput<'a> {
+    async fn open(output_file: &str, opts: &'a DataMapOptions) -> tokio::io::Result<Self> {
+        let sharded = opts.max_output_file_rows.is_some() || opts.max_output_file_bytes.is_some();
+        let filename = if sharded { shard_filename(output_file, 0) } else { output_file.to_string() };
+        let output = open_output(&filename, opts, false).await?;
+        Ok(Self {
+            output_file: output_file.to_string(),
+            opts,
+            current: Some((output, 0)),
+            files: vec![filename],
+        })
+    }
+
+    async fn write(&mut self, mut v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        let (max_rows, max_bytes) = (self.opts.max_output_file_rows, self.opts.max_output_file_bytes);
+        while !v.is_empty() {
+            let Some((output, rows)) = self.current.as_mut() else {
+                return Err(std::io::Error::other("no output file open"));
+            };
+            if max_rows.is_some_and(|max| *rows >= max) || max_bytes.is_some_and(|max| *rows > 0 && output.bytes_written() >= max) {
+                self.roll_over().await?;
+                continue;
+            }
+            let rest = match max_rows {
+                Some(max) if *rows + v.len() > max => v.split_off(max - *rows),
+                _ => Vec::new(),
+            };
+            *rows += v.len();
+            output.write(v, diff_flag).await?;
+            v = rest;
+        }
+        Ok(())
+    }
+
+    async fn roll_over(&mut self) -> tokio::io::Result<()> {
+        if let Some((output, _)) = self.current.take() {
+            close_output(output, self.opts).await?;
+        }
+        let filename = shard_filename(&self.output_file, self.files.len());
+        self.current = Some((open_output(&filename, self.opts, false).await?, 0));
+        self.files.push(filename);
+        Ok(())
+    }
+
+    async fn close(self) -> Vec<String> {
+        if let Some((output, _)) = self.current {
+            let _ = close_output(output, self.opts).await;
+        }
+        self.files
+    }
+}
+
+/*
+ * hive style partitioned output, objects go to "{base}/prefix=a/prefix2=b/part-00000.parquet"
+ * after the first "depth" segments of their prefix. writers are opened lazily, the least recently
+ * used one is closed once too many are open, and a partition written to again gets a new part file.
+ */
+struct PartitionedOutput<'a> {
+    base_dir: String,
+    depth: usize,
+    opts: &'a DataMapOptions,
+    // open writers, most recently used last
+    open: Vec<(String, ObjectOutput)>,
+    // part files created of each partition
+    parts: HashMap<String, usize>,
+}
+
+impl<'a> PartitionedOutput<'a> {
+    fn new(base_dir: &str, depth: usize, opts: &'a DataMapOptions) -> Self {
+        Self {
+            base_dir: base_dir.to_string(),
+            depth,
+            opts,
+            open: Vec::new(),
+            parts: HashMap::new(),
+        }
+    }
+
+    async fn write(&mut self, prefix: &str, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        if v.is_empty() {
+            return Ok(());
+        }
+
+        let partition = partition_dir(prefix, self.depth);
+        let output = match self.open.iter().position(|(p, _)| *p == partition) {
+            Some(i) => {
+                let entry = self.open.remove(i);
+                self.open.push(entry);
+                &mut self.open.last_mut().unwrap().1
+            },
+            None => {
+                if self.open.len() >= core::DEFAULT_PARTITION_MAX_OPEN_WRITERS {
+                    let (_, lru) = self.open.remove(0);
+                    close_output(lru, self.opts).await?;
+                }
+                let part = self.parts.entry(partition.clone()).or_insert(0);
+                let dir = format!("{}/{}", self.base_dir, partition);
+                let filename = format!("{}/part-{:05}.{}{}", dir, part, self.opts.output_format.extension(), self.opts.output_compression.suffix());
+                *part += 1;
+                tokio::fs::create_dir_all(&dir).await?;
+                let output = match open_output_with_buffer(&filename, self.opts, false, MB).await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        warn!("Data Map Task - unable to open partition file {}: {}", filename, e);
+                        return Err(e);
+                    }
+                };
+                self.open.push((partition, output));
+                &mut self.open.last_mut().unwrap().1
+            },
+        };
+        output.write(v, diff_flag).await
+    }
+
+    async fn close(self) {
+        for (_, output) in self.open {
+            let _ = close_output(output, self.opts).await;
+        }
+        let files: usize = self.parts.values().sum();
+        info!("Data Map Task - {} partitions written into {} files under {}", self.parts.len(), files, self.base_dir);
+    }
+}
+
+// hive partition directory of first depth segments of prefix, missing ones are the hive default partition
+/*
+ * objects of list mode written to output as batches arrive instead of held in map until dump, map only
+ * counts objects of each prefix for ks file. all objects are needed at once for sorted or partitioned output,
+ * duplicates, "--since-ks" and checkpoints, and both sides of diff meet in map, so those keep the map
+ */
+fn is_streamed(opts: &DataMapOptions, checkpoint: bool) -> bool {
+    opts.mode == RunMode::List && !checkpoint && !opts.sorted && opts.partition_depth.is_none()
+        && opts.find_duplicates.is_none() && opts.since_ks.is_none()
+}
+
+struct StreamedOutput<'a> {
+    // no output with count only
+    output: Option<ShardedOutput<'a>>,
+    output_file: String,
+    // all but filtered out objects
+    summary: ObjectSummary,
+    exported: usize,
+    // first write error, output is closed with objects written before it
+    failed: Option<std::io::Error>,
+}
+
+impl<'a> StreamedOutput<'a> {
+    async fn open(output_file: &str, opts: &'a DataMapOptions) -> tokio::io::Result<Self> {
+        let output = match opts.count_only {
+            true => None,
+            false => Some(ShardedOutput::open(output_file, opts).await?),
+        };
+        Ok(Self {
+            output,
+            output_file: output_file.to_string(),
+            summary: ObjectSummary::default().with_prefix_report(opts.prefix_report),
+            exported: 0,
+            failed: None,
+        })
+    }
+
+    async fn write(&mut self, obj_map: &ObjectMap, prefix: &ObjectPrefix, items: Vec<(ObjectName, ObjectProps)>) {
+        obj_map.count_insert(&items);
+        let mut v = Vec::with_capacity(items.len());
+        for (name, props) in items {
+            if export_flag(prefix, &name, &props, false, &mut self.summary).is_some() {
+                v.push((props.listed_key(prefix, &name), props));
+            }
+        }
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        self.exported += v.len();
+        if let Err(e) = output.write(v, OUTPUT_DIR_FLAG_PLUS).await {
+            warn!("Data Map Task - failed to write objects to {}, output closed: {}", self.output_file, e);
+            if let Some(output) = self.output.take() {
+                output.close().await;
+            }
+            self.failed = Some(e);
+        }
+    }
+
+    // return count of objects exported, summary and output files
+    async fn close(self) -> tokio::io::Result<(usize, ObjectSummary, Vec<String>)> {
+        if let Some(e) = self.failed {
+            return Err(e);
+        }
+        let files = match self.output {
+            Some(output) => output.close().await,
+            None => Vec::new(),
+        };
+        Ok((self.exported, self.summary, files))
+    }
+}
+
+fn partition_dir(prefix: &str, depth: usize) -> String {
+    let mut segments = prefix.split('/').filter(|s| !s.is_empty());
+    (0..depth).map(|level| {
+        let column = if level == 0 { "prefix".to_string() } else { format!("prefix{}", level + 1) };
+        let value = segments.next().map_or(HIVE_DEFAULT_PARTITION.to_string(), escape_partition_value);
+        format!("{}={}", column, value)
+    }).collect::<Vec<_>>().join("/")
+}
+
+// percent encode all but alphanumeric, "-", "_" and ".", also dots of "." and ".." not to walk out of directory
+fn escape_partition_value(value: &str) -> String {
+    let only_dots = value.bytes().all(|b| b == b'.');
+    value.bytes().map(|b| match b {
+        b'.' if !only_dots => ".".to_string(),
+        b if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' => (b as char).to_string(),
+        b => format!("%{:02X}", b),
+    }).collect()
+}
+
+// insert suffix before extension of output file, e.g. "out.parquet" to "out_only_left.parquet",
+// extension of compressed output takes the one before it as well, e.g. "out.csv.gz" to "out_only_left.csv.gz"
+fn insert_suffix(output_file: &str, suffix: &str) -> String {
+
+    let name_start = output_file.rfind('/').map_or(0, |i| i + 1);
+    let name = &output_file[name_start..];
+    let compressed = [KsCompression::Gzip, KsCompression::Zstd].iter()
+        .map(|c| c.suffix()).find(|ext| name.len() > ext.len() && name.ends_with(ext)).unwrap_or("");
+    let (stem, ext) = match name[..name.len() - compressed.len()].rfind('.') {
+        Some(i) if i > 0 => output_file.split_at(name_start + i),
+        _ => output_file.split_at(output_file.len() - compressed.len()),
+    };
+
+    format!("{stem}_{suffix}{ext}")
+}
+
+pub fn diff_split_filenames(output_file: &str) -> Vec<String> {
+    DIFF_SPLIT_SUFFIX.iter().map(|suffix| insert_suffix(output_file, suffix)).collect()
+}
+
+// shard of output file, e.g. "out.parquet" to "out_part-00000.parquet"
+fn shard_filename(output_file: &str, shard: usize) -> String {
+    insert_suffix(output_file, &format!("part-{:05}", shard))
+}
+
+impl std::fmt::Display for PrefixMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (prefix, object) = self.get_stats();
+        write!(f, "prefix count {}, object count {}", utils::fmt_count(prefix), utils::fmt_count(object))
+    }
+}
+
+// 64 bits of md5 of key name, summed up per prefix into a digest of its names independent of list order
+fn key_digest(name: &str) -> u64 {
+    let digest = md5::compute(name.as_bytes());
+    u64::from_le_bytes(digest.0[..8].try_into().unwrap())
+}
+
+#[derive(Clone)]
+pub struct ObjectMap {
+    inner: Arc<Mutex<HashMap<ObjectName, ObjectProps>>>,
+    count: Arc<AtomicUsize>,
+    // wrapping sum of key_digest of names inserted
+    digest: Arc<AtomicU64>,
+    // names evicted once resolved, without spare capacity of String, props listed again of them are duplicates
+    evicted: Arc<Mutex<HashSet<Box<str>>>>,
+}
+
+impl ObjectMap {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(HashMap::new())),
+            count: Arc::new(AtomicUsize::new(0)),
+            digest: Arc::new(AtomicU64::new(0)),
+            evicted: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn get_count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn inc_count(&self) {
+        let _ = self.count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn get_digest(&self) -> u64 {
+        self.digest.load(Ordering::SeqCst)
+    }
+
+    // objects written to output as they arrive are only counted, their names summed into digest
+    pub fn count_insert(&self, items: &[(ObjectName, ObjectProps)]) {
+        let digest = items.iter().fold(0u64, |sum, (name, _)| sum.wrapping_add(key_digest(name)));
+        self.digest.fetch_add(digest, Ordering::SeqCst);
+        self.count.fetch_add(items.len(), Ordering::SeqCst);
+    }
+
+    #[allow(dead_code)]
+    pub fn dec_count(&self) {
+        let _ = self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /*
+     * return:
+     *   - (vec for mtime check, vec for astrisk)
+     */
+    pub async fn bulk_insert(&self, prefix: &str, items: Vec<(ObjectName, ObjectProps)>) -> Vec<ObjectKey> {
+
+        let mut astrisk = Vec::new();
+        let _prefix = prefix.to_string();
+        let mut hash = self.inner.lock().await;
+        let evicted = self.evicted.lock().await;
+
+        for item in items {
+            let name = item.0;
+            let props = item.1;
+            if let Some(exist_props) = hash.get_mut(&name) {
+                // compare and trigger next flow
+                match exist_props.r#match(&props, &_prefix, &name) {
+                    MatchResult::Astrisk => {
+                        // collect the confirmed astrisk one
+                        let key = ObjectKey::encode(&_prefix, &name);
+                        astrisk.push(key);
+                    },
+                    MatchResult::Dup => {
+                        // if this is a duplicated props, just ignore it
+                    },
+                    MatchResult::Equal => {
+                    },
+                    MatchResult::Ignore => {
+                    },
+                    res => {
+                        // we only care about the one need to do mtime check
+                        // ignore other match result
+                        // do nothing for time based equal here
+                        debug!("match results {} {} {:?}", prefix, name.as_str(), res);
+                    },
+                }
+            } else if evicted.is_empty() || !evicted.contains(name.as_str()) {
+                self.digest.fetch_add(key_digest(&name), Ordering::SeqCst);
+                hash.insert(name, props);
+                self.inc_count();
+            }
+        }
+
+        astrisk
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KeySpacePair {
+    index: usize,
+    start: String,
+    end: Option<String>,
+}
+
+impl KeySpacePair {
+    pub fn new(index: usize, start: String, end: String) -> Self {
+
+        let e = if end.is_empty() { None } else { Some(end) };
+
+        Self {
+            index,
+            start,
+            end: e,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn to_task_input(&self) -> (&str, Option<&str>) {
+        (&self.start, self.end.as_deref())
+    }
+}
+
+// key space range (start, end) under prefix, a line of "{prefix}\t{start}\t{end}" in ks hints file,
+// written for ranges failed with --on-error continue, an empty end is the end of key space
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KsRange {
+    pub prefix: String,
+    pub start: String,
+    pub end: Option<String>,
+}
+
+impl KsRange {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let range = Self {
+            prefix: fields.next()?.to_string(),
+            start: fields.next()?.to_string(),
+            end: Some(fields.next()?).filter(|e| !e.is_empty()).map(|e| e.to_string()),
+        };
+        fields.next().is_none().then_some(range)
+    }
+
+    pub fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\n", self.prefix, self.start, self.end.as_deref().unwrap_or(""))
+    }
+}
+
+// prefix and object count of each entry of binary ks file, in order of file
+fn read_ks_binary(buf: &[u8]) -> std::io::Result<Vec<(String, usize)>> {
+    use std::io::Read;
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+    let Some(mut r) = buf.strip_prefix(KS_BINARY_MAGIC) else {
+        return Err(invalid("not a binary ks file".to_string()));
+    };
+    let mut u16_buf = [0u8; 2];
+    let mut u32_buf = [0u8; 4];
+    let mut u64_buf = [0u8; 8];
+    r.read_exact(&mut u16_buf)?;
+    let version = u16::from_le_bytes(u16_buf);
+    if version != KS_BINARY_VERSION {
+        return Err(invalid(format!("unsupported binary ks version {}", version)));
+    }
+    r.read_exact(&mut u16_buf)?;
+    r.read_exact(&mut u64_buf)?;
+    // count of header is not trusted for allocation, each entry takes at least 12 bytes
+    let len = u64::from_le_bytes(u64_buf) as usize;
+    let mut entries = Vec::with_capacity(len.min(r.len() / 12));
+    for _ in 0..len {
+        r.read_exact(&mut u32_buf)?;
+        let prefix_len = u32::from_le_bytes(u32_buf) as usize;
+        if prefix_len > r.len() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let (prefix, rest) = r.split_at(prefix_len);
+        let prefix = std::str::from_utf8(prefix).map_err(|e| invalid(e.to_string()))?.to_string();
+        r = rest;
+        r.read_exact(&mut u64_buf)?;
+        entries.push((prefix, u64::from_le_bytes(u64_buf) as usize));
+    }
+    Ok(entries)
+}
+
+// ks hints of input file, with lines dropped
+#[derive(Debug, Default, PartialEq)]
+pub struct KsHintsInput {
+    pub hints: Vec<String>,
+    // lines with tab are ranges, keys are kept as is without trim
+    pub ranges: Vec<KsRange>,
+    // count of lines empty after trim
+    pub blank: usize,
+    // line numbers of lines not in utf-8 or with NUL
+    pub invalid: Vec<usize>,
+    // count of prefixes if input is a binary ks file, split into hints
+    pub ks_entries: Option<usize>,
+}
+
+// one prefix per line with whitespace trimmed, or one range per line of tab separated fields,
+// blank lines skipped and invalid ones rejected.
+// a binary ks file of a previous run is split into hints of about even object count, as "--emit-ks-hints" does
+pub fn read_ks_hints(mut reader: impl std::io::BufRead, splits: usize) -> std::io::Result<KsHintsInput> {
+    let mut input = KsHintsInput::default();
+    if reader.fill_buf()?.starts_with(KS_BINARY_MAGIC) {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let mut counts = read_ks_binary(&buf)?;
+        counts.sort();
+        let counts: Vec<(&str, usize)> = counts.iter().map(|(prefix, count)| (prefix.as_str(), *count)).collect();
+        input.hints = split_ks(&counts, splits);
+        input.ks_entries = Some(counts.len());
+        return Ok(input);
+    }
+    let mut buf = Vec::new();
+    let mut line_no = 0;
+    while reader.read_until(b'\n', &mut buf)? > 0 {
+        line_no += 1;
+        match std::str::from_utf8(&buf).map(|line| line.trim_end_matches(['\n', '\r'])) {
+            Ok(line) if line.contains('\0') => input.invalid.push(line_no),
+            Ok(line) if line.contains('\t') => match KsRange::parse(line) {
+                Some(range) => input.ranges.push(range),
+                None => input.invalid.push(line_no),
+            },
+            Ok(line) => match line.trim() {
+                "" => input.blank += 1,
+                line => input.hints.push(line.to_string()),
+            },
+            Err(_) => input.invalid.push(line_no),
+        }
+        buf.clear();
+    }
+    Ok(input)
+}
+
+// object count of each prefix of ks file exported by a previous run, with "--since-ks",
+// and digest of key names of each prefix if the run left a digest file next to it
+#[derive(Debug, Default)]
+pub struct SinceKs {
+    counts: HashMap<ObjectPrefix, usize>,
+    digests: Option<HashMap<ObjectPrefix, u64>>,
+}
+
+// prefixes and net objects of this run against ks file of "--since-ks"
+#[derive(Debug, Default, PartialEq)]
+struct SinceDelta {
+    added: usize,
+    changed: usize,
+    removed: usize,
+    unchanged: usize,
+    // net count of objects more or less in prefixes, a lower bound of ones added and removed
+    objects_added: usize,
+    objects_removed: usize,
+}
+
+impl SinceKs {
+    // text or binary ks file, already decompressed
+    pub fn read(mut reader: impl std::io::BufRead) -> std::io::Result<Self> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+
+        let mut counts = HashMap::new();
+        if buf.starts_with(KS_BINARY_MAGIC) {
+            counts.extend(read_ks_binary(&buf)?);
+        } else {
+            let mut rd = csv::ReaderBuilder::new().has_headers(false).from_reader(buf.as_slice());
+            for (n, record) in rd.records().enumerate() {
+                let record = record.map_err(|e| invalid(e.to_string()))?;
+                match (record.get(0), record.get(1).and_then(|c| c.parse::<usize>().ok())) {
+                    (Some(prefix), Some(count)) if record.len() == 2 => counts.insert(prefix.to_string(), count),
+                    _ => return Err(invalid(format!("line {}: expect \"prefix\",\"count\"", n + 1))),
+                };
+            }
+        }
+        Ok(Self { counts, digests: None })
+    }
+
+    // digest file of ks file, lines of "prefix","digest in hex"
+    pub fn with_digests(mut self, reader: impl std::io::Read) -> std::io::Result<Self> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let mut digests = HashMap::new();
+        let mut rd = csv::ReaderBuilder::new().has_headers(false).from_reader(reader);
+        for (n, record) in rd.records().enumerate() {
+            let record = record.map_err(|e| invalid(e.to_string()))?;
+            match (record.get(0), record.get(1).and_then(|d| u64::from_str_radix(d, 16).ok())) {
+                (Some(prefix), Some(digest)) if record.len() == 2 => digests.insert(prefix.to_string(), digest),
+                _ => return Err(invalid(format!("line {}: expect \"prefix\",\"digest\"", n + 1))),
+            };
+        }
+        self.digests = Some(digests);
+        Ok(self)
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    pub fn has_digests(&self) -> bool {
+        self.digests.is_some()
+    }
+
+    // same count, and same key names if digests are known
+    fn is_unchanged(&self, prefix: &str, count: usize, digest: u64) -> bool {
+        self.counts.get(prefix) == Some(&count)
+            && self.digests.as_ref().map_or(true, |digests| digests.get(prefix) == Some(&digest))
+    }
+
+    // prefixes of map against previous ones, by object count and digest of each
+    fn delta(&self, current: &HashMap<&str, (usize, u64)>) -> SinceDelta {
+        let mut delta = SinceDelta::default();
+        for (prefix, (count, digest)) in current {
+            match self.counts.get(*prefix) {
+                None => {
+                    delta.added += 1;
+                    delta.objects_added += count;
+                },
+                Some(_) if self.is_unchanged(prefix, *count, *digest) => delta.unchanged += 1,
+                Some(previous) => {
+                    delta.changed += 1;
+                    delta.objects_added += count.saturating_sub(*previous);
+                    delta.objects_removed += previous.saturating_sub(*count);
+                },
+            }
+        }
+        for (prefix, previous) in &self.counts {
+            if !current.contains_key(prefix.as_str()) {
+                delta.removed += 1;
+                delta.objects_removed += previous;
+            }
+        }
+        delta
+    }
+}
+
+#[derive(Clone)]
+pub struct KeySpaceHints {
+    inner: VecDeque<KeySpacePair>,
+    inflight: HashMap<usize, KeySpacePair>,
+    done: Vec<KeySpacePair>,
+}
+
+impl KeySpaceHints {
+    pub fn new_from(hints: &Vec<String>) -> Self {
+
+        let mut v = VecDeque::new();
+
+        let mut index = 0;
+        let last = "".to_string();
+        // start from top level
+        let mut start = "".to_string();
+        for key in hints {
+            v.push_back(KeySpacePair::new(index, start.clone(), key.to_string()));
+            start = key.to_string();
+            index += 1;
+        }
+        // push last
+        v.push_back(KeySpacePair::new(index, start, last));
+
+        Self {
+            inner: v,
+            inflight: HashMap::new(),
+            done: Vec::new(),
+        }
+    }
+
+    // hints from pairs of (start, end) as is, e.g. partitions left in checkpoint
+    pub fn new_from_pairs(pairs: impl Iterator<Item = (String, Option<String>)>) -> Self {
+
+        let v = pairs.enumerate()
+            .map(|(index, (start, end))| KeySpacePair::new(index, start, end.unwrap_or_default()))
+            .collect();
+
+        Self {
+            inner: v,
+            inflight: HashMap::new(),
+            done: Vec::new(),
+        }
+    }
+
+    // boundaries at start prefix followed by each character of sorted charset but the first, keys before the first
+    // character go to the first partition and ones after the last to the last partition
+    pub fn new_seeded(prefix: &str, charset: &[char]) -> Self {
+        let hints: Vec<String> = charset.iter().skip(1).map(|c| format!("{}{}", prefix, c)).collect();
+        Self::new_from(&hints)
+    }
+
+    // one partition of whole key space, no hints given
+    pub fn is_whole(&self) -> bool {
+        self.inflight.is_empty() && self.inner.len() == 1 && self.inner[0].to_task_input() == ("", None)
+    }
+
+    pub fn next(&mut self) -> Option<KeySpacePair> {
+        if let Some(pair) = self.inner.pop_front() {
+            self.inflight.insert(pair.index(), pair.clone());
+            return Some(pair);
+        }
+        None
+    }
+
+    pub fn finish(&mut self, index: usize) {
+        if let Some(pair) = self.inflight.remove(&index) {
+            self.done.push(pair);
+            return;
+        }
+        panic!("index {} not in inflight list", index);
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    // pairs either not started yet or still in flight
+    pub fn unfinished(&self) -> impl Iterator<Item = &KeySpacePair> {
+        self.inflight.values().chain(self.inner.iter())
+    }
+}
+
+// ranges of key space partitions of all start prefixes in list order, split into at most n chunks of contiguous
+// partitions, sizes differ by one partition at most
+pub fn split_chunks(start_prefixes: &[(String, KeySpaceHints)], n: usize) -> Vec<Vec<KsRange>> {
+    let ranges: Vec<KsRange> = start_prefixes.iter().flat_map(|(prefix, hints)| hints.unfinished().map(|pair| {
+        let (start, end) = pair.to_task_input();
+        KsRange { prefix: prefix.clone(), start: start.to_string(), end: end.map(|e| e.to_string()) }
+    })).collect();
+    let n = n.min(ranges.len()).max(1);
+    let (size, larger) = (ranges.len() / n, ranges.len() % n);
+    let mut ranges = ranges.into_iter();
+    (0..n).map(|i| ranges.by_ref().take(size + usize::from(i < larger)).collect()).collect()
+}
+
+// chunk file of index, e.g. "chunks.input" to "chunks_chunk-00000.input"
+pub fn chunk_filename(filename: &str, index: usize) -> String {
+    insert_suffix(filename, &format!("chunk-{:05}", index))
+}
+
+// one ks hints file of failed ranges format per chunk, and names of them one per line to filename
+pub fn write_chunks(filename: &str, chunks: &[Vec<KsRange>]) -> std::io::Result<Vec<String>> {
+    let mut filenames = Vec::new();
+    for (i, ranges) in chunks.iter().enumerate() {
+        let chunk_file = chunk_filename(filename, i);
+        std::fs::write(&chunk_file, ranges.iter().map(|r| r.to_line()).collect::<String>())?;
+        filenames.push(chunk_file);
+    }
+    std::fs::write(filename, filenames.iter().map(|f| format!("{}\n", f)).collect::<String>())?;
+    Ok(filenames)
+}
+
+/*
+ * pick boundaries out of prefix counts sorted in lex, each partition gets about total / splits objects.
+ * objects of a boundary prefix are after it as of start after, so the prefix next to a full partition
+ * is taken as boundary
+ */
+fn split_ks(counts: &[(&str, usize)], splits: usize) -> Vec<String> {
+    let total: usize = counts.iter().map(|(_, count)| count).sum();
+    let target = total.div_ceil(splits.max(1));
+
+    let mut hints = Vec::new();
+    let mut cum = 0;
+    for (prefix, count) in counts {
+        if cum >= target && !prefix.is_empty() && hints.len() + 1 < splits {
+            hints.push(prefix.to_string());
+            cum = 0;
+        }
+        cum += count;
+    }
+    hints
+}
+
+// write ks hints from listed objects, skipped if list is not complete
+async fn emit_ks_hints(ctx: &DataMapContext, map: &PrefixMap) {
+    let Some(filename) = &ctx.opts.emit_ks_hints else {
+        return;
+    };
+    if ctx.is_quit() {
+        warn!("Data Map Task - list is not complete, ks hints not emitted to {}", filename);
+        return;
+    }
+    let hints = map.ks_hints(ctx.opts.ks_hints_splits).await;
+    let content: String = hints.iter().map(|h| format!("{}\n", h)).collect();
+    match tokio::fs::write(filename, content).await {
+        Ok(()) => info!("Data Map Task - {} ks hints emitted to {}", hints.len(), filename),
+        Err(e) => warn!("Data Map Task - failed to emit ks hints to {}: {}", filename, e),
+    }
+}
+
+// return summary of objects dumped, None if failed
+async fn do_dump(ctx: &DataMapContext, map: &PrefixMap, stream: Option<StreamedOutput<'_>>, filename_ks: &str, filename_output: &str,
+        started: Instant) -> Option<ObjectSummary> {
+
+    let include_eq = false;
+    info!("Data Map Task - final map stats {}", map);
+    if stream.is_some() {
+        info!("Data Map Task - closing {}, objects written as listed", filename_output);
+    } else if ctx.opts.diff_split && ctx.opts.mode == RunMode::BiDir {
+        info!("Data Map Task - dumping all object keys to {}, include_eq: {}",
+            diff_split_filenames(filename_output).join(", "), include_eq);
+    } else {
+        info!("Data Map Task - dumping all object keys to {}, include_eq: {}", filename_output, include_eq);
+    }
+    // objects not listed yet would show up as only right and be removed from target, or be missing from manifest
+    let diff_complete = !ctx.is_quit() && !ctx.g_state.is_list_failed();
+    if let (Some(script_opts), false) = (&ctx.opts.diff_script, diff_complete) {
+        warn!("Data Map Task - diff is not complete, sync script not written to {}", script_opts.filename);
+    }
+    if let (Some(manifest_opts), false) = (&ctx.opts.diff_batch_manifest, diff_complete) {
+        warn!("Data Map Task - diff is not complete, batch manifest not written to {}", manifest_opts.filename);
+    }
+    let dumped = match (stream, ctx.opts.find_duplicates) {
+        (Some(stream), _) => stream.close().await.map(|(exported, summary, files)| (exported, summary, files, None)),
+        (None, Some(on)) => map.dump_duplicates(filename_output, on, &ctx.opts).await
+            .map(|(dups, summary)| (dups.objects, summary, vec![filename_output.to_string()], Some((on, dups)))),
+        (None, None) => map.dump(filename_output, include_eq, &ctx.opts, diff_complete).await
+            .map(|(exported, summary, files)| (exported, summary, files, None)),
+    };
+    let mut output_files = Vec::new();
+    let dumped = match dumped {
+        Ok((exported, summary, files, dups)) => {
+            info!("Data Map Task - {} objects exported", utils::fmt_count(exported));
+            print_summary(ctx, &summary, started);
+            if ctx.opts.max_output_file_rows.is_some() || ctx.opts.max_output_file_bytes.is_some() {
+                print_line(ctx, &format!("Output - {} files written: {}", files.len(), files.join(", ")));
+            }
+            output_files = files;
+            if let Some(since) = &ctx.opts.since_ks {
+                let d = map.since_delta(since).await;
+                print_line(ctx, &format!("Since - prefixes {} added, {} changed, {} removed, {} unchanged not exported",
+                    utils::fmt_count(d.added), utils::fmt_count(d.changed), utils::fmt_count(d.removed), utils::fmt_count(d.unchanged)));
+                print_line(ctx, &format!("Since - objects at least {} added, {} removed, by count of each prefix",
+                    utils::fmt_count(d.objects_added), utils::fmt_count(d.objects_removed)));
+            }
+            if let Some((on, dups)) = dups {
+                let on = if on == DuplicateOn::SizeEtag { "size and etag" } else { "etag" };
+                print_line(ctx, &format!("Duplicates - {} groups of same {}, {} objects, {} reclaimable", utils::fmt_count(dups.groups),
+                    on, utils::fmt_count(dups.objects), utils::fmt_bytes_human(dups.reclaimable)));
+            }
+            Some((exported, summary))
+        },
+        Err(e) => {
+            warn!("Data Map Task - failed to dump object keys to {}: {}", filename_output, e);
+            None
+        },
+    };
+    let exported = dumped.as_ref().map(|(exported, _)| *exported);
+    info!("Data Map Task - dumping ks to {}", filename_ks);
+    let ks_dumped = map.dump_ks(filename_ks, ctx.opts.ks_format, ctx.opts.ks_compression).await;
+    if ks_dumped.is_ok() && ctx.opts.mode != RunMode::BiDir {
+        let filename = format!("{}{}", filename_ks, KS_DIGEST_SUFFIX);
+        if let Err(e) = map.dump_ks_digest(&filename).await {
+            warn!("Data Map Task - failed to dump key digests to {}: {}", filename, e);
+        }
+    }
+    // output is closed and readable, but only has objects listed before interrupted
+    if ctx.is_quit() {
+        if let Some(exported) = exported {
+            print_line(ctx, &format!("Partial - run interrupted, {} objects written to {}", utils::fmt_count(exported), filename_output));
+        }
+        if ks_dumped.is_ok() {
+            print_line(ctx, &format!("Partial - ks of {} prefixes written to {}", utils::fmt_count(map.get_count()), filename_ks));
+        }
+    }
+    if let (Some(filename), Some(exported)) = (&ctx.opts.emit_manifest, exported) {
+        match provenance::write_manifest(filename, &ctx.opts.run_metadata, exported, &output_files, filename_ks, ctx.is_quit()) {
+            Ok(_) => info!("Data Map Task - run manifest written to {}", filename),
+            Err(e) => warn!("Data Map Task - failed to write run manifest {}: {}", filename, e),
+        }
+    }
+    emit_ks_hints(ctx, map).await;
+    info!("Data Map Task - quit");
+    dumped.map(|(_, summary)| summary)
+}
+
+// print totals of map instead of writing any output file, return summary of objects counted
+async fn do_count(ctx: &DataMapContext, map: &PrefixMap, stream: Option<StreamedOutput<'_>>, started: Instant) -> ObjectSummary {
+
+    info!("Data Map Task - final map stats {}", map);
+    let summary = match stream {
+        // nothing to write, so it never fails
+        Some(stream) => stream.close().await.map(|(_, summary, _)| summary).unwrap_or_default(),
+        None => map.count(ctx.opts.prefix_report).await,
+    };
+    print_summary(ctx, &summary, started);
+    emit_ks_hints(ctx, map).await;
+    info!("Data Map Task - quit");
+    summary
+}
+
+// final totals go to stdout, and to log as well if it is written to file
+fn print_summary(ctx: &DataMapContext, summary: &ObjectSummary, started: Instant) {
+    ctx.g_state.finish_progress_bar();
+
+    let mut lines = Vec::new();
+    if ctx.opts.mode == RunMode::BiDir {
+        let labels = [(OUTPUT_DIR_FLAG_PLUS, DIFF_SPLIT_SUFFIX[0]), (OUTPUT_DIR_FLAG_MINUS, DIFF_SPLIT_SUFFIX[1]),
+            (OUTPUT_DIR_FLAG_ASTRISK, DIFF_SPLIT_SUFFIX[2]), (OUTPUT_DIR_FLAG_EQUAL, "unchanged")];
+        for (flag, label) in labels {
+            lines.push(format!("Summary - {}: {}", label, summary.describe(flag)));
+        }
+    } else {
+        lines.push(format!("Summary - {}", summary.describe(OUTPUT_DIR_FLAG_PLUS)));
+    }
+    if ctx.opts.skip_empty || ctx.opts.skip_dir_markers {
+        let mut skipped = Vec::new();
+        if ctx.opts.skip_empty {
+            skipped.push(format!("{} zero-byte objects", utils::fmt_count(ctx.skipped.empty)));
+        }
+        if ctx.opts.skip_dir_markers {
+            skipped.push(format!("{} directory markers", utils::fmt_count(ctx.skipped.dir_markers)));
+        }
+        lines.push(format!("Summary - skipped {}", skipped.join(", ")));
+    }
+    for v in ctx.g_state.read_verify_results() {
+        let rate = if v.checked == 0 { 0.0 } else { v.missing as f64 * 100.0 / v.checked as f64 };
+        let mut line = format!("Summary - verify sample of {}: {} checked, {} not found ({:.2}%)", v.bucket,
+            utils::fmt_count(v.checked), utils::fmt_count(v.missing), rate);
+        if v.failed > 0 {
+            line.push_str(&format!(", {} failed to check", utils::fmt_count(v.failed)));
+        }
+        lines.push(line);
+    }
+    lines.push(format!("Summary - elapsed {:.1}s", started.elapsed().as_secs_f64()));
+    if let Some(peak) = utils::peak_rss() {
+        lines.push(format!("Summary - peak rss {}", utils::fmt_bytes_human(peak as u64)));
+    }
+    if ctx.opts.size_histogram {
+        lines.push(format!("Size Histogram - {:>20} {:>12} {:>12}", "size", "objects", "bytes"));
+        for (lower, upper, objects, bytes) in summary.histogram() {
+            let range = match upper {
+                Some(upper) => format!("{} - {}", fmt_size_bound(lower), fmt_size_bound(upper)),
+                None => format!("> {}", fmt_size_bound(lower)),
+            };
+            lines.push(format!("Size Histogram - {:>20} {:>12} {:>12}", range, utils::fmt_count(objects), utils::fmt_bytes_human(bytes)));
+        }
+    }
+
+    if ctx.opts.prefix_report.is_some() {
+        lines.push(format!("Prefix Report - {:>12} {:>12}  {}", "objects", "bytes", "prefix"));
+        for (group, objects, bytes) in summary.prefix_report() {
+            lines.push(format!("Prefix Report - {:>12} {:>12}  {}", utils::fmt_count(objects), utils::fmt_bytes_human(bytes), group));
+        }
+    }
+
+    for line in lines {
+        print_line(ctx, &line);
+    }
+
+    if let Some(filename) = &ctx.opts.prefix_report_file {
+        match summary.write_prefix_report_csv(filename) {
+            Ok(_) => info!("Data Map Task - prefix report written to {}", filename),
+            Err(e) => warn!("Data Map Task - failed to write prefix report to {}: {}", filename, e),
+        }
+    }
+    if let Some(filename) = &ctx.opts.report_file {
+        write_report(ctx, summary, started, filename);
+    }
+    if let Some(filename) = &ctx.opts.size_histogram_file {
+        match summary.write_histogram_csv(filename) {
+            Ok(_) => info!("Data Map Task - size histogram written to {}", filename),
+            Err(e) => warn!("Data Map Task - failed to write size histogram to {}: {}", filename, e),
+        }
+    }
+}
+
+// same totals as summary lines, with state of run and counters of list requests
+fn write_report(ctx: &DataMapContext, summary: &ObjectSummary, started: Instant, filename: &str) {
+    let status = if ctx.is_quit() {
+        "interrupted, output only has objects listed so far"
+    } else if ctx.g_state.is_list_failed() {
+        "incomplete, list of some key space failed"
+    } else {
+        "complete"
+    };
+    let totals: Vec<(&str, usize, u64)> = if ctx.opts.mode == RunMode::BiDir {
+        [(OUTPUT_DIR_FLAG_PLUS, DIFF_SPLIT_SUFFIX[0]), (OUTPUT_DIR_FLAG_MINUS, DIFF_SPLIT_SUFFIX[1]),
+            (OUTPUT_DIR_FLAG_ASTRISK, DIFF_SPLIT_SUFFIX[2]), (OUTPUT_DIR_FLAG_EQUAL, "unchanged")].into_iter()
+            .map(|(flag, label)| (label, summary.objects[flag as usize], summary.bytes[flag as usize]))
+            .collect()
+    } else {
+        vec![("objects", summary.objects[OUTPUT_DIR_FLAG_PLUS as usize], summary.bytes[OUTPUT_DIR_FLAG_PLUS as usize])]
+    };
+    let content = report::render(&ctx.opts.run_metadata, status, started.elapsed().as_secs_f64(), &totals, &ctx.g_state);
+    match std::fs::write(filename, content) {
+        Ok(_) => info!("Data Map Task - run report written to {}", filename),
+        Err(e) => warn!("Data Map Task - failed to write run report to {}: {}", filename, e),
+    }
+}
+
+// line of final report to stdout, and to log as well if it is written to file
+fn print_line(ctx: &DataMapContext, line: &str) {
+    println!("{}", line);
+    if ctx.opts.log_to_file {
+        info!("{}", line);
+    }
+}
+
+// take all batches left in channel on quit, list tasks are stopped so nothing more is coming
+async fn drain_channel(ctx: &mut DataMapContext, map: &PrefixMap, stream: &mut Option<StreamedOutput<'_>>) {
+    let mut batches = 0;
+    while let Ok(hash) = ctx.data_map_channel.try_recv() {
+        insert_batch(ctx, map, stream, hash).await;
+        batches += 1;
+    }
+    if batches > 0 {
+        info!("Data Map Task - {} batches drained from channel on quit", batches);
+    }
+}
+
+// insert a batch from list task into map, or write it to output if streamed,
+// objects to skip are dropped before they meet the other side in diff mode
+async fn insert_batch(ctx: &mut DataMapContext, map: &PrefixMap, stream: &mut Option<StreamedOutput<'_>>,
+        mut hash: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>) {
+    if ctx.opts.key_compare != KeyCompare::Exact && ctx.opts.mode == RunMode::BiDir {
+        hash = normalize_keys(ctx.opts.key_compare, hash);
+    }
+    for (prefix, mut items) in hash.into_iter() {
+        if ctx.opts.skip_empty || ctx.opts.skip_dir_markers {
+            skip_objects(ctx.opts.skip_empty, ctx.opts.skip_dir_markers, &mut ctx.skipped, &prefix, &mut items);
+            if items.is_empty() {
+                continue;
+            }
+        }
+        let object_hash = map.get_object_hash(&prefix).await;
+        match stream.as_mut() {
+            Some(stream) => stream.write(&object_hash, &prefix, items).await,
+            None => {
+                let _ = object_hash.bulk_insert(&prefix, items).await;
+            },
+        }
+    }
+}
+
+// regroup objects by keys normalized, so keys of both sides differing only in case or unicode form meet in map,
+// key listed is kept in props for output. version id is kept as is
+fn normalize_keys(key_compare: KeyCompare, hash: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>) -> HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> {
+    let mut output: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::with_capacity(hash.len());
+    for (prefix, items) in hash.into_iter() {
+        for (name, mut props) in items {
+            let key = ObjectKey::encode(&prefix, &name);
+            let (listed, version_id) = key.split_version();
+            let normalized = key_compare.normalize(listed);
+            if normalized == listed {
+                output.entry(prefix.clone()).or_default().push((name, props));
+                continue;
+            }
+            let normalized = match version_id {
+                Some(version_id) => ObjectKey::with_version(&normalized, version_id),
+                None => ObjectKey::from(normalized.as_ref()),
+            };
+            props.set_listed_key(key.as_str());
+            let (prefix, name) = normalized.decode();
+            output.entry(prefix).or_default().push((name, props));
+        }
+    }
+    output
+}
+
+// common prefix rows of delimiter list are always kept, delete markers have no size so are never empty
+fn skip_objects(skip_empty: bool, skip_dir_markers: bool, skipped: &mut SkippedObjects, prefix: &ObjectPrefix, items: &mut Vec<(ObjectName, ObjectProps)>) {
+    items.retain(|(name, props)| {
+        if props.is_common_prefix() {
+            return true;
+        }
+        if skip_dir_markers && ObjectKey::encode(prefix, name).is_dir_marker() {
+            skipped.dir_markers += 1;
+            return false;
+        }
+        if skip_empty && props.size() == 0 && !props.is_delete_marker() {
+            skipped.empty += 1;
+            return false;
+        }
+        true
+    });
+}
+
+// append objects of partitions completed since last checkpoint to output, then commit them into checkpoint file
+async fn do_checkpoint(ctx: &mut DataMapContext, map: &PrefixMap, output: &mut ObjectOutput, checkpointer: &Checkpointer,
+        summary: &mut ObjectSummary) -> tokio::io::Result<()> {
+
+    let done = checkpointer.take_pending();
+    if done.is_empty() {
+        return Ok(());
+    }
+
+    // batches of done partitions were sent before they were handed over, drain them into map first
+    while let Ok(hash) = ctx.data_map_channel.try_recv() {
+        insert_batch(ctx, map, &mut None, hash).await;
+    }
+
+    let objects = map.take_completed(&done).await;
+    let count = objects.len();
+    for (_, props) in objects.iter() {
+        summary.add(OUTPUT_DIR_FLAG_PLUS, props.size());
+    }
+    output.write(objects, OUTPUT_DIR_FLAG_PLUS).await?;
+    output.flush().await?;
+    let (complete, total) = checkpointer.commit(&done).await?;
+    info!("Data Map Task - checkpoint {} objects of {} partitions appended, {}/{} partitions complete",
+        utils::fmt_count(count), done.len(), complete, total);
+
+    Ok(())
+}
+
+// final checkpoint, objects of incomplete partitions are dropped as they will be listed again on resume
+async fn do_checkpoint_dump(ctx: &mut DataMapContext, map: &PrefixMap, filename_ks: &str, mut output: ObjectOutput, checkpointer: &Checkpointer,
+        mut summary: ObjectSummary, started: Instant) {
+
+    info!("Data Map Task - final map stats {}", map);
+    if let Err(e) = do_checkpoint(ctx, map, &mut output, checkpointer, &mut summary).await {
+        warn!("Data Map Task - failed to checkpoint to {}: {}", checkpointer.filename(), e);
+    }
+    // objects appended by this run only, not the ones of runs resumed from
+    print_summary(ctx, &summary, started);
+    let _ = output.close().await;
+    let dropped = map.remaining().await;
+    if dropped > 0 {
+        warn!("Data Map Task - {} objects of incomplete partitions dropped, resume with --resume {}",
+            utils::fmt_count(dropped), checkpointer.filename());
+    }
+    info!("Data Map Task - dumping ks to {}", filename_ks);
+    let _ = map.dump_ks(filename_ks, ctx.opts.ks_format, ctx.opts.ks_compression).await;
+    // objects listed by runs resumed from are not in map
+    match &ctx.opts.emit_ks_hints {
+        Some(filename) if checkpointer.is_resumed() => warn!("Data Map Task - resumed run, ks hints not emitted to {}", filename),
+        _ => emit_ks_hints(ctx, map).await,
+    }
+    info!("Data Map Task - quit");
+}
+
+pub async fn data_map_task(mut ctx: DataMapContext, filename_ks: String, filename_output: String) -> () {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("Data Map Task - started");
+    let started = Instant::now();
+
+    let map = PrefixMap::new();
+    let mut has_more_in_queue;
+
+    // objects are appended to output at each checkpoint instead of dumped at the end
+    let g_state = ctx.g_state.clone();
+    let checkpointer = g_state.checkpointer();
+    let mut checkpoint_output = None;
+    let mut checkpoint_summary = ObjectSummary::default();
+    if let Some(checkpointer) = checkpointer {
+        match open_output(&filename_output, &ctx.opts, checkpointer.is_resumed()).await {
+            Ok(output) => checkpoint_output = Some(output),
+            Err(e) => {
+                error!("Data Map Task - unable to open output file {}: {}", filename_output, e);
+                ctx.complete();
+                ctx.quit();
+                return;
+            }
+        }
+    }
+
+    // output of list mode is written while listing, output options are borrowed from a copy as ctx is borrowed mutably
+    let opts = ctx.opts.clone();
+    let mut stream = None;
+    if is_streamed(&opts, checkpointer.is_some()) {
+        match StreamedOutput::open(&filename_output, &opts).await {
+            Ok(output) => stream = Some(output),
+            Err(e) => {
+                error!("Data Map Task - unable to open output file {}: {}", filename_output, e);
+                ctx.complete();
+                ctx.quit();
+                return;
+            }
+        }
+    }
+
+    let mut last_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let mut last_evict = Instant::now();
+
+    loop {
+        // sample list tasks state before recv, so anything sent before they completed is drained
+        let list_tasks_running = ctx.all_list_tasks_is_running();
+        match ctx.data_map_channel.try_recv() {
+            Ok(hash) => {
+                insert_batch(&mut ctx, &map, &mut stream, hash).await;
+                has_more_in_queue = true;
+            },
+            Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
+                has_more_in_queue = false;
+            },
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
+                warn!("Data Map Task - consumer recv channel disconnected in data map task");
+                break;
+            }
+        }
+        ctx.g_state.set_queued_batches(ctx.data_map_channel.len());
+
+        // under memory pressure, diff objects done with are dropped instead of held until dump
+        if ctx.opts.mode == RunMode::BiDir && last_evict.elapsed().as_secs() >= core::DEFAULT_MEMORY_EVICT_INTERVAL_SECS
+            && ctx.g_state.memory_governor().is_over_cap() {
+            let evicted = map.evict_resolved().await;
+            if evicted > 0 {
+                info!("Data Map Task - rss over max memory, {} resolved objects evicted from map", utils::fmt_count(evicted));
+            }
+            last_evict = Instant::now();
+        }
+
+        if ctx.is_quit() {
+            drain_channel(&mut ctx, &map, &mut stream).await;
+        }
+
+        if let Some(checkpointer) = checkpointer {
+            if ctx.is_quit() || (!list_tasks_running && !has_more_in_queue) {
+                if ctx.is_quit() {
+                    info!("Data Map Task - force quit, complete partitions are kept in checkpoint");
+                }
+                if let Some(output) = checkpoint_output.take() {
+                    do_checkpoint_dump(&mut ctx, &map, &filename_ks, output, checkpointer,
+                        std::mem::take(&mut checkpoint_summary), started).await;
+                }
+                ctx.complete();
+                ctx.quit();
+                return;
+            }
+            if let (true, Some(output)) = (checkpointer.is_due(), checkpoint_output.as_mut()) {
+                if let Err(e) = do_checkpoint(&mut ctx, &map, output, checkpointer, &mut checkpoint_summary).await {
+                    warn!("Data Map Task - failed to checkpoint to {}: {}", checkpointer.filename(), e);
+                }
+            }
+        } else if ctx.opts.count_only && (ctx.is_quit() || (!list_tasks_running && !has_more_in_queue)) {
+            if ctx.is_quit() {
+                info!("Data Map Task - force quit, counts *MAY INCONSISTENT*");
+            }
+            let summary = do_count(&ctx, &map, stream.take(), started).await;
+            if !ctx.is_quit() && ctx.opts.mode == RunMode::BiDir {
+                ctx.g_state.set_diff_count(summary.differences(&ctx.opts.diff_status));
+            }
+            ctx.complete();
+            ctx.quit();
+            return;
+        } else if ctx.is_quit() {
+            if ctx.opts.mode == RunMode::BiDir {
+                info!("Data Map Task - force quit, dump objects listed so far, diff result *MAY INCONSISTENT*");
+            } else {
+                info!("Data Map Task - force quit, dump objects listed so far");
+            }
+            do_dump(&ctx, &map, stream.take(), &filename_ks, &filename_output, started).await;
+            ctx.complete();
+            return;
+        } else if !list_tasks_running && !has_more_in_queue {
+            // counted the same way as do_count, so --fail-on-diff does not depend on --count-only
+            let summary = do_dump(&ctx, &map, stream.take(), &filename_ks, &filename_output, started).await;
+            if let (Some(summary), RunMode::BiDir) = (summary, &ctx.opts.mode) {
+                ctx.g_state.set_diff_count(summary.differences(&ctx.opts.diff_status));
+            }
+            ctx.complete();
+            ctx.quit();
+            return;
+        }
+
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
         if now - last_ts > core::DEFAULT_TASK_HEARTBEAT_INTERVAL_SECS {
             info!("Data Map Task - {}", map);
@@ -383,5 +1985,607 @@ pub async fn data_map_task(mut ctx: DataMapContext, filename_ks: String, filenam
             last_ts = now;
         }
     }
-    ()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "0123456789abcdef0123456789abcdef";
+
+    fn props(dir: u8, size: i64, etag: Option<&str>) -> ObjectProps {
+        let obj = aws_sdk_s3::types::Object::builder().key("k").size(size).set_e_tag(etag.map(|e| format!("\"{}\"", e))).build();
+        let mut props: ObjectProps = (&obj).into();
+        props.set_dir(dir);
+        props
+    }
+
+    #[test]
+    fn test_diff_split_filenames() {
+        assert_eq!(diff_split_filenames("a_b_2024.parquet"),
+            vec!["a_b_2024_only_left.parquet", "a_b_2024_only_right.parquet", "a_b_2024_changed.parquet"]);
+        assert_eq!(diff_split_filenames("out.v1/diff")[0], "out.v1/diff_only_left");
+        assert_eq!(diff_split_filenames("./out/.hidden")[2], "./out/.hidden_changed");
+        assert_eq!(shard_filename("out/a.parquet", 12), "out/a_part-00012.parquet");
+        assert_eq!(diff_split_filenames("out.csv.gz")[0], "out_only_left.csv.gz");
+        assert_eq!(shard_filename("a.ndjson.zst", 1), "a_part-00001.ndjson.zst");
+        assert_eq!(shard_filename("dir.v1/out.gz", 1), "dir.v1/out_part-00001.gz");
+    }
+
+    #[tokio::test]
+    async fn test_evict_resolved() {
+        let (left, right) = (core::S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, core::S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE);
+        let map = PrefixMap::new();
+        let obj_map = map.get_object_hash("d/").await;
+        obj_map.bulk_insert("d/", vec![("eq".to_string(), props(left, 10, Some(ETAG))), ("changed".to_string(), props(left, 10, Some(ETAG))),
+            ("only_left".to_string(), props(left, 10, Some(ETAG)))]).await;
+        obj_map.bulk_insert("d/", vec![("eq".to_string(), props(right, 10, Some(ETAG))), ("changed".to_string(), props(right, 20, Some(ETAG)))]).await;
+
+        assert_eq!(map.evict_resolved().await, 1);
+        assert_eq!(map.remaining().await, 2);
+        // listed again on retry, still a duplicate of the evicted one
+        obj_map.bulk_insert("d/", vec![("eq".to_string(), props(right, 10, Some(ETAG)))]).await;
+        assert_eq!(map.remaining().await, 2);
+
+        let summary = map.count(None).await;
+        assert_eq!(summary.objects, [1, 1, 0, 1]);
+        assert_eq!(summary.bytes[OUTPUT_DIR_FLAG_EQUAL as usize], 10);
+        assert_eq!(obj_map.get_count(), 3);
+
+        // only the exact name is taken as evicted, not one of the same length or prefix
+        obj_map.bulk_insert("d/", vec![("eq2".to_string(), props(right, 10, Some(ETAG))), ("qe".to_string(), props(right, 10, Some(ETAG)))]).await;
+        assert_eq!(map.remaining().await, 4);
+        assert_eq!(obj_map.get_count(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_count_only() {
+        let list = core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE;
+
+        // list mode, every object counted once under the same flag
+        let map = PrefixMap::new();
+        map.get_object_hash("a/x/").await.bulk_insert("a/x/", vec![("1".to_string(), props(list, 5, Some(ETAG))),
+            ("2".to_string(), props(list, 20, Some(ETAG)))]).await;
+        map.get_object_hash("b/").await.bulk_insert("b/", vec![("3".to_string(), props(list, 1, Some(ETAG)))]).await;
+
+        let summary = map.count(Some(1)).await;
+        let plus = OUTPUT_DIR_FLAG_PLUS as usize;
+        assert_eq!(summary.objects, [0, 3, 0, 0]);
+        assert_eq!((summary.bytes[plus], summary.min_size[plus], summary.max_size[plus]), (26, 1, 20));
+        assert_eq!(summary.differences(&[]), 3);
+        let groups: Vec<(usize, u64)> = summary.prefix_report().into_iter().map(|(_, objects, bytes)| (objects, bytes)).collect();
+        assert_eq!(groups, [(2, 25), (1, 1)]);
+
+        // diff mode, one count per status category
+        let (left, right) = (core::S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, core::S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE);
+        let map = PrefixMap::new();
+        let obj_map = map.get_object_hash("d/").await;
+        obj_map.bulk_insert("d/", vec![("eq".to_string(), props(left, 10, Some(ETAG))), ("changed".to_string(), props(left, 10, Some(ETAG))),
+            ("only_left".to_string(), props(left, 7, Some(ETAG)))]).await;
+        obj_map.bulk_insert("d/", vec![("eq".to_string(), props(right, 10, Some(ETAG))), ("changed".to_string(), props(right, 30, Some(ETAG))),
+            ("only_right".to_string(), props(right, 4, Some(ETAG)))]).await;
+
+        let summary = map.count(None).await;
+        assert_eq!(summary.objects, [1, 1, 1, 1]);
+        assert_eq!(summary.bytes[OUTPUT_DIR_FLAG_PLUS as usize], 7);
+        assert_eq!(summary.bytes[OUTPUT_DIR_FLAG_MINUS as usize], 4);
+        assert_eq!(summary.differences(&[]), 3);
+        assert!(summary.prefix_report().is_empty());
+    }
+
+    fn test_opts(mode: RunMode) -> DataMapOptions {
+        DataMapOptions {
+            filters: Vec::new(),
+            filter_combine: core::FilterCombine::And,
+            mode,
+            ks_format: KsFormat::Text,
+            ks_compression: KsCompression::None,
+            output_format: OutputFormat::Parquet,
+            columns: Vec::new(),
+            output_columns: None,
+            parquet: utils::ParquetOptions::default(),
+            arrow_compression: core::ArrowCompression::None,
+            output_compression: KsCompression::None,
+            diff_changed_after: None,
+            diff_lmtime_tolerance: 0,
+            diff_on: Vec::new(),
+            diff_direction: core::DiffDirection::Both,
+            diff_split: false,
+            diff_status: Vec::new(),
+            null_terminated: false,
+            versions: false,
+            shallow: false,
+            count_only: false,
+            log_to_file: false,
+            size_histogram: false,
+            size_histogram_file: None,
+            prefix_report: None,
+            prefix_report_file: None,
+            report_file: None,
+            time_format: core::TimeFormat::default(),
+            key_compare: KeyCompare::Exact,
+            skip_empty: false,
+            skip_dir_markers: false,
+            partition_depth: None,
+            sorted: false,
+            max_output_file_rows: None,
+            max_output_file_bytes: None,
+            emit_ks_hints: None,
+            ks_hints_splits: 1,
+            find_duplicates: None,
+            diff_script: None,
+            diff_batch_manifest: None,
+            with_tags: false,
+            with_owner: false,
+            with_checksum: false,
+            enrich_all: false,
+            run_metadata: provenance::RunMetadata::default(),
+            emit_manifest: None,
+            since_ks: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_quit_flushes_partial_output() {
+        let list = core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE;
+        let dir = std::env::temp_dir().join(format!("partial-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename_output = dir.join("out.parquet").to_string_lossy().to_string();
+        let filename_ks = dir.join("out.ks").to_string_lossy().to_string();
+
+        // batches still in channel when quit is signaled, list tasks are already stopped
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tx.send(HashMap::from([("a/".to_string(), vec![("1".to_string(), props(list, 1, None)), ("2".to_string(), props(list, 2, None))])])).unwrap();
+        tx.send(HashMap::from([("b/".to_string(), vec![("3".to_string(), props(list, 3, None))])])).unwrap();
+        let quit = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let g_state = core::GlobalState::new(quit, 1, 0, "test".to_string());
+        let ctx = DataMapContext::new(rx, g_state.clone(), test_opts(RunMode::List));
+        data_map_task(ctx, filename_ks.clone(), filename_output.clone()).await;
+        assert!(!g_state.data_map_task_is_running());
+
+        // footer is written, so the partial file is readable
+        let file = std::fs::File::open(&filename_output).unwrap();
+        let reader = parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+        let ks = std::fs::read_to_string(&filename_ks).unwrap();
+        assert_eq!(ks.lines().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_streamed_output() {
+        let list = core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE;
+        let mut opts = test_opts(RunMode::List);
+        opts.output_format = OutputFormat::Ndjson;
+        opts.columns = utils::parse_columns(None, &opts.optional_columns()).unwrap();
+        let filename = std::env::temp_dir().join(format!("streamed-{}.ndjson", std::process::id())).to_string_lossy().to_string();
+
+        // rows are written as batches arrive, map keeps only counts of each prefix
+        let (_tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+        let mut ctx = DataMapContext::new(rx, g_state, opts.clone());
+        let map = PrefixMap::new();
+        let mut stream = Some(StreamedOutput::open(&filename, &opts).await.unwrap());
+        insert_batch(&mut ctx, &map, &mut stream, HashMap::from([("a".to_string(), vec![("1".to_string(), props(list, 1, None)),
+            ("2".to_string(), props(list, 2, None))])])).await;
+        insert_batch(&mut ctx, &map, &mut stream, HashMap::from([("b".to_string(), vec![("3".to_string(), props(list, 3, None))])])).await;
+        assert_eq!(map.remaining().await, 0);
+        assert_eq!(map.get_object_hash("a").await.get_count(), 2);
+        assert_eq!(map.get_object_hash("b").await.get_count(), 1);
+
+        let (exported, summary, files) = stream.unwrap().close().await.unwrap();
+        assert_eq!((exported, summary.objects[OUTPUT_DIR_FLAG_PLUS as usize]), (3, 3));
+        assert_eq!(files, vec![filename.clone()]);
+        let rows = std::fs::read_to_string(&filename).unwrap();
+        std::fs::remove_file(&filename).unwrap();
+        assert_eq!(rows.lines().count(), 3);
+        assert!(rows.contains("a/1") && rows.contains("a/2") && rows.contains("b/3"), "{}", rows);
+
+        // all objects are needed at once for sorted output, diff and checkpoints
+        assert!(is_streamed(&opts, false));
+        assert!(!is_streamed(&opts, true));
+        assert!(!is_streamed(&test_opts(RunMode::BiDir), false));
+        opts.sorted = true;
+        assert!(!is_streamed(&opts, false));
+    }
+
+    #[tokio::test]
+    async fn test_csv_streamed_task() {
+        let list = core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE;
+        let mut opts = test_opts(RunMode::List);
+        opts.output_format = OutputFormat::Csv;
+        opts.columns = utils::parse_columns(Some("key,size"), &opts.optional_columns()).unwrap();
+        let dir = std::env::temp_dir().join(format!("csv-streamed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename_output = dir.join("out.csv").to_string_lossy().to_string();
+        let filename_ks = dir.join("out.ks").to_string_lossy().to_string();
+
+        // list tasks are done, the task writes queued batches as it takes them and closes output once drained
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tx.send(HashMap::from([("a".to_string(), vec![("x,y".to_string(), props(list, 1, None)), ("q\"t".to_string(), props(list, 2, None))])])).unwrap();
+        tx.send(HashMap::from([("b".to_string(), vec![("z".to_string(), props(list, 3, None))])])).unwrap();
+        let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+        let ctx = DataMapContext::new(rx, g_state.clone(), opts);
+        data_map_task(ctx, filename_ks.clone(), filename_output.clone()).await;
+        assert!(!g_state.data_map_task_is_running());
+
+        let mut rows: Vec<String> = std::fs::read_to_string(&filename_output).unwrap().lines().map(String::from).collect();
+        rows[1..].sort();
+        assert_eq!(rows, ["key,size", "\"a/q\"\"t\",2", "\"a/x,y\",1", "b/z,3"]);
+        let ks = std::fs::read_to_string(&filename_ks).unwrap();
+        assert_eq!(ks.lines().count(), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_sent_before_list_task_completed() {
+        let dir = std::env::temp_dir().join(format!("last-batch-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename_output = dir.join("out.csv").to_string_lossy().to_string();
+        let filename_ks = dir.join("out.ks").to_string_lossy().to_string();
+
+        // last batch of list task lands while data map task checks whether list tasks are still running
+        for round in 0..50 {
+            let mut opts = test_opts(RunMode::List);
+            opts.output_format = OutputFormat::Csv;
+            opts.columns = utils::parse_columns(Some("key"), &opts.optional_columns()).unwrap();
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+            g_state.list_task_start(core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE);
+            let (list_state, list_tx) = (g_state.clone(), tx.clone());
+            let list_task = std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_micros(100 * (round % 10)));
+                let props = props(core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE, 0, None);
+                list_tx.send(HashMap::from([("a".to_string(), vec![("k".to_string(), props)])])).unwrap();
+                list_state.list_task_complete(core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE);
+            });
+            let ctx = DataMapContext::new(rx, g_state, opts);
+            data_map_task(ctx, filename_ks.clone(), filename_output.clone()).await;
+            list_task.join().unwrap();
+            drop(tx);
+
+            let rows: Vec<String> = std::fs::read_to_string(&filename_output).unwrap().lines().map(String::from).collect();
+            assert_eq!(rows, ["key", "a/k"], "round {}", round);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_diff_count_of_diff_status() {
+        let (left, right) = (core::S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE, core::S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE);
+        let dir = std::env::temp_dir().join(format!("diff-status-count-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename_output = dir.join("out.csv").to_string_lossy().to_string();
+        let filename_ks = dir.join("out.ks").to_string_lossy().to_string();
+
+        // --fail-on-diff gets the same count whether output is written or only counted
+        let mut counts = Vec::new();
+        for count_only in [false, true] {
+            let mut opts = test_opts(RunMode::BiDir);
+            opts.output_format = OutputFormat::Csv;
+            opts.columns = utils::parse_columns(Some("key"), &opts.optional_columns()).unwrap();
+            opts.diff_status = vec![DiffStatus::Changed];
+            opts.count_only = count_only;
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            tx.send(HashMap::from([("d".to_string(), vec![("changed".to_string(), props(left, 10, Some(ETAG))),
+                ("only_left".to_string(), props(left, 10, Some(ETAG))), ("eq".to_string(), props(left, 10, Some(ETAG)))])])).unwrap();
+            tx.send(HashMap::from([("d".to_string(), vec![("changed".to_string(), props(right, 20, Some(ETAG))),
+                ("eq".to_string(), props(right, 10, Some(ETAG)))])])).unwrap();
+            let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+            let ctx = DataMapContext::new(rx, g_state.clone(), opts);
+            data_map_task(ctx, filename_ks.clone(), filename_output.clone()).await;
+            counts.push(g_state.read_diff_count());
+            drop(tx);
+        }
+        let rows: Vec<String> = std::fs::read_to_string(&filename_output).unwrap().lines().map(String::from).collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rows.len(), 2, "{:?}", rows);
+        assert_eq!(counts, [Some(1), Some(1)]);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_output() {
+        use std::io::BufRead;
+        let batch = |keys: std::ops::Range<usize>| -> Vec<(ObjectKey, ObjectProps)> {
+            keys.map(|i| {
+                let obj = aws_sdk_s3::types::Object::builder().key(format!("dir/{}", i)).size(i as i64).build();
+                (ObjectKey::from(format!("dir/{}", i).as_str()), (&obj).into())
+            }).collect()
+        };
+        let read_lines = |filename: &str| -> Vec<String> {
+            utils::open_decompressed(filename).unwrap().lines().map(|l| l.unwrap()).collect()
+        };
+        for format in [OutputFormat::Csv, OutputFormat::Ndjson] {
+            for compression in [KsCompression::Gzip, KsCompression::Zstd] {
+                let mut opts = test_opts(RunMode::List);
+                opts.output_format = format;
+                opts.output_compression = compression;
+                opts.columns = utils::parse_columns(None, &opts.optional_columns()).unwrap();
+                let filename = std::env::temp_dir().join(format!("out-{}.{}{}", std::process::id(), format.extension(), compression.suffix()))
+                    .to_string_lossy().to_string();
+                let header = if format == OutputFormat::Csv { 1 } else { 0 };
+
+                // flushed output is a complete stream on its own, as of a checkpoint or an interrupted run
+                let mut output = open_output(&filename, &opts, false).await.unwrap();
+                output.write(batch(0..1000), OUTPUT_DIR_FLAG_PLUS).await.unwrap();
+                output.flush().await.unwrap();
+                assert_eq!(read_lines(&filename).len(), header + 1000, "{:?} {:?}", format, compression);
+                output.write(batch(1000..1500), OUTPUT_DIR_FLAG_PLUS).await.unwrap();
+                close_output(output, &opts).await.unwrap();
+
+                let lines = read_lines(&filename);
+                std::fs::remove_file(&filename).unwrap();
+                assert_eq!(lines.len(), header + 1500);
+                assert!(lines[header].contains("dir/0"), "{}", lines[header]);
+                assert!(lines.last().unwrap().contains("dir/1499"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_skip_objects() {
+        // "a/b/" decodes into prefix "a/b" and empty name
+        let items = vec![("".to_string(), props(0, 0, None)), ("empty".to_string(), props(0, 0, None)), ("obj".to_string(), props(0, 3, None)),
+            ("sub".to_string(), ObjectProps::new_common_prefix())];
+
+        let mut skipped = SkippedObjects::default();
+        let mut kept = items.clone();
+        skip_objects(true, true, &mut skipped, &"a/b".to_string(), &mut kept);
+        let names: Vec<&str> = kept.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["obj", "sub"]);
+        assert_eq!((skipped.empty, skipped.dir_markers), (1, 1));
+
+        let mut skipped = SkippedObjects::default();
+        let mut kept = items.clone();
+        skip_objects(false, true, &mut skipped, &"a/b".to_string(), &mut kept);
+        assert_eq!(kept.len(), 3);
+        assert_eq!((skipped.empty, skipped.dir_markers), (0, 1));
+    }
+
+    #[test]
+    fn test_normalize_keys() {
+        let mut hash: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::new();
+        hash.insert("Data".to_string(), vec![("A.txt".to_string(), ObjectProps::default()), ("b\0V1".to_string(), ObjectProps::default())]);
+        hash.insert("data".to_string(), vec![("c.txt".to_string(), ObjectProps::default())]);
+
+        let output = normalize_keys(KeyCompare::CaseInsensitive, hash);
+        assert_eq!(output.len(), 1);
+        let mut items: Vec<(String, String)> = output["data"].iter()
+            .map(|(name, props)| (name.clone(), props.listed_key(&"data".to_string(), name).as_str().to_string()))
+            .collect();
+        items.sort();
+        assert_eq!(items, [
+            ("a.txt".to_string(), "Data/A.txt".to_string()),
+            ("b\0V1".to_string(), "Data/b\0V1".to_string()),
+            ("c.txt".to_string(), "data/c.txt".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_dup_key() {
+        let etag = "5d41402abc4b2a76b9719d911017c592";
+        assert_eq!(dup_key(&props(0, 5, Some(etag)), DuplicateOn::Etag), dup_key(&props(0, 7, Some(etag)), DuplicateOn::Etag));
+        assert_ne!(dup_key(&props(0, 5, Some(etag)), DuplicateOn::SizeEtag), dup_key(&props(0, 7, Some(etag)), DuplicateOn::SizeEtag));
+        assert_ne!(dup_key(&props(0, 5, Some(etag)), DuplicateOn::Etag), dup_key(&props(0, 5, Some("d41d8cd98f00b204e9800998ecf8427e-2")), DuplicateOn::Etag));
+        // nothing to reclaim of empty objects, nothing to compare without etag
+        assert_eq!(dup_key(&props(0, 0, Some(etag)), DuplicateOn::Etag), None);
+        assert_eq!(dup_key(&props(0, 5, None), DuplicateOn::Etag), None);
+    }
+
+    #[test]
+    fn test_read_ks_hints() {
+        let input = read_ks_hints(&b"dir0/sub1\n\n  dir1/sub0  \r\nbad\0key\n\xff\n   \ndir2"[..], 4).unwrap();
+        assert_eq!(input, KsHintsInput {
+            hints: vec!["dir0/sub1".to_string(), "dir1/sub0".to_string(), "dir2".to_string()],
+            ranges: vec![],
+            blank: 2,
+            invalid: vec![4, 5],
+            ks_entries: None,
+        });
+
+        let range = KsRange { prefix: "dir1/".to_string(), start: "dir1/a ".to_string(), end: None };
+        let input = read_ks_hints(format!("{}dir1/\t\tdir1/b\r\nx\ty\n", range.to_line()).as_bytes(), 4).unwrap();
+        assert_eq!(input.ranges, vec![range, KsRange { prefix: "dir1/".to_string(), start: "".to_string(), end: Some("dir1/b".to_string()) }]);
+        assert_eq!(input.invalid, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_read_ks_hints_binary() {
+        let dir = std::env::temp_dir().join(format!("ks-binary-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("src.ksb").to_string_lossy().to_string();
+
+        let map = PrefixMap::new();
+        for prefix in ["d/", "a/", "c/", "b/"] {
+            let names = (0..10).map(|n| (n.to_string(), props(0, 1, None))).collect();
+            map.get_object_hash(prefix).await.bulk_insert(prefix, names).await;
+        }
+        map.dump_ks(&filename, KsFormat::Binary, KsCompression::Zstd).await.unwrap();
+
+        // split as hints of previous run, decompressed first as any ks hints file
+        let input = read_ks_hints(utils::open_decompressed(&filename).unwrap(), 2).unwrap();
+        assert_eq!(input.hints, vec!["c/"]);
+        assert_eq!(input.ks_entries, Some(4));
+        assert!(input.ranges.is_empty());
+        assert_eq!(read_ks_hints(utils::open_decompressed(&filename).unwrap(), 4).unwrap().hints, vec!["b/", "c/", "d/"]);
+
+        // truncated or of other version
+        let binary = std::fs::read(&filename).unwrap();
+        let binary = zstd::decode_all(binary.as_slice()).unwrap();
+        assert!(read_ks_hints(&binary[..binary.len() - 1], 2).is_err());
+        let mut other = binary.clone();
+        other[4] = 9;
+        assert!(read_ks_hints(other.as_slice(), 2).unwrap_err().to_string().contains("version 9"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // load time of text and binary ks files of the same prefixes, run with
+    // cargo test --release -- --ignored bench_ks_load --nocapture
+    #[test]
+    #[ignore]
+    fn bench_ks_load() {
+        const ENTRIES: usize = 1_000_000;
+        let prefixes: Vec<String> = (0..ENTRIES).map(|n| format!("data/{:04}/{:08}/", n % 1000, n)).collect();
+        let mut text = Vec::new();
+        let mut binary = KS_BINARY_MAGIC.to_vec();
+        binary.extend(KS_BINARY_VERSION.to_le_bytes());
+        binary.extend(0u16.to_le_bytes());
+        binary.extend((ENTRIES as u64).to_le_bytes());
+        for (n, prefix) in prefixes.iter().enumerate() {
+            text.extend(format!("\"{}\",\"{}\"\n", prefix, n).as_bytes());
+            binary.extend((prefix.len() as u32).to_le_bytes());
+            binary.extend(prefix.as_bytes());
+            binary.extend((n as u64).to_le_bytes());
+        }
+
+        for (name, buf) in [("text", &text), ("binary", &binary)] {
+            let started = std::time::Instant::now();
+            let since = SinceKs::read(buf.as_slice()).unwrap();
+            assert_eq!(since.len(), ENTRIES);
+            println!("{} ks of {} prefixes, {} bytes, loaded in {:?}", name, ENTRIES, buf.len(), started.elapsed());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_since_ks() {
+        let since = SinceKs::read(&b"\"a/\",\"3\"\n\"b, c/\",\"2\"\n\"gone/\",\"4\"\n"[..]).unwrap();
+        assert_eq!(since.len(), 3);
+        assert!(!since.has_digests());
+        assert!(since.is_unchanged("b, c/", 2, 0));
+        assert!(!since.is_unchanged("a/", 2, 0));
+        let current = HashMap::from([("a/", (5, 0)), ("b, c/", (2, 0)), ("new/", (1, 0))]);
+        assert_eq!(since.delta(&current), SinceDelta { added: 1, changed: 1, removed: 1, unchanged: 1, objects_added: 3, objects_removed: 4 });
+
+        let mut binary = KS_BINARY_MAGIC.to_vec();
+        binary.extend(KS_BINARY_VERSION.to_le_bytes());
+        binary.extend(0u16.to_le_bytes());
+        binary.extend(1u64.to_le_bytes());
+        binary.extend(2u32.to_le_bytes());
+        binary.extend(b"a/");
+        binary.extend(3u64.to_le_bytes());
+        assert!(SinceKs::read(binary.as_slice()).unwrap().is_unchanged("a/", 3, 0));
+        assert!(SinceKs::read(&binary[..binary.len() - 1]).is_err());
+        assert!(SinceKs::read(&b"\"a/\",\"x\"\n"[..]).is_err());
+
+        // digests of previous run, same count of a prefix with other keys is changed
+        let listed = |keys: Vec<(&'static str, Vec<&'static str>)>| async move {
+            let map = PrefixMap::new();
+            for (prefix, names) in keys {
+                map.get_object_hash(prefix).await.bulk_insert(prefix, names.iter().map(|n| (n.to_string(), props(0, 1, Some(ETAG)))).collect()).await;
+            }
+            map
+        };
+        let previous = listed(vec![("a/", vec!["1", "2"]), ("b\"/", vec!["1"])]).await;
+        let filename = std::env::temp_dir().join(format!("since-{}.ks{}", std::process::id(), KS_DIGEST_SUFFIX));
+        let filename = filename.to_str().unwrap();
+        previous.dump_ks_digest(filename).await.unwrap();
+        let since = SinceKs::read(&b"\"a/\",\"2\"\n\"b\"\"/\",\"1\"\n"[..]).unwrap()
+            .with_digests(std::fs::File::open(filename).unwrap()).unwrap();
+        std::fs::remove_file(filename).unwrap();
+        assert!(since.has_digests());
+
+        // names in other order are the same, a renamed one is not
+        let current = listed(vec![("a/", vec!["2", "1"]), ("b\"/", vec!["2"])]).await;
+        assert_eq!(current.since_delta(&since).await, SinceDelta { changed: 1, unchanged: 1, ..Default::default() });
+        let opts = DataMapOptions { since_ks: Some(Arc::new(since)), ..test_opts(RunMode::List) };
+        assert!(is_since_unchanged(&opts, "a/", &current.get_object_hash("a/").await));
+        assert!(!is_since_unchanged(&opts, "b\"/", &current.get_object_hash("b\"/").await));
+        assert!(SinceKs::default().with_digests(&b"\"a/\",\"xyz\"\n"[..]).is_err());
+    }
+
+    #[test]
+    fn test_split_ks() {
+        let counts = [("a/", 10), ("b/", 10), ("c/", 10), ("d/", 10)];
+        assert_eq!(split_ks(&counts, 2), vec!["c/"]);
+        assert_eq!(split_ks(&counts, 4), vec!["b/", "c/", "d/"]);
+        // never more partitions than asked, nor than prefixes
+        assert_eq!(split_ks(&counts, 100).len(), 3);
+        assert_eq!(split_ks(&[("big/", 100), ("small/", 1)], 4), vec!["small/"]);
+        // objects at top level can not be split at
+        assert!(split_ks(&[("", 10)], 4).is_empty());
+        assert!(split_ks(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn test_split_chunks() {
+        let hints = |list: &[&str]| KeySpaceHints::new_from(&list.iter().map(|h| h.to_string()).collect());
+        let start_prefixes = vec![("a/".to_string(), hints(&["a/1", "a/2", "a/3"])), ("b/".to_string(), hints(&["b/1"]))];
+        let chunks = split_chunks(&start_prefixes, 4);
+        assert_eq!(chunks.iter().map(|c| c.len()).collect::<Vec<_>>(), vec![2, 2, 1, 1]);
+        assert_eq!(chunks[1][1], KsRange { prefix: "a/".to_string(), start: "a/3".to_string(), end: None });
+        assert_eq!(chunks[2][0].to_line(), "b/\t\tb/1\n");
+        // never more chunks than partitions
+        assert_eq!(split_chunks(&start_prefixes, 10).len(), 6);
+        assert_eq!(chunk_filename("out/chunks.input", 3), "out/chunks_chunk-00003.input");
+    }
+
+    #[test]
+    fn test_seeded_hints() {
+        assert!(KeySpaceHints::new_from(&Vec::new()).is_whole());
+        let mut hints = KeySpaceHints::new_seeded("logs/", &['0', '8', 'f']);
+        assert!(!hints.is_whole());
+        assert_eq!(hints.len(), 3);
+        assert_eq!(hints.next().unwrap().to_task_input(), ("", Some("logs/8")));
+        assert_eq!(hints.next().unwrap().to_task_input(), ("logs/8", Some("logs/f")));
+        assert_eq!(hints.next().unwrap().to_task_input(), ("logs/f", None));
+    }
+
+    #[test]
+    fn test_partition_dir() {
+        assert_eq!(partition_dir("logs/2024/01/", 1), "prefix=logs");
+        assert_eq!(partition_dir("logs/2024/01/", 2), "prefix=logs/prefix2=2024");
+        assert_eq!(partition_dir("logs/", 2), "prefix=logs/prefix2=__HIVE_DEFAULT_PARTITION__");
+        assert_eq!(partition_dir("/", 1), "prefix=__HIVE_DEFAULT_PARTITION__");
+        assert_eq!(partition_dir("a b=c/", 1), "prefix=a%20b%3Dc");
+        assert_eq!(partition_dir("../v1.2/", 2), "prefix=%2E%2E/prefix2=v1.2");
+    }
+
+    #[test]
+    fn test_object_summary() {
+        let mut summary = ObjectSummary::default();
+        summary.add(OUTPUT_DIR_FLAG_PLUS, 100);
+        summary.add(OUTPUT_DIR_FLAG_PLUS, 0);
+        summary.add(OUTPUT_DIR_FLAG_PLUS, 2048);
+        summary.add(OUTPUT_DIR_FLAG_ASTRISK, 10);
+        summary.add(OUTPUT_DIR_FLAG_EQUAL, 10);
+        assert_eq!(summary.differences(&[]), 4);
+        assert_eq!(summary.describe(OUTPUT_DIR_FLAG_PLUS),
+            "3 objects, 2.1 KiB (2148 bytes), size min 0 B, max 2.0 KiB, avg 716 B");
+        assert_eq!(summary.describe(OUTPUT_DIR_FLAG_MINUS),
+            "0 objects, 0 B (0 bytes), size min 0 B, max 0 B, avg 0 B");
+
+        let histogram: Vec<_> = summary.histogram().collect();
+        assert_eq!(histogram.len(), SIZE_HISTOGRAM_BUCKETS);
+        assert_eq!(histogram[0], (0, Some(1024), 4, 120));
+        assert_eq!(histogram[1], (1024, Some(4096), 1, 2048));
+        assert_eq!(histogram[16].1, None);
+        assert_eq!(size_histogram_bucket(1023), 0);
+        assert_eq!(size_histogram_bucket(1 << 40), 16);
+        assert_eq!(size_histogram_bucket((1 << 40) - 1), 15);
+        assert_eq!(fmt_size_bound(256 * 1024), "256 KiB");
+        assert_eq!(fmt_size_bound(1 << 40), "1 TiB");
+    }
+
+    #[test]
+    fn test_prefix_report() {
+        assert_eq!(prefix_group("logs/2024/01", 2), "logs/2024");
+        assert_eq!(prefix_group("logs", 2), "logs");
+        assert_eq!(prefix_group("/", 1), "/");
+
+        let mut summary = ObjectSummary::default().with_prefix_report(Some(1));
+        for (prefix, size) in [("logs/2024", 10), ("logs", 5), ("/", 100), ("data/a/b", 20)] {
+            summary.add_to_prefix_report(prefix, size);
+        }
+        assert_eq!(summary.prefix_report(), vec![
+            ("/".to_string(), 1, 100),
+            ("data/".to_string(), 1, 20),
+            ("logs/".to_string(), 2, 15),
+        ]);
+        assert!(ObjectSummary::default().with_prefix_report(None).prefix_report().is_empty());
+    }
 }
@@ -8,8 +8,62 @@ pub const ERROR_S3_MISSING_REGION: u8 = 0x4;
 pub const ERROR_S3_NO_BUCKET: u8 = 0x10;
 pub const ERROR_S3_ACCESS_DENIED: u8 = 0x11;
 pub const ERROR_S3_PERMANENT_REDIRECT: u8 = 0x12;
+pub const ERROR_TLS_CERTIFICATE: u8 = 0x13;
+pub const ERROR_S3_SUSPECT_PAGE: u8 = 0x14;
 pub const ERROR_S3_UNKOWN: u8 = 0xff;
 
+// requester pays buckets deny list calls without x-amz-request-payer header with plain AccessDenied
+pub const HINT_REQUEST_PAYER: &str = "rerun with --request-payer if bucket is requester pays";
+
+// certificate of custom endpoint not trusted, e.g. self-signed one of MinIO
+pub const HINT_TLS_CERTIFICATE: &str = "rerun with --tls-ca-cert <pem> to trust CA of endpoint, or --tls-insecure to skip verification";
+
+// truncated page of endpoint without a way on to next page, or short of page size, with --strict-pagination
+pub const HINT_SUSPECT_PAGE: &str = "endpoint may drop keys between pages, check count of range, or rerun without --strict-pagination to list on after last key of page";
+
+// rustls only tells why certificate is rejected deep in the source chain of connector error
+pub fn is_tls_certificate_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(e) = source {
+        let msg = e.to_string();
+        if msg.contains("invalid peer certificate") || msg.contains("certificate verify failed") {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+// S3 asks to slow down with 503 SlowDown, or 429 of some S3 compatible services
+pub fn is_throttle_status(code: u16) -> bool {
+    code == 429 || code == 503
+}
+
+// category of errors tallied over a run, in order of end of run breakdown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCategory {
+    Throttle,
+    Timeout,
+    ServerError,
+    AccessDenied,
+    Other,
+}
+
+impl ErrorCategory {
+    pub const ALL: [ErrorCategory; 5] = [ErrorCategory::Throttle, ErrorCategory::Timeout, ErrorCategory::ServerError,
+        ErrorCategory::AccessDenied, ErrorCategory::Other];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorCategory::Throttle => "throttle",
+            ErrorCategory::Timeout => "timeout",
+            ErrorCategory::ServerError => "5xx",
+            ErrorCategory::AccessDenied => "access denied",
+            ErrorCategory::Other => "other",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FlatRuntimeError {
     errno: u8,
@@ -17,15 +71,21 @@ pub struct FlatRuntimeError {
     next_start: String,
     // http status code or 0 if we don't have
     http_status_code: u16,
+    // how to fix it, shown after message
+    hint: Option<&'static str>,
+    // region of bucket given by PermanentRedirect
+    redirect_region: Option<String>,
 }
 
 impl FlatRuntimeError {
     pub fn new(errno: u8, errmsg: String, next_start: String) -> Self {
         Self {
-            errno: errno,
-            errmsg: errmsg,
-            next_start: next_start,
+            errno,
+            errmsg,
+            next_start,
             http_status_code: 0,
+            hint: None,
+            redirect_region: None,
         }
     }
 
@@ -40,6 +100,40 @@ impl FlatRuntimeError {
         false
     }
 
+    // transient errors worth a retry with backoff: timeouts, connection errors, throttling and 5xx,
+    // but not client errors like 403 or 404
+    pub fn is_retryable(&self) -> bool {
+        match self.errno {
+            ERROR_S3_NEXT_STREAM_TIMEOUT | ERROR_S3_CLIENT_CONNECTION_TIMEOUT | ERROR_S3_CLIENT_GENERIC => true,
+            ERROR_S3_UNKOWN => is_throttle_status(self.http_status_code) || (500..600).contains(&self.http_status_code),
+            _ => false,
+        }
+    }
+
+    pub fn category(&self) -> ErrorCategory {
+        match self.errno {
+            _ if is_throttle_status(self.http_status_code) => ErrorCategory::Throttle,
+            ERROR_S3_NEXT_STREAM_TIMEOUT | ERROR_S3_CLIENT_CONNECTION_TIMEOUT => ErrorCategory::Timeout,
+            ERROR_S3_ACCESS_DENIED => ErrorCategory::AccessDenied,
+            _ if (500..600).contains(&self.http_status_code) => ErrorCategory::ServerError,
+            _ => ErrorCategory::Other,
+        }
+    }
+
+    pub fn with_hint(mut self, hint: Option<&'static str>) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    pub fn with_redirect_region(mut self, region: Option<String>) -> Self {
+        self.redirect_region = region;
+        self
+    }
+
+    pub fn redirect_region(&self) -> Option<&str> {
+        self.redirect_region.as_deref()
+    }
+
     pub fn with_http_status_code(self, code: u16) -> Self {
         let mut s = self;
         s.http_status_code = code;
@@ -59,11 +153,182 @@ impl FlatRuntimeError {
 
 impl std::fmt::Display for FlatRuntimeError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let next_start = if self.next_start == "" {
+        let next_start = if self.next_start.is_empty() {
             "/"
         } else {
             &self.next_start
         };
-        write!(f, "errno: {}, msg: {}, next_start: {}", self.errno, self.errmsg, next_start)
+        write!(f, "errno: {}, msg: {}, next_start: {}", self.errno, self.errmsg, next_start)?;
+        if let Some(hint) = self.hint {
+            write!(f, ", hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+// invalid cli option detected at startup
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptionError {
+    option: &'static str,
+    errmsg: String,
+}
+
+impl OptionError {
+    pub fn new(option: &'static str, errmsg: String) -> Self {
+        Self {
+            option,
+            errmsg,
+        }
+    }
+}
+
+impl std::fmt::Display for OptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid option --{}: {}", self.option, self.errmsg)
+    }
+}
+
+// sts assume role failed when building s3 client
+#[derive(Debug, Clone)]
+pub struct AssumeRoleError {
+    role_arn: String,
+    errmsg: String,
+}
+
+impl AssumeRoleError {
+    pub fn new(role_arn: &str, errmsg: String) -> Self {
+        Self {
+            role_arn: role_arn.to_string(),
+            errmsg,
+        }
+    }
+}
+
+impl std::fmt::Display for AssumeRoleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unable to assume role {}: {}", self.role_arn, self.errmsg)
+    }
+}
+
+// filter expression parse error, position is char index in the original expression
+#[derive(Debug)]
+pub struct FilterParseError {
+    expr: String,
+    pub position: Option<usize>,
+    errmsg: String,
+}
+
+impl FilterParseError {
+    pub fn new(expr: &str, position: Option<usize>, errmsg: String) -> Self {
+        Self {
+            expr: expr.to_string(),
+            position,
+            errmsg,
+        }
+    }
+
+    // error of a part of expression starting at offset, reported in whole expression
+    pub fn within(self, expr: &str, offset: usize) -> Self {
+        Self::new(expr, self.position.map(|pos| pos + offset), self.errmsg)
+    }
+}
+
+impl std::fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.position {
+            Some(pos) => write!(f, "{} at position {}\n    {}\n    {}^", self.errmsg, pos, self.expr, " ".repeat(pos)),
+            None => write!(f, "{} in \"{}\"", self.errmsg, self.expr),
+        }
+    }
+}
+
+// manifest or data file of S3 Inventory not readable or not parsable
+#[derive(Debug, Clone)]
+pub struct InventoryError {
+    location: String,
+    errmsg: String,
+}
+
+impl InventoryError {
+    pub fn new(location: &str, errmsg: String) -> Self {
+        Self {
+            location: location.to_string(),
+            errmsg,
+        }
+    }
+}
+
+impl std::fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "unable to read inventory {}: {}", self.location, self.errmsg)
+    }
+}
+
+// config file given by "--config" not readable or with invalid options
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    filename: String,
+    errmsg: String,
+}
+
+impl ConfigError {
+    pub fn new(filename: &str, errmsg: String) -> Self {
+        Self {
+            filename: filename.to_string(),
+            errmsg,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid config file {}: {}", self.filename, self.errmsg)
+    }
+}
+
+// targets file of batch run not readable or with invalid lines
+#[derive(Debug, Clone)]
+pub struct TargetsError {
+    filename: String,
+    errmsg: String,
+}
+
+impl TargetsError {
+    pub fn new(filename: &str, errmsg: String) -> Self {
+        Self {
+            filename: filename.to_string(),
+            errmsg,
+        }
+    }
+}
+
+impl std::fmt::Display for TargetsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid targets file {}: {}", self.filename, self.errmsg)
+    }
+}
+
+// open files a run may need are over soft limit of RLIMIT_NOFILE, with a concurrency of list tasks that fits
+#[derive(Debug, Clone, PartialEq)]
+pub struct NofileLimitError {
+    needed: u64,
+    limit: u64,
+    concurrency: usize,
+}
+
+impl NofileLimitError {
+    pub fn new(needed: u64, limit: u64, concurrency: usize) -> Self {
+        Self {
+            needed,
+            limit,
+            concurrency,
+        }
+    }
+}
+
+impl std::fmt::Display for NofileLimitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "up to {} open files needed, over soft limit {} of open files, lower --concurrency to {}, \
+            or raise the limit with --raise-nofile or \"ulimit -n {}\"", self.needed, self.limit, self.concurrency, self.needed)
     }
 }
@@ -0,0 +1,1262 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use regex::{Regex, RegexBuilder};
+use rhai::{Engine, EvalAltResult, AST, Scope};
+use rhai::serde::to_dynamic;
+use log::{debug, warn};
+use crate::core::{FilterCombine, KeyCompare, ObjectKey, ObjectName, ObjectPrefix, ObjectProps, ObjectRecord, ObjectTags, RunMode};
+use crate::error::FilterParseError;
+use crate::utils;
+
+// object props could be referred without "SOURCE." for convenience
+const FILTER_BARE_PROPERTY: [&str; 5] = ["size", "last_modified", "storage_class", "owner_id", "owner_display_name"];
+
+// function of tag value, "tag:" is rewritten into it
+const FILTER_FN_TAG: &str = "tag_value";
+
+// key match operators, same precedence as "in", looser than "<" but tighter than "=="
+const FILTER_OP_REGEX: &str = "~";
+const FILTER_OP_GLOB: &str = "glob";
+// anchored at start or end of key, plain byte comparisons without regex
+const FILTER_OP_STARTS_WITH: &str = "startswith";
+const FILTER_OP_ENDS_WITH: &str = "endswith";
+pub const FILTER_OP_ANCHORED: [&str; 2] = [FILTER_OP_STARTS_WITH, FILTER_OP_ENDS_WITH];
+const FILTER_OP_PRECEDENCE: u8 = 110;
+
+const OBJECT_FILTER_ALLOWED_VARIABLE: [&str; 4] = ["SOURCE", "TARGET", "KEY", "TAGS"];
+const OBJECT_FILTER_ALLOWED_PROPERTY: [&str; 5] = ["size", "last_modified", "storage_class", "owner_id", "owner_display_name"];
+// props only put into SOURCE and TARGET if referred
+const OBJECT_FILTER_OWNER_PROPERTY: [&str; 2] = ["owner_id", "owner_display_name"];
+
+/*
+ * rewrite convenience syntax of filter expression into rhai expression before compile:
+ *   - bare "size" / "last_modified" / "storage_class" / "owner_id" / "owner_display_name" to props of SOURCE,
+ *     bare "key" to KEY
+ *   - "tag:env" or "tag:\"cost center\"" to value of object tag, "" if object has no such tag
+ *   - single "=" to "==", as assignment is never valid in filter
+ *   - "and" / "or" to "&&" / "||", "not" to "!" over the whole comparison after it
+ *   - number with size suffix like "100MB" to bytes in integer
+ *   - date "2024-01-01" or RFC 3339 datetime to unix timestamp, UTC is assumed if no offset
+ *   - "now" or relative "now-7d" to unix timestamp, evaluated once at compile time
+ * string literals are kept as is
+ */
+pub fn rewrite_expr(expr: &str) -> Result<Rewritten, FilterParseError> {
+    rewrite_expr_at(expr, Utc::now().timestamp())
+}
+
+// try to scan a date or datetime literal at position i, return end position and timestamp
+fn scan_datetime(chars: &[char], i: usize) -> Option<Result<(usize, i64), String>> {
+
+    // date part in form of YYYY-MM-DD
+    let is_date = chars.len() >= i + 10 &&
+        chars[i..i + 10].iter().enumerate().all(|(n, c)| if n == 4 || n == 7 { *c == '-' } else { c.is_ascii_digit() });
+    if !is_date {
+        return None;
+    }
+
+    let mut end = i + 10;
+    if end < chars.len() && chars[end] == 'T' {
+        while end < chars.len() && (chars[end].is_ascii_digit() || ":.+-TZ".contains(chars[end])) {
+            end += 1;
+        }
+    }
+    let literal: String = chars[i..end].iter().collect();
+
+    let ts = if end == i + 10 {
+        NaiveDate::parse_from_str(&literal, "%Y-%m-%d")
+            .map(|d| d.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp())
+            .map_err(|e| e.to_string())
+    } else {
+        DateTime::parse_from_rfc3339(&literal)
+            .map(|dt| dt.timestamp())
+            .or_else(|_| NaiveDateTime::parse_from_str(&literal, "%Y-%m-%dT%H:%M:%S")
+                .map(|dt| dt.and_utc().timestamp()))
+            .map_err(|e| e.to_string())
+    };
+
+    Some(ts.map(|ts| (end, ts)).map_err(|e| format!("invalid datetime \"{literal}\": {e}")))
+}
+
+// try to scan relative offset like "-7d" after "now", return end position and offset in secs
+fn scan_relative(chars: &[char], i: usize) -> Option<(usize, i64)> {
+
+    let mut pos = i;
+    let skip_spaces = |mut pos: usize| {
+        while pos < chars.len() && chars[pos].is_whitespace() {
+            pos += 1;
+        }
+        pos
+    };
+
+    pos = skip_spaces(pos);
+    let sign = match chars.get(pos) {
+        Some('-') => -1,
+        Some('+') => 1,
+        _ => return None,
+    };
+    pos = skip_spaces(pos + 1);
+
+    let start = pos;
+    while pos < chars.len() && chars[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    let n: i64 = chars[start..pos].iter().collect::<String>().parse().ok()?;
+    let unit = match chars.get(pos) {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        Some('w') => 7 * 86400,
+        _ => return None,
+    };
+    // unit must not be followed by other identifier chars
+    if chars.get(pos + 1).is_some_and(|c| c.is_ascii_alphanumeric() || *c == '_') {
+        return None;
+    }
+
+    Some((pos + 1, sign * n * unit))
+}
+
+// scan tag name at position i, either quoted or of letters, digits and "_-./@",
+// return end position and name as string literal
+fn scan_tag_name(chars: &[char], i: usize) -> Option<(usize, String)> {
+    let mut end = i;
+    if let Some(&quote) = chars.get(i).filter(|c| **c == '"' || **c == '\'') {
+        end += 1;
+        while end < chars.len() && chars[end] != quote {
+            if chars[end] == '\\' {
+                end += 1;
+            }
+            end += 1;
+        }
+        if end >= chars.len() || end == i + 1 {
+            return None;
+        }
+        let name: String = chars[i + 1..end].iter().collect();
+        return Some((end + 1, format!("\"{}\"", if quote == '"' { name } else { name.replace('"', "\\\"") })));
+    }
+    while end < chars.len() && (chars[end].is_alphanumeric() || "_-./@".contains(chars[end])) {
+        end += 1;
+    }
+    (end > i).then(|| (end, format!("\"{}\"", chars[i..end].iter().collect::<String>())))
+}
+
+// rewritten expression, with position in original expression of each char
+pub struct Rewritten {
+    pub expr: String,
+    positions: Vec<usize>,
+    orig_len: usize,
+}
+
+impl Rewritten {
+    // map char index in rewritten expression back to original one, end maps to end
+    pub fn orig_position(&self, pos: usize) -> usize {
+        self.positions.get(pos).copied().unwrap_or(self.orig_len)
+    }
+
+    fn push(&mut self, s: &str, orig: usize) {
+        for c in s.chars() {
+            self.expr.push(c);
+            self.positions.push(orig);
+        }
+    }
+
+    fn ends_with_property_dot(&self) -> bool {
+        self.expr.trim_end().ends_with('.')
+    }
+
+    fn follows_key_match_op(&self) -> bool {
+        let expr = self.expr.trim_end();
+        expr.ends_with(FILTER_OP_REGEX) ||
+            (expr.ends_with(FILTER_OP_GLOB) && !expr[..expr.len() - FILTER_OP_GLOB.len()].ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+    }
+}
+
+fn rewrite_expr_at(expr: &str, now: i64) -> Result<Rewritten, FilterParseError> {
+
+    let chars: Vec<char> = expr.chars().collect();
+    let mut out = Rewritten {
+        expr: String::with_capacity(expr.len() * 2),
+        positions: Vec::with_capacity(expr.len() * 2),
+        orig_len: chars.len(),
+    };
+    let err = |pos: usize, msg: String| FilterParseError::new(expr, Some(pos), msg);
+
+    // "not" binds looser than comparison, wrap its operand until next "and"/"or"/")" in same group
+    let mut depth = 0;
+    let mut pending_not: Vec<usize> = Vec::new();
+    let close_not = |out: &mut Rewritten, pending_not: &mut Vec<usize>, depth: usize, pos: usize| {
+        while pending_not.last() == Some(&depth) {
+            pending_not.pop();
+            out.push(")", pos);
+        }
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '"' || c == '\'' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(err(start, "unterminated string literal".to_string()));
+            }
+            i += 1;
+            // backslash in pattern of key match operator is kept for regex, except before quote
+            let is_pattern = out.follows_key_match_op();
+            for n in start..i {
+                if is_pattern && chars[n] == '\\' && chars.get(n + 1) != Some(&c) && chars.get(n.wrapping_sub(1)) != Some(&'\\') {
+                    out.push("\\", n);
+                }
+                out.push(chars[n].encode_utf8(&mut [0; 4]), n);
+            }
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().collect();
+            // identifier after "." is a property, keep it
+            let is_property = out.ends_with_property_dot();
+            match ident.as_str() {
+                "now" if !is_property => {
+                    let offset = match scan_relative(&chars, i) {
+                        Some((end, offset)) => {
+                            i = end;
+                            offset
+                        },
+                        None => 0,
+                    };
+                    out.push(&(now + offset).to_string(), start);
+                },
+                "and" if !is_property => {
+                    close_not(&mut out, &mut pending_not, depth, start);
+                    out.push("&&", start);
+                },
+                "or" if !is_property => {
+                    close_not(&mut out, &mut pending_not, depth, start);
+                    out.push("||", start);
+                },
+                "not" if !is_property => {
+                    pending_not.push(depth);
+                    out.push("!(", start);
+                },
+                "key" if !is_property => out.push("KEY", start),
+                "tag" if !is_property && chars.get(i) == Some(&':') => {
+                    let (end, name) = scan_tag_name(&chars, i + 1)
+                        .ok_or_else(|| err(start, "tag name expected after \"tag:\"".to_string()))?;
+                    i = end;
+                    out.push(&format!("{FILTER_FN_TAG}(TAGS, {name})"), start);
+                },
+                p if !is_property && FILTER_BARE_PROPERTY.contains(&p) => {
+                    out.push("SOURCE.", start);
+                    out.push(p, start);
+                },
+                _ => out.push(&ident, start),
+            }
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            if let Some(res) = scan_datetime(&chars, i) {
+                let (end, ts) = res.map_err(|e| err(i, e))?;
+                out.push(&ts.to_string(), i);
+                i = end;
+                continue;
+            }
+
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '_') {
+                i += 1;
+            }
+            let digits: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+            let suffix_start = i;
+            while i < chars.len() && chars[i].is_ascii_alphabetic() {
+                i += 1;
+            }
+            if suffix_start == i {
+                out.push(&digits, start);
+                continue;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            let suffix: String = chars[suffix_start..i].iter().collect();
+            let bytes = utils::parse_size(&format!("{digits}{suffix}"))
+                .map_err(|e| err(start, format!("invalid number \"{literal}\": {e}")))?;
+            out.push(&bytes.to_string(), start);
+            continue;
+        }
+
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                close_not(&mut out, &mut pending_not, depth, i);
+                depth = depth.saturating_sub(1);
+            },
+            '&' | '|' if chars.get(i + 1) == Some(&c) => {
+                close_not(&mut out, &mut pending_not, depth, i);
+            },
+            '=' if chars.get(i + 1) != Some(&'=') && !matches!(chars.get(i.wrapping_sub(1)), Some('=' | '!' | '<' | '>')) => {
+                out.push("==", i);
+                i += 1;
+                continue;
+            },
+            _ => {},
+        }
+        out.push(c.encode_utf8(&mut [0; 4]), i);
+        i += 1;
+    }
+    close_not(&mut out, &mut pending_not, depth, chars.len());
+    // unbalanced "(" leaves "not" at outer depth open, let compiler report missing ")"
+    while pending_not.pop().is_some() {
+        out.push(")", chars.len());
+    }
+
+    Ok(out)
+}
+
+// regex and glob patterns with key match operators, compiled once before listing
+#[derive(Debug, Default)]
+pub struct KeyPatterns {
+    regex: HashMap<String, Regex>,
+    glob: HashMap<String, Regex>,
+    // patterns match keys normalized by it, case-insensitive or in NFC themselves
+    key_compare: KeyCompare,
+}
+
+impl KeyPatterns {
+    pub fn is_empty(&self) -> bool {
+        self.regex.is_empty() && self.glob.is_empty()
+    }
+
+    fn add(&mut self, op: &str, pattern: &str) -> Result<(), String> {
+        let (cache, re) = if op == FILTER_OP_REGEX {
+            (&mut self.regex, pattern.to_string())
+        } else {
+            (&mut self.glob, glob_to_regex(pattern))
+        };
+        if !cache.contains_key(pattern) {
+            // lowercase of pattern would turn escapes like "\D" into others, case is ignored by regex instead
+            let re = if self.key_compare == KeyCompare::Nfc { self.key_compare.normalize(&re).into_owned() } else { re };
+            let compiled = RegexBuilder::new(&re).case_insensitive(self.key_compare == KeyCompare::CaseInsensitive).build()
+                .map_err(|e| format!("invalid {op} pattern \"{pattern}\": {e}"))?;
+            cache.insert(pattern.to_string(), compiled);
+        }
+        Ok(())
+    }
+
+    fn is_match(&self, op: &str, key: &str, pattern: &str) -> Result<bool, Box<EvalAltResult>> {
+        let cache = if op == FILTER_OP_REGEX { &self.regex } else { &self.glob };
+        match cache.get(pattern) {
+            Some(re) => Ok(re.is_match(key)),
+            None => Err(format!("{op} pattern \"{pattern}\" not compiled").into()),
+        }
+    }
+}
+
+// translate glob into anchored regex:
+//   - "*" and "?" match within one path segment, never cross "/"
+//   - "**" matches across "/", "**/" also matches no directory at all
+//   - "[abc]" / "[!abc]" char class
+pub fn glob_to_regex(glob: &str) -> String {
+
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::from("^");
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    re.push_str("(?:.*/)?");
+                    i += 1;
+                } else {
+                    re.push_str(".*");
+                }
+                i += 1;
+            },
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' if chars[i + 1..].contains(&']') => {
+                re.push('[');
+                i += 1;
+                if chars[i] == '!' {
+                    re.push('^');
+                    i += 1;
+                }
+                while chars[i] != ']' {
+                    if chars[i] == '\\' || chars[i] == '[' {
+                        re.push('\\');
+                    }
+                    re.push(chars[i]);
+                    i += 1;
+                }
+                re.push(']');
+            },
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+        i += 1;
+    }
+    re.push('$');
+    re
+}
+
+// key match operators must be known by engine before compile, anchored ones need no pattern so bound right away
+pub fn register_key_operators(engine: &mut Engine) {
+    for op in [FILTER_OP_REGEX, FILTER_OP_GLOB, FILTER_OP_STARTS_WITH, FILTER_OP_ENDS_WITH] {
+        engine.register_custom_operator(op, FILTER_OP_PRECEDENCE)
+            .expect("custom operator for key match");
+    }
+    engine.register_fn(FILTER_OP_STARTS_WITH, |key: &str, prefix: &str| key.starts_with(prefix));
+    engine.register_fn(FILTER_OP_ENDS_WITH, |key: &str, suffix: &str| key.ends_with(suffix));
+}
+
+// string comparisons "==", "!=", startswith and endswith on both sides normalized by key compare,
+// overriding built-in operators of strings requires fast operators off
+pub(crate) fn register_string_compare(engine: &mut Engine, key_compare: KeyCompare) {
+    if key_compare == KeyCompare::Exact {
+        return;
+    }
+    engine.set_fast_operators(false);
+    engine.register_fn("==", move |a: &str, b: &str| key_compare.normalize(a) == key_compare.normalize(b));
+    engine.register_fn("!=", move |a: &str, b: &str| key_compare.normalize(a) != key_compare.normalize(b));
+    engine.register_fn(FILTER_OP_STARTS_WITH, move |key: &str, prefix: &str| key_compare.normalize(key).starts_with(key_compare.normalize(prefix).as_ref()));
+    engine.register_fn(FILTER_OP_ENDS_WITH, move |key: &str, suffix: &str| key_compare.normalize(key).ends_with(key_compare.normalize(suffix).as_ref()));
+}
+
+// after all expressions compiled, bind key match operators to compiled patterns
+pub fn register_key_functions(engine: &mut Engine, patterns: Arc<KeyPatterns>) {
+    for op in [FILTER_OP_REGEX, FILTER_OP_GLOB] {
+        let patterns = patterns.clone();
+        engine.register_fn(op, move |key: &str, pattern: &str| patterns.is_match(op, key, pattern));
+    }
+}
+
+// tags of object in filter scope, none if not fetched
+#[derive(Debug, Clone)]
+pub struct FilterTags(pub Option<std::sync::Arc<ObjectTags>>);
+
+pub fn register_tag_functions(engine: &mut Engine) {
+    engine.register_type_with_name::<FilterTags>("Tags");
+    engine.register_fn(FILTER_FN_TAG, |tags: &mut FilterTags, name: &str| -> String {
+        tags.0.as_ref()
+            .and_then(|tags| tags.iter().find(|(k, _)| k == name))
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default()
+    });
+}
+
+// rewrite and compile filter expression, errors are reported in position of original expression,
+// patterns of key match operators are compiled into patterns
+pub fn compile(engine: &Engine, expr: &str, patterns: &mut KeyPatterns) -> Result<AST, FilterParseError> {
+    let rewritten = rewrite_expr(expr)?;
+    debug!("filter expression \"{}\" rewritten to \"{}\"", expr, rewritten.expr);
+    // rhai position is 1-based column in rewritten expression
+    let orig_position = |pos: rhai::Position| pos.position().map(|col| rewritten.orig_position(col - 1));
+
+    let ast = engine.compile_expression(&rewritten.expr).map_err(|e| {
+        FilterParseError::new(expr, orig_position(e.1), e.0.to_string())
+    })?;
+
+    let mut res = Ok(());
+    ast.walk(&mut |nodes| {
+        let (call, pos) = match nodes.last() {
+            Some(rhai::ASTNode::Expr(rhai::Expr::FnCall(call, pos))) => (call, pos),
+            // single call expression is kept as statement
+            Some(rhai::ASTNode::Stmt(rhai::Stmt::FnCall(call, pos))) => (call, pos),
+            _ => return true,
+        };
+        let op = call.name.as_str();
+        if op != FILTER_OP_REGEX && op != FILTER_OP_GLOB {
+            return true;
+        }
+        res = match call.args.get(1) {
+            Some(rhai::Expr::StringConstant(pattern, pattern_pos)) => patterns.add(op, pattern)
+                .map_err(|e| FilterParseError::new(expr, orig_position(*pattern_pos), e)),
+            _ => Err(FilterParseError::new(expr, orig_position(*pos),
+                format!("pattern of {op} operator must be a string literal"))),
+        };
+        res.is_ok()
+    });
+    res?;
+
+    Ok(ast)
+}
+
+// boolean structure of a filter expression, "and", "or", "not" and groups in parentheses are evaluated here
+// with short-circuit, each comparison between them is rewritten and compiled by rhai on its own
+#[derive(Debug)]
+pub(crate) enum FilterExpr {
+    And(Vec<FilterExpr>),
+    Or(Vec<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Predicate(AST),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BoolToken {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Text,
+}
+
+// token of boolean structure with its char range in original expression
+#[derive(Debug, Clone, Copy)]
+struct Token {
+    kind: BoolToken,
+    start: usize,
+    end: usize,
+}
+
+// split expression into boolean operators, parentheses and text of comparisons, string literals and
+// tag names are text, "and" / "or" / "not" after "." are properties
+fn tokenize(chars: &[char], expr: &str) -> Result<Vec<Token>, FilterParseError> {
+    let mut tokens = Vec::new();
+    let mut push = |kind: BoolToken, start: usize, end: usize| tokens.push(Token { kind, start, end });
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '"' || c == '\'' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' {
+                    i += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(FilterParseError::new(expr, Some(start), "unterminated string literal".to_string()));
+            }
+            i += 1;
+            push(BoolToken::Text, start, i);
+            continue;
+        }
+        if c.is_ascii_alphanumeric() || c == '_' {
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let is_property = chars[..start].iter().rev().find(|c| !c.is_whitespace()) == Some(&'.');
+            let kind = match word.as_str() {
+                "and" if !is_property => BoolToken::And,
+                "or" if !is_property => BoolToken::Or,
+                "not" if !is_property => BoolToken::Not,
+                "tag" if chars.get(i) == Some(&':') => {
+                    // name is checked by rewrite of comparison
+                    i = scan_tag_name(chars, i + 1).map_or(i + 1, |(end, _)| end);
+                    BoolToken::Text
+                },
+                _ => BoolToken::Text,
+            };
+            push(kind, start, i);
+            continue;
+        }
+        let kind = match c {
+            '(' => BoolToken::Open,
+            ')' => BoolToken::Close,
+            '&' | '|' if chars.get(i + 1) == Some(&c) => {
+                i += 1;
+                if c == '&' { BoolToken::And } else { BoolToken::Or }
+            },
+            _ => BoolToken::Text,
+        };
+        i += 1;
+        push(kind, start, i);
+    }
+    Ok(tokens)
+}
+
+// recursive descent over tokens, "or" binds loosest, then "and", then "not"
+struct ExprParser<'a> {
+    expr: &'a str,
+    chars: Vec<char>,
+    tokens: Vec<Token>,
+    pos: usize,
+    engine: &'a Engine,
+    patterns: &'a mut KeyPatterns,
+}
+
+impl ExprParser<'_> {
+    fn peek(&self) -> Option<BoolToken> {
+        self.tokens.get(self.pos).map(|t| t.kind)
+    }
+
+    fn text(&self, token: &Token) -> String {
+        self.chars[token.start..token.end].iter().collect()
+    }
+
+    fn err(&self, pos: usize, msg: String) -> FilterParseError {
+        FilterParseError::new(self.expr, Some(pos), msg)
+    }
+
+    // error of a missing comparison at current token, after the one before it
+    fn expect_comparison(&self) -> FilterParseError {
+        match (self.tokens.get(self.pos), self.pos.checked_sub(1).map(|n| &self.tokens[n])) {
+            (Some(found), _) => self.err(found.start, format!("expect comparison, found \"{}\"", self.text(found))),
+            (None, Some(last)) => self.err(self.chars.len(), format!("expect comparison after \"{}\"", self.text(last))),
+            (None, None) => self.err(0, "expect comparison, found empty expression".to_string()),
+        }
+    }
+
+    fn parse(mut self) -> Result<FilterExpr, FilterParseError> {
+        let parsed = self.parse_or()?;
+        match self.tokens.get(self.pos) {
+            Some(found) => Err(self.err(found.start, format!("expect \"and\", \"or\" or end of expression, found \"{}\"", self.text(found)))),
+            None => Ok(parsed),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let mut items = vec![self.parse_and()?];
+        while self.peek() == Some(BoolToken::Or) {
+            self.pos += 1;
+            items.push(self.parse_and()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { FilterExpr::Or(items) })
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let mut items = vec![self.parse_unary()?];
+        while self.peek() == Some(BoolToken::And) {
+            self.pos += 1;
+            items.push(self.parse_unary()?);
+        }
+        Ok(if items.len() == 1 { items.pop().unwrap() } else { FilterExpr::And(items) })
+    }
+
+    fn parse_unary(&mut self) -> Result<FilterExpr, FilterParseError> {
+        if self.peek() == Some(BoolToken::Not) {
+            self.pos += 1;
+            return Ok(FilterExpr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    // group in parentheses if followed by a boolean operator, ")" or end, otherwise
+    // a comparison taking parentheses of its own, e.g. "(size + 1) > 10"
+    fn parse_primary(&mut self) -> Result<FilterExpr, FilterParseError> {
+        match self.peek() {
+            None | Some(BoolToken::And) | Some(BoolToken::Or) | Some(BoolToken::Close) => return Err(self.expect_comparison()),
+            Some(BoolToken::Open) => {
+                let open = self.tokens[self.pos];
+                let Some(close) = self.matching_close(self.pos) else {
+                    return Err(self.err(self.chars.len(), format!("expect \")\" to close \"(\" at position {}", open.start)));
+                };
+                if matches!(self.tokens.get(close + 1).map(|t| t.kind), None | Some(BoolToken::And) | Some(BoolToken::Or) | Some(BoolToken::Close)) {
+                    self.pos += 1;
+                    let inner = self.parse_or()?;
+                    if self.pos != close {
+                        let found = self.tokens[self.pos];
+                        return Err(self.err(found.start, format!("expect \")\" to close \"(\" at position {}, found \"{}\"",
+                            open.start, self.text(&found))));
+                    }
+                    self.pos += 1;
+                    return Ok(inner);
+                }
+            },
+            _ => {},
+        }
+        self.parse_predicate()
+    }
+
+    fn matching_close(&self, open: usize) -> Option<usize> {
+        let mut depth = 0;
+        for (n, token) in self.tokens.iter().enumerate().skip(open) {
+            match token.kind {
+                BoolToken::Open => depth += 1,
+                BoolToken::Close => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(n);
+                    }
+                },
+                _ => {},
+            }
+        }
+        None
+    }
+
+    // comparison up to next "and" / "or" or unmatched ")" outside its own parentheses
+    fn parse_predicate(&mut self) -> Result<FilterExpr, FilterParseError> {
+        let first = self.pos;
+        let mut depth = 0;
+        while let Some(kind) = self.peek() {
+            match kind {
+                BoolToken::Open => depth += 1,
+                BoolToken::Close if depth == 0 => break,
+                BoolToken::Close => depth -= 1,
+                BoolToken::And | BoolToken::Or if depth == 0 => break,
+                _ => {},
+            }
+            self.pos += 1;
+        }
+        let (start, end) = (self.tokens[first].start, self.tokens[self.pos - 1].end);
+        let comparison: String = self.chars[start..end].iter().collect();
+        let ast = compile(self.engine, &comparison, self.patterns).map_err(|e| e.within(self.expr, start))?;
+        Ok(FilterExpr::Predicate(ast))
+    }
+}
+
+impl FilterExpr {
+    // parse boolean structure of expression, comparisons compiled with engine, errors in position of expression
+    pub(crate) fn parse(engine: &Engine, expr: &str, patterns: &mut KeyPatterns) -> Result<Self, FilterParseError> {
+        let chars: Vec<char> = expr.chars().collect();
+        let tokens = tokenize(&chars, expr)?;
+        ExprParser { expr, chars, tokens, pos: 0, engine, patterns }.parse()
+    }
+
+    pub(crate) fn eval(&self, engine: &Engine, scope: &mut Scope) -> Result<bool, Box<EvalAltResult>> {
+        match self {
+            Self::And(items) => {
+                for item in items {
+                    if !item.eval(engine, scope)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+            Self::Or(items) => {
+                for item in items {
+                    if item.eval(engine, scope)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            },
+            Self::Not(item) => item.eval(engine, scope).map(|matched| !matched),
+            Self::Predicate(ast) => engine.eval_ast_with_scope::<bool>(scope, ast),
+        }
+    }
+
+    // compiled comparisons, in order of expression
+    pub(crate) fn predicates(&self) -> Vec<&AST> {
+        match self {
+            Self::And(items) | Self::Or(items) => items.iter().flat_map(|item| item.predicates()).collect(),
+            Self::Not(item) => item.predicates(),
+            Self::Predicate(ast) => vec![ast],
+        }
+    }
+}
+
+// compiled filter expressions with a shared restricted engine, parsed once and evaluated on each object
+#[derive(Debug)]
+pub struct Filter {
+    engine: Engine,
+    // original expressions, errors of check are reported in them
+    exprs: Vec<String>,
+    parsed: Vec<FilterExpr>,
+    combine: FilterCombine,
+    // KEY is only pushed into scope if referred
+    uses_key: bool,
+    // TAGS as well, referred by "tag:" of filter
+    pub(crate) uses_tags: bool,
+    // owner props of SOURCE and TARGET
+    pub(crate) uses_owner: bool,
+    // startswith or endswith operators on key
+    pub(crate) uses_anchored: bool,
+    // KEY in scope is normalized by it, as are string comparisons
+    key_compare: KeyCompare,
+}
+
+impl Filter {
+    // compile all filter expressions with a shared restricted engine
+    pub(crate) fn compile(exprs: &[String], combine: FilterCombine, key_compare: KeyCompare) -> Result<Self, FilterParseError> {
+        let mut engine = Engine::new();
+        engine.set_fail_on_invalid_map_property(true)
+            .set_max_variables(4) // only SOURCE, TARGET, KEY and TAGS
+            .set_max_map_size(5); // only size, last_modified, storage_class, owner_id and owner_display_name
+        register_key_operators(&mut engine);
+        register_string_compare(&mut engine, key_compare);
+        register_tag_functions(&mut engine);
+
+        let mut parsed = Vec::with_capacity(exprs.len());
+        let mut patterns = KeyPatterns { key_compare, ..Default::default() };
+        for expr in exprs {
+            parsed.push(FilterExpr::parse(&engine, expr, &mut patterns)?);
+        }
+        let asts: Vec<&AST> = parsed.iter().flat_map(|p| p.predicates()).collect();
+        let uses_variable = |variable: &str| asts.iter().any(|ast| {
+            let mut found = false;
+            ast.walk(&mut |nodes| {
+                found |= matches!(nodes.last(), Some(rhai::ASTNode::Expr(rhai::Expr::Variable(names, _, _))) if names.1 == variable);
+                !found
+            });
+            found
+        });
+        let uses_key = !patterns.is_empty() || uses_variable("KEY");
+        let uses_tags = uses_variable("TAGS");
+        let uses_owner = asts.iter().any(|ast| {
+            let mut found = false;
+            ast.walk(&mut |nodes| {
+                found |= matches!(nodes.last(), Some(rhai::ASTNode::Expr(rhai::Expr::Property(props, _)))
+                    if OBJECT_FILTER_OWNER_PROPERTY.contains(&props.2.as_str()));
+                !found
+            });
+            found
+        });
+        let uses_anchored = asts.iter().any(|ast| {
+            let mut found = false;
+            ast.walk(&mut |nodes| {
+                // single call expression is kept as statement
+                found |= matches!(nodes.last(), Some(rhai::ASTNode::Expr(rhai::Expr::FnCall(call, _)))
+                    | Some(rhai::ASTNode::Stmt(rhai::Stmt::FnCall(call, _))) if FILTER_OP_ANCHORED.contains(&call.name.as_str()));
+                !found
+            });
+            found
+        });
+        register_key_functions(&mut engine, Arc::new(patterns));
+
+        Ok(Self {
+            engine,
+            exprs: exprs.to_vec(),
+            parsed,
+            combine,
+            uses_key,
+            uses_tags,
+            uses_owner,
+            uses_anchored,
+            key_compare,
+        })
+    }
+
+    // props of object in object map, owner is "" if not listed
+    fn props_dynamic(&self, props: &ObjectProps) -> rhai::Dynamic {
+        let mut map = to_dynamic(props).unwrap().cast::<rhai::Map>();
+        if self.uses_owner {
+            let owner = props.owner();
+            let id = owner.and_then(|o| o.id.clone()).unwrap_or_default();
+            let display_name = owner.and_then(|o| o.display_name.clone()).unwrap_or_default();
+            map.insert("owner_id".into(), id.into());
+            map.insert("owner_display_name".into(), display_name.into());
+        }
+        rhai::Dynamic::from_map(map).into_read_only()
+    }
+
+    // build scope of object key and props, TARGET only in diff mode
+    pub(crate) fn scope(&self, prefix: &ObjectPrefix, name: &ObjectName, source: &ObjectProps, target: Option<&ObjectProps>) -> Scope<'static> {
+        self.scope_with_key(|| ObjectKey::encode(prefix, name).split_version().0.to_string(), source, target)
+    }
+
+    // key is only built if referred
+    fn scope_with_key(&self, key: impl FnOnce() -> String, source: &ObjectProps, target: Option<&ObjectProps>) -> Scope<'static> {
+        let mut scope = Scope::new();
+        scope.push_constant_dynamic("SOURCE", self.props_dynamic(source));
+        if let Some(target) = target {
+            scope.push_constant_dynamic("TARGET", self.props_dynamic(target));
+        }
+        if self.uses_key {
+            let key = key();
+            let key = match self.key_compare.normalize(&key) {
+                std::borrow::Cow::Owned(normalized) => normalized,
+                std::borrow::Cow::Borrowed(_) => key,
+            };
+            scope.push_constant("KEY", key);
+        }
+        if self.uses_tags {
+            scope.push_constant("TAGS", FilterTags(source.tags()));
+        }
+        scope
+    }
+
+    // evaluate all expressions against scope, short-circuit by combine mode
+    pub(crate) fn eval(&self, scope: &mut Scope) -> Result<bool, Box<EvalAltResult>> {
+        for parsed in &self.parsed {
+            let matched = parsed.eval(&self.engine, scope)?;
+            match (self.combine, matched) {
+                (FilterCombine::And, false) => return Ok(false),
+                (FilterCombine::Or, true) => return Ok(true),
+                _ => {},
+            }
+        }
+        Ok(self.combine == FilterCombine::And)
+    }
+
+    // check any invalidation of input filter experssion
+    pub(crate) fn check_expr(&self, mode: RunMode) -> Result<(), FilterParseError> {
+
+        /*
+         * since rhai only check object map existence when the expression is executed to specific condition,
+         * go through AST to check all variable's props to ensure only allowed object props is used.
+        */
+        for (expr, ast) in self.exprs.iter().zip(&self.parsed).flat_map(|(expr, p)| p.predicates().into_iter().map(move |ast| (expr, ast))) {
+            let mut res = Ok(());
+            ast.walk(&mut |nodes| {
+                for node in nodes {
+                    let errmsg = match node {
+                        // Property(Box<((getter, hash), (setter, hash), prop)>, position)
+                        rhai::ASTNode::Expr(rhai::Expr::Property(props, _)) if !OBJECT_FILTER_ALLOWED_PROPERTY.contains(&props.2.as_str()) =>
+                            format!("object property \"{}\" not allowed", props.2),
+                        rhai::ASTNode::Expr(rhai::Expr::Variable(names, _, _)) if !OBJECT_FILTER_ALLOWED_VARIABLE.contains(&names.1.as_str()) =>
+                            format!("variable \"{}\" not allowed", names.1),
+                        _ => continue,
+                    };
+                    res = Err(FilterParseError::new(expr, None, errmsg));
+                    return false;
+                }
+                true
+            });
+            res?;
+        }
+
+        // then we build fake ObjectProps to test AST to see
+        // any other invalidation exist in expression
+        let props = ObjectProps::default();
+        let target = if mode == RunMode::BiDir { Some(&props) } else { None };
+        let mut scope = self.scope_with_key(String::new, &props, target);
+
+        // evaluate each comparison on its own, combine mode and short-circuit may skip some of them
+        for (expr, ast) in self.exprs.iter().zip(&self.parsed).flat_map(|(expr, p)| p.predicates().into_iter().map(move |ast| (expr, ast))) {
+            self.engine.eval_ast_with_scope::<bool>(&mut scope, ast)
+                .map_err(|e| FilterParseError::new(expr, None, format!("validation failed: {e}")))?;
+        }
+        Ok(())
+    }
+
+    // parse one filter expression of list mode, in syntax of "--filter"
+    pub fn parse(expr: &str) -> Result<Self, FilterParseError> {
+        let filter = Self::compile(&[expr.to_string()], FilterCombine::And, KeyCompare::Exact)?;
+        filter.check_expr(RunMode::List)?;
+        Ok(filter)
+    }
+
+    // object is kept by filter, one failed to evaluate is kept as in listing
+    pub fn matches(&self, record: &ObjectRecord) -> bool {
+        let mut scope = self.scope_with_key(|| record.key.split_version().0.to_string(), &record.props, None);
+        match self.eval(&mut scope) {
+            Ok(matched) => matched,
+            Err(e) => {
+                warn!("failed to apply filter for {:?}, error: {e:?}", record.key);
+                true
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::primitives::DateTime;
+    use aws_sdk_s3::types::Object;
+
+    fn eval_filter(exprs: &[&str], combine: FilterCombine, size: u64, last_modified: u64) -> bool {
+        let exprs: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+        let filter = Filter::compile(&exprs, combine, KeyCompare::Exact).unwrap();
+        assert!(filter.check_expr(RunMode::List).is_ok());
+
+        let object = Object::builder().size(size as i64).last_modified(DateTime::from_secs(last_modified as i64)).build();
+        let props = ObjectProps::from(&object);
+        let mut scope = filter.scope(&"data".to_string(), &"obj.bin".to_string(), &props, None);
+        filter.eval(&mut scope).unwrap()
+    }
+
+    #[test]
+    fn test_object_filter_combine() {
+        let exprs = ["SOURCE.size > 100", "SOURCE.last_modified > 1000"];
+
+        // and: all should match
+        assert!(eval_filter(&exprs, FilterCombine::And, 200, 2000));
+        assert!(!eval_filter(&exprs, FilterCombine::And, 200, 500));
+        assert!(!eval_filter(&exprs, FilterCombine::And, 50, 2000));
+
+        // or: any should match
+        assert!(eval_filter(&exprs, FilterCombine::Or, 200, 500));
+        assert!(eval_filter(&exprs, FilterCombine::Or, 50, 2000));
+        assert!(!eval_filter(&exprs, FilterCombine::Or, 50, 500));
+
+        // expression with its own boolean operators mixed with combine mode
+        let exprs = ["SOURCE.size > 100 && SOURCE.size < 300", "SOURCE.last_modified == 0"];
+        assert!(eval_filter(&exprs, FilterCombine::Or, 400, 0));
+        assert!(!eval_filter(&exprs, FilterCombine::And, 400, 0));
+        assert!(eval_filter(&exprs, FilterCombine::And, 200, 0));
+
+        // single filter behaves the same in both modes
+        assert!(eval_filter(&["SOURCE.size == 1"], FilterCombine::And, 1, 0));
+        assert!(eval_filter(&["SOURCE.size == 1"], FilterCombine::Or, 1, 0));
+    }
+
+    #[test]
+    fn test_object_filter_owner() {
+        let exprs = vec!["SOURCE.owner_id == \"abc\"".to_string()];
+        let filter = Filter::compile(&exprs, FilterCombine::And, KeyCompare::Exact).unwrap();
+        assert!(filter.check_expr(RunMode::List).is_ok() && filter.uses_owner);
+
+        let (prefix, name) = ("data".to_string(), "obj.bin".to_string());
+        let mut props = ObjectProps::default();
+        assert!(!filter.eval(&mut filter.scope(&prefix, &name, &props, None)).unwrap());
+        props.set_owner(Some(&aws_sdk_s3::types::Owner::builder().id("abc").build()));
+        assert!(filter.eval(&mut filter.scope(&prefix, &name, &props, None)).unwrap());
+    }
+
+    #[test]
+    fn test_filter_parse() {
+        let record = |key: &str, size: i64| ObjectRecord::from(&Object::builder().key(key).size(size).build());
+        let filter = Filter::parse(r#"size > 1KB and key glob "logs/**""#).unwrap();
+        assert!(filter.matches(&record("logs/2024/a.gz", 2048)));
+        assert!(!filter.matches(&record("logs/a.gz", 100)));
+        assert!(!filter.matches(&record("data/a.gz", 2048)));
+
+        assert!(Filter::parse("SOURCE.color == 1").is_err());
+        // no TARGET in list mode
+        assert!(Filter::parse("TARGET.size > 1").is_err());
+        assert!(Filter::parse("size >").is_err());
+    }
+
+    // shape of parsed expression, comparisons as "p"
+    fn shape(expr: &FilterExpr) -> String {
+        let join = |items: &[FilterExpr], op: &str| items.iter().map(shape).collect::<Vec<_>>().join(op);
+        match expr {
+            FilterExpr::And(items) => format!("({})", join(items, " and ")),
+            FilterExpr::Or(items) => format!("({})", join(items, " or ")),
+            FilterExpr::Not(item) => format!("not {}", shape(item)),
+            FilterExpr::Predicate(_) => "p".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_filter_expr() {
+        let mut engine = Engine::new();
+        register_key_operators(&mut engine);
+        let parse = |expr: &str| FilterExpr::parse(&engine, expr, &mut KeyPatterns::default());
+        let parsed_shape = |expr: &str| shape(&parse(expr).unwrap());
+
+        assert_eq!(parsed_shape(r#"(size > 1GB or key ~ ".*\.bak") and not key ~ "^tmp/""#), "((p or p) and not p)");
+        assert_eq!(parsed_shape("size > 1 and not size > 10 or size == 0"), "((p and not p) or p)");
+        assert_eq!(parsed_shape("size > 1 && (size < 5 || size == 9) && size != 3"), "(p and (p or p) and p)");
+        assert_eq!(parsed_shape("not not (size > 1)"), "not not p");
+        assert_eq!(parsed_shape("((size > 1))"), "p");
+        // parentheses of a comparison, tag name and property named as operators are not boolean structure
+        assert_eq!(parsed_shape("(size + 1) > 10 or (1) == size"), "(p or p)");
+        assert_eq!(parsed_shape(r#"tag:or = "and" and SOURCE.size > 1"#), "(p and p)");
+        assert_eq!(parsed_shape(r#"key = "a and b" or key = "not (x""#), "(p or p)");
+
+        // expected token at position of whole expression
+        let err = |expr: &str| parse(expr).unwrap_err();
+        let e = err("size > 1 and");
+        assert_eq!(e.position, Some(12));
+        assert!(e.to_string().starts_with(r#"expect comparison after "and""#), "{}", e);
+        let e = err("(size > 1 or size < 0");
+        assert_eq!(e.position, Some(21));
+        assert!(e.to_string().starts_with(r#"expect ")" to close "(" at position 0"#), "{}", e);
+        let e = err("size > 1) and size < 5");
+        assert_eq!(e.position, Some(8));
+        assert!(e.to_string().starts_with(r#"expect "and", "or" or end of expression, found ")""#), "{}", e);
+        assert_eq!(err("size > 1 or or size < 1").position, Some(12));
+        assert_eq!(err("").position, Some(0));
+        // error of comparison is reported in place of whole expression
+        assert_eq!(err("size > 1 and size > 100XB").position, Some(20));
+        assert_eq!(err(r#"size > 1 and key ~ "(unclosed""#).position, Some(19));
+    }
+
+    #[test]
+    fn test_filter_expr_short_circuit() {
+        let record = |key: &str, size: i64| ObjectRecord::from(&Object::builder().key(key).size(size).build());
+        let filter = Filter::parse(r#"(size > 1GB or key ~ ".*\.bak") and not key ~ "^tmp/""#).unwrap();
+        assert!(filter.matches(&record("data/a.bak", 1)));
+        assert!(filter.matches(&record("data/a", 2 << 30)));
+        assert!(!filter.matches(&record("tmp/a.bak", 1)));
+        assert!(!filter.matches(&record("data/a", 1)));
+
+        // right hand side is not evaluated once left one decides, it would fail on a function not known
+        let filter = Filter::compile(&["size > 1 or no_such_fn(size)".to_string()], FilterCombine::And, KeyCompare::Exact).unwrap();
+        let mut props = ObjectProps::from(&Object::builder().size(2).build());
+        let (prefix, name) = ("d/".to_string(), "a".to_string());
+        assert_eq!(filter.eval(&mut filter.scope(&prefix, &name, &props, None)).ok(), Some(true));
+        props = ObjectProps::from(&Object::builder().size(0).build());
+        assert!(filter.eval(&mut filter.scope(&prefix, &name, &props, None)).is_err());
+        // each comparison is still validated up front
+        assert!(filter.check_expr(RunMode::List).is_err());
+    }
+
+    fn rw(expr: &str) -> Result<String, FilterParseError> {
+        rewrite_expr(expr).map(|r| r.expr)
+    }
+
+    fn rw_at(expr: &str, now: i64) -> Result<String, FilterParseError> {
+        rewrite_expr_at(expr, now).map(|r| r.expr)
+    }
+
+    #[test]
+    fn test_rewrite_expr() {
+        assert_eq!(rw("size > 104857600").unwrap(), "SOURCE.size > 104857600");
+        assert_eq!(rw("size >= 1024 and size < 1048576").unwrap(),
+            "SOURCE.size >= 1024 && SOURCE.size < 1048576");
+        assert_eq!(rw("size > 100MB or TARGET.size == 1KiB").unwrap(),
+            "SOURCE.size > 104857600 || TARGET.size == 1024");
+        assert_eq!(rw("SOURCE.size > 1_000").unwrap(), "SOURCE.size > 1000");
+        assert_eq!(rw("storage_class = \"GLACIER\" and size != 0").unwrap(),
+            "SOURCE.storage_class == \"GLACIER\" && SOURCE.size != 0");
+        assert_eq!(rw("size <= 1 or size == 2").unwrap(), "SOURCE.size <= 1 || SOURCE.size == 2");
+
+        // existing rhai syntax is kept
+        let expr = "(SOURCE.size > 1000 && SOURCE.last_modified >= 1704038400) || TARGET.size == 100000";
+        assert_eq!(rw(expr).unwrap(), expr);
+
+        // string literal untouched
+        assert_eq!(rw("\"size and 1MB\"").unwrap(), "\"size and 1MB\"");
+
+        assert!(rw("size > 100XB").is_err());
+        assert!(rw("\"size").is_err());
+    }
+
+    #[test]
+    fn test_rewrite_expr_datetime() {
+        let now = 1704067200; // 2024-01-01T00:00:00Z
+        assert_eq!(rw_at("last_modified > 2024-01-01", now).unwrap(), "SOURCE.last_modified > 1704067200");
+        assert_eq!(rw_at("last_modified > 2024-01-01T01:00:00Z", now).unwrap(), "SOURCE.last_modified > 1704070800");
+        assert_eq!(rw_at("last_modified > 2024-01-01T01:00:00", now).unwrap(), "SOURCE.last_modified > 1704070800");
+        assert_eq!(rw_at("last_modified > 2024-01-01T02:00:00+01:00", now).unwrap(), "SOURCE.last_modified > 1704070800");
+        assert_eq!(rw_at("last_modified > now-7d", now).unwrap(), "SOURCE.last_modified > 1703462400");
+        assert_eq!(rw_at("last_modified > now - 1h and size > 1KB", now).unwrap(),
+            "SOURCE.last_modified > 1704063600 && SOURCE.size > 1024");
+        assert_eq!(rw_at("last_modified <= now", now).unwrap(), "SOURCE.last_modified <= 1704067200");
+        // arithmetic without unit is left to rhai
+        assert_eq!(rw_at("last_modified > now - 60", now).unwrap(), "SOURCE.last_modified > 1704067200 - 60");
+
+        assert!(rw_at("last_modified > 2024-13-01", now).is_err());
+        assert!(rw_at("last_modified > 2024-01-01Tfoo", now).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_expr_not() {
+        assert_eq!(rw("not size > 1KB").unwrap(), "!( SOURCE.size > 1024)");
+        assert_eq!(rw("size > 1 and not size > 10 or size == 0").unwrap(),
+            "SOURCE.size > 1 && !( SOURCE.size > 10 )|| SOURCE.size == 0");
+        assert_eq!(rw("not (size > 1 or size < 10) && size != 5").unwrap(),
+            "!( (SOURCE.size > 1 || SOURCE.size < 10) )&& SOURCE.size != 5");
+        assert_eq!(rw("(not not size > 1)").unwrap(), "(!( !( SOURCE.size > 1)))");
+    }
+
+    #[test]
+    fn test_rewrite_expr_tag() {
+        assert_eq!(rw(r#"tag:env = "prod""#).unwrap(), r#"tag_value(TAGS, "env") == "prod""#);
+        assert_eq!(rw(r#"tag:"cost center" != "" and size > 1"#).unwrap(),
+            r#"tag_value(TAGS, "cost center") != "" && SOURCE.size > 1"#);
+        assert_eq!(rw(r#"tag:'a"b' = 'x'"#).unwrap(), r#"tag_value(TAGS, "a\"b") == 'x'"#);
+        assert_eq!(rw("SOURCE.tag").unwrap(), "SOURCE.tag");
+        assert!(rw("tag: = 1").is_err());
+
+        let mut engine = Engine::new();
+        register_tag_functions(&mut engine);
+        let ast = compile(&engine, r#"tag:env = "prod""#, &mut KeyPatterns::default()).unwrap();
+        let eval = |tags: Option<ObjectTags>| {
+            let mut scope = rhai::Scope::new();
+            scope.push_constant("TAGS", FilterTags(tags.map(std::sync::Arc::new)));
+            engine.eval_ast_with_scope::<bool>(&mut scope, &ast).unwrap()
+        };
+        assert!(eval(Some(vec![("env".to_string(), "prod".to_string())])));
+        assert!(!eval(Some(vec![])));
+        assert!(!eval(None));
+    }
+
+    fn compile_expr(expr: &str) -> Result<AST, FilterParseError> {
+        let mut engine = Engine::new();
+        register_key_operators(&mut engine);
+        compile(&engine, expr, &mut KeyPatterns::default())
+    }
+
+    #[test]
+    fn test_compile_error_position() {
+        assert!(compile_expr("(size > 1GB or size < 1KB) and not size == 0").is_ok());
+
+        // position refers to original expression, not the rewritten one
+        let err = compile_expr("(size > 1GB or size < 1KB and size == 0").unwrap_err();
+        assert_eq!(err.position, Some(39));
+        let err = compile_expr("size > 1GB and and size < 1").unwrap_err();
+        assert_eq!(err.position, Some(15));
+        let err = compile_expr("size > 100XB").unwrap_err();
+        assert_eq!(err.position, Some(7));
+    }
+
+    #[test]
+    fn test_key_patterns() {
+        assert_eq!(glob_to_regex("data/*.parquet"), "^data/[^/]*\\.parquet$");
+
+        let mut engine = Engine::new();
+        register_key_operators(&mut engine);
+        let mut patterns = KeyPatterns::default();
+        let asts: Vec<AST> = [
+            r#"key ~ "\.bak$" and not key ~ "^tmp/""#,
+            r#"key glob "data/*/2024/??/*.parquet""#,
+            r#"key glob "logs/**/*.gz" or key glob "[!a-c]*""#,
+        ].iter().map(|expr| compile(&engine, expr, &mut patterns).unwrap()).collect();
+        register_key_functions(&mut engine, Arc::new(patterns));
+
+        let eval = |n: usize, key: &str| {
+            let mut scope = rhai::Scope::new();
+            scope.push_constant("KEY", key.to_string());
+            engine.eval_ast_with_scope::<bool>(&mut scope, &asts[n]).unwrap()
+        };
+        assert!(eval(0, "data/a.bak"));
+        assert!(!eval(0, "tmp/a.bak"));
+        assert!(!eval(0, "data/a.bak.gz"));
+        assert!(eval(1, "data/x/2024/01/a.parquet"));
+        assert!(!eval(1, "data/x/y/2024/01/a.parquet"));
+        assert!(!eval(1, "data/x/2024/1/a.parquet"));
+        assert!(eval(2, "logs/a.gz"));
+        assert!(eval(2, "logs/2024/01/a.gz"));
+        assert!(eval(2, "data"));
+        assert!(!eval(2, "data/a"));
+        assert!(!eval(2, "ba"));
+
+        let ast = compile(&engine, r#"key startswith "data/" and not key endswith ".tmp" or key endswith "~""#, &mut KeyPatterns::default()).unwrap();
+        let eval_anchored = |key: &str| {
+            let mut scope = rhai::Scope::new();
+            scope.push_constant("KEY", key.to_string());
+            engine.eval_ast_with_scope::<bool>(&mut scope, &ast).unwrap()
+        };
+        assert!(eval_anchored("data/a.parquet"));
+        assert!(!eval_anchored("data/a.tmp"));
+        assert!(!eval_anchored("logs/data/a"));
+        assert!(eval_anchored("logs/a~"));
+        // "." of suffix is a plain char, not any char of regex
+        assert!(eval_anchored("data/a_tmp"));
+
+        // invalid pattern and non literal pattern rejected at compile time
+        let err = compile_expr(r#"size > 1 and key ~ "(unclosed""#).unwrap_err();
+        assert_eq!(err.position, Some(19));
+        assert!(compile_expr(r#"key ~ key"#).is_err());
+    }
+
+    #[test]
+    fn test_filter_key_compare() {
+        let props = ObjectProps::default();
+        let eval = |expr: &str, key_compare: KeyCompare, prefix: &str, name: &str| {
+            let filter = Filter::compile(&[expr.to_string()], FilterCombine::And, key_compare).unwrap();
+            let mut scope = filter.scope(&prefix.to_string(), &name.to_string(), &props, None);
+            filter.eval(&mut scope).unwrap()
+        };
+        let expr = r#"key = "Data/A.txt""#;
+        assert!(!eval(expr, KeyCompare::Exact, "data", "a.txt"));
+        assert!(eval(expr, KeyCompare::CaseInsensitive, "data", "a.txt"));
+        assert!(!eval(r#"key != "DATA/a.TXT""#, KeyCompare::CaseInsensitive, "Data", "A.txt"));
+        assert!(eval(r#"key startswith "DATA/" and key endswith ".Txt""#, KeyCompare::CaseInsensitive, "data", "a.txt"));
+        assert!(eval(r#"key glob "Data/*.TXT""#, KeyCompare::CaseInsensitive, "data", "a.txt"));
+        // "\D" is still non-digit with case ignored, not turned into "\d"
+        assert!(eval(r#"key ~ "^\D+/A""#, KeyCompare::CaseInsensitive, "data", "a.txt"));
+        assert!(!eval(r#"key ~ "^\D+/A""#, KeyCompare::Exact, "data", "a.txt"));
+
+        // "e" followed by combining acute accent in literal, composed one in key
+        let expr = "key = \"caf\u{65}\u{301}.txt\"";
+        assert!(!eval(expr, KeyCompare::Exact, "/", "caf\u{e9}.txt"));
+        assert!(eval(expr, KeyCompare::Nfc, "/", "caf\u{e9}.txt"));
+        assert!(eval("key ~ \"^caf\u{65}\u{301}\"", KeyCompare::Nfc, "/", "caf\u{e9}.txt"));
+        assert!(!eval(r#"key = "CAFE.txt""#, KeyCompare::Nfc, "/", "cafe.txt"));
+    }
+}
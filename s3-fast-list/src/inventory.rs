@@ -0,0 +1,463 @@
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Instant;
+use arrow::array::{Array, AsArray};
+use arrow::compute::cast;
+use arrow::datatypes::{DataType, Int64Type, TimeUnit};
+use aws_sdk_s3::primitives::DateTime;
+use aws_sdk_s3::types::{Object, ObjectStorageClass};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::Deserialize;
+use log::{info, error};
+use crate::core::{self, S3TaskContext};
+use crate::error::InventoryError;
+use crate::tasks_s3;
+use crate::utils;
+
+// objects sent to data map in one batch, same as a page of list response
+const INVENTORY_BATCH_OBJECTS: usize = 1000;
+const INVENTORY_ARN_PREFIX: &str = "arn:aws:s3:::";
+
+// manifest.json of an S3 Inventory report, data files are keys of destination bucket
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub source_bucket: String,
+    pub destination_bucket: String,
+    pub file_format: String,
+    // column names of csv files in order, e.g. "Bucket, Key, Size, LastModifiedDate"
+    #[serde(default)]
+    pub file_schema: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ManifestFile {
+    pub key: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+impl Manifest {
+    pub fn parse(buf: &[u8]) -> Result<Self, String> {
+        let manifest: Self = serde_json::from_slice(buf).map_err(|e| e.to_string())?;
+        match manifest.file_format.to_ascii_lowercase().as_str() {
+            "csv" | "parquet" => Ok(manifest),
+            "orc" => Err("ORC inventory is not supported, configure inventory as CSV or Parquet".to_string()),
+            format => Err(format!("unknown inventory file format {}", format)),
+        }
+    }
+
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let buf = std::fs::read(filename).map_err(|e| e.to_string())?;
+        Self::parse(&buf)
+    }
+
+    // destination bucket is in arn form
+    pub fn destination_bucket_name(&self) -> &str {
+        self.destination_bucket.strip_prefix(INVENTORY_ARN_PREFIX).unwrap_or(&self.destination_bucket)
+    }
+}
+
+// manifest is either "s3://bucket/key" or a local file
+pub fn parse_s3_location(location: &str) -> Option<(&str, &str)> {
+    location.strip_prefix("s3://")?.split_once('/')
+}
+
+// one row of inventory, version columns are only there if inventory includes all versions
+#[derive(Debug, Clone, Default, PartialEq)]
+struct InventoryRow {
+    key: String,
+    size: i64,
+    last_modified_ms: Option<i64>,
+    etag: Option<String>,
+    storage_class: Option<String>,
+    is_latest: bool,
+    is_delete_marker: bool,
+}
+
+impl InventoryRow {
+    // same shape as an object of list response, goes through the same conversion into object props
+    fn to_object(&self) -> Object {
+        // etag of list response is quoted, inventory one is not
+        let etag = self.etag.as_deref().filter(|e| core::is_etag(e)).map(|e| format!("\"{}\"", e));
+        Object::builder()
+            .key(&self.key)
+            .size(self.size)
+            .set_last_modified(self.last_modified_ms.map(DateTime::from_millis))
+            .set_e_tag(etag)
+            .set_storage_class(self.storage_class.as_deref().filter(|s| !s.is_empty()).map(ObjectStorageClass::from))
+            .build()
+    }
+}
+
+// keys of csv inventory are url encoded, space as "+"
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 2;
+                    },
+                    None => out.push(b'%'),
+                }
+            },
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn parse_csv(data: &[u8], file_schema: &str) -> Result<Vec<InventoryRow>, String> {
+    let columns: Vec<String> = file_schema.split(',').map(|c| c.trim().to_string()).collect();
+    let column = |name: &str| columns.iter().position(|c| c == name);
+    let key_idx = column("Key").ok_or("no Key column in file schema of manifest")?;
+    let (size_idx, mtime_idx, etag_idx, class_idx) = (column("Size"), column("LastModifiedDate"), column("ETag"), column("StorageClass"));
+    let (latest_idx, marker_idx) = (column("IsLatest"), column("IsDeleteMarker"));
+
+    let mut reader = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(data);
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let field = |idx: Option<usize>| idx.and_then(|i| record.get(i)).filter(|v| !v.is_empty());
+        let last_modified_ms = match field(mtime_idx) {
+            Some(v) => Some(chrono::DateTime::parse_from_rfc3339(v).map_err(|e| format!("last modified date \"{}\": {}", v, e))?.timestamp_millis()),
+            None => None,
+        };
+        rows.push(InventoryRow {
+            key: url_decode(field(Some(key_idx)).unwrap_or_default()),
+            size: field(size_idx).map_or(Ok(0), |v| v.parse()).map_err(|e| format!("size: {}", e))?,
+            last_modified_ms,
+            etag: field(etag_idx).map(|v| v.to_string()),
+            storage_class: field(class_idx).map(|v| v.to_string()),
+            is_latest: field(latest_idx).map_or(true, |v| v == "true"),
+            is_delete_marker: field(marker_idx).is_some_and(|v| v == "true"),
+        });
+    }
+    Ok(rows)
+}
+
+fn parse_parquet(data: bytes::Bytes) -> Result<Vec<InventoryRow>, String> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(data).map_err(|e| e.to_string())?
+        .build().map_err(|e| e.to_string())?;
+
+    let mut rows = Vec::new();
+    for batch in reader {
+        let batch = batch.map_err(|e| e.to_string())?;
+        let column = |name: &str, data_type: &DataType| -> Result<Option<Arc<dyn Array>>, String> {
+            batch.column_by_name(name).map(|c| cast(c, data_type).map_err(|e| format!("column {}: {}", name, e))).transpose()
+        };
+        let keys = column("key", &DataType::Utf8)?.ok_or("no key column in parquet inventory")?;
+        let sizes = column("size", &DataType::Int64)?;
+        // timestamps of any unit normalized to millis
+        let mtimes = match column("last_modified_date", &DataType::Timestamp(TimeUnit::Millisecond, None))? {
+            Some(c) => Some(cast(&c, &DataType::Int64).map_err(|e| e.to_string())?),
+            None => None,
+        };
+        let etags = column("e_tag", &DataType::Utf8)?;
+        let classes = column("storage_class", &DataType::Utf8)?;
+        let latests = column("is_latest", &DataType::Boolean)?;
+        let markers = column("is_delete_marker", &DataType::Boolean)?;
+
+        let string = |c: &Option<Arc<dyn Array>>, i: usize| c.as_ref()
+            .filter(|c| c.is_valid(i)).map(|c| c.as_string::<i32>().value(i).to_string());
+        let int = |c: &Option<Arc<dyn Array>>, i: usize| c.as_ref()
+            .filter(|c| c.is_valid(i)).map(|c| c.as_primitive::<Int64Type>().value(i));
+        let bool = |c: &Option<Arc<dyn Array>>, i: usize| c.as_ref()
+            .filter(|c| c.is_valid(i)).map(|c| c.as_boolean().value(i));
+
+        let keys = Some(keys);
+        for i in 0..batch.num_rows() {
+            let Some(key) = string(&keys, i) else {
+                continue;
+            };
+            rows.push(InventoryRow {
+                key,
+                size: int(&sizes, i).unwrap_or(0),
+                last_modified_ms: int(&mtimes, i),
+                etag: string(&etags, i),
+                storage_class: string(&classes, i),
+                is_latest: bool(&latests, i).unwrap_or(true),
+                is_delete_marker: bool(&markers, i).unwrap_or(false),
+            });
+        }
+    }
+    Ok(rows)
+}
+
+// raw bytes of a manifest or data file, from data dir standing for destination bucket if given
+async fn fetch(ctx: &S3TaskContext, bucket: &str, key: &str, data_dir: Option<&str>) -> Result<Vec<u8>, String> {
+    if let Some(dir) = data_dir {
+        let path = std::path::Path::new(dir).join(key);
+        return tokio::fs::read(&path).await.map_err(|e| format!("{}: {}", path.display(), e));
+    }
+    let output = ctx.s3_client().get_object()
+        .bucket(bucket)
+        .key(key)
+        .set_request_payer(ctx.request_payer.clone())
+        .send().await
+        .map_err(|e| format!("s3://{}/{}: {}", bucket, key, aws_sdk_s3::error::DisplayErrorContext(e)))?;
+    let body = output.body.collect().await.map_err(|e| format!("s3://{}/{}: {}", bucket, key, e))?;
+    Ok(body.into_bytes().to_vec())
+}
+
+async fn load_manifest(ctx: &S3TaskContext, location: &str) -> Result<Manifest, String> {
+    match parse_s3_location(location) {
+        Some((bucket, key)) => Manifest::parse(&fetch(ctx, bucket, key, None).await?),
+        None => Manifest::load(location),
+    }
+}
+
+// rows of one data file, gzip csv is decompressed first
+fn parse_data_file(manifest: &Manifest, key: &str, data: Vec<u8>) -> Result<Vec<InventoryRow>, String> {
+    if manifest.file_format.eq_ignore_ascii_case("parquet") {
+        return parse_parquet(bytes::Bytes::from(data));
+    }
+    if key.ends_with(".gz") {
+        let mut plain = Vec::new();
+        flate2::read::MultiGzDecoder::new(data.as_slice()).read_to_end(&mut plain).map_err(|e| e.to_string())?;
+        return parse_csv(&plain, &manifest.file_schema);
+    }
+    parse_csv(&data, &manifest.file_schema)
+}
+
+/*
+ * task to feed objects of an S3 Inventory report into data map, in place of list task of one side.
+ * data files are fetched concurrently and parsed in parallel, up to one per worker thread of runtime,
+ * rows under start prefixes and not excluded are sent as objects, only current versions are taken
+ * from inventory of all versions.
+ */
+pub async fn inventory_main_task(ctx: &S3TaskContext, location: &str, data_dir: Option<String>,
+        start_prefixes: Vec<String>, flat_concurrency: usize, threads: usize) {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("Inventory Task - {} - started, manifest {}", ctx.s3_bucket_name, location);
+    let started = Instant::now();
+
+    let manifest = match load_manifest(ctx, location).await {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            error!("Inventory Task - {} - {}", ctx.s3_bucket_name, InventoryError::new(location, e));
+            ctx.g_state.set_list_failed();
+            ctx.complete();
+            return;
+        }
+    };
+    let total_bytes: u64 = manifest.files.iter().map(|f| f.size).sum();
+    info!("Inventory Task - {} - inventory of bucket {}, {} {} files of {} in bucket {}, parsed by {} threads", ctx.s3_bucket_name,
+        manifest.source_bucket, manifest.files.len(), manifest.file_format, utils::fmt_bytes_human(total_bytes),
+        manifest.destination_bucket_name(), threads);
+
+    let manifest = Arc::new(manifest);
+    let start_prefixes = Arc::new(start_prefixes);
+    // fetch of data files is capped by concurrency, parsing by threads
+    let parsers = Arc::new(tokio::sync::Semaphore::new(threads.max(1)));
+    let mut set = tokio::task::JoinSet::new();
+    let mut failed = false;
+    let (mut count, mut bytes) = (0, 0);
+    for file in manifest.files.iter() {
+        while set.len() >= flat_concurrency && !failed {
+            match set.join_next().await {
+                Some(Ok(Ok((n, b)))) => (count, bytes) = (count + n, bytes + b),
+                Some(Ok(Err(e))) => {
+                    error!("Inventory Task - {} - {}", ctx.s3_bucket_name, e);
+                    failed = true;
+                },
+                Some(Err(e)) => {
+                    error!("Inventory Task - {} - {}", ctx.s3_bucket_name, e);
+                    failed = true;
+                },
+                None => {},
+            }
+        }
+        ctx.g_state.wait_for_memory().await;
+        if ctx.is_quit() || failed {
+            set.abort_all();
+            info!("Inventory Task - {} - all handler aborted", ctx.s3_bucket_name);
+            break;
+        }
+
+        let task_ctx = ctx.clone();
+        let manifest = manifest.clone();
+        let start_prefixes = start_prefixes.clone();
+        let parsers = parsers.clone();
+        let data_dir = data_dir.clone();
+        let key = file.key.clone();
+        set.spawn(async move {
+            task_ctx.g_state.inc_active_list_tasks();
+            let res = read_data_file(&task_ctx, &manifest, &key, data_dir.as_deref(), &start_prefixes, &parsers).await;
+            task_ctx.g_state.dec_active_list_tasks();
+            res.map_err(|e| InventoryError::new(&key, e))
+        });
+    }
+    while let Some(res) = set.join_next().await {
+        match res {
+            Ok(Ok((n, b))) => (count, bytes) = (count + n, bytes + b),
+            Ok(Err(e)) => {
+                error!("Inventory Task - {} - {}", ctx.s3_bucket_name, e);
+                failed = true;
+            },
+            // aborted on quit or failure
+            Err(_) => {},
+        }
+    }
+
+    if failed {
+        ctx.g_state.set_list_failed();
+    } else {
+        let elapsed = started.elapsed().as_secs_f64().max(0.001);
+        info!("Inventory Task - {} - {} objects loaded from {} of data files in {:.1}s, {} objects/s, {}/s", ctx.s3_bucket_name,
+            utils::fmt_count(count), utils::fmt_bytes_human(bytes as u64), elapsed, utils::fmt_count((count as f64 / elapsed) as usize),
+            utils::fmt_bytes_human((bytes as f64 / elapsed) as u64));
+    }
+    if ctx.is_running() {
+        ctx.complete();
+        if !failed {
+            info!("Inventory Task - {} - completed", ctx.s3_bucket_name);
+            tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+        }
+    }
+    info!("Inventory Task - {} - quit", ctx.s3_bucket_name);
+}
+
+// fetch one data file, parse it on a blocking thread once a parser is free, and send its objects to data map,
+// gives (objects, bytes of data file)
+async fn read_data_file(ctx: &S3TaskContext, manifest: &Arc<Manifest>, key: &str, data_dir: Option<&str>,
+        start_prefixes: &Arc<Vec<String>>, parsers: &tokio::sync::Semaphore) -> Result<(usize, usize), String> {
+
+    let data = fetch(ctx, manifest.destination_bucket_name(), key, data_dir).await?;
+    let bytes = data.len();
+    let _permit = parsers.acquire().await.map_err(|e| e.to_string())?;
+    let (task_ctx, manifest, start_prefixes, key) = (ctx.clone(), manifest.clone(), start_prefixes.clone(), key.to_string());
+    let count = tokio::task::spawn_blocking(move || -> Result<usize, String> {
+        let rows = parse_data_file(&manifest, &key, data)?;
+        let objects: Vec<Object> = rows.iter()
+            .filter(|r| r.is_latest && !r.is_delete_marker)
+            .filter(|r| start_prefixes.iter().any(|p| r.key.starts_with(p.as_str())) && !task_ctx.is_excluded(&r.key))
+            .map(|r| r.to_object())
+            .collect();
+        for chunk in objects.chunks(INVENTORY_BATCH_OBJECTS) {
+            tasks_s3::send_objects(&task_ctx, chunk);
+        }
+        Ok(objects.len())
+    }).await.map_err(|e| e.to_string())??;
+    Ok((count, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_inventory() {
+        let manifest = Manifest::parse(br#"{
+            "sourceBucket": "src", "destinationBucket": "arn:aws:s3:::inv",
+            "fileFormat": "CSV", "fileSchema": "Bucket, Key, Size, LastModifiedDate, ETag, StorageClass, IsLatest",
+            "files": [{"key": "src/config/data/a.csv.gz", "size": 10, "MD5checksum": "x"}]
+        }"#).unwrap();
+        assert_eq!(manifest.destination_bucket_name(), "inv");
+        assert!(Manifest::parse(br#"{"sourceBucket": "s", "destinationBucket": "d", "fileFormat": "ORC", "files": []}"#).is_err());
+        assert_eq!(parse_s3_location("s3://inv/src/manifest.json"), Some(("inv", "src/manifest.json")));
+        assert_eq!(parse_s3_location("manifest.json"), None);
+
+        let data = b"\"src\",\"dir/a+b%2Fc%ZZ\",\"12\",\"2024-01-01T00:00:00.000Z\",\"d41d8cd98f00b204e9800998ecf8427e\",\"GLACIER\",\"true\"\n\
+            \"src\",\"dir/old\",\"1\",\"2024-01-01T00:00:00.000Z\",\"c8af37b371ec442ad415feeb87d83246-2\",\"STANDARD\",\"false\"\n";
+        let rows = parse_csv(data, &manifest.file_schema).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].key, "dir/a b/c%ZZ");
+        assert_eq!(rows[0].size, 12);
+        assert_eq!(rows[0].last_modified_ms, Some(1704067200000));
+        assert!(rows[0].is_latest && !rows[1].is_latest);
+
+        let obj = rows[1].to_object();
+        assert_eq!(obj.e_tag(), Some("\"c8af37b371ec442ad415feeb87d83246-2\""));
+        assert_eq!(obj.last_modified().map(|t| t.secs()), Some(1704067200));
+        let props = core::ObjectProps::from(&rows[0].to_object());
+        assert_eq!(props.etag_string(), "d41d8cd98f00b204e9800998ecf8427e");
+
+        // malformed etag is dropped, the row is still listed
+        let data = b"\"src\",\"a\",\"1\",\"2024-01-01T00:00:00.000Z\",\"d41d8cd98f00b204e9800998ecf8427e-abc\",\"STANDARD\",\"true\"\n\
+            \"src\",\"b\",\"1\",\"2024-01-01T00:00:00.000Z\",\"d41d8cd98f00b204e9800998ecf8427ex\",\"STANDARD\",\"true\"\n";
+        let rows = parse_csv(data, &manifest.file_schema).unwrap();
+        assert_eq!(rows.len(), 2);
+        for row in &rows {
+            assert_eq!(row.to_object().e_tag(), None, "{:?}", row.etag);
+            assert_eq!(core::ObjectProps::from(&row.to_object()).etag(), ([0; 16], 0));
+        }
+    }
+
+    #[test]
+    fn test_parse_parquet_inventory() {
+        use std::sync::Arc;
+        use arrow::array::{BooleanArray, Int64Array, RecordBatch, StringArray, TimestampMillisecondArray};
+
+        let batch = RecordBatch::try_from_iter(vec![
+            ("key", Arc::new(StringArray::from(vec!["a b", "c"])) as Arc<dyn Array>),
+            ("size", Arc::new(Int64Array::from(vec![1, 2]))),
+            ("last_modified_date", Arc::new(TimestampMillisecondArray::from(vec![1704067200000, 0]).with_timezone("UTC"))),
+            ("e_tag", Arc::new(StringArray::from(vec![Some("d41d8cd98f00b204e9800998ecf8427e"), None]))),
+            ("is_delete_marker", Arc::new(BooleanArray::from(vec![false, true]))),
+        ]).unwrap();
+        let mut buf = Vec::new();
+        let mut writer = parquet::arrow::ArrowWriter::try_new(&mut buf, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let rows = parse_parquet(bytes::Bytes::from(buf)).unwrap();
+        assert_eq!(rows.len(), 2);
+        // keys of parquet inventory are not url encoded
+        assert_eq!(rows[0].key, "a b");
+        assert_eq!(rows[0].last_modified_ms, Some(1704067200000));
+        assert_eq!(rows[1].etag, None);
+        assert!(rows[0].is_latest && rows[1].is_delete_marker);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_inventory_task() {
+        use std::io::Write;
+        let dir = std::env::temp_dir().join(format!("inventory-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        let mut files = Vec::new();
+        for i in 0..4 {
+            let rows: String = (0..300).map(|j| format!("\"src\",\"dir/{}/{}\",\"{}\"\n", i, j, j)).collect();
+            let key = format!("data/{}.csv.gz", i);
+            let mut encoder = flate2::write::GzEncoder::new(std::fs::File::create(dir.join(&key)).unwrap(), flate2::Compression::default());
+            encoder.write_all(rows.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+            files.push(format!("{{\"key\": \"{}\", \"size\": 1}}", key));
+        }
+        let manifest = dir.join("manifest.json");
+        std::fs::write(&manifest, format!(r#"{{"sourceBucket": "src", "destinationBucket": "arn:aws:s3:::inv",
+            "fileFormat": "CSV", "fileSchema": "Bucket, Key, Size", "files": [{}]}}"#, files.join(","))).unwrap();
+
+        let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string());
+        let config = aws_sdk_s3::Config::builder().behavior_version(aws_sdk_s3::config::BehaviorVersion::latest()).build();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let ctx = S3TaskContext::with_client("src", aws_sdk_s3::Client::from_conf(config), tx, core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE, g_state.clone());
+        // files fetched 3 at a time, parsed 2 at a time
+        inventory_main_task(&ctx, manifest.to_str().unwrap(), Some(dir.to_string_lossy().to_string()),
+            vec!["dir/1/".to_string(), "dir/3/".to_string()], 3, 2).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!g_state.is_list_failed());
+
+        let mut keys = Vec::new();
+        while let Ok(hash) = rx.try_recv() {
+            for (prefix, items) in hash {
+                keys.extend(items.iter().map(|(name, _)| core::ObjectKey::encode(&prefix, name).as_str().to_string()));
+            }
+        }
+        keys.sort();
+        let mut expected: Vec<String> = [1, 3].iter().flat_map(|i| (0..300).map(move |j| format!("dir/{}/{}", i, j))).collect();
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+}
@@ -0,0 +1,26 @@
+// object filter and record types are public for use as a library, the rest backs the s3-fast-list binary
+mod error;
+mod core;
+mod data_map;
+mod tasks_s3;
+mod utils;
+mod stats;
+mod mon;
+mod coverage;
+mod checkpoint;
+pub mod filter;
+mod metrics;
+#[cfg(unix)]
+mod control;
+mod inventory;
+mod baseline;
+mod watch;
+mod script;
+mod config;
+mod provenance;
+mod report;
+mod credentials;
+mod batch;
+pub mod cli;
+
+pub use crate::core::{ObjectKey, ObjectProps, ObjectRecord};
@@ -0,0 +1,163 @@
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+use log::{info, warn, error};
+use crate::core::GlobalState;
+
+const METRICS_REQUEST_MAX_BYTES: usize = 8192;
+const METRICS_REQUEST_TIMEOUT_SECS: u64 = 5;
+// min interval of two samples of request rate, scrapes in between reuse last rate
+const METRICS_RPS_MIN_INTERVAL_SECS: f64 = 1.0;
+
+// list requests sampled at last scrape, to get current request rate
+struct RpsSampler {
+    last_ts: Instant,
+    last_requests: usize,
+    rps: f64,
+}
+
+impl RpsSampler {
+    fn new(requests: usize) -> Self {
+        Self {
+            last_ts: Instant::now(),
+            last_requests: requests,
+            rps: 0.0,
+        }
+    }
+
+    fn sample(&mut self, requests: usize) -> f64 {
+        let elapsed = self.last_ts.elapsed().as_secs_f64();
+        if elapsed >= METRICS_RPS_MIN_INTERVAL_SECS {
+            self.rps = (requests - self.last_requests) as f64 / elapsed;
+            self.last_ts = Instant::now();
+            self.last_requests = requests;
+        }
+        self.rps
+    }
+}
+
+// serve live counters of global state in prometheus text format on GET /metrics,
+// runs until aborted by mon task on quit
+pub async fn metrics_server(addr: SocketAddr, g_state: GlobalState) {
+
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Metrics Server - unable to listen on {}: {}", addr, e);
+            return;
+        }
+    };
+    info!("Metrics Server - serving on http://{}/metrics", addr);
+
+    let sampler = Arc::new(Mutex::new(RpsSampler::new(g_state.read_list_request())));
+    loop {
+        let (stream, _) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Metrics Server - accept error: {}", e);
+                continue;
+            }
+        };
+        let g_state = g_state.clone();
+        let sampler = sampler.clone();
+        tokio::spawn(async move {
+            let timeout = tokio::time::Duration::from_secs(METRICS_REQUEST_TIMEOUT_SECS);
+            let _ = tokio::time::timeout(timeout, handle_request(stream, &g_state, &sampler)).await;
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, g_state: &GlobalState,
+        sampler: &Mutex<RpsSampler>) -> std::io::Result<()> {
+
+    // only request line matters, read until end of headers
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !buf.windows(4).any(|w| w == b"\r\n\r\n") && buf.len() < METRICS_REQUEST_MAX_BYTES {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    let request = String::from_utf8_lossy(&buf);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+
+    let (status, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let rps = sampler.lock().unwrap().sample(g_state.read_list_request());
+            ("200 OK", render_metrics(g_state, rps))
+        },
+        (Some("GET"), Some(_)) => ("404 Not Found", "not found, try /metrics\n".to_string()),
+        _ => ("405 Method Not Allowed", "only GET is supported\n".to_string()),
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body);
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn render_metrics(g_state: &GlobalState, rps: f64) -> String {
+
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, labels: String, value: String| {
+        let _ = writeln!(out, "# HELP s3_fast_list_{name} {help}");
+        let _ = writeln!(out, "# TYPE s3_fast_list_{name} {kind}");
+        let _ = writeln!(out, "s3_fast_list_{name}{labels} {value}");
+    };
+
+    // run id is only on info metric, join on it to tell runs apart
+    let labels = format!("{{run_id=\"{}\",version=\"{}\"}}", escape_label(g_state.run_id()), env!("CARGO_PKG_VERSION"));
+    metric("info", "gauge", "Run of s3-fast-list, labeled with run id and version.",
+        labels, "1".to_string());
+    metric("objects_listed_total", "counter", "Objects returned by list requests of all buckets.",
+        String::new(), g_state.read_listed_objects().to_string());
+    metric("bytes_listed_total", "counter", "Sum of size of objects returned by list requests.",
+        String::new(), g_state.read_listed_bytes().to_string());
+    metric("list_requests_total", "counter", "List requests issued.",
+        String::new(), g_state.read_list_request().to_string());
+    metric("list_retries_total", "counter", "List requests retried by retry policy.",
+        String::new(), g_state.read_list_retry().to_string());
+    metric("list_requests_per_second", "gauge", "List requests per second since previous scrape.",
+        String::new(), format!("{:.3}", rps));
+    metric("active_list_tasks", "gauge", "Key space partitions being listed.",
+        String::new(), g_state.read_active_list_tasks().to_string());
+    metric("data_map_channel_batches", "gauge", "Batches of list results waiting for the data map task.",
+        String::new(), g_state.read_queued_batches().to_string());
+    metric("channel_backpressure_total", "counter", "Times list tasks paused on backlog of data map channel.",
+        String::new(), g_state.read_backpressure().to_string());
+
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_render_metrics() {
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "run-\"1\"".to_string());
+        g_state.add_listed(3, 1024);
+        g_state.inc_list_request();
+        g_state.inc_active_list_tasks();
+
+        let out = render_metrics(&g_state, 1.5);
+        assert!(out.contains("s3_fast_list_info{run_id=\"run-\\\"1\\\"\",version=\""));
+        assert!(out.contains("\ns3_fast_list_objects_listed_total 3\n"));
+        assert!(out.contains("\ns3_fast_list_bytes_listed_total 1024\n"));
+        assert!(out.contains("\ns3_fast_list_list_requests_total 1\n"));
+        assert!(out.contains("\ns3_fast_list_list_requests_per_second 1.500\n"));
+        assert!(out.contains("\ns3_fast_list_active_list_tasks 1\n"));
+        assert!(out.contains("# TYPE s3_fast_list_list_retries_total counter\n"));
+        g_state.set_queued_batches(7);
+        assert!(render_metrics(&g_state, 0.0).contains("\ns3_fast_list_data_map_channel_batches 7\n"));
+    }
+}
@@ -1,32 +1,378 @@
-use log::info;
-use crate::core::{MonContext, DEFAULT_TASK_HEARTBEAT_INTERVAL_SECS};
+use std::io::Write;
+use log::{info, warn};
+use crate::core::{GlobalState, MonContext, DEFAULT_TASK_HEARTBEAT_INTERVAL_SECS};
+use crate::error::ErrorCategory;
+use crate::utils::{self, fmt_bytes, fmt_count};
+use crate::metrics;
+#[cfg(unix)]
+use crate::control;
+
+const PROGRESS_BAR_WIDTH: usize = 30;
+const PROGRESS_REFRESH_MS: u64 = 1000;
+// partitions done before eta is estimated, first ones done are not telling as their sizes differ
+const PROGRESS_ETA_MIN_PARTITIONS: usize = 4;
+
+// list tasks busy on average below this share of concurrency ran out of key space partitions
+const TUNING_LOW_CONCURRENCY_RATIO: f64 = 0.5;
+// throttled list requests in percent over it slow the run down more than a lower rate would
+const TUNING_THROTTLE_RATE_PERCENT: f64 = 1.0;
+// samples of listing before busy list tasks are telling
+const TUNING_MIN_SAMPLES: usize = 2;
+
+// objects listed and busy list tasks at each heartbeat while listing, for tuning report at end of run
+#[derive(Debug, Default)]
+struct TuningSamples {
+    samples: usize,
+    active_tasks: usize,
+    peak_rate: f64,
+    first: Option<(std::time::Instant, usize)>,
+    last: Option<(std::time::Instant, usize)>,
+    finished: bool,
+}
+
+impl TuningSamples {
+    fn sample(&mut self, now: std::time::Instant, objects: usize, active_tasks: usize) {
+        // list tasks not started yet
+        if objects == 0 && active_tasks == 0 {
+            return;
+        }
+        self.record(now, objects);
+        self.samples += 1;
+        self.active_tasks += active_tasks;
+    }
+
+    // objects of tail of listing after last heartbeat, no list task is busy any more
+    fn finish(&mut self, now: std::time::Instant, objects: usize) {
+        if !self.finished && self.samples > 0 {
+            self.record(now, objects);
+            self.finished = true;
+        }
+    }
+
+    fn record(&mut self, now: std::time::Instant, objects: usize) {
+        if let Some((ts, last_objects)) = self.last {
+            let elapsed = now.duration_since(ts).as_secs_f64();
+            if elapsed > 0.0 {
+                self.peak_rate = self.peak_rate.max((objects - last_objects) as f64 / elapsed);
+            }
+        }
+        self.first.get_or_insert((now, objects));
+        self.last = Some((now, objects));
+    }
+
+    fn average_rate(&self) -> f64 {
+        match (self.first, self.last) {
+            (Some((first_ts, first_objects)), Some((last_ts, last_objects))) if last_ts > first_ts =>
+                (last_objects - first_objects) as f64 / last_ts.duration_since(first_ts).as_secs_f64(),
+            _ => 0.0,
+        }
+    }
+
+    fn average_active_tasks(&self) -> f64 {
+        if self.samples == 0 {
+            return 0.0;
+        }
+        self.active_tasks as f64 / self.samples as f64
+    }
+}
+
+// throttling first, more partitions do not help while requests are throttled
+fn tuning_recommendation(average_active_tasks: f64, concurrency: usize, throttle_rate: f64, max_rps: Option<u32>) -> String {
+    if throttle_rate > TUNING_THROTTLE_RATE_PERCENT {
+        return match max_rps {
+            Some(max_rps) => format!("{:.2}% of list requests throttled, lower --max-rps below {}", throttle_rate, max_rps),
+            None => format!("{:.2}% of list requests throttled, cap request rate with --max-rps or use --adaptive-concurrency", throttle_rate),
+        };
+    }
+    if average_active_tasks < concurrency as f64 * TUNING_LOW_CONCURRENCY_RATIO {
+        return format!("only {:.1} of {} list tasks busy on average, add more ks hints to split key space into more partitions",
+            average_active_tasks, concurrency);
+    }
+    "no change, list tasks were kept busy without throttling".to_string()
+}
+
+// lines of tuning report from samples of listing and counters of whole run
+fn tuning_report(samples: &TuningSamples, g_state: &GlobalState, concurrency: usize) -> Vec<String> {
+    let list_requests = g_state.read_list_request();
+    let throttles = g_state.errors().read(ErrorCategory::Throttle);
+    let throttle_rate = if list_requests == 0 { 0.0 } else { throttles as f64 * 100.0 / list_requests as f64 };
+    let max_rps = g_state.rate_limiters().iter().map(|l| l.max_rps()).min();
+    let recommendation = if samples.samples < TUNING_MIN_SAMPLES {
+        format!("none, listing too short for {} samples of busy list tasks", TUNING_MIN_SAMPLES)
+    } else {
+        tuning_recommendation(samples.average_active_tasks(), concurrency, throttle_rate, max_rps)
+    };
+    vec![
+        format!("Tuning - peak {} objects/s, average {} objects/s, {:.1} of {} list tasks busy on average, {} of {} list requests throttled",
+            fmt_count(samples.peak_rate as usize), fmt_count(samples.average_rate() as usize), samples.average_active_tasks(), concurrency,
+            fmt_count(throttles), fmt_count(list_requests)),
+        format!("Tuning - recommend: {}", recommendation),
+    ]
+}
+
+fn fmt_eta(secs: u64) -> String {
+    match secs {
+        s if s >= 3600 => format!("{}h{:02}m", s / 3600, s % 3600 / 60),
+        s if s >= 60 => format!("{}m{:02}s", s / 60, s % 60),
+        s => format!("{}s", s),
+    }
+}
+
+// one line of progress bar, eta is from partitions done so far as total of objects is unknown
+fn progress_line(objects: usize, errors: usize, elapsed_secs: f64, partitions: (usize, usize)) -> String {
+    let (done, total) = partitions;
+    let filled = (PROGRESS_BAR_WIDTH * done).checked_div(total).unwrap_or(0);
+    let percent = (100 * done).checked_div(total).map(|p| format!("{:3}%", p)).unwrap_or("  ?%".to_string());
+    let rate = if elapsed_secs > 0.0 { (objects as f64 / elapsed_secs) as usize } else { 0 };
+    let eta = if done > 0 && done >= PROGRESS_ETA_MIN_PARTITIONS.min(total) {
+        fmt_eta((elapsed_secs * (total - done) as f64 / done as f64) as u64)
+    } else {
+        "unknown".to_string()
+    };
+    let mut line = format!("[{}{}] {} {}/{} partitions, {} objects, {} objects/s, ETA {}", "#".repeat(filled),
+        ".".repeat(PROGRESS_BAR_WIDTH - filled), percent, fmt_count(done), fmt_count(total), fmt_count(objects),
+        fmt_count(rate), eta);
+    if errors > 0 {
+        line.push_str(&format!(", {} errors", fmt_count(errors)));
+    }
+    line
+}
+
+// progress bar line on stdout, redrawn in place until finished
+pub struct ProgressBar {
+    started: std::time::Instant,
+    finished: std::sync::Mutex<bool>,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        Self {
+            started: std::time::Instant::now(),
+            finished: std::sync::Mutex::new(false),
+        }
+    }
+
+    fn write(&self, g_state: &GlobalState, end: &str) {
+        let line = progress_line(g_state.read_listed_objects(), g_state.errors().total(), self.started.elapsed().as_secs_f64(),
+            g_state.read_partitions());
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "\r\x1b[2K{}{}", line, end);
+        let _ = stdout.flush();
+    }
+
+    pub fn draw(&self, g_state: &GlobalState) {
+        let finished = self.finished.lock().unwrap();
+        if !*finished {
+            self.write(g_state, "");
+        }
+    }
+
+    // last state is drawn and kept above lines written after it, bar is not drawn again
+    pub fn finish(&self, g_state: &GlobalState) {
+        let mut finished = self.finished.lock().unwrap();
+        if !*finished {
+            self.write(g_state, "\n");
+            *finished = true;
+        }
+    }
+}
 
 pub async fn mon_task(ctx: MonContext) -> () {
 
     ctx.start();
     ctx.g_state.wait_to_start().await;
 
-    info!("Mon Task - started");
+    info!("Mon Task - started, run id {}", ctx.g_state.run_id());
+
+    let metrics_server = ctx.metrics_addr.map(|addr| tokio::spawn(metrics::metrics_server(addr, ctx.g_state.clone())));
+    #[cfg(unix)]
+    let control_server = ctx.control_socket.clone().map(|path| tokio::spawn(control::control_server(path, ctx.g_state.clone())));
+    let stats_json_task = ctx.stats_json.clone().map(|(writer, interval_secs)| {
+        let g_state = ctx.g_state.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(tokio::time::Duration::from_secs(interval_secs));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = writer.write(&g_state, false) {
+                    warn!("Mon Task - unable to write stats json: {}", e);
+                }
+            }
+        })
+    });
+
+    // requests let through by each rate limiter at last heartbeat
+    let mut last_ts = tokio::time::Instant::now();
+    let mut last_acquired: Vec<usize> = ctx.g_state.rate_limiters().iter().map(|l| l.read_acquired()).collect();
+    let mut tuning = TuningSamples::default();
 
     loop {
+        let mem_governor = ctx.g_state.memory_governor();
         if ctx.is_quit() {
+            ctx.g_state.finish_progress_bar();
+            if let Some(server) = &metrics_server {
+                server.abort();
+            }
+            #[cfg(unix)]
+            if let (Some(server), Some(path)) = (&control_server, &ctx.control_socket) {
+                server.abort();
+                control::remove_socket(path);
+            }
+            if let Some(task) = &stats_json_task {
+                task.abort();
+            }
+            if let Some((writer, _)) = &ctx.stats_json {
+                if let Err(e) = writer.write(&ctx.g_state, true) {
+                    warn!("Mon Task - unable to write final stats json: {}", e);
+                }
+            }
+            let errors = ctx.g_state.errors();
+            let list_requests = ctx.g_state.read_list_request();
+            info!("Mon Task - {} errors of {} list requests ({:.2}%): {}", fmt_count(errors.total()), fmt_count(list_requests),
+                errors.rate(list_requests), errors);
+            let list_retry = ctx.g_state.read_list_retry();
+            if list_retry > 0 {
+                info!("Mon Task - list calls retried {} times in total", fmt_count(list_retry));
+            }
+            info!("Mon Task - throttle policy {}, {} throttled responses", ctx.g_state.on_throttle().name(),
+                fmt_count(ctx.g_state.throttle().read_throttled()));
+            let listed = ctx.g_state.read_listed_objects();
+            if list_requests > 0 && listed > 0 {
+                info!("Mon Task - {} objects per list request on average, page size {}", fmt_count(listed / list_requests),
+                    ctx.g_state.page_size());
+            }
+            // pages of endpoint no larger than this, so listing takes more requests than page size tells
+            let peak_page = ctx.g_state.read_peak_truncated_page();
+            if peak_page > 0 && peak_page < ctx.g_state.page_size() {
+                warn!("Mon Task - endpoint returned at most {} keys per page of page size {}, list requests are {:.1}x as many, \
+                    --page-size {} matches it", peak_page, ctx.g_state.page_size(), ctx.g_state.page_size() as f64 / peak_page as f64, peak_page);
+            }
+            let suspect_pages = ctx.g_state.read_suspect_pages();
+            if suspect_pages > 0 {
+                warn!("Mon Task - {} suspect pages, truncated without usable continuation token or short of page size, \
+                    endpoint may have dropped keys between pages", fmt_count(suspect_pages));
+            }
+            if mem_governor.is_enabled() {
+                info!("Mon Task - memory throttling triggered {} times, channel to data map capped {} times",
+                    fmt_count(mem_governor.read_throttled()), fmt_count(mem_governor.read_channel_capped()));
+            }
+            let backpressure = ctx.g_state.read_backpressure();
+            if backpressure > 0 {
+                info!("Mon Task - list tasks paused on data map channel backlog {} times, peak {} batches queued",
+                    fmt_count(backpressure), fmt_count(ctx.g_state.read_peak_queued_batches()));
+            }
+            if let Some(adaptive) = ctx.g_state.adaptive_concurrency() {
+                info!("Mon Task - adaptive concurrency ended at {} of max {}, throttled {} times",
+                    adaptive.limit(), adaptive.max(), fmt_count(adaptive.read_throttled()));
+            }
+            if let Some(report) = &ctx.tuning_report {
+                tuning.finish(ctx.g_state.read_list_ended().unwrap_or_else(std::time::Instant::now), ctx.g_state.read_listed_objects());
+                for line in tuning_report(&tuning, &ctx.g_state, report.concurrency) {
+                    println!("{}", line);
+                    if report.log_to_file {
+                        info!("{}", line);
+                    }
+                }
+            }
             ctx.complete();
             info!("Mon Task - quit");
-            return ();
+            return;
+        }
+
+        if ctx.tuning_report.is_some() {
+            let objects = ctx.g_state.read_listed_objects();
+            match ctx.g_state.read_list_ended() {
+                None => tuning.sample(std::time::Instant::now(), objects, ctx.g_state.read_active_list_tasks()),
+                Some(ended) => tuning.finish(ended, objects),
+            }
+        }
+
+        if let Some(bar) = ctx.g_state.progress_bar() {
+            // bar is finished once listing is, there is nothing to progress while objects are dumped
+            if ctx.g_state.all_list_tasks_is_running() {
+                bar.draw(&ctx.g_state);
+            } else {
+                bar.finish(&ctx.g_state);
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(PROGRESS_REFRESH_MS)).await;
+            continue;
         }
 
         let tracker_stats = format!("{}", ctx.get_tracker());
-        if tracker_stats.len() > 0 {
+        if !tracker_stats.is_empty() {
             info!("Mon Task - http status: {}", tracker_stats);
         }
 
         let task_next_stream_timeout = ctx.g_state.read_task_next_stream_timeout();
         let s3_client_timeout = ctx.g_state.read_s3_client_timeout();
         let s3_client_generic_error = ctx.g_state.read_s3_client_generic_error();
-        if task_next_stream_timeout > 0 || s3_client_timeout > 0 || s3_client_generic_error > 0 {
-            info!("Mon Task - next stream timeout: {}, s3 client timeout: {}, s3 client generic error: {}",
-                task_next_stream_timeout, s3_client_timeout, s3_client_generic_error);
+        let s3_redirect = ctx.g_state.read_s3_redirect();
+        let list_retry = ctx.g_state.read_list_retry();
+        if task_next_stream_timeout > 0 || s3_client_timeout > 0 || s3_client_generic_error > 0 || s3_redirect > 0 || list_retry > 0 {
+            info!("Mon Task - next stream timeout: {}, s3 client timeout: {}, s3 client generic error: {}, s3 redirect: {}, list retry: {}",
+                fmt_count(task_next_stream_timeout), fmt_count(s3_client_timeout),
+                fmt_count(s3_client_generic_error), fmt_count(s3_redirect), fmt_count(list_retry));
+        }
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(last_ts).as_secs_f64();
+        for (limiter, last) in ctx.g_state.rate_limiters().iter().zip(last_acquired.iter_mut()) {
+            let acquired = limiter.read_acquired();
+            if elapsed > 0.0 {
+                info!("Mon Task - list requests of {}: {:.1} rps of max {} rps, {} in total",
+                    limiter.label(), (acquired - *last) as f64 / elapsed, limiter.max_rps(), fmt_count(acquired));
+            }
+            *last = acquired;
+        }
+        last_ts = now;
+        if let Some(adaptive) = ctx.g_state.adaptive_concurrency() {
+            info!("Mon Task - adaptive concurrency {} of max {}, {} active list tasks, throttled {} times",
+                adaptive.limit(), adaptive.max(), ctx.g_state.read_active_list_tasks(), fmt_count(adaptive.read_throttled()));
+        }
+        let queued = ctx.g_state.read_queued_batches();
+        let backpressure = ctx.g_state.read_backpressure();
+        if queued > 0 || backpressure > 0 {
+            info!("Mon Task - data map channel {} batches queued, list tasks paused {} times",
+                fmt_count(queued), fmt_count(backpressure));
+        }
+        if mem_governor.is_enabled() {
+            let rss = utils::current_rss().unwrap_or(0);
+            info!("Mon Task - rss {} of max memory {}, throttled {} times",
+                fmt_bytes(rss as u64), fmt_bytes(mem_governor.max_bytes() as u64), fmt_count(mem_governor.read_throttled()));
         }
         tokio::time::sleep(tokio::time::Duration::from_secs(DEFAULT_TASK_HEARTBEAT_INTERVAL_SECS)).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_line() {
+        assert_eq!(progress_line(500, 0, 10.0, (0, 0)),
+            format!("[{}]   ?% 0/0 partitions, 500 objects, 50 objects/s, ETA unknown", ".".repeat(30)));
+        assert_eq!(progress_line(500, 2, 10.0, (3, 12)),
+            format!("[{}{}]  25% 3/12 partitions, 500 objects, 50 objects/s, ETA unknown, 2 errors", "#".repeat(7), ".".repeat(23)));
+        assert_eq!(progress_line(500, 0, 10.0, (4, 12)),
+            format!("[{}{}]  33% 4/12 partitions, 500 objects, 50 objects/s, ETA 20s", "#".repeat(10), ".".repeat(20)));
+        assert_eq!(fmt_eta(3725), "1h02m");
+        assert_eq!(fmt_eta(125), "2m05s");
+    }
+
+    #[test]
+    fn test_tuning_samples() {
+        let started = std::time::Instant::now();
+        let mut samples = TuningSamples::default();
+        samples.sample(started, 0, 10);
+        samples.sample(started + std::time::Duration::from_secs(5), 5000, 10);
+        samples.sample(started + std::time::Duration::from_secs(10), 6000, 1);
+        samples.finish(started + std::time::Duration::from_secs(12), 6000);
+        assert_eq!(samples.peak_rate as usize, 1000);
+        assert_eq!(samples.average_rate() as usize, 500);
+        assert_eq!(samples.average_active_tasks(), 7.0);
+
+        assert!(tuning_recommendation(7.0, 100, 0.0, None).starts_with("only 7.0 of 100 list tasks busy"));
+        assert!(tuning_recommendation(7.0, 100, 5.0, Some(500)).ends_with("lower --max-rps below 500"));
+        assert!(tuning_recommendation(90.0, 100, 0.5, None).starts_with("no change"));
+    }
+}
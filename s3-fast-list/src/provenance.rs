@@ -0,0 +1,115 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+
+// footer keys of parquet output are field names under this namespace, e.g. "s3_fast_list.bucket"
+const FOOTER_KEY_PREFIX: &str = "s3_fast_list.";
+
+// how output is produced, written in footer of each parquet output and in manifest with --emit-manifest
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunMetadata {
+    pub version: String,
+    pub run_id: String,
+    // subcommand of run, e.g. "list" or "diff"
+    pub mode: String,
+    pub bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    // custom endpoint url, none of aws
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub prefixes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_bucket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_endpoint: Option<String>,
+    pub filters: Vec<String>,
+    // unix seconds
+    pub started_at: u64,
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl RunMetadata {
+    // footer entries of one output file closed now with its object count, strings as is and others in json
+    pub fn footer(&self, objects: usize) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        let fields = serde_json::to_value(self).unwrap();
+        let fields = fields.as_object().unwrap().iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .chain([("ended_at", now_secs().into()), ("objects", objects.into())]);
+        for (name, value) in fields {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                value => value.to_string(),
+            };
+            entries.push((format!("{}{}", FOOTER_KEY_PREFIX, name), value));
+        }
+        entries
+    }
+}
+
+// sidecar json of run next to output files
+#[derive(Serialize)]
+struct RunManifest<'a> {
+    #[serde(flatten)]
+    metadata: &'a RunMetadata,
+    ended_at: u64,
+    // objects exported to all output files
+    objects: usize,
+    output_files: &'a [String],
+    ks_file: &'a str,
+    // run is interrupted, output only has objects listed so far
+    partial: bool,
+}
+
+pub fn write_manifest(filename: &str, metadata: &RunMetadata, objects: usize, output_files: &[String], ks_file: &str,
+        partial: bool) -> std::io::Result<()> {
+    let manifest = RunManifest {
+        metadata,
+        ended_at: now_secs(),
+        objects,
+        output_files,
+        ks_file,
+        partial,
+    };
+    let mut content = serde_json::to_string_pretty(&manifest)?;
+    content.push('\n');
+    std::fs::write(filename, content)
+}
+
+// manifest file next to output, extension of output format replaced, e.g. "out.parquet" to "out.manifest.json"
+pub fn manifest_filename(output_file: &str, extension: &str) -> String {
+    let stem = output_file.strip_suffix(&format!(".{}", extension)).unwrap_or(output_file);
+    format!("{}.manifest.json", stem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_footer() {
+        let metadata = RunMetadata {
+            version: "1.0.0".to_string(),
+            mode: "list".to_string(),
+            bucket: "b".to_string(),
+            prefixes: vec!["a/".to_string()],
+            started_at: 1704067200,
+            ..Default::default()
+        };
+        let footer: std::collections::HashMap<_, _> = metadata.footer(3).into_iter().collect();
+        assert_eq!(footer["s3_fast_list.bucket"], "b");
+        assert_eq!(footer["s3_fast_list.prefixes"], "[\"a/\"]");
+        assert_eq!(footer["s3_fast_list.started_at"], "1704067200");
+        assert_eq!(footer["s3_fast_list.objects"], "3");
+        assert!(footer.contains_key("s3_fast_list.ended_at"));
+        assert!(!footer.contains_key("s3_fast_list.target_bucket"));
+
+        assert_eq!(manifest_filename("out/a.parquet", "parquet"), "out/a.manifest.json");
+        assert_eq!(manifest_filename("out", "parquet"), "out.manifest.json");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::core::GlobalState;
+use crate::error::ErrorCategory;
+use crate::provenance::{self, RunMetadata};
+use crate::utils;
+
+// width of field names, values of all lines start at the same column
+const LABEL_WIDTH: usize = 20;
+
+// "s3://bucket, region r, endpoint e" of one side
+fn fmt_side(bucket: &str, region: Option<&str>, endpoint: Option<&str>) -> String {
+    let mut side = format!("s3://{}", bucket);
+    side.push_str(&format!(", region {}", region.unwrap_or("<default>")));
+    side.push_str(&format!(", endpoint {}", endpoint.unwrap_or("<aws default>")));
+    side
+}
+
+fn push_field(report: &mut String, indent: usize, label: &str, value: &str) {
+    report.push_str(&format!("{:indent$}{:<width$}{}\n", "", label, value, indent = indent, width = LABEL_WIDTH - indent));
+}
+
+/*
+ * plain text report of a run for people to read, e.g. attached to a ticket, out of run metadata,
+ * totals of summary as (label, objects, bytes) and counters of global state at end of run
+ */
+pub fn render(metadata: &RunMetadata, status: &str, elapsed_secs: f64, totals: &[(&str, usize, u64)], g_state: &GlobalState) -> String {
+    let mut report = String::from("s3-fast-list run report\n\n");
+    push_field(&mut report, 0, "run id", &metadata.run_id);
+    push_field(&mut report, 0, "version", &metadata.version);
+    push_field(&mut report, 0, "mode", &metadata.mode);
+    push_field(&mut report, 0, "source", &fmt_side(&metadata.bucket, metadata.region.as_deref(), metadata.endpoint.as_deref()));
+    if let Some(target) = &metadata.target_bucket {
+        push_field(&mut report, 0, "target", &fmt_side(target, metadata.target_region.as_deref(), metadata.target_endpoint.as_deref()));
+    }
+    let prefixes: Vec<String> = metadata.prefixes.iter().map(|p| format!("\"{}\"", p)).collect();
+    push_field(&mut report, 0, "prefix", &prefixes.join(", "));
+    push_field(&mut report, 0, "filter", &if metadata.filters.is_empty() { "none".to_string() } else { metadata.filters.join(" ; ") });
+    push_field(&mut report, 0, "started", &utils::fmt_rfc3339(metadata.started_at));
+    push_field(&mut report, 0, "ended", &utils::fmt_rfc3339(provenance::now_secs()));
+    push_field(&mut report, 0, "elapsed", &format!("{:.1}s", elapsed_secs));
+    push_field(&mut report, 0, "status", status);
+
+    report.push_str("\nobjects\n");
+    for (label, objects, bytes) in totals {
+        push_field(&mut report, 2, label, &format!("{} objects, {} ({} bytes)", utils::fmt_count(*objects), utils::fmt_bytes_human(*bytes), bytes));
+    }
+
+    let list_requests = g_state.read_list_request();
+    let errors = g_state.errors();
+    report.push_str("\nrequests\n");
+    push_field(&mut report, 2, "list requests", &utils::fmt_count(list_requests));
+    push_field(&mut report, 2, "retries", &utils::fmt_count(g_state.read_list_retry()));
+    push_field(&mut report, 2, "throttle policy", &format!("{}, {} throttled", g_state.on_throttle().name(),
+        utils::fmt_count(g_state.throttle().read_throttled())));
+    push_field(&mut report, 2, "errors", &format!("{} ({:.2}%)", utils::fmt_count(errors.total()), errors.rate(list_requests)));
+    for category in ErrorCategory::ALL {
+        push_field(&mut report, 4, category.name(), &utils::fmt_count(errors.read(category)));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_render() {
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "run".to_string());
+        g_state.inc_list_request();
+        g_state.inc_list_request();
+        g_state.errors().inc(ErrorCategory::Throttle);
+        let metadata = RunMetadata {
+            version: "1.0.0".to_string(),
+            run_id: "run".to_string(),
+            mode: "diff".to_string(),
+            bucket: "src".to_string(),
+            prefixes: vec!["a/".to_string()],
+            target_bucket: Some("dst".to_string()),
+            target_endpoint: Some("http://127.0.0.1:9000".to_string()),
+            started_at: 1704067200,
+            ..Default::default()
+        };
+        let report = render(&metadata, "complete", 2.5, &[("only_left", 123, 2048)], &g_state);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines.contains(&"mode                diff"));
+        assert!(lines.contains(&"source              s3://src, region <default>, endpoint <aws default>"));
+        assert!(lines.contains(&"target              s3://dst, region <default>, endpoint http://127.0.0.1:9000"));
+        assert!(lines.contains(&"prefix              \"a/\""));
+        assert!(lines.contains(&"filter              none"));
+        assert!(lines.contains(&"started             2024-01-01T00:00:00Z"));
+        assert!(lines.contains(&"  only_left         123 objects, 2.0 KiB (2048 bytes)"));
+        assert!(lines.contains(&"  errors            1 (50.00%)"));
+        assert!(lines.contains(&"  throttle policy   backoff, 0 throttled"));
+        assert!(lines.contains(&"    throttle        1"));
+    }
+}
@@ -0,0 +1,230 @@
+use tokio::io::AsyncWriteExt;
+use crate::core::{ObjectKey, ObjectProps};
+
+// bucket of one side of diff, and how aws cli reaches it
+#[derive(Debug, Clone, Default)]
+pub struct ScriptSide {
+    pub bucket: String,
+    pub region: Option<String>,
+    pub endpoint: Option<String>,
+    pub profile: Option<String>,
+    pub request_payer: bool,
+}
+
+impl ScriptSide {
+    fn url(&self, key: &str) -> String {
+        shell_quote(&format!("s3://{}/{}", self.bucket, key))
+    }
+
+    // options of aws cli addressing this side, region is given by the caller as it differs for copy source
+    fn cli_opts(&self) -> String {
+        let mut opts = String::new();
+        if let Some(endpoint) = &self.endpoint {
+            opts.push_str(&format!(" --endpoint-url {}", shell_quote(endpoint)));
+        }
+        if let Some(profile) = &self.profile {
+            opts.push_str(&format!(" --profile {}", shell_quote(profile)));
+        }
+        if self.request_payer {
+            opts.push_str(" --request-payer requester");
+        }
+        opts
+    }
+
+    fn region_opt(&self, name: &str) -> String {
+        self.region.as_ref().map(|r| format!(" --{} {}", name, shell_quote(r))).unwrap_or_default()
+    }
+}
+
+// options of sync script with "--diff-emit-script"
+#[derive(Debug, Clone)]
+pub struct DiffScriptOptions {
+    pub filename: String,
+    pub source: ScriptSide,
+    pub target: ScriptSide,
+    // header comment of script
+    pub comment: String,
+}
+
+// single quote for posix shell, a quote inside is closed, escaped and reopened
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// copy within one endpoint and credentials is server side, otherwise object is streamed through this host
+fn copy_line(opts: &DiffScriptOptions, key: &str) -> String {
+    let (source, target) = (&opts.source, &opts.target);
+    if source.endpoint == target.endpoint && source.profile == target.profile {
+        // one request payer option of aws cli covers both buckets
+        let payer = if source.request_payer && !target.request_payer { " --request-payer requester" } else { "" };
+        format!("aws s3 cp {} {}{}{}{}{}\n", source.url(key), target.url(key), source.region_opt("source-region"),
+            target.region_opt("region"), target.cli_opts(), payer)
+    } else {
+        format!("aws s3 cp {} -{}{} | aws s3 cp - {}{}{}\n", source.url(key), source.region_opt("region"), source.cli_opts(),
+            target.url(key), target.region_opt("region"), target.cli_opts())
+    }
+}
+
+fn delete_line(opts: &DiffScriptOptions, key: &str) -> String {
+    let target = &opts.target;
+    format!("aws s3 rm {}{}{}\n", target.url(key), target.region_opt("region"), target.cli_opts())
+}
+
+// shell script of one-way sync from source to target
+pub struct DiffScript {
+    writer: tokio::io::BufWriter<tokio::fs::File>,
+    opts: DiffScriptOptions,
+    copies: usize,
+    deletes: usize,
+}
+
+impl DiffScript {
+    pub async fn create(opts: &DiffScriptOptions) -> tokio::io::Result<Self> {
+        let file = tokio::fs::File::create(&opts.filename).await?;
+        let mut writer = tokio::io::BufWriter::new(file);
+        writer.write_all(format!("#!/bin/sh\n# {}\nset -e\n", opts.comment).as_bytes()).await?;
+        Ok(Self {
+            writer,
+            opts: opts.clone(),
+            copies: 0,
+            deletes: 0,
+        })
+    }
+
+    // only left and changed objects are copied, only right ones removed
+    pub async fn write_diff(&mut self, plus: &[(ObjectKey, ObjectProps)], astrisk: &[(ObjectKey, ObjectProps)],
+            minus: &[(ObjectKey, ObjectProps)]) -> tokio::io::Result<()> {
+        self.write_copies(plus).await?;
+        self.write_copies(astrisk).await?;
+        self.write_deletes(minus).await
+    }
+
+    async fn write_copies(&mut self, v: &[(ObjectKey, ObjectProps)]) -> tokio::io::Result<()> {
+        for (key, _) in v {
+            let line = copy_line(&self.opts, key.as_str());
+            self.writer.write_all(line.as_bytes()).await?;
+        }
+        self.copies += v.len();
+        Ok(())
+    }
+
+    async fn write_deletes(&mut self, v: &[(ObjectKey, ObjectProps)]) -> tokio::io::Result<()> {
+        for (key, _) in v {
+            let line = delete_line(&self.opts, key.as_str());
+            self.writer.write_all(line.as_bytes()).await?;
+        }
+        self.deletes += v.len();
+        Ok(())
+    }
+
+    // return count of copy and delete commands
+    pub async fn close(mut self) -> tokio::io::Result<(usize, usize)> {
+        self.writer.flush().await?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            tokio::fs::set_permissions(&self.opts.filename, std::fs::Permissions::from_mode(0o755)).await?;
+        }
+        Ok((self.copies, self.deletes))
+    }
+}
+
+// options of s3 batch operations manifest with "--diff-emit-batch-manifest"
+#[derive(Debug, Clone)]
+pub struct BatchManifestOptions {
+    pub filename: String,
+    // source bucket, the one objects are copied from
+    pub bucket: String,
+    // write version id column
+    pub versions: bool,
+}
+
+// url encode key for manifest, all but unreserved chars and "/", so "+" and space do not depend on decoder
+pub fn manifest_key(key: &str) -> String {
+    key.bytes().map(|b| match b {
+        b if b.is_ascii_alphanumeric() || b"-_.~/".contains(&b) => (b as char).to_string(),
+        b => format!("%{:02X}", b),
+    }).collect()
+}
+
+// s3 batch operations manifest csv of objects to copy from source, "bucket,key[,version id]" with no header
+pub struct BatchManifest {
+    writer: tokio::io::BufWriter<tokio::fs::File>,
+    opts: BatchManifestOptions,
+    entries: usize,
+    // delete markers can not be copied
+    skipped: usize,
+}
+
+impl BatchManifest {
+    pub async fn create(opts: &BatchManifestOptions) -> tokio::io::Result<Self> {
+        let file = tokio::fs::File::create(&opts.filename).await?;
+        Ok(Self {
+            writer: tokio::io::BufWriter::new(file),
+            opts: opts.clone(),
+            entries: 0,
+            skipped: 0,
+        })
+    }
+
+    fn line(&self, key: &ObjectKey) -> String {
+        let (key, version_id) = key.split_version();
+        match version_id.filter(|_| self.opts.versions) {
+            Some(version_id) => format!("{},{},{}\n", self.opts.bucket, manifest_key(key), version_id),
+            None => format!("{},{}\n", self.opts.bucket, manifest_key(key)),
+        }
+    }
+
+    // only left and changed objects, nothing to copy for only right ones
+    pub async fn write_diff(&mut self, plus: &[(ObjectKey, ObjectProps)], astrisk: &[(ObjectKey, ObjectProps)]) -> tokio::io::Result<()> {
+        for (key, props) in plus.iter().chain(astrisk) {
+            if props.is_delete_marker() {
+                self.skipped += 1;
+                continue;
+            }
+            let line = self.line(key);
+            self.writer.write_all(line.as_bytes()).await?;
+            self.entries += 1;
+        }
+        Ok(())
+    }
+
+    // return count of entries and of delete markers skipped
+    pub async fn close(mut self) -> tokio::io::Result<(usize, usize)> {
+        self.writer.flush().await?;
+        Ok((self.entries, self.skipped))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(target_endpoint: Option<&str>) -> DiffScriptOptions {
+        DiffScriptOptions {
+            filename: String::new(),
+            source: ScriptSide { bucket: "src".to_string(), region: Some("us-east-1".to_string()), ..Default::default() },
+            target: ScriptSide { bucket: "dst".to_string(), endpoint: target_endpoint.map(|e| e.to_string()), ..Default::default() },
+            comment: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_script_lines() {
+        assert_eq!(shell_quote("it's $HOME"), r"'it'\''s $HOME'");
+
+        let o = opts(None);
+        assert_eq!(copy_line(&o, "a b/c"), "aws s3 cp 's3://src/a b/c' 's3://dst/a b/c' --source-region 'us-east-1'\n");
+        assert_eq!(delete_line(&o, "x'y"), r"aws s3 rm 's3://dst/x'\''y'".to_string() + "\n");
+
+        let o = opts(Some("https://r2.example.com"));
+        assert_eq!(copy_line(&o, "k"), "aws s3 cp 's3://src/k' - --region 'us-east-1' | aws s3 cp - 's3://dst/k' --endpoint-url 'https://r2.example.com'\n");
+    }
+
+    #[test]
+    fn test_manifest_key() {
+        assert_eq!(manifest_key("a/b-c_d.e~f"), "a/b-c_d.e~f");
+        assert_eq!(manifest_key("a b+c,d"), "a%20b%2Bc%2Cd");
+        assert_eq!(manifest_key("ü"), "%C3%BC");
+    }
+}
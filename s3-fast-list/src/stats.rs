@@ -1,7 +1,13 @@
-use std::sync::Arc;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::HashMap;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
+use serde::Serialize;
+use crate::core::GlobalState;
+use crate::error::ErrorCategory;
+use crate::utils;
 
 pub struct HttpStatusCodeTracker {
     map: RwLock<HashMap<u16, Arc<AtomicUsize>>>
@@ -17,9 +23,8 @@ impl HttpStatusCodeTracker {
 
     pub async fn inc(&self, code: u16) {
         let lock = self.map.read().await;
-        let val = lock.get(&code);
-        if val.is_some() {
-            let counter = Arc::clone(val.unwrap());
+        if let Some(val) = lock.get(&code) {
+            let counter = Arc::clone(val);
             // drop lock immediately as we get back atomic
             drop(lock);
             let _ = counter.fetch_add(1, Ordering::SeqCst);
@@ -74,3 +79,179 @@ impl std::fmt::Display for HttpStatusCodeTracker {
         write!(f, "{}", stats.join(", "))
     }
 }
+
+// errors of list tasks by category, each failed request counted once whether retried or not
+#[derive(Debug, Default)]
+pub struct ErrorCounters {
+    counts: [AtomicUsize; 5],
+}
+
+impl ErrorCounters {
+    pub fn inc(&self, category: ErrorCategory) {
+        self.counts[category as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read(&self, category: ErrorCategory) -> usize {
+        self.counts[category as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().map(|c| c.load(Ordering::Relaxed)).sum()
+    }
+
+    // errors per list request in percent, 0 if no request sent
+    pub fn rate(&self, requests: usize) -> f64 {
+        if requests == 0 {
+            return 0.0;
+        }
+        self.total() as f64 * 100.0 / requests as f64
+    }
+}
+
+impl std::fmt::Display for ErrorCounters {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let counts: Vec<String> = ErrorCategory::ALL.iter()
+            .map(|c| format!("{} {}", c.name(), self.read(*c)))
+            .collect();
+        write!(f, "{}", counts.join(", "))
+    }
+}
+
+// one line of --stats-json file
+#[derive(Debug, Serialize)]
+struct StatsSnapshot<'a> {
+    run_id: &'a str,
+    timestamp: u64,
+    elapsed_secs: f64,
+    objects_listed: usize,
+    bytes_listed: u64,
+    list_requests: usize,
+    list_retries: usize,
+    list_errors: usize,
+    // list requests per second since previous snapshot
+    rps: f64,
+    active_list_tasks: usize,
+    // truncated pages without usable continuation token or short of page size
+    suspect_pages: usize,
+    // list tasks held by "pause" of --control-socket
+    paused: bool,
+    // peak rss of process so far, linux only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak_rss_bytes: Option<usize>,
+    #[serde(rename = "final")]
+    is_final: bool,
+}
+
+// snapshots of global state counters as json, with request rate since previous one
+pub struct StatsSampler {
+    started: Instant,
+    // (time, list requests) of previous snapshot
+    last: Mutex<(Instant, usize)>,
+}
+
+impl StatsSampler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            last: Mutex::new((now, 0)),
+        }
+    }
+
+    pub fn snapshot_json(&self, g_state: &GlobalState, is_final: bool) -> serde_json::Result<String> {
+        let now = Instant::now();
+        let list_requests = g_state.read_list_request();
+        let rps = {
+            let mut last = self.last.lock().unwrap();
+            let elapsed = now.duration_since(last.0).as_secs_f64();
+            let rps = if elapsed > 0.0 { (list_requests - last.1) as f64 / elapsed } else { 0.0 };
+            *last = (now, list_requests);
+            rps
+        };
+
+        let snapshot = StatsSnapshot {
+            run_id: g_state.run_id(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
+            elapsed_secs: (now.duration_since(self.started).as_secs_f64() * 1000.0).round() / 1000.0,
+            objects_listed: g_state.read_listed_objects(),
+            bytes_listed: g_state.read_listed_bytes(),
+            list_requests,
+            list_retries: g_state.read_list_retry(),
+            list_errors: g_state.errors().total(),
+            rps: (rps * 1000.0).round() / 1000.0,
+            active_list_tasks: g_state.read_active_list_tasks(),
+            suspect_pages: g_state.read_suspect_pages(),
+            paused: g_state.is_paused(),
+            peak_rss_bytes: utils::peak_rss(),
+            is_final,
+        };
+        serde_json::to_string(&snapshot)
+    }
+}
+
+// append snapshots of global state counters as json lines
+pub struct StatsJsonWriter {
+    filename: String,
+    sampler: StatsSampler,
+}
+
+impl StatsJsonWriter {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+            sampler: StatsSampler::new(),
+        }
+    }
+
+    pub fn write(&self, g_state: &GlobalState, is_final: bool) -> std::io::Result<()> {
+        let mut line = self.sampler.snapshot_json(g_state, is_final)?;
+        line.push('\n');
+
+        let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&self.filename)?;
+        file.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn test_stats_json_writer() {
+        let filename = std::env::temp_dir().join(format!("stats-{}.ndjson", std::process::id()));
+        let _ = std::fs::remove_file(&filename);
+        let g_state = GlobalState::new(Arc::new(AtomicBool::new(false)), 1, 0, "run".to_string());
+        let writer = StatsJsonWriter::new(filename.to_str().unwrap());
+
+        g_state.add_listed(2, 10);
+        writer.write(&g_state, false).unwrap();
+        g_state.inc_list_request();
+        writer.write(&g_state, true).unwrap();
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&filename).unwrap()
+            .lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        let _ = std::fs::remove_file(&filename);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["run_id"], "run");
+        assert_eq!(lines[0]["objects_listed"], 2);
+        assert_eq!(lines[0]["bytes_listed"], 10);
+        assert_eq!(lines[0]["final"], false);
+        assert_eq!(lines[0]["paused"], false);
+        assert_eq!(lines[0]["suspect_pages"], 0);
+        assert_eq!(lines[1]["list_requests"], 1);
+        assert_eq!(lines[1]["final"], true);
+    }
+
+    #[test]
+    fn test_error_counters() {
+        let errors = ErrorCounters::default();
+        assert_eq!(errors.rate(0), 0.0);
+        errors.inc(ErrorCategory::Throttle);
+        errors.inc(ErrorCategory::Throttle);
+        errors.inc(ErrorCategory::Other);
+        assert_eq!(errors.total(), 3);
+        assert_eq!(errors.rate(300), 1.0);
+        assert_eq!(errors.to_string(), "throttle 2, timeout 0, 5xx 0, access denied 0, other 1");
+    }
+}
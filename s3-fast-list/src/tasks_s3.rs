@@ -1,22 +1,396 @@
-use std::time::Duration;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
-use aws_sdk_s3::operation::list_objects_v2::ListObjectsV2Error;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::config::{ConfigBag, Intercept, RuntimeComponents};
+use aws_sdk_s3::config::interceptors::BeforeDeserializationInterceptorContextRef;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{BoxError, SdkError};
+use chrono::DateTime;
 use tokio::time::{Instant, timeout_at};
-use log::{info, debug, error};
+use log::{info, debug, warn, error};
 use crate::core;
 use crate::core::{S3TaskContext, ObjectKey, ObjectPrefix, ObjectName, ObjectProps};
 use crate::data_map;
+use crate::utils;
+use crate::coverage::{self, PartitionCoverage, PartitionStatus};
 use crate::error::*;
 
-pub async fn flat_list_main_task(ctx: &S3TaskContext, start_prefix: &str,
-        flat_concurrency: usize, hints: data_map::KeySpaceHints) -> () {
-    flat_reactor_task(ctx, start_prefix, flat_concurrency, hints).await
+// start prefixes are disjoint, each one with its own ks hints, all sent to the same data map
+pub async fn flat_list_main_task(ctx: &S3TaskContext, start_prefixes: Vec<(String, data_map::KeySpaceHints)>, flat_concurrency: usize,
+        one_per_prefix: bool, shallow: Option<core::ShallowListOptions>, coverage_file: Option<String>) {
+    if one_per_prefix {
+        let start_prefixes: Vec<String> = start_prefixes.into_iter().map(|(prefix, _)| prefix).collect();
+        return one_per_prefix_reactor_task(ctx, &start_prefixes, flat_concurrency).await;
+    }
+    if let Some(shallow) = shallow {
+        let start_prefixes: Vec<String> = start_prefixes.into_iter().map(|(prefix, _)| prefix).collect();
+        return shallow_reactor_task(ctx, &start_prefixes, flat_concurrency, &shallow).await;
+    }
+    let start_prefixes = match &ctx.hash_seed {
+        Some(charset) => {
+            let whole = start_prefixes.iter().filter(|(_, hints)| hints.is_whole()).count();
+            if whole > 0 {
+                info!("Flat List S3 Task - {} - {} start prefixes without ks hints seeded into {} partitions each by first characters \"{}\"",
+                    ctx.s3_bucket_name, whole, charset.len(), charset.iter().collect::<String>());
+            }
+            seed_start_prefixes(start_prefixes, charset)
+        },
+        None => start_prefixes,
+    };
+    let mut records = flat_reactor_task(ctx, start_prefixes, flat_concurrency).await;
+
+    if let Some(filename) = coverage_file {
+        info!("Flat List S3 Task - {} - dumping coverage of {} partitions to {}", ctx.s3_bucket_name, records.len(), filename);
+        if let Err(e) = coverage::write_coverage(&filename, &mut records).await {
+            error!("Flat List S3 Task - {} - failed to write coverage file {}: {}", ctx.s3_bucket_name, filename, e);
+        }
+    }
+}
+
+// start prefixes without ks hints split at each character of charset, so list tasks start all over key space
+// instead of walking it from the start, hints of others are kept as given
+pub fn seed_start_prefixes(start_prefixes: Vec<(String, data_map::KeySpaceHints)>, charset: &[char])
+        -> Vec<(String, data_map::KeySpaceHints)> {
+    start_prefixes.into_iter().map(|(prefix, hints)| {
+        let hints = if hints.is_whole() { data_map::KeySpaceHints::new_seeded(&prefix, charset) } else { hints };
+        (prefix, hints)
+    }).collect()
 }
 
-// task to control concurrency of s3 flat list
-async fn flat_reactor_task(ctx: &S3TaskContext, start_prefix: &str,
-        flat_concurrency: usize, mut hints: data_map::KeySpaceHints) -> () {
+// task to list lexicographically-first object under each common prefix of start prefixes
+async fn one_per_prefix_reactor_task(ctx: &S3TaskContext, start_prefixes: &[String], flat_concurrency: usize) {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("One Per Prefix S3 Task - {} - started", ctx.s3_bucket_name);
+
+    let mut common_prefixes = Vec::new();
+    for start_prefix in start_prefixes {
+        let mut listed = CommonPrefixes::default();
+        let mut retries = 0;
+        loop {
+            let token = listed.continuation_token.clone();
+            let err = match list_common_prefixes(ctx, start_prefix, &mut listed).await {
+                Ok(()) => {
+                    common_prefixes.append(&mut listed.prefixes);
+                    break;
+                },
+                Err(err) => err,
+            };
+            // retries are counted on consecutive failures, reset once list moves on
+            if listed.continuation_token != token {
+                retries = 0;
+            }
+            if !ctx.is_quit() && retry_on_error(ctx, &err, &mut retries).await {
+                continue;
+            }
+            ctx.g_state.set_list_failed();
+            if let Some(splits) = &ctx.shared_splits {
+                splits.merge(Vec::new()).await;
+            }
+            if ctx.is_running() {
+                info!("One Per Prefix S3 Task - {} - unexpected quit - {}", ctx.s3_bucket_name, err);
+                ctx.complete();
+            }
+            if ctx.g_state.on_error() == core::OnError::Abort {
+                ctx.g_state.quit();
+            }
+            return;
+        }
+    }
+    info!("One Per Prefix S3 Task - {} - {} common prefixes found", ctx.s3_bucket_name, common_prefixes.len());
+    if let Some(splits) = &ctx.shared_splits {
+        common_prefixes = splits.merge(common_prefixes).await;
+        info!("One Per Prefix S3 Task - {} - {} common prefixes of both sides to sample", ctx.s3_bucket_name, common_prefixes.len());
+    }
+
+    let mut set = tokio::task::JoinSet::new();
+    for prefix in common_prefixes {
+        while set.len() >= ctx.g_state.list_concurrency(flat_concurrency) {
+            let _ = set.join_next().await;
+        }
+        ctx.g_state.wait_for_memory().await;
+        if ctx.is_quit() {
+            set.abort_all();
+            info!("One Per Prefix S3 Task - {} - all handler aborted", ctx.s3_bucket_name);
+            break;
+        }
+        let task_ctx = ctx.clone();
+        set.spawn(async move {
+            let mut retries = 0;
+            while let Err(err) = list_first_object(&task_ctx, &prefix).await {
+                if task_ctx.is_quit() || !retry_request(&task_ctx, &err, &mut retries).await {
+                    info!("One Per Prefix S3 Task - {} - skip prefix {} - {}", task_ctx.s3_bucket_name, prefix, err);
+                    return;
+                }
+            }
+        });
+    }
+    while set.join_next().await.is_some() {
+    }
+
+    if ctx.is_running() {
+        ctx.complete();
+        info!("One Per Prefix S3 Task - {} - completed", ctx.s3_bucket_name);
+        tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+    }
+    info!("One Per Prefix S3 Task - {} - quit", ctx.s3_bucket_name);
+}
+
+// common prefixes listed so far under a start prefix, kept across retries to resume from the last page
+#[derive(Debug, Default)]
+struct CommonPrefixes {
+    prefixes: Vec<String>,
+    // token of the page after the last one listed, None before the first page
+    continuation_token: Option<String>,
+    first_object_sent: bool,
+}
+
+// list with "/" delimiter to collect all common prefixes under start prefix, from the page after the ones listed,
+// the first object directly under start prefix is sent to data map as well
+async fn list_common_prefixes(ctx: &S3TaskContext, prefix: &str, listed: &mut CommonPrefixes) -> std::result::Result<(), FlatRuntimeError> {
+
+    let mut stream = ctx.s3_client().list_objects_v2()
+        .bucket(&ctx.s3_bucket_name)
+        .prefix(prefix)
+        .delimiter("/")
+        .set_max_keys(ctx.g_state.max_keys())
+        .set_request_payer(ctx.request_payer.clone())
+        .set_continuation_token(listed.continuation_token.clone())
+        .into_paginator()
+        .send();
+
+    loop {
+        ctx.wait_for_rate_limit().await;
+        let timeout_duration = ctx.response_timeout;
+        let res = timeout_at(Instant::now() + timeout_duration, stream.next()).await;
+        if res.is_err() {
+            ctx.g_state.inc_task_next_stream_timeout();
+            return Err(FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), prefix.to_string()));
+        }
+
+        let response = match res.unwrap() {
+            Some(response) => response,
+            None => break,
+        };
+        let objects = response.map_err(|e| to_flat_runtime_error(ctx, e, prefix.to_string()))?;
+
+        if !listed.first_object_sent {
+            if let Some(obj) = objects.contents().first() {
+                send_objects(ctx, std::slice::from_ref(obj));
+                listed.first_object_sent = true;
+            }
+        }
+        for p in objects.common_prefixes() {
+            if let Some(p) = p.prefix() {
+                listed.prefixes.push(p.to_string());
+            }
+        }
+        listed.continuation_token = objects.next_continuation_token().map(|t| t.to_string());
+    }
+
+    Ok(())
+}
+
+// retry of a single request, as retry_on_error with "--max-retries", without it the retries of SDK are final
+// instead of sending the request again at once, endlessly. redirects followed and throttling waited for either way
+async fn retry_request(ctx: &S3TaskContext, err: &FlatRuntimeError, retries: &mut u32) -> bool {
+    let throttle_wait = err.category() == ErrorCategory::Throttle && ctx.g_state.on_throttle() == core::OnThrottle::Wait;
+    let redirect = err.redirect_region().is_some() && ctx.follow_redirects();
+    if ctx.retry.is_none() && !throttle_wait && !redirect {
+        ctx.g_state.errors().inc(err.category());
+        return false;
+    }
+    retry_on_error(ctx, err, retries).await
+}
+
+// fetch a single page with max keys 1 to get first object under prefix
+async fn list_first_object(ctx: &S3TaskContext, prefix: &str) -> std::result::Result<(), FlatRuntimeError> {
+
+    ctx.wait_for_rate_limit().await;
+    let request = ctx.s3_client().list_objects_v2()
+        .bucket(&ctx.s3_bucket_name)
+        .prefix(prefix)
+        .max_keys(1)
+        .set_request_payer(ctx.request_payer.clone())
+        .send();
+
+    let timeout_duration = ctx.response_timeout;
+    let res = timeout_at(Instant::now() + timeout_duration, request).await;
+    if res.is_err() {
+        ctx.g_state.inc_task_next_stream_timeout();
+        return Err(FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), prefix.to_string()));
+    }
+
+    let objects = res.unwrap().map_err(|e| to_flat_runtime_error(ctx, e, prefix.to_string()))?;
+    send_objects(ctx, objects.contents());
+
+    Ok(())
+}
+
+// task to list with delimiter level by level, common prefixes above max depth are listed again,
+// the ones at max depth are sent to data map as rows of their own
+async fn shallow_reactor_task(ctx: &S3TaskContext, start_prefixes: &[String], flat_concurrency: usize, opts: &core::ShallowListOptions) {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("Shallow List S3 Task - {} - started, delimiter \"{}\", max depth {}", ctx.s3_bucket_name, opts.delimiter, opts.max_depth);
+
+    let mut pending: std::collections::VecDeque<(String, usize)> = start_prefixes.iter().map(|p| (p.clone(), 1)).collect();
+    let mut set = tokio::task::JoinSet::new();
+    let mut failed = false;
+    while !pending.is_empty() || !set.is_empty() {
+        // fill up to concurrency, then wait for any done to get more prefixes
+        while set.len() < ctx.g_state.list_concurrency(flat_concurrency) && !ctx.is_quit() {
+            let Some((prefix, depth)) = pending.pop_front() else {
+                break;
+            };
+            ctx.g_state.wait_for_memory().await;
+            let task_ctx = ctx.clone();
+            let delimiter = opts.delimiter.clone();
+            set.spawn(async move {
+                let mut retries = 0;
+                loop {
+                    match list_delimited(&task_ctx, &prefix, &delimiter).await {
+                        Ok(common_prefixes) => return Ok((common_prefixes, depth)),
+                        Err(err) if !task_ctx.is_quit() && retry_on_error(&task_ctx, &err, &mut retries).await => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+            });
+        }
+        if ctx.is_quit() {
+            set.abort_all();
+            info!("Shallow List S3 Task - {} - all handler aborted", ctx.s3_bucket_name);
+            break;
+        }
+
+        match set.join_next().await {
+            Some(Ok(Ok((common_prefixes, depth)))) => {
+                // rows of common prefixes are sent right away, the ones next to max depth listed again
+                send_common_prefixes(ctx, &common_prefixes);
+                if depth < opts.max_depth {
+                    pending.extend(common_prefixes.into_iter().map(|p| (p, depth + 1)));
+                }
+            },
+            Some(Ok(Err(err))) => {
+                info!("Shallow List S3 Task - {} - unexpected quit - {}", ctx.s3_bucket_name, err);
+                ctx.g_state.set_list_failed();
+                if ctx.g_state.on_error() == core::OnError::Abort {
+                    ctx.g_state.quit();
+                }
+                set.abort_all();
+                failed = true;
+                break;
+            },
+            Some(Err(_)) | None => {},
+        }
+    }
+
+    if ctx.is_running() {
+        ctx.complete();
+        if !failed {
+            info!("Shallow List S3 Task - {} - completed", ctx.s3_bucket_name);
+            tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+        }
+    }
+    info!("Shallow List S3 Task - {} - quit", ctx.s3_bucket_name);
+}
+
+// list one level under prefix with delimiter, objects are sent to data map, common prefixes returned
+async fn list_delimited(ctx: &S3TaskContext, prefix: &str, delimiter: &str) -> std::result::Result<Vec<String>, FlatRuntimeError> {
+
+    let mut stream = ctx.s3_client().list_objects_v2()
+        .bucket(&ctx.s3_bucket_name)
+        .prefix(prefix)
+        .delimiter(delimiter)
+        .set_max_keys(ctx.g_state.max_keys())
+        .set_request_payer(ctx.request_payer.clone())
+        .into_paginator()
+        .send();
+
+    let mut common_prefixes = Vec::new();
+    loop {
+        ctx.wait_for_rate_limit().await;
+        let timeout_duration = ctx.response_timeout;
+        let res = timeout_at(Instant::now() + timeout_duration, stream.next()).await;
+        if res.is_err() {
+            ctx.g_state.inc_task_next_stream_timeout();
+            return Err(FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), prefix.to_string()));
+        }
+
+        let response = match res.unwrap() {
+            Some(response) => response,
+            None => break,
+        };
+        let objects = response.map_err(|e| to_flat_runtime_error(ctx, e, prefix.to_string()))?;
+
+        send_objects(ctx, objects.contents());
+        common_prefixes.extend(objects.common_prefixes().iter().filter_map(|p| p.prefix().map(|p| p.to_string())));
+    }
+
+    Ok(common_prefixes)
+}
+
+// send common prefixes as rows to data map, grouped by prefix the same as objects
+fn send_common_prefixes(ctx: &S3TaskContext, common_prefixes: &[String]) {
+
+    let mut output: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::new();
+    for p in common_prefixes {
+        let key: ObjectKey = p.as_str().into();
+        let (prefix, name) = key.decode();
+        let mut props = ObjectProps::new_common_prefix();
+        props.set_dir(ctx.dir);
+        output.entry(prefix).or_default().push((name, props));
+    }
+
+    if output.is_empty() {
+        return;
+    }
+    count_listed(ctx, &output);
+    if let Err(e) = ctx.data_map_channel.send(output) {
+        panic!("failed to send output data to data map channel, err: {}", e);
+    }
+}
+
+// group objects by prefix and send to data map
+pub(crate) fn send_objects(ctx: &S3TaskContext, objects: &[aws_sdk_s3::types::Object]) {
+
+    let mut output: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::new();
+    for obj in objects {
+        if let Some(obj_key) = obj.key() {
+            let key: ObjectKey = obj_key.into();
+            let (prefix, name) = key.decode();
+            let mut props: ObjectProps = obj.into();
+            props.set_dir(ctx.dir);
+            output.entry(prefix).or_default().push((name, props));
+        }
+    }
+
+    if output.is_empty() {
+        return;
+    }
+    count_listed(ctx, &output);
+    if let Err(e) = ctx.data_map_channel.send(output) {
+        panic!("failed to send output data to data map channel, err: {}", e);
+    }
+}
+
+fn count_listed(ctx: &S3TaskContext, output: &HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>) {
+    let (objects, bytes) = output.values().flatten()
+        .fold((0, 0), |(objects, bytes), (_, props)| (objects + 1, bytes + props.size()));
+    ctx.g_state.add_listed(objects, bytes);
+    if let Some(sampler) = &ctx.verify_sample {
+        sampler.sample(output);
+    }
+}
+
+// task to control concurrency of s3 flat list, return coverage of all key space pairs
+async fn flat_reactor_task(ctx: &S3TaskContext, mut start_prefixes: Vec<(String, data_map::KeySpaceHints)>,
+        flat_concurrency: usize) -> Vec<PartitionCoverage> {
 
     ctx.start();
     ctx.g_state.wait_to_start().await;
@@ -24,39 +398,334 @@ async fn flat_reactor_task(ctx: &S3TaskContext, start_prefix: &str,
     info!("Flat List S3 Task - {} - started", ctx.s3_bucket_name);
     tokio::task::yield_now().await;
 
+    let mut records = Vec::new();
+    if run_key_space_pairs(ctx, &mut start_prefixes, flat_concurrency, &mut records).await {
+        // side is not complete until sampled keys are checked, so data map reports them in summary
+        if let Some(sampler) = &ctx.verify_sample {
+            verify_sampled(ctx, sampler.take(), flat_concurrency).await;
+        }
+        ctx.complete();
+        info!("Flat List S3 Task - {} - completed", ctx.s3_bucket_name);
+        tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+    }
+
+    // pairs not started or aborted are left incomplete
+    for (start_prefix, hints) in start_prefixes.iter() {
+        for pair in hints.unfinished() {
+            let (start, end) = pair.to_task_input();
+            records.push(new_partition_coverage(ctx, start_prefix, start, end, 0, PartitionStatus::Incomplete));
+        }
+    }
+
+    if let Some(exclude) = &ctx.exclude {
+        info!("Flat List S3 Task - {} - skipped {} subtrees of {} excluded prefixes", ctx.s3_bucket_name,
+            exclude.read_pruned(), exclude.prefixes().len());
+    }
+    if let Some(fetcher) = &ctx.tags {
+        let (fetched, failed) = fetcher.read_counts();
+        info!("Flat List S3 Task - {} - fetched tags of {} objects, {} failed", ctx.s3_bucket_name,
+            utils::fmt_count(fetched), utils::fmt_count(failed));
+    }
+    if let Some((algorithm, fetcher)) = &ctx.checksum {
+        let (fetched, failed) = fetcher.read_counts();
+        info!("Flat List S3 Task - {} - fetched {} checksums of {} objects, {} failed", ctx.s3_bucket_name, algorithm.name(),
+            utils::fmt_count(fetched), utils::fmt_count(failed));
+    }
+    if let Some(fetcher) = &ctx.enrich {
+        let (fetched, failed) = fetcher.read_counts();
+        info!("Flat List S3 Task - {} - fetched metadata of {} objects with HeadObject, {} failed", ctx.s3_bucket_name,
+            utils::fmt_count(fetched), utils::fmt_count(failed));
+    }
+    info!("Flat List S3 Task - {} - quit", ctx.s3_bucket_name);
+    records
+}
+
+// fetch tags, checksum and HeadObject metadata of listed objects of one page before they are sent to data map,
+// delete markers have none, objects failed to fetch are sent without them
+async fn fetch_object_attrs(ctx: &S3TaskContext, output: &mut HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>) {
+    use futures::StreamExt;
+
+    let concurrency = match (&ctx.tags, &ctx.checksum, &ctx.enrich) {
+        (Some(fetcher), _, _) | (None, Some((_, fetcher)), _) | (None, None, Some(fetcher)) => fetcher.concurrency,
+        (None, None, None) => return,
+    };
+    // futures are built up front, closures kept in stream state would not be Send to spawn list task
+    let requests: Vec<_> = output.iter_mut()
+        .flat_map(|(prefix, objects)| objects.iter_mut().map(move |(name, props)| (ObjectKey::encode(prefix, name), props)))
+        .filter(|(_, props)| !props.is_delete_marker())
+        .map(|(key, props)| fetch_one_object_attrs(ctx, key, props))
+        .collect();
+    futures::stream::iter(requests).buffer_unordered(concurrency).collect::<Vec<()>>().await;
+}
+
+async fn fetch_one_object_attrs(ctx: &S3TaskContext, key: ObjectKey, props: &mut ObjectProps) {
+    let (key, version_id) = key.split_version();
+    if let Some(fetcher) = &ctx.tags {
+        let Ok(_permit) = fetcher.permits.acquire().await else {
+            return;
+        };
+        match get_object_tags(ctx, key, version_id).await {
+            Ok(tags) => {
+                props.set_tags(tags);
+                fetcher.inc_fetched();
+            },
+            Err(err) => {
+                warn!("Flat List S3 Task - {} - unable to fetch tags of {} - {}", ctx.s3_bucket_name, key, err);
+                fetcher.inc_failed();
+            },
+        }
+    }
+    if let Some((algorithm, fetcher)) = &ctx.checksum {
+        let Ok(_permit) = fetcher.permits.acquire().await else {
+            return;
+        };
+        match get_object_checksum(ctx, *algorithm, key, version_id).await {
+            Ok(checksum) => {
+                props.set_checksum(checksum.as_deref());
+                fetcher.inc_fetched();
+            },
+            Err(err) => {
+                warn!("Flat List S3 Task - {} - unable to fetch checksum of {} - {}", ctx.s3_bucket_name, key, err);
+                fetcher.inc_failed();
+            },
+        }
+    }
+    if let Some(fetcher) = &ctx.enrich {
+        let Ok(_permit) = fetcher.permits.acquire().await else {
+            return;
+        };
+        match head_object(ctx, key, version_id).await {
+            Ok(head) => {
+                props.set_head(head);
+                fetcher.inc_fetched();
+            },
+            Err(err) => {
+                warn!("Flat List S3 Task - {} - unable to fetch metadata of {} - {}", ctx.s3_bucket_name, key, err);
+                fetcher.inc_failed();
+            },
+        }
+    }
+}
+
+// tags of one object, an object with no tag set is an empty one
+async fn get_object_tags(ctx: &S3TaskContext, key: &str, version_id: Option<&str>) -> std::result::Result<core::ObjectTags, FlatRuntimeError> {
+    let mut retries = 0;
+    loop {
+        ctx.wait_for_rate_limit().await;
+        let request = ctx.s3_client().get_object_tagging()
+            .bucket(&ctx.s3_bucket_name)
+            .key(key)
+            .set_version_id(version_id.map(|v| v.to_string()))
+            .set_request_payer(ctx.request_payer.clone())
+            .send();
+
+        let timeout_duration = ctx.response_timeout;
+        let err = match timeout_at(Instant::now() + timeout_duration, request).await {
+            Ok(Ok(output)) => {
+                return Ok(output.tag_set().iter().map(|t| (t.key().to_string(), t.value().to_string())).collect());
+            },
+            // some S3 compatible stores respond error instead of empty tag set
+            Ok(Err(sdk_err)) if sdk_err.code() == Some("NoSuchTagSet") => return Ok(Vec::new()),
+            Ok(Err(sdk_err)) => to_flat_runtime_error(ctx, sdk_err, key.to_string()),
+            Err(_) => {
+                ctx.g_state.inc_task_next_stream_timeout();
+                FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), key.to_string())
+            },
+        };
+        if ctx.is_quit() || !retry_on_error(ctx, &err, &mut retries).await {
+            return Err(err);
+        }
+    }
+}
+
+// checksum of one object with GetObjectAttributes, none if uploaded without one of this algorithm
+async fn get_object_checksum(ctx: &S3TaskContext, algorithm: core::ChecksumAlgorithm, key: &str, version_id: Option<&str>)
+        -> std::result::Result<Option<String>, FlatRuntimeError> {
+    let mut retries = 0;
+    loop {
+        ctx.wait_for_rate_limit().await;
+        // attributes of SSE-C object are rejected with 400 without its key
+        let (sse_algorithm, sse_key, sse_key_md5) = ctx.sse_customer_headers();
+        let request = ctx.s3_client().get_object_attributes()
+            .bucket(&ctx.s3_bucket_name)
+            .key(key)
+            .set_version_id(version_id.map(|v| v.to_string()))
+            .object_attributes(aws_sdk_s3::types::ObjectAttributes::Checksum)
+            .set_request_payer(ctx.request_payer.clone())
+            .set_sse_customer_algorithm(sse_algorithm)
+            .set_sse_customer_key(sse_key)
+            .set_sse_customer_key_md5(sse_key_md5)
+            .send();
+
+        let timeout_duration = ctx.response_timeout;
+        let err = match timeout_at(Instant::now() + timeout_duration, request).await {
+            Ok(Ok(output)) => {
+                return Ok(output.checksum().and_then(|c| algorithm.value(c)).map(|c| c.to_string()));
+            },
+            Ok(Err(sdk_err)) => to_flat_runtime_error(ctx, sdk_err, key.to_string()),
+            Err(_) => {
+                ctx.g_state.inc_task_next_stream_timeout();
+                FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), key.to_string())
+            },
+        };
+        if ctx.is_quit() || !retry_on_error(ctx, &err, &mut retries).await {
+            return Err(err);
+        }
+    }
+}
+
+// metadata of one object with HeadObject, part number 1 is asked for to get parts count of multipart upload
+async fn head_object(ctx: &S3TaskContext, key: &str, version_id: Option<&str>) -> std::result::Result<core::ObjectHead, FlatRuntimeError> {
+    let mut retries = 0;
+    loop {
+        ctx.wait_for_rate_limit().await;
+        // HEAD of SSE-C object is rejected with 400 without its key
+        let (sse_algorithm, sse_key, sse_key_md5) = ctx.sse_customer_headers();
+        let request = ctx.s3_client().head_object()
+            .bucket(&ctx.s3_bucket_name)
+            .key(key)
+            .set_version_id(version_id.map(|v| v.to_string()))
+            .part_number(1)
+            .set_request_payer(ctx.request_payer.clone())
+            .set_sse_customer_algorithm(sse_algorithm)
+            .set_sse_customer_key(sse_key)
+            .set_sse_customer_key_md5(sse_key_md5)
+            .send();
+
+        let timeout_duration = ctx.response_timeout;
+        let err = match timeout_at(Instant::now() + timeout_duration, request).await {
+            Ok(Ok(output)) => return Ok(core::ObjectHead::from(&output)),
+            Ok(Err(sdk_err)) => to_flat_runtime_error(ctx, sdk_err, key.to_string()),
+            Err(_) => {
+                ctx.g_state.inc_task_next_stream_timeout();
+                FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), key.to_string())
+            },
+        };
+        if ctx.is_quit() || !retry_on_error(ctx, &err, &mut retries).await {
+            return Err(err);
+        }
+    }
+}
+
+// HEAD each sampled key with the same concurrency and rate limit as list tasks, a 404 means a listed key is not retrievable
+async fn verify_sampled(ctx: &S3TaskContext, keys: Vec<(String, Option<String>)>, flat_concurrency: usize) {
+    use futures::StreamExt;
+
+    info!("Flat List S3 Task - {} - verifying {} sampled keys", ctx.s3_bucket_name, keys.len());
+    let mut result = core::VerifyResult {
+        bucket: ctx.s3_bucket_name.clone(),
+        ..Default::default()
+    };
+    let mut heads = futures::stream::iter(keys)
+        .map(|(key, version_id)| async move {
+            let res = head_sampled(ctx, &key, version_id.as_deref()).await;
+            (key, version_id, res)
+        })
+        .buffer_unordered(ctx.g_state.list_concurrency(flat_concurrency));
+    while let Some((key, version_id, res)) = heads.next().await {
+        if ctx.is_quit() {
+            info!("Flat List S3 Task - {} - verify aborted", ctx.s3_bucket_name);
+            return;
+        }
+        result.checked += 1;
+        let version = version_id.map(|v| format!(" version {}", v)).unwrap_or_default();
+        match res {
+            Ok(true) => {},
+            Ok(false) => {
+                warn!("Flat List S3 Task - {} - listed key {}{} not found", ctx.s3_bucket_name, key, version);
+                result.missing += 1;
+            },
+            Err(err) => {
+                warn!("Flat List S3 Task - {} - unable to verify key {}{} - {}", ctx.s3_bucket_name, key, version, err);
+                result.failed += 1;
+            },
+        }
+    }
+    ctx.g_state.add_verify_result(result);
+}
+
+// return false if key responds 404
+async fn head_sampled(ctx: &S3TaskContext, key: &str, version_id: Option<&str>) -> std::result::Result<bool, FlatRuntimeError> {
+    let mut retries = 0;
+    loop {
+        ctx.wait_for_rate_limit().await;
+        // HEAD of SSE-C object is rejected with 400 without its key
+        let (sse_algorithm, sse_key, sse_key_md5) = ctx.sse_customer_headers();
+        let request = ctx.s3_client().head_object()
+            .bucket(&ctx.s3_bucket_name)
+            .key(key)
+            .set_version_id(version_id.map(|v| v.to_string()))
+            .set_request_payer(ctx.request_payer.clone())
+            .set_sse_customer_algorithm(sse_algorithm)
+            .set_sse_customer_key(sse_key)
+            .set_sse_customer_key_md5(sse_key_md5)
+            .send();
+
+        let timeout_duration = ctx.response_timeout;
+        let err = match timeout_at(Instant::now() + timeout_duration, request).await {
+            Ok(Ok(_)) => return Ok(true),
+            Ok(Err(sdk_err)) if sdk_err.as_service_error().is_some_and(|e| e.is_not_found()) => return Ok(false),
+            Ok(Err(sdk_err)) => to_flat_runtime_error(ctx, sdk_err, key.to_string()),
+            Err(_) => {
+                ctx.g_state.inc_task_next_stream_timeout();
+                FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), key.to_string())
+            },
+        };
+        if ctx.is_quit() || !retry_on_error(ctx, &err, &mut retries).await {
+            return Err(err);
+        }
+    }
+}
+
+// list key space pairs of all start prefixes to complete, coverage of finished pairs is pushed to records,
+// return false if quit before all pairs are done
+pub(crate) async fn run_key_space_pairs(ctx: &S3TaskContext, start_prefixes: &mut [(String, data_map::KeySpaceHints)],
+        flat_concurrency: usize, records: &mut Vec<PartitionCoverage>) -> bool {
+
     let mut joins = Vec::new();
     let mut last_ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    ctx.g_state.add_partitions(start_prefixes.iter().map(|(_, hints)| hints.len()).sum());
+    // start prefixes are taken one after another, pairs of next one start as soon as the previous runs out
+    let mut current = 0;
     loop {
 
-        // if we in low pressure
-        if joins.len() < flat_concurrency {
+        // if we in low pressure, limit is tuned by throttling with adaptive concurrency
+        if joins.len() < ctx.g_state.list_concurrency(flat_concurrency) && current < start_prefixes.len() {
+            let (start_prefix, hints) = &mut start_prefixes[current];
             if let Some(pair) = hints.next() {
                 let task_ctx = ctx.clone();
-                let start_prefix = start_prefix.to_string();
+                let start_prefix = start_prefix.clone();
+                let index = current;
 
                 let h = tokio::task::spawn(async move {
                         let (start, end) = pair.to_task_input();
-                        flat_list_run_to_complete(&task_ctx, &start_prefix, start, end).await;
-                        pair
+                        task_ctx.g_state.inc_active_list_tasks();
+                        let record = flat_list_run_to_complete(&task_ctx, &start_prefix, start, end).await;
+                        task_ctx.g_state.dec_active_list_tasks();
+                        (index, pair, record)
                     });
                 joins.push(h);
-                continue;
+            } else {
+                current += 1;
             }
+            continue;
         }
 
-        if joins.len() == 0 {
-            ctx.complete();
-            info!("Flat List S3 Task - {} - completed", ctx.s3_bucket_name);
-            tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
-            break;
+        if joins.is_empty() {
+            return true;
         }
 
         let mut waitings = Vec::new();
         while let Some(h) = joins.pop() {
             if h.is_finished() {
                 match h.await {
-                    Ok(pair) => {
-                        hints.finish(pair.index());
+                    Ok((index, pair, record)) => {
+                        start_prefixes[index].1.finish(pair.index());
+                        ctx.g_state.inc_partitions_done();
+                        if let Some(checkpointer) = ctx.g_state.checkpointer() {
+                            checkpointer.partition_done(&record);
+                        }
+                        records.push(record);
                     },
                     Err(e) => {
                         panic!("task join handler error {:?}", e);
@@ -80,52 +749,391 @@ async fn flat_reactor_task(ctx: &S3TaskContext, start_prefix: &str,
                 h.abort();
             }
             info!("Flat List S3 Task - {} - all handler aborted", ctx.s3_bucket_name);
-            break;
+            return false;
         }
     }
+}
 
-    info!("Flat List S3 Task - {} - quit", ctx.s3_bucket_name);
-    ()
+fn new_partition_coverage(ctx: &S3TaskContext, prefix: &str, start: &str, end: Option<&str>,
+        objects: usize, status: PartitionStatus) -> PartitionCoverage {
+    PartitionCoverage {
+        bucket: ctx.s3_bucket_name.clone(),
+        prefix: prefix.to_string(),
+        start: start.to_string(),
+        end: end.map(|s| s.to_string()),
+        objects,
+        status,
+    }
+}
+
+const S3_HEADER_BUCKET_REGION: &str = "x-amz-bucket-region";
+
+// capture "Date" and bucket region header of raw http response
+#[derive(Debug, Clone, Default)]
+struct ProbeHeaderInterceptor {
+    date: Arc<Mutex<Option<String>>>,
+    region: Arc<Mutex<Option<String>>>,
+}
+
+impl Intercept for ProbeHeaderInterceptor {
+    fn name(&self) -> &'static str {
+        "ProbeHeaderInterceptor"
+    }
+
+    fn read_after_transmit(&self, context: &BeforeDeserializationInterceptorContextRef<'_>,
+            _runtime_components: &RuntimeComponents, _cfg: &mut ConfigBag) -> Result<(), BoxError> {
+        let headers = context.response().headers();
+        if let Some(date) = headers.get("date") {
+            *self.date.lock().unwrap() = Some(date.to_string());
+        }
+        if let Some(region) = headers.get(S3_HEADER_BUCKET_REGION) {
+            *self.region.lock().unwrap() = Some(region.to_string());
+        }
+        Ok(())
+    }
 }
 
-async fn flat_list_run_to_complete(ctx: &S3TaskContext, prefix: &str, start: &str, until: Option<&str>) {
+pub struct BucketProbe {
+    // local time minus server time in secs
+    pub clock_skew: Option<i64>,
+    // region where bucket located
+    pub region: Option<String>,
+    // tls handshake failed on certificate of endpoint, no request will ever get through
+    pub tls_rejected: bool,
+}
+
+// region from location constraint, it's empty for us-east-1 and "EU" for eu-west-1 buckets of early days
+fn location_to_region(location: Option<&str>) -> String {
+    match location {
+        None | Some("") => "us-east-1".to_string(),
+        Some("EU") => "eu-west-1".to_string(),
+        Some(region) => region.to_string(),
+    }
+}
+
+// single GetBucketLocation request without retry, sent to us-east-1 if no region configured
+pub async fn get_bucket_region(client: &aws_sdk_s3::Client, bucket: &str) -> std::result::Result<String, String> {
+    let mut config_override = aws_sdk_s3::config::Config::builder()
+        .retry_config(aws_config::retry::RetryConfig::disabled());
+    if client.config().region().is_none() {
+        config_override = config_override.region(aws_sdk_s3::config::Region::new("us-east-1"));
+    }
+    let output = client.get_bucket_location()
+        .bucket(bucket)
+        .customize()
+        .config_override(config_override)
+        .send()
+        .await
+        .map_err(|e| match e.as_service_error().and_then(|e| e.meta().code()) {
+            Some(code) => code.to_string(),
+            None => aws_sdk_s3::error::DisplayErrorContext(&e).to_string(),
+        })?;
+    Ok(location_to_region(output.location_constraint().map(|l| l.as_str())))
+}
+
+// send a single HeadBucket request and collect server time and bucket region from response headers,
+// any response (even access denied or redirect) carries them
+pub async fn probe_bucket(client: &aws_sdk_s3::Client, bucket: &str) -> BucketProbe {
+
+    let interceptor = ProbeHeaderInterceptor::default();
+    let res = client.head_bucket()
+        .bucket(bucket)
+        .customize()
+        .config_override(
+            aws_sdk_s3::config::Config::builder()
+                .retry_config(aws_config::retry::RetryConfig::disabled())
+        )
+        .interceptor(interceptor.clone())
+        .send()
+        .await;
+    let local = chrono::Utc::now().timestamp();
+
+    let date = interceptor.date.lock().unwrap().take();
+    let clock_skew = date.and_then(|date| match DateTime::parse_from_rfc2822(&date) {
+        Ok(server) => {
+            let skew = local - server.timestamp();
+            debug!("clock skew probe for bucket {}: server date {}, skew {}s", bucket, date, skew);
+            Some(skew)
+        },
+        Err(e) => {
+            debug!("unable to parse server date header \"{}\": {}", date, e);
+            None
+        }
+    });
+
+    let region = interceptor.region.lock().unwrap().take();
+    let tls_rejected = matches!(&res, Err(SdkError::DispatchFailure(e))
+        if e.as_connector_error().is_some_and(|e| is_tls_certificate_error(e)));
+    BucketProbe {
+        clock_skew,
+        region,
+        tls_rejected,
+    }
+}
+
+// decide whether to call again after list call failed, backoff before it with retry policy,
+// otherwise keep on all errors allowed to continue as SDK already retried
+async fn retry_on_error(ctx: &S3TaskContext, err: &FlatRuntimeError, retries: &mut u32) -> bool {
+    ctx.g_state.errors().inc(err.category());
+    // list call sent to configured region is sent again to bucket region, once the client of all tasks is switched to it.
+    // a task failed by a request sent before another task switched it retries as well, redirect again is not followed
+    if let Some(bucket_region) = err.redirect_region().filter(|_| ctx.follow_redirects()) {
+        if *retries > 0 {
+            return false;
+        }
+        if !ctx.follow_redirect(bucket_region) {
+            debug!("client of bucket {} switched to region {} already", ctx.s3_bucket_name, bucket_region);
+        }
+        *retries += 1;
+        return true;
+    }
+    if err.category() == ErrorCategory::Throttle {
+        match ctx.g_state.on_throttle() {
+            core::OnThrottle::Abort => {
+                let abort_after = ctx.g_state.throttle_abort_after();
+                if let Some((streak, throttled)) = ctx.g_state.throttle().streak().filter(|(streak, _)| *streak >= abort_after) {
+                    if ctx.g_state.abort_on_throttle() {
+                        error!("Flat List S3 Task - {} - {} responses throttled in {}s without a success, abort run - {}",
+                            ctx.s3_bucket_name, utils::fmt_count(throttled), streak.as_secs(), err);
+                    }
+                    return false;
+                }
+            },
+            // throttled retries are not counted against max retries, backoff grows with the streak of all tasks
+            core::OnThrottle::Wait if !ctx.is_quit() => {
+                let policy = ctx.retry.unwrap_or(core::RetryPolicy { max_retries: 0, base_delay_ms: core::DEFAULT_RETRY_BASE_DELAY_MS });
+                let streak = ctx.g_state.throttle().streak().map_or(0, |(_, throttled)| throttled);
+                let delay = policy.backoff(streak as u32);
+                ctx.g_state.inc_list_retry();
+                debug!("throttled, wait {}ms to retry - {}", delay.as_millis(), err);
+                tokio::time::sleep(delay).await;
+                return true;
+            },
+            _ => {},
+        }
+    }
+    let Some(policy) = ctx.retry else {
+        return err.continue_on_error();
+    };
+    if !err.is_retryable() || *retries >= policy.max_retries {
+        return false;
+    }
+
+    let delay = policy.backoff(*retries);
+    *retries += 1;
+    ctx.g_state.inc_list_retry();
+    debug!("retry {} of {} in {}ms - {}", retries, policy.max_retries, delay.as_millis(), err);
+    tokio::time::sleep(delay).await;
+    true
+}
+
+async fn flat_list_run_to_complete(ctx: &S3TaskContext, prefix: &str, start: &str, until: Option<&str>) -> PartitionCoverage {
 
     let mut start_after = start.to_string();
-    let prefix = prefix.to_string();
-    while let Err(err) = flat_list(ctx, &prefix, &start_after, until).await {
-        if err.continue_on_error() {
+    let mut objects = 0;
+    let mut retries = 0;
+    while let Err(err) = flat_list(ctx, prefix, &start_after, until, &mut objects).await {
+        // retries are counted on consecutive failures, reset once list moves on
+        if err.next_start() != start_after {
+            retries = 0;
+        }
+        if retry_on_error(ctx, &err, &mut retries).await {
             start_after = err.next_start();
             continue;
         }
-        if ctx.is_running() {
-            info!("Flat List S3 Task - {} - unexpected quit - {}", ctx.s3_bucket_name, err);
-            ctx.complete();
+        ctx.g_state.set_list_failed();
+        if ctx.g_state.on_error() == core::OnError::Continue {
+            // objects before next start are already sent, only the rest of range is left to retry
+            let range = data_map::KsRange { prefix: prefix.to_string(), start: err.next_start(), end: until.map(|u| u.to_string()) };
+            warn!("Flat List S3 Task - {} - range after \"{}\" until {} of prefix \"{}\" failed, continue - {}", ctx.s3_bucket_name,
+                range.start, until.map(|u| format!("\"{}\"", u)).as_deref().unwrap_or("end of key space"), prefix, err);
+            ctx.g_state.add_failed_range(range);
+        } else if !ctx.is_quit() {
+            info!("Flat List S3 Task - {} - unexpected quit, abort run - {}", ctx.s3_bucket_name, err);
+            ctx.g_state.quit();
         }
-        return;
+        return new_partition_coverage(ctx, prefix, start, until, objects, PartitionStatus::Incomplete);
     }
+    new_partition_coverage(ctx, prefix, start, until, objects, PartitionStatus::Complete)
 }
 
-async fn flat_list(ctx: &S3TaskContext, prefix: &str, start_after: &str, until: Option<&str>) -> std::result::Result<(), FlatRuntimeError> {
+// map sdk error of ListObjectsV2 or ListObjectVersions into flat runtime error
+fn to_flat_runtime_error<E>(ctx: &S3TaskContext, sdk_err: SdkError<E, HttpResponse>,
+        next_start: String) -> FlatRuntimeError
+    where E: ProvideErrorMetadata + std::error::Error + 'static {
 
-    // Build the request with more detailed debug information
-    let request = ctx.s3_client.list_objects_v2()
-        .bucket(&ctx.s3_bucket_name)
-        .prefix(prefix)
-        .start_after(start_after);
+    // Log detailed error information
+    error!("S3 API error: {:?}", sdk_err);
+
+    match &sdk_err {
+        aws_sdk_s3::error::SdkError::ServiceError(err) => {
+            let errno;
+            let mut hint = None;
+            let mut redirect_region = None;
+            match err.err() {
+                e if e.meta().code() == Some("NoSuchBucket") => {
+                    error!("NoSuchBucket error: {}", e);
+                    errno = ERROR_S3_NO_BUCKET;
+                },
+                e => {
+                    let code = e.meta().code();
+                    error!("Service error code: {:?}, message: {:?}",
+                           code, e.meta().message());
+
+                    match code {
+                        Some("AccessDenied") => {
+                            errno = ERROR_S3_ACCESS_DENIED;
+                            if ctx.request_payer.is_none() {
+                                hint = Some(HINT_REQUEST_PAYER);
+                            }
+                        },
+                        Some("PermanentRedirect") => {
+                            ctx.g_state.inc_s3_redirect();
+                            redirect_region = err.raw().headers().get(S3_HEADER_BUCKET_REGION).map(|r| r.to_string());
+                            let bucket_region = redirect_region.as_deref().unwrap_or("<unknown>");
+                            if !ctx.follow_redirects() {
+                                error!("bucket {} is located in region {}, please rerun with --region {} or --follow-redirects",
+                                    ctx.s3_bucket_name, bucket_region, bucket_region);
+                            }
+                            errno = ERROR_S3_PERMANENT_REDIRECT;
+                        },
+                        Some(other_code) => {
+                            error!("Unknown service error code: {}", other_code);
+                            errno = ERROR_S3_UNKOWN;
+                        },
+                        None => {
+                            error!("Service error without code: {}", e);
+                            panic!("unknown error occurs from list request {}", e);
+                        },
+                    }
+                    error!("Full error details: {}", e);
+                },
+            }
+            let http_status_code = err.raw().status().as_u16();
+            error!("HTTP status code: {}", http_status_code);
+            return FlatRuntimeError::new(
+                errno,
+                err.err().meta().message().unwrap_or("No error message").to_string(),
+                next_start
+            )
+            .with_hint(hint)
+            .with_redirect_region(redirect_region)
+            .with_http_status_code_tracker(http_status_code, ctx.get_tracker());
+        },
+        aws_sdk_s3::error::SdkError::DispatchFailure(err) => {
+            error!("Dispatch failure: {:?}", err);
+
+            // Check for region-related errors
+            if let Some(conn_err) = err.as_connector_error() {
+                let err_str = conn_err.to_string();
+                if err_str.contains("region must be set") {
+                    error!("Region error: A region must be set when using S3");
+                    error!("Fix: Set region using --region parameter, AWS_REGION environment variable, or in AWS profile");
+                    return FlatRuntimeError::new(
+                        ERROR_S3_MISSING_REGION,
+                        "Region must be set when using S3. Fix: Use --region parameter, AWS_REGION env var, or set in AWS profile.".to_string(),
+                        next_start
+                    );
+                }
+            }
+
+            if let Some(conn_err) = err.as_connector_error().filter(|e| is_tls_certificate_error(*e)) {
+                error!("TLS certificate of endpoint rejected: {}", conn_err);
+                return FlatRuntimeError::new(
+                    ERROR_TLS_CERTIFICATE,
+                    "TLS certificate of endpoint not trusted".to_string(),
+                    next_start
+                ).with_hint(Some(HINT_TLS_CERTIFICATE));
+            }
+
+            if err.is_timeout() {
+                if let Some(conn_err) = err.as_connector_error() {
+                    error!("Connection timeout error: {}", conn_err);
+                    ctx.g_state.inc_s3_client_timeout();
+                    return FlatRuntimeError::new(
+                        ERROR_S3_CLIENT_CONNECTION_TIMEOUT,
+                        conn_err.to_string(),
+                        next_start
+                    );
+                } else {
+                    error!("Connection timeout but no connector error");
+                    ctx.g_state.inc_s3_client_timeout();
+                    return FlatRuntimeError::new(
+                        ERROR_S3_CLIENT_CONNECTION_TIMEOUT,
+                        "Unknown timeout error".to_string(),
+                        next_start
+                    );
+                }
+            }
+        },
+        aws_sdk_s3::error::SdkError::ResponseError(err) => {
+            error!("Response error: {:?}", err);
+        },
+        aws_sdk_s3::error::SdkError::TimeoutError(err) => {
+            error!("Timeout error: {:?}", err);
+        },
+        aws_sdk_s3::error::SdkError::ConstructionFailure(err) => {
+            error!("Construction failure: {:?}", err);
+        },
+        _ => {
+            error!("Other SDK error type: {:?}", sdk_err);
+        }
+    }
+    ctx.g_state.inc_s3_client_generic_error();
+    FlatRuntimeError::new(ERROR_S3_CLIENT_GENERIC, sdk_err.to_string(), next_start)
+}
+
+async fn flat_list(ctx: &S3TaskContext, prefix: &str, start_after: &str, until: Option<&str>,
+        objects_count: &mut usize) -> std::result::Result<(), FlatRuntimeError> {
+
+    if ctx.versions {
+        return flat_list_versions(ctx, prefix, start_after, until, objects_count).await;
+    }
+
+    // partition starting inside an excluded prefix starts after it, or is skipped if ends inside as well
+    let mut list_start = start_after.to_string();
+    if let Some(skip) = ctx.skip_excluded(start_after) {
+        if until.is_some_and(|end| end <= skip.as_str()) {
+            debug!("pair start {}, end {:?} is excluded", start_after, until);
+            return Ok(());
+        }
+        list_start = skip;
+    }
 
-    // Debug log the request details
-    debug!("Sending S3 request: bucket={}, prefix={}, start_after={}",
-           &ctx.s3_bucket_name, prefix, start_after);
+    // Build the request with more detailed debug information
+    let new_stream = |start_after: &str| {
+        // Debug log the request details
+        debug!("Sending S3 request: bucket={}, prefix={}, start_after={}",
+               &ctx.s3_bucket_name, prefix, start_after);
+        ctx.s3_client().list_objects_v2()
+            .bucket(&ctx.s3_bucket_name)
+            .prefix(prefix)
+            .start_after(start_after)
+            .set_max_keys(ctx.g_state.max_keys())
+            .set_request_payer(ctx.request_payer.clone())
+            .set_fetch_owner(ctx.with_owner.then_some(true))
+            .into_paginator().send()
+    };
 
     // Create the paginator
-    let mut stream = request.into_paginator().send();
+    let mut stream = new_stream(&list_start);
 
     debug!("input pair start {}, end {:?}", start_after, until);
-    let mut next_start = start_after.to_string();
+    let mut next_start = list_start.clone();
     let mut is_ended = false;
+    let mut last_token: Option<String> = None;
     loop {
 
-        let timeout_duration = Duration::from_secs(core::DEFAULT_S3_CLIENT_TIMEOUT);
+        // no more pages after max objects, task is aborted on quit like on ctrl-c, partition is left incomplete
+        if ctx.g_state.max_objects_reached() {
+            std::future::pending::<()>().await;
+        }
+
+        // hold on next page while data map channel is backed up or memory is over cap
+        ctx.g_state.wait_for_memory().await;
+        ctx.wait_for_rate_limit().await;
+
+        let timeout_duration = ctx.response_timeout;
         debug!("Waiting for S3 response with timeout of {} seconds", timeout_duration.as_secs());
         let res = timeout_at(Instant::now() + timeout_duration, stream.next()).await;
 
@@ -142,119 +1150,37 @@ async fn flat_list(ctx: &S3TaskContext, prefix: &str, start_after: &str, until:
         let response = paginator.unwrap();
 
         if let Err(sdk_err) = response {
-            // Log detailed error information
-            error!("S3 API error: {:?}", sdk_err);
-
-            match &sdk_err {
-                aws_sdk_s3::error::SdkError::ServiceError(err) => {
-                    let errno;
-                    match err.err() {
-                        ListObjectsV2Error::NoSuchBucket(e) => {
-                            error!("NoSuchBucket error: {}", e);
-                            errno = ERROR_S3_NO_BUCKET;
-                        },
-                        e @ _ => {
-                            let code = e.meta().code();
-                            error!("Service error code: {:?}, message: {:?}",
-                                   code, e.meta().message());
-
-                            match code {
-                                Some("AccessDenied") => {
-                                    errno = ERROR_S3_ACCESS_DENIED;
-                                },
-                                Some("PermanentRedirect") => {
-                                    errno = ERROR_S3_PERMANENT_REDIRECT;
-                                },
-                                Some(other_code) => {
-                                    error!("Unknown service error code: {}", other_code);
-                                    errno = ERROR_S3_UNKOWN;
-                                },
-                                None => {
-                                    error!("Service error without code: {}", e);
-                                    panic!("unknown error occurs from ListObjectsV2 {}", e);
-                                },
-                            }
-                            error!("Full error details: {}", e);
-                        },
-                    }
-                    let http_status_code = err.raw().status().as_u16();
-                    error!("HTTP status code: {}", http_status_code);
-                    return Err(
-                        FlatRuntimeError::new(
-                            errno,
-                            err.err().meta().message().unwrap_or("No error message").to_string(),
-                            next_start
-                        )
-                        .with_http_status_code_tracker(http_status_code, ctx.get_tracker())
-                    );
-                },
-                aws_sdk_s3::error::SdkError::DispatchFailure(err) => {
-                    error!("Dispatch failure: {:?}", err);
-
-                    // Check for region-related errors
-                    if let Some(conn_err) = err.as_connector_error() {
-                        let err_str = conn_err.to_string();
-                        if err_str.contains("region must be set") {
-                            error!("Region error: A region must be set when using S3");
-                            error!("Fix: Set region using --region parameter, AWS_REGION environment variable, or in AWS profile");
-                            return Err(
-                                FlatRuntimeError::new(
-                                    ERROR_S3_MISSING_REGION,
-                                    "Region must be set when using S3. Fix: Use --region parameter, AWS_REGION env var, or set in AWS profile.".to_string(),
-                                    next_start
-                                )
-                            );
-                        }
-                    }
-
-                    if err.is_timeout() {
-                        if let Some(conn_err) = err.as_connector_error() {
-                            error!("Connection timeout error: {}", conn_err);
-                            ctx.g_state.inc_s3_client_timeout();
-                            return Err(
-                               FlatRuntimeError::new(
-                                    ERROR_S3_CLIENT_CONNECTION_TIMEOUT,
-                                    conn_err.to_string(),
-                                    next_start
-                                )
-                            );
-                        } else {
-                            error!("Connection timeout but no connector error");
-                            ctx.g_state.inc_s3_client_timeout();
-                            return Err(
-                               FlatRuntimeError::new(
-                                    ERROR_S3_CLIENT_CONNECTION_TIMEOUT,
-                                    "Unknown timeout error".to_string(),
-                                    next_start
-                                )
-                            );
-                        }
-                    }
-                },
-                aws_sdk_s3::error::SdkError::ResponseError(err) => {
-                    error!("Response error: {:?}", err);
-                },
-                aws_sdk_s3::error::SdkError::TimeoutError(err) => {
-                    error!("Timeout error: {:?}", err);
-                },
-                aws_sdk_s3::error::SdkError::ConstructionFailure(err) => {
-                    error!("Construction failure: {:?}", err);
-                },
-                _ => {
-                    error!("Other SDK error type: {:?}", sdk_err);
-                }
-            }
-            ctx.g_state.inc_s3_client_generic_error();
-            return Err(FlatRuntimeError::new(ERROR_S3_CLIENT_GENERIC, sdk_err.to_string(), next_start));
+            return Err(to_flat_runtime_error(ctx, sdk_err, next_start));
         }
 
         let objects = response.unwrap();
         let mut output: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::new();
 
         assert!(objects.contents().len() == objects.key_count.unwrap_or(0) as usize);
+        let is_truncated = objects.is_truncated().unwrap_or(false);
+        if is_truncated {
+            ctx.g_state.add_truncated_page(objects.contents().len());
+        }
+        // paginator stops silently on a truncated page without next token or with the same one again,
+        // a page going back to keys listed already is of a token not advancing either
+        let page_start = next_start.clone();
+        let next_token = objects.next_continuation_token().filter(|t| !t.is_empty());
+        let first_key = objects.contents().first().and_then(|obj| obj.key());
+        let suspect = match (is_truncated, next_token) {
+            _ if first_key.is_some_and(|key| !page_start.is_empty() && key <= page_start.as_str()) =>
+                Some((format!("page going back to key \"{}\" listed already", first_key.unwrap()), true)),
+            (false, _) => None,
+            (true, None) => Some(("truncated page without continuation token".to_string(), true)),
+            (true, token) if token == last_token.as_deref() => Some(("truncated page repeating previous continuation token".to_string(), true)),
+            (true, _) if objects.contents().len() < ctx.g_state.page_size() => Some((format!("truncated page of {} keys, page size {}",
+                objects.contents().len(), ctx.g_state.page_size()), false)),
+            _ => None,
+        };
+        last_token = next_token.map(|t| t.to_string());
         let mut key_count = objects.key_count.unwrap_or(0);
         // collect data group by prefix
         for obj in objects.contents() {
+            key_count -= 1;
             if let Some(obj_key) = obj.key() {
 
                 let key: ObjectKey = obj_key.into();
@@ -268,28 +1194,55 @@ async fn flat_list(ctx: &S3TaskContext, prefix: &str, start_after: &str, until:
                     }
                 }
 
+                // sent with an earlier page
+                if !page_start.is_empty() && obj_key <= page_start.as_str() {
+                    continue;
+                }
+
                 // remember last key to next start for failsafe
-                if key_count == 1 {
+                if key_count == 0 {
                     next_start = obj_key.to_string();
                 }
 
+                if ctx.is_excluded(obj_key) {
+                    continue;
+                }
+
                 let (prefix, name) = key.decode();
                 let mut props: ObjectProps = obj.into();
                 props.set_dir(ctx.dir);
+                if ctx.with_owner {
+                    props.set_owner(obj.owner());
+                }
 
                 if let Some(v) = output.get_mut(&prefix) {
                     v.push((name, props));
                 } else {
-                    let mut v = Vec::new();
-                    v.push((name, props));
-                    output.insert(prefix, v);
+                    output.insert(prefix, vec![(name, props)]);
                 }
+                *objects_count += 1;
             }
-            key_count -= 1;
         }
 
+        // page ends inside an excluded prefix, jump over the rest of it
+        let mut is_restarted = false;
+        if !is_ended && is_truncated {
+            if let Some(skip) = ctx.skip_excluded(&next_start) {
+                debug!("pruned excluded prefix at key {}", next_start);
+                if until.is_some_and(|end| end <= skip.as_str()) {
+                    is_ended = true;
+                } else {
+                    stream = new_stream(&skip);
+                    next_start = skip;
+                    is_restarted = true;
+                }
+            }
+        }
+
+        fetch_object_attrs(ctx, &mut output).await;
+        count_listed(ctx, &output);
         if let Err(e) = ctx.data_map_channel.send(output) {
-            if ctx.is_quit() != true {
+            if !ctx.is_quit() {
                 panic!("error on send data to data map channel");
             }
             panic!("failed to send output data to data map channel, err: {}", e);
@@ -298,8 +1251,538 @@ async fn flat_list(ctx: &S3TaskContext, prefix: &str, start_after: &str, until:
         if is_ended {
             break;
         }
+
+        // keys of page are sent, with --strict-pagination the rest of range after them fails
+        if let Some((reason, stops_stream)) = suspect {
+            let suspect_pages = ctx.g_state.inc_suspect_page();
+            if ctx.g_state.is_strict_pagination() {
+                return Err(FlatRuntimeError::new(ERROR_S3_SUSPECT_PAGE, format!("{} after \"{}\"", reason, page_start), next_start)
+                    .with_hint(Some(HINT_SUSPECT_PAGE)));
+            }
+            if stops_stream {
+                warn!("Flat List S3 Task - {} - {} after \"{}\" of prefix \"{}\", list on after last key \"{}\"",
+                    ctx.s3_bucket_name, reason, page_start, prefix, next_start);
+                if !is_restarted {
+                    stream = new_stream(&next_start);
+                }
+            } else if suspect_pages == 1 {
+                warn!("Flat List S3 Task - {} - {} after \"{}\" of prefix \"{}\", keys may be missing, more such pages are only counted",
+                    ctx.s3_bucket_name, reason, page_start, prefix);
+            }
+        }
     }
     debug!("finished pair start {}, end {:?}", start_after, until);
 
     Ok(())
 }
+
+/*
+ * list all versions and delete markers of objects in range (start_after, until),
+ * ListObjectVersions has no paginator, so follow key and version id markers by hand.
+ * each version is sent to data map as an object with version id suffixed to its name.
+ */
+async fn flat_list_versions(ctx: &S3TaskContext, prefix: &str, start_after: &str, until: Option<&str>,
+        objects_count: &mut usize) -> std::result::Result<(), FlatRuntimeError> {
+
+    debug!("input pair start {}, end {:?} for versions", start_after, until);
+    // versions of one key may span pages, only keys before the last one of a page are complete,
+    // which is safe to restart from on failure
+    let mut next_start = start_after.to_string();
+    let mut key_marker = start_after.to_string();
+    let mut version_id_marker: Option<String> = None;
+    if let Some(skip) = ctx.skip_excluded(start_after) {
+        if until.is_some_and(|end| end <= skip.as_str()) {
+            debug!("pair start {}, end {:?} is excluded for versions", start_after, until);
+            return Ok(());
+        }
+        next_start = skip.clone();
+        key_marker = skip;
+    }
+    loop {
+
+        // no more pages after max objects, task is aborted on quit like on ctrl-c, partition is left incomplete
+        if ctx.g_state.max_objects_reached() {
+            std::future::pending::<()>().await;
+        }
+
+        // hold on next page while data map channel is backed up or memory is over cap
+        ctx.g_state.wait_for_memory().await;
+        ctx.wait_for_rate_limit().await;
+
+        let request = ctx.s3_client().list_object_versions()
+            .bucket(&ctx.s3_bucket_name)
+            .prefix(prefix)
+            .set_key_marker(Some(key_marker.clone()).filter(|m| !m.is_empty()))
+            .set_version_id_marker(version_id_marker.clone())
+            .set_max_keys(ctx.g_state.max_keys())
+            .set_request_payer(ctx.request_payer.clone());
+
+        let timeout_duration = ctx.response_timeout;
+        let res = timeout_at(Instant::now() + timeout_duration, request.send()).await;
+        if res.is_err() {
+            debug!("flat list versions timeout next_start: {}", next_start);
+            ctx.g_state.inc_task_next_stream_timeout();
+            return Err(FlatRuntimeError::new(ERROR_S3_NEXT_STREAM_TIMEOUT, "client timeout".to_string(), next_start));
+        }
+
+        let page = match res.unwrap() {
+            Ok(page) => page,
+            Err(sdk_err) => return Err(to_flat_runtime_error(ctx, sdk_err, next_start)),
+        };
+        if page.is_truncated().unwrap_or(false) {
+            ctx.g_state.add_truncated_page(page.versions().len() + page.delete_markers().len());
+        }
+
+        let versions = page.versions().iter()
+            .map(|v| (v.key(), v.version_id(), ObjectProps::from(v), v.owner()));
+        let markers = page.delete_markers().iter()
+            .map(|m| (m.key(), m.version_id(), ObjectProps::from(m), m.owner()));
+
+        let mut output: HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>> = HashMap::new();
+        let mut is_ended = false;
+        let mut page_keys: Vec<&str> = Vec::new();
+        for (obj_key, version_id, mut props, owner) in versions.chain(markers) {
+            let Some(obj_key) = obj_key else {
+                continue;
+            };
+            if let Some(end) = until {
+                // we need lexicographical compare here
+                // because "end" could be a non-exist key/prefix
+                if end <= obj_key {
+                    debug!("pair end at key: {}", obj_key);
+                    is_ended = true;
+                    continue;
+                }
+            }
+            page_keys.push(obj_key);
+            if ctx.is_excluded(obj_key) {
+                continue;
+            }
+
+            let key = ObjectKey::with_version(obj_key, version_id.unwrap_or("null"));
+            let (prefix, name) = key.decode();
+            props.set_dir(ctx.dir);
+            // list object versions always returns owner, only kept if asked for
+            if ctx.with_owner {
+                props.set_owner(owner);
+            }
+            output.entry(prefix).or_default().push((name, props));
+            *objects_count += 1;
+        }
+
+        // remember last complete key to next start for failsafe
+        if let Some(last) = page_keys.iter().max() {
+            if let Some(complete) = page_keys.iter().filter(|k| *k < last).max() {
+                next_start = complete.to_string();
+            }
+        }
+
+        fetch_object_attrs(ctx, &mut output).await;
+        count_listed(ctx, &output);
+        if let Err(e) = ctx.data_map_channel.send(output) {
+            if !ctx.is_quit() {
+                panic!("error on send data to data map channel");
+            }
+            panic!("failed to send output data to data map channel, err: {}", e);
+        }
+
+        if is_ended || !page.is_truncated().unwrap_or(false) {
+            break;
+        }
+        key_marker = page.next_key_marker().unwrap_or_default().to_string();
+        version_id_marker = page.next_version_id_marker().map(|v| v.to_string());
+
+        // next page starts inside an excluded prefix, jump over the rest of it
+        if let Some(skip) = ctx.skip_excluded(&key_marker) {
+            debug!("pruned excluded prefix at key {} for versions", key_marker);
+            if until.is_some_and(|end| end <= skip.as_str()) {
+                break;
+            }
+            next_start = skip.clone();
+            key_marker = skip;
+            version_id_marker = None;
+        }
+    }
+    debug!("finished pair start {}, end {:?} for versions", start_after, until);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    type Requests = Arc<Mutex<Vec<String>>>;
+    type Listed = tokio::sync::mpsc::UnboundedReceiver<HashMap<ObjectPrefix, Vec<(ObjectName, ObjectProps)>>>;
+
+    // S3 endpoint on a local port, each request is answered by handler with status and xml body for its path and query
+    async fn mock_s3(handler: impl Fn(&str) -> (u16, String) + Send + Sync + 'static) -> (String, Requests) {
+        mock_s3_headers(move |target| {
+            let (status, body) = handler(target);
+            (status, Vec::new(), body)
+        }).await
+    }
+
+    // as mock_s3, with response headers given by handler, e.g. of HeadObject
+    async fn mock_s3_headers(handler: impl Fn(&str) -> (u16, Vec<(&'static str, &'static str)>, String) + Send + Sync + 'static)
+            -> (String, Requests) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Requests = Arc::new(Mutex::new(Vec::new()));
+        let (handler, seen) = (Arc::new(handler), requests.clone());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (handler, seen) = (handler.clone(), seen.clone());
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 4096];
+                    while !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut chunk).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                        }
+                    }
+                    let head = String::from_utf8_lossy(&buf).to_string();
+                    let target = head.split(' ').nth(1).unwrap_or_default().to_string();
+                    let (status, headers, body) = handler(&target);
+                    seen.lock().unwrap().push(target);
+                    let headers: String = headers.iter().map(|(name, value)| format!("{}: {}\r\n", name, value)).collect();
+                    let content_type = if headers.contains("Content-Type") { "" } else { "Content-Type: application/xml\r\n" };
+                    let response = format!("HTTP/1.1 {} Mock\r\n{}{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        status, headers, content_type, body.len(), body);
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.shutdown().await;
+                });
+            }
+        });
+        (endpoint, requests)
+    }
+
+    // ListObjectsV2 page of keys, truncated with next token if given
+    fn list_page(keys: &[&str], truncated: bool, next_token: Option<&str>) -> String {
+        let contents: String = keys.iter().map(|key| format!("<Contents><Key>{}</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified>\
+            <ETag>&quot;0123456789abcdef0123456789abcdef&quot;</ETag><Size>1</Size><StorageClass>STANDARD</StorageClass></Contents>", key)).collect();
+        let token = next_token.map(|t| format!("<NextContinuationToken>{}</NextContinuationToken>", t)).unwrap_or_default();
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+            <Name>b</Name><Prefix></Prefix><KeyCount>{}</KeyCount><MaxKeys>1000</MaxKeys><IsTruncated>{}</IsTruncated>{}{}</ListBucketResult>",
+            keys.len(), truncated, token, contents)
+    }
+
+    // page of delimiter list with common prefixes and objects directly under prefix
+    fn prefix_page(prefixes: &[&str], keys: &[&str], next_token: Option<&str>) -> String {
+        let common: String = prefixes.iter().map(|p| format!("<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>", p)).collect();
+        list_page(keys, next_token.is_some(), next_token).replace("</ListBucketResult>", &format!("{}</ListBucketResult>", common))
+    }
+
+    fn mock_ctx(endpoint: &str, g_state: core::GlobalState) -> (S3TaskContext, Listed) {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(aws_sdk_s3::config::BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .endpoint_url(endpoint)
+            .force_path_style(true)
+            .credentials_provider(aws_credential_types::Credentials::new("x", "y", None, None, "test"))
+            .retry_config(aws_sdk_s3::config::retry::RetryConfig::disabled())
+            .interceptor(g_state.throttle_interceptor())
+            .build();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let ctx = S3TaskContext::with_client("b", aws_sdk_s3::Client::from_conf(config), tx, core::S3_TASK_CONTEXT_DIR_LEFT_LIST_MODE, g_state);
+        (ctx, rx)
+    }
+
+    fn g_state() -> core::GlobalState {
+        core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 1, 0, "test".to_string())
+    }
+
+    fn listed_keys(rx: &mut Listed) -> Vec<String> {
+        let mut keys = Vec::new();
+        while let Ok(hash) = rx.try_recv() {
+            for (prefix, items) in hash {
+                keys.extend(items.iter().map(|(name, _)| ObjectKey::encode(&prefix, name).as_str().to_string()));
+            }
+        }
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_page_size() {
+        let (endpoint, requests) = mock_s3(|target| match target.contains("continuation-token=t1") {
+            false => (200, list_page(&["a", "b"], true, Some("t1"))),
+            true => (200, list_page(&["c"], false, None)),
+        }).await;
+        let (ctx, mut rx) = mock_ctx(&endpoint, g_state().with_page_size(Some(3)));
+        let mut count = 0;
+        flat_list(&ctx, "", "", None, &mut count).await.unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(listed_keys(&mut rx), ["a", "b", "c"]);
+
+        // MaxKeys sent on every page, endpoint capping below it is noticed
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.contains("max-keys=3")), "{:?}", requests);
+        assert_eq!(ctx.g_state.read_peak_truncated_page(), 2);
+
+        let (endpoint, requests) = mock_s3(|_| (200, list_page(&["a"], false, None))).await;
+        let (ctx, _rx) = mock_ctx(&endpoint, g_state());
+        flat_list(&ctx, "", "", None, &mut 0).await.unwrap();
+        assert!(!requests.lock().unwrap()[0].contains("max-keys"));
+        assert_eq!(ctx.g_state.page_size(), core::DEFAULT_LIST_PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_enrich_all() {
+        let (endpoint, requests) = mock_s3_headers(|target| {
+            if target.contains("list-type=2") {
+                (200, Vec::new(), list_page(&["a", "b", "c"], false, None))
+            } else if target.starts_with("/b/a?") {
+                (200, vec![("Content-Type", "text/plain"), ("x-amz-meta-team", "data"), ("x-amz-mp-parts-count", "3"),
+                    ("x-amz-server-side-encryption", "aws:kms"), ("x-amz-tagging-count", "2")], String::new())
+            } else if target.starts_with("/b/b?") {
+                (200, vec![("Content-Type", "image/png")], String::new())
+            } else {
+                (404, Vec::new(), String::new())
+            }
+        }).await;
+        let (ctx, mut rx) = mock_ctx(&endpoint, g_state());
+        let ctx = ctx.with_enrich(Some(2));
+        flat_list(&ctx, "", "", None, &mut 0).await.unwrap();
+
+        let mut heads = HashMap::new();
+        while let Ok(hash) = rx.try_recv() {
+            for (prefix, items) in hash {
+                heads.extend(items.into_iter().map(|(name, props)| (ObjectKey::encode(&prefix, &name).as_str().to_string(), props.head().cloned())));
+            }
+        }
+        let head = heads["a"].clone().unwrap();
+        assert_eq!(head.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(head.server_side_encryption.as_deref(), Some("aws:kms"));
+        assert_eq!(head.metadata.get("team").map(|v| v.as_str()), Some("data"));
+        assert_eq!((head.parts_count, head.tag_count), (Some(3), Some(2)));
+        assert_eq!(heads["b"].as_ref().and_then(|h| h.content_type.clone()).as_deref(), Some("image/png"));
+        // failed one is sent without metadata
+        assert_eq!(heads["c"], None);
+        assert_eq!(ctx.enrich.as_ref().unwrap().read_counts(), (2, 1));
+
+        // one HEAD per object, asking for part 1 to get parts count
+        let requests = requests.lock().unwrap().clone();
+        assert_eq!(requests.len(), 4);
+        assert!(requests.iter().filter(|r| !r.contains("list-type=2")).all(|r| r.contains("partNumber=1")), "{:?}", requests);
+    }
+
+    // second page repeats continuation token of first one, or goes back to keys of it
+    async fn mock_suspect_pages(regress: bool) -> (String, Requests) {
+        mock_s3(move |target| {
+            if target.contains("continuation-token=t1") {
+                match regress {
+                    false => (200, list_page(&["c", "d"], true, Some("t1"))),
+                    true => (200, list_page(&["a", "b", "c", "d"], true, Some("t2"))),
+                }
+            } else if target.contains("start-after=d") {
+                (200, list_page(&["e"], false, None))
+            } else {
+                (200, list_page(&["a", "b"], true, Some("t1")))
+            }
+        }).await
+    }
+
+    #[tokio::test]
+    async fn test_suspect_pages() {
+        for regress in [false, true] {
+            let (endpoint, requests) = mock_suspect_pages(regress).await;
+            // pages of page size are not short ones
+            let (ctx, mut rx) = mock_ctx(&endpoint, g_state().with_page_size(Some(2)));
+            let mut count = 0;
+            flat_list(&ctx, "", "", None, &mut count).await.unwrap();
+            // listed on after last key of suspect page, nothing sent twice
+            assert_eq!(listed_keys(&mut rx), ["a", "b", "c", "d", "e"], "regress {}", regress);
+            assert_eq!(count, 5);
+            assert_eq!(ctx.g_state.read_suspect_pages(), 1);
+            assert_eq!(requests.lock().unwrap().len(), 3);
+
+            // range fails after keys of suspect page under --strict-pagination
+            let (endpoint, requests) = mock_suspect_pages(regress).await;
+            let (ctx, mut rx) = mock_ctx(&endpoint, g_state().with_page_size(Some(2)).with_strict_pagination(true));
+            let e = flat_list(&ctx, "", "", None, &mut 0).await.unwrap_err();
+            let reason = if regress { "page going back to key \"a\" listed already" } else { "repeating previous continuation token" };
+            assert!(e.to_string().contains(reason), "{}", e);
+            assert_eq!(e.next_start(), "d");
+            assert!(!e.continue_on_error());
+            assert_eq!(listed_keys(&mut rx), ["a", "b", "c", "d"]);
+            assert_eq!(ctx.g_state.read_suspect_pages(), 1);
+            assert_eq!(requests.lock().unwrap().len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_throttle() {
+        const SLOW_DOWN: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>SlowDown</Code>\
+            <Message>Please reduce your request rate.</Message></Error>";
+        // first two list calls throttled, then listing goes through
+        let throttled_twice = || {
+            let calls = std::sync::atomic::AtomicUsize::new(0);
+            move |_: &str| match calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                0 | 1 => (503, SLOW_DOWN.to_string()),
+                _ => (200, list_page(&["a", "b"], false, None)),
+            }
+        };
+
+        // backoff without retry policy fails range on first throttle
+        let (endpoint, _) = mock_s3(throttled_twice()).await;
+        let (ctx, _rx) = mock_ctx(&endpoint, g_state());
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(ctx.g_state.is_list_failed());
+        assert_eq!(ctx.g_state.throttle().read_throttled(), 1);
+
+        // wait retries until throttling is over, not bound to max retries
+        let (endpoint, requests) = mock_s3(throttled_twice()).await;
+        let (ctx, mut rx) = mock_ctx(&endpoint, g_state().with_on_throttle(core::OnThrottle::Wait));
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(!ctx.g_state.is_list_failed());
+        assert_eq!(listed_keys(&mut rx), ["a", "b"]);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+        assert_eq!(ctx.g_state.read_list_retry(), 2);
+        assert_eq!(ctx.g_state.throttle().read_throttled(), 2);
+        assert!(ctx.g_state.throttle().streak().is_none());
+
+        // abort stops run once throttled longer than window, retries left or not
+        let (endpoint, _) = mock_s3(|_| (503, SLOW_DOWN.to_string())).await;
+        let g_state = g_state().with_on_throttle(core::OnThrottle::Abort)
+            .with_throttle_abort_after(std::time::Duration::from_millis(200));
+        let (mut ctx, _rx) = mock_ctx(&endpoint, g_state);
+        ctx.retry = Some(core::RetryPolicy { max_retries: 1000, base_delay_ms: 20 });
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(ctx.g_state.is_throttle_aborted());
+        assert!(ctx.g_state.is_quit());
+        let (streak, throttled) = ctx.g_state.throttle().streak().unwrap();
+        assert!(streak >= std::time::Duration::from_millis(200));
+        assert!(throttled > 1 && (throttled as u32) < 1000);
+    }
+
+    #[tokio::test]
+    async fn test_one_per_prefix() {
+        const INTERNAL_ERROR: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>InternalError</Code>\
+            <Message>We encountered an internal error.</Message></Error>";
+        // second page of common prefixes fails once, first object of d/b/ always fails
+        let failed = std::sync::atomic::AtomicBool::new(false);
+        let handler = move |target: &str| {
+            if target.contains("delimiter=") {
+                if !target.contains("continuation-token=t1") {
+                    return (200, prefix_page(&["d/a/"], &["d/x"], Some("t1")));
+                }
+                if !failed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                    return (500, INTERNAL_ERROR.to_string());
+                }
+                return (200, prefix_page(&["d/b/", "d/c/"], &[], None));
+            }
+            match target {
+                t if t.contains("prefix=d%2Fa%2F") => (200, list_page(&["d/a/1"], true, Some("n"))),
+                t if t.contains("prefix=d%2Fc%2F") => (200, list_page(&["d/c/x/1"], false, None)),
+                _ => (500, INTERNAL_ERROR.to_string()),
+            }
+        };
+        let (endpoint, requests) = mock_s3(handler).await;
+        let (mut ctx, mut rx) = mock_ctx(&endpoint, g_state());
+        ctx.retry = Some(core::RetryPolicy { max_retries: 2, base_delay_ms: 1 });
+        one_per_prefix_reactor_task(&ctx, &["d/".to_string()], 4).await;
+        assert_eq!(listed_keys(&mut rx), ["d/a/1", "d/c/x/1", "d/x"]);
+        assert!(!ctx.g_state.is_list_failed());
+
+        // failed page resumed from its token, not from the first page, first object of a prefix retried up to max retries
+        let requests = requests.lock().unwrap().clone();
+        let delimited: Vec<&String> = requests.iter().filter(|r| r.contains("delimiter=")).collect();
+        assert_eq!(delimited.len(), 3);
+        assert!(delimited[1..].iter().all(|r| r.contains("continuation-token=t1")));
+        assert_eq!(requests.iter().filter(|r| r.contains("prefix=d%2Fb%2F")).count(), 3);
+        assert!(requests.iter().filter(|r| !r.contains("delimiter=")).all(|r| r.contains("max-keys=1")));
+        assert_eq!(ctx.g_state.read_list_retry(), 3);
+
+        // without retry policy, a failed first object is skipped after the retries of SDK
+        let (endpoint, requests) = mock_s3(|_| (500, INTERNAL_ERROR.to_string())).await;
+        let (ctx, _rx) = mock_ctx(&endpoint, g_state());
+        assert!(list_first_object(&ctx, "d/b/").await.is_err());
+        let err = list_first_object(&ctx, "d/b/").await.unwrap_err();
+        let mut retries = 0;
+        assert!(!retry_request(&ctx, &err, &mut retries).await);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_one_per_prefix_diff() {
+        // common prefixes of each side, first object of a prefix is the same key on both sides if it exists
+        let side = |prefixes: &'static [&'static str]| move |target: &str| {
+            if target.contains("delimiter=") {
+                return (200, prefix_page(prefixes, &[], None));
+            }
+            match prefixes.iter().find(|p| target.contains(&format!("prefix={}", p.replace('/', "%2F")))) {
+                Some(p) => (200, list_page(&[&format!("{}1", p)], false, None)),
+                None => (200, list_page(&[], false, None)),
+            }
+        };
+        let (left, left_requests) = mock_s3(side(&["a/", "b/"])).await;
+        let (right, right_requests) = mock_s3(side(&["b/", "c/"])).await;
+        let g_state = core::GlobalState::new(Arc::new(std::sync::atomic::AtomicBool::new(false)), 2, 0, "test".to_string());
+        let splits = Some(Arc::new(core::SharedSplits::new(2)));
+        let (left_ctx, mut left_rx) = mock_ctx(&left, g_state.clone());
+        let mut left_ctx = left_ctx.with_shared_splits(splits.clone());
+        left_ctx.dir = core::S3_TASK_CONTEXT_DIR_LEFT_DIFF_MODE;
+        let (right_ctx, mut right_rx) = mock_ctx(&right, g_state);
+        let mut right_ctx = right_ctx.with_shared_splits(splits);
+        right_ctx.dir = core::S3_TASK_CONTEXT_DIR_RIGHT_DIFF_MODE;
+
+        let prefixes = ["".to_string()];
+        tokio::join!(one_per_prefix_reactor_task(&left_ctx, &prefixes, 2), one_per_prefix_reactor_task(&right_ctx, &prefixes, 2));
+        assert_eq!(listed_keys(&mut left_rx), ["a/1", "b/1"]);
+        assert_eq!(listed_keys(&mut right_rx), ["b/1", "c/1"]);
+        // both sides fetched the same merged prefixes, the ones missing on a side come back empty
+        for requests in [left_requests, right_requests] {
+            let mut fetched: Vec<String> = requests.lock().unwrap().iter().filter(|r| !r.contains("delimiter="))
+                .filter_map(|r| r.split("prefix=").nth(1).map(|p| p.split('&').next().unwrap().to_string())).collect();
+            fetched.sort();
+            assert_eq!(fetched, ["a%2F", "b%2F", "c%2F"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_follow_redirect() {
+        const PERMANENT_REDIRECT: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>PermanentRedirect</Code>\
+            <Message>The bucket you are attempting to access must be addressed using the specified endpoint.</Message></Error>";
+        let redirect_once = || {
+            let calls = std::sync::atomic::AtomicUsize::new(0);
+            move |_: &str| match calls.fetch_add(1, std::sync::atomic::Ordering::Relaxed) {
+                0 => (301, vec![("x-amz-bucket-region", "eu-west-1")], PERMANENT_REDIRECT.to_string()),
+                _ => (200, Vec::new(), list_page(&["a", "b"], false, None)),
+            }
+        };
+        let region = |ctx: &S3TaskContext| ctx.s3_client().config().region().map(|r| r.to_string());
+
+        // without --follow-redirects the range fails in configured region
+        let (endpoint, requests) = mock_s3_headers(redirect_once()).await;
+        let (ctx, _rx) = mock_ctx(&endpoint, g_state());
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(ctx.g_state.is_list_failed());
+        assert_eq!(ctx.g_state.read_s3_redirect(), 1);
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert_eq!(region(&ctx).as_deref(), Some("us-east-1"));
+
+        // followed, client of all tasks switched to bucket region and list call sent again
+        let (endpoint, requests) = mock_s3_headers(redirect_once()).await;
+        let (ctx, mut rx) = mock_ctx(&endpoint, g_state());
+        let ctx = ctx.with_follow_redirects(true);
+        let other_task = ctx.clone();
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(!ctx.g_state.is_list_failed());
+        assert_eq!(listed_keys(&mut rx), ["a", "b"]);
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert_eq!(region(&other_task).as_deref(), Some("eu-west-1"));
+        assert!(!other_task.follow_redirect("eu-west-1"));
+
+        // redirect again from bucket region is not followed endlessly
+        let (endpoint, requests) = mock_s3_headers(|_| (301, vec![("x-amz-bucket-region", "eu-west-1")], PERMANENT_REDIRECT.to_string())).await;
+        let (ctx, _rx) = mock_ctx(&endpoint, g_state());
+        let ctx = ctx.with_follow_redirects(true);
+        flat_list_run_to_complete(&ctx, "", "", None).await;
+        assert!(ctx.g_state.is_list_failed());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
@@ -1,93 +1,1798 @@
 use std::sync::Arc;
-use tokio::io::AsyncWrite;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 use arrow_array::array::ArrayRef;
-use arrow_array::array::{UInt8Array, UInt64Array, StringArray};
+use arrow_array::array::{BooleanArray, UInt8Array, UInt32Array, UInt64Array, StringArray, TimestampSecondArray};
 use arrow_array::RecordBatch;
-use arrow_schema::{DataType, Field, Schema, SchemaRef};
-use parquet::file::properties::{WriterProperties, WriterVersion};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow_ipc::CompressionType;
+use arrow_ipc::writer::{IpcWriteOptions, StreamWriter};
+use parquet::file::properties::{EnabledStatistics, WriterProperties, WriterVersion, DEFAULT_MAX_ROW_GROUP_SIZE};
+use parquet::schema::types::ColumnPath;
 use parquet::arrow::async_writer::AsyncArrowWriter;
-use parquet::basic::{Compression, Encoding, GzipLevel};
+use parquet::basic::{Compression, Encoding, GzipLevel, ZstdLevel};
+use parquet::format::{KeyValue, SortingColumn};
 use log::warn;
-use crate::core::{ObjectKey, ObjectProps};
+use serde::Serialize;
+use crate::core::{ObjectKey, ObjectProps, ObjectHead, TimeFormat, ArrowCompression, KsCompression, KB, MB, GB, MAX_HASH_CHARSET_LEN};
+use crate::error::OptionError;
+
+// options of parquet writer from cli
+#[derive(Debug, Clone)]
+pub struct ParquetOptions {
+    pub compression: Compression,
+    // max rows of row group, writer default if None
+    pub row_group_size: Option<usize>,
+    // flush row group once its encoded size reaches this
+    pub max_row_group_bytes: Option<usize>,
+    // rows are written in key order, recorded as sorting column of row groups
+    pub sorted: bool,
+    // bloom filter of key column with this false positive probability
+    pub bloom_filter_fpp: Option<f64>,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::GZIP(GzipLevel::try_new(6).unwrap()),
+            row_group_size: None,
+            max_row_group_bytes: None,
+            sorted: false,
+            bloom_filter_fpp: None,
+        }
+    }
+}
+
+// parse compression codec like "zstd" or "zstd:9", level is only allowed for gzip and zstd
+pub fn parse_parquet_compression(s: &str) -> Result<Compression, String> {
+    let (codec, level) = match s.split_once(':') {
+        Some((codec, level)) => {
+            let level: u32 = level.parse().map_err(|_| format!("invalid compression level \"{level}\""))?;
+            (codec, Some(level))
+        },
+        None => (s, None),
+    };
+
+    let compression = match (codec.to_ascii_lowercase().as_str(), level) {
+        ("none", None) => Compression::UNCOMPRESSED,
+        ("snappy", None) => Compression::SNAPPY,
+        ("lz4", None) => Compression::LZ4_RAW,
+        ("gzip", level) => {
+            let level = GzipLevel::try_new(level.unwrap_or(6)).map_err(|e| e.to_string())?;
+            Compression::GZIP(level)
+        },
+        ("zstd", level) => {
+            let level = match level {
+                Some(level) => ZstdLevel::try_new(level as i32).map_err(|e| e.to_string())?,
+                None => ZstdLevel::default(),
+            };
+            Compression::ZSTD(level)
+        },
+        ("none" | "snappy" | "lz4", Some(_)) => {
+            return Err(format!("compression level is not supported by \"{codec}\""));
+        },
+        _ => {
+            return Err(format!("unknown compression \"{codec}\", expect one of none, snappy, gzip, zstd, lz4"));
+        },
+    };
+
+    Ok(compression)
+}
+
+// columns of output beyond default ones, by run mode and options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OptionalColumns {
+    pub diff_mode: bool,
+    pub versions: bool,
+    pub shallow: bool,
+    pub duplicates: bool,
+    pub tags: bool,
+    pub owner: bool,
+    pub checksum: bool,
+    pub enrich: bool,
+}
+
+// schema of object metadata, same columns in parquet and arrow ipc output
+fn object_schema(optional: OptionalColumns) -> SchemaRef {
+
+    // define fields
+    let field_key = Field::new("Key", DataType::Utf8, false);
+    let field_size = Field::new("Size", DataType::UInt64, false);
+    let field_last_modified = Field::new("LastModified", DataType::Timestamp(TimeUnit::Second, Some("UTC".into())), false);
+    let field_etag = Field::new("ETag", DataType::Utf8, false);
+    let field_diff_flag = Field::new("DiffFlag", DataType::UInt8, false);
+    let field_storage_class = Field::new("StorageClass", DataType::Utf8, false);
+
+    // define schema, differing attributes are only exported in diff mode
+    let mut fields = vec![field_key, field_size, field_last_modified, field_etag, field_diff_flag, field_storage_class];
+    if optional.diff_mode {
+        fields.push(Field::new("DiffAttrs", DataType::Utf8, false));
+    }
+    if optional.versions {
+        fields.push(Field::new("VersionId", DataType::Utf8, false));
+        fields.push(Field::new("IsLatest", DataType::Boolean, false));
+        fields.push(Field::new("IsDeleteMarker", DataType::Boolean, false));
+    }
+    if optional.shallow {
+        fields.push(Field::new("Type", DataType::Utf8, false));
+    }
+    if optional.duplicates {
+        fields.push(Field::new("DupGroup", DataType::UInt64, false));
+        fields.push(Field::new("DupCount", DataType::UInt64, false));
+    }
+    // json object of tag key to value, null if failed to fetch
+    if optional.tags {
+        fields.push(Field::new("Tags", DataType::Utf8, true));
+    }
+    // null if not in list response
+    if optional.owner {
+        fields.push(Field::new("OwnerId", DataType::Utf8, true));
+        fields.push(Field::new("OwnerDisplayName", DataType::Utf8, true));
+    }
+    // null if object is uploaded without checksum of the algorithm or failed to fetch
+    if optional.checksum {
+        fields.push(Field::new("Checksum", DataType::Utf8, true));
+    }
+    // all null if failed to fetch
+    if optional.enrich {
+        fields.extend(HeadColumn::ALL.iter().map(|c| Field::new(c.field_name(), c.data_type(), true)));
+    }
+    Arc::new(Schema::new(fields))
+}
+
+// record batch of rows in object schema, with group id and count of each row written with duplicates
+fn object_batch(schema_ref: &SchemaRef, optional: OptionalColumns, v: &[(ObjectKey, ObjectProps)], diff_flag: u8,
+        dups: &[DupGroup]) -> RecordBatch {
+
+    let mut vec_key: Vec<&str> = Vec::new();
+    let mut vec_size: Vec<u64> = Vec::new();
+    let mut vec_last_modified: Vec<i64> = Vec::new();
+    let mut vec_etag: Vec<String> = Vec::new();
+    let mut vec_diff_flag: Vec<u8> = Vec::new();
+    let mut vec_storage_class: Vec<&str> = Vec::new();
+    let mut vec_diff_attrs: Vec<String> = Vec::new();
+    let mut vec_version_id: Vec<&str> = Vec::new();
+    let mut vec_is_latest: Vec<bool> = Vec::new();
+    let mut vec_is_delete_marker: Vec<bool> = Vec::new();
+    let mut vec_type: Vec<&str> = Vec::new();
+    let mut vec_tags: Vec<Option<String>> = Vec::new();
+    let mut vec_owner_id: Vec<Option<&str>> = Vec::new();
+    let mut vec_owner_display_name: Vec<Option<&str>> = Vec::new();
+    let mut vec_checksum: Vec<Option<&str>> = Vec::new();
+
+    let _: Vec<_> = v.iter().map(|(key, props)| {
+        let (key, version_id) = key.split_version();
+        vec_key.push(key);
+        vec_size.push(props.size());
+        vec_last_modified.push(props.last_modified() as i64);
+        vec_etag.push(props.etag_string());
+        vec_diff_flag.push(diff_flag);
+        vec_storage_class.push(props.storage_class());
+        if optional.diff_mode {
+            vec_diff_attrs.push(props.diff_attrs().join(","));
+        }
+        if optional.versions {
+            vec_version_id.push(version_id.unwrap_or_default());
+            vec_is_latest.push(props.is_latest());
+            vec_is_delete_marker.push(props.is_delete_marker());
+        }
+        if optional.shallow {
+            vec_type.push(props.object_type());
+        }
+        if optional.tags {
+            vec_tags.push(props.tags_json().map(|t| serde_json::Value::Object(t).to_string()));
+        }
+        if optional.owner {
+            vec_owner_id.push(props.owner().and_then(|o| o.id.as_deref()));
+            vec_owner_display_name.push(props.owner().and_then(|o| o.display_name.as_deref()));
+        }
+        if optional.checksum {
+            vec_checksum.push(props.checksum());
+        }
+    }).collect();
+
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec_key)) as ArrayRef,
+        Arc::new(UInt64Array::from(vec_size)) as ArrayRef,
+        Arc::new(TimestampSecondArray::from(vec_last_modified).with_timezone("UTC")) as ArrayRef,
+        Arc::new(StringArray::from(vec_etag)) as ArrayRef,
+        Arc::new(UInt8Array::from(vec_diff_flag)) as ArrayRef,
+        Arc::new(StringArray::from(vec_storage_class)) as ArrayRef,
+    ];
+    if optional.diff_mode {
+        columns.push(Arc::new(StringArray::from(vec_diff_attrs)) as ArrayRef);
+    }
+    if optional.versions {
+        columns.push(Arc::new(StringArray::from(vec_version_id)) as ArrayRef);
+        columns.push(Arc::new(BooleanArray::from(vec_is_latest)) as ArrayRef);
+        columns.push(Arc::new(BooleanArray::from(vec_is_delete_marker)) as ArrayRef);
+    }
+    if optional.shallow {
+        columns.push(Arc::new(StringArray::from(vec_type)) as ArrayRef);
+    }
+    if optional.duplicates {
+        columns.push(Arc::new(UInt64Array::from_iter_values(dups.iter().map(|d| d.0))) as ArrayRef);
+        columns.push(Arc::new(UInt64Array::from_iter_values(dups.iter().map(|d| d.1))) as ArrayRef);
+    }
+    if optional.tags {
+        columns.push(Arc::new(StringArray::from(vec_tags)) as ArrayRef);
+    }
+    if optional.owner {
+        columns.push(Arc::new(StringArray::from(vec_owner_id)) as ArrayRef);
+        columns.push(Arc::new(StringArray::from(vec_owner_display_name)) as ArrayRef);
+    }
+    if optional.checksum {
+        columns.push(Arc::new(StringArray::from(vec_checksum)) as ArrayRef);
+    }
+    if optional.enrich {
+        columns.extend(HeadColumn::ALL.iter().map(|c| c.array(v)));
+    }
+
+    RecordBatch::try_new(Arc::clone(schema_ref), columns).unwrap()
+}
+
+// object schema of parquet and arrow ipc output, only columns of --output-columns kept if given
+struct ProjectedSchema {
+    full: SchemaRef,
+    // indices of kept columns in full schema
+    projection: Option<Vec<usize>>,
+    output: SchemaRef,
+}
+
+impl ProjectedSchema {
+    fn new(optional: OptionalColumns, columns: Option<&[OutputColumn]>) -> Self {
+        let full = object_schema(optional);
+        let projection = columns.map(|columns| columns.iter()
+            .map(|c| full.index_of(c.field_name()).expect("output columns are checked against schema"))
+            .collect::<Vec<_>>());
+        let output = match &projection {
+            Some(indices) => Arc::new(full.project(indices).unwrap()),
+            None => Arc::clone(&full),
+        };
+        Self { full, projection, output }
+    }
+
+    // rows are built in full schema, columns left out are dropped before written
+    fn batch(&self, optional: OptionalColumns, v: &[(ObjectKey, ObjectProps)], diff_flag: u8, dups: &[DupGroup]) -> RecordBatch {
+        let batch = object_batch(&self.full, optional, v, diff_flag, dups);
+        match &self.projection {
+            Some(indices) => batch.project(indices).unwrap(),
+            None => batch,
+        }
+    }
+}
+
+// rows written between checks of max row group bytes, a large batch would overshoot it by far if written at once
+const PARQUET_BYTES_CHECK_ROWS: usize = 1024;
 
 pub struct AsyncParquetOutput<W> {
-    schema_ref: SchemaRef,
+    schema: ProjectedSchema,
     writer: AsyncArrowWriter<W>,
+    max_row_group_bytes: Option<usize>,
+    optional: OptionalColumns,
+    rows: usize,
 }
 
-impl<W: AsyncWrite + Unpin + Send> AsyncParquetOutput<W> {
+// duplicate group id and count of its members, of each row written with duplicates
+pub type DupGroup = (u64, u64);
 
-    pub fn new(buf_wr: W) -> Self {
+impl<W: AsyncWrite + Unpin + Send> AsyncParquetOutput<W> {
 
-        // define fields
-        let field_key = Field::new("Key", DataType::Utf8, false);
-        let field_size = Field::new("Size", DataType::UInt64, false);
-        let field_last_modified = Field::new("LastModified", DataType::UInt64, false);
-        let field_etag = Field::new("ETag", DataType::Utf8, false);
-        let field_diff_flag = Field::new("DiffFlag", DataType::UInt8, false);
+    pub fn new(buf_wr: W, opts: &ParquetOptions, optional: OptionalColumns, columns: Option<&[OutputColumn]>) -> Self {
 
-        // define schema
-        let schema_ref = Arc::new(Schema::new(vec![field_key, field_size, field_last_modified, field_etag, field_diff_flag]));
+        let schema = ProjectedSchema::new(optional, columns);
 
         // define writer props
-        let writer_props = WriterProperties::builder()
+        let mut builder = WriterProperties::builder()
             .set_writer_version(WriterVersion::PARQUET_1_0)
             .set_encoding(Encoding::PLAIN)
-            .set_compression(Compression::GZIP(GzipLevel::try_new(6).unwrap()))
-            .build();
+            .set_compression(opts.compression);
+        if let Some(rows) = opts.row_group_size {
+            builder = builder.set_max_row_group_size(rows);
+        }
+        // min and max of size and mtime in row group and page headers, for engines to prune them
+        for column in ["Size", "LastModified"] {
+            builder = builder.set_column_statistics_enabled(ColumnPath::from(column), EnabledStatistics::Page);
+        }
+        if let Some(fpp) = opts.bloom_filter_fpp {
+            // every key of a full row group is distinct
+            let ndv = opts.row_group_size.unwrap_or(DEFAULT_MAX_ROW_GROUP_SIZE) as u64;
+            builder = builder.set_column_bloom_filter_enabled(ColumnPath::from("Key"), true)
+                .set_column_bloom_filter_fpp(ColumnPath::from("Key"), fpp)
+                .set_column_bloom_filter_ndv(ColumnPath::from("Key"), ndv);
+        }
+        if let Some(key_idx) = schema.output.index_of("Key").ok().filter(|_| opts.sorted) {
+            builder = builder.set_sorting_columns(Some(vec![SortingColumn { column_idx: key_idx as i32, descending: false, nulls_first: false }]));
+        }
+        let writer_props = builder.build();
 
         // build writer
-        let writer = AsyncArrowWriter::try_new(buf_wr, Arc::clone(&schema_ref), Some(writer_props.clone())).unwrap();
+        let writer = AsyncArrowWriter::try_new(buf_wr, Arc::clone(&schema.output), Some(writer_props.clone())).unwrap();
 
         Self {
-            schema_ref: schema_ref,
-            writer: writer,
+            schema,
+            writer,
+            max_row_group_bytes: opts.max_row_group_bytes,
+            optional,
+            rows: 0,
         }
 
     }
 
     pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        self.write_rows(v, diff_flag, &[]).await
+    }
 
-        if v.len() == 0 {
+    async fn write_rows(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8, dups: &[DupGroup]) -> tokio::io::Result<()> {
+
+        if v.is_empty() {
             return Ok(());
         }
 
-        let mut vec_key: Vec<&str> = Vec::new();
-        let mut vec_size: Vec<u64> = Vec::new();
-        let mut vec_last_modified: Vec<u64> = Vec::new();
-        let mut vec_etag: Vec<String> = Vec::new();
-        let mut vec_diff_flag: Vec<u8> = Vec::new();
-
-        let _: Vec<_> = v.iter().map(|(key, props)| {
-            vec_key.push(key.as_str());
-            vec_size.push(props.size());
-            vec_last_modified.push(props.last_modified());
-            vec_etag.push(props.etag_string());
-            vec_diff_flag.push(diff_flag);
-        }).collect();
-
-        let columns: Vec<ArrayRef> = vec![
-            Arc::new(StringArray::from(vec_key)) as ArrayRef,
-            Arc::new(UInt64Array::from(vec_size)) as ArrayRef,
-            Arc::new(UInt64Array::from(vec_last_modified)) as ArrayRef,
-            Arc::new(StringArray::from(vec_etag)) as ArrayRef,
-            Arc::new(UInt8Array::from(vec_diff_flag)) as ArrayRef
-        ];
-
-        let batch = RecordBatch::try_new(Arc::clone(&self.schema_ref), columns).unwrap();
-        let res = self.writer.write(&batch).await;
-        if res.is_ok() {
+        let batch = self.schema.batch(self.optional, &v, diff_flag, dups);
+        self.rows += v.len();
+        let Some(max_bytes) = self.max_row_group_bytes else {
+            if let Err(e) = self.writer.write(&batch).await {
+                warn!("parquet writer write op failed {:?}", e);
+            }
             return Ok(());
-        } else {
-            warn!("parquet writer write op failed {:?}", res.unwrap());
+        };
+
+        // row count limit is checked inside writer, flush here if size limit reached first, page by page of batch
+        let mut offset = 0;
+        while offset < batch.num_rows() {
+            let len = PARQUET_BYTES_CHECK_ROWS.min(batch.num_rows() - offset);
+            if let Err(e) = self.writer.write(&batch.slice(offset, len)).await {
+                warn!("parquet writer write op failed {:?}", e);
+            }
+            offset += len;
+            if self.writer.in_progress_size() >= max_bytes {
+                if let Err(e) = self.writer.flush().await {
+                    warn!("parquet writer flush op failed {:?}", e);
+                }
+            }
         }
 
         Ok(())
     }
 
+    pub async fn flush(&mut self) -> tokio::io::Result<()> {
+        if let Err(e) = self.writer.flush().await {
+            warn!("parquet writer flush op failed {:?}", e);
+        }
+        Ok(())
+    }
+
     pub async fn close(self) -> tokio::io::Result<()> {
         let _ = self.writer.close().await;
 
         Ok(())
     }
 }
+
+// arrow ipc stream of record batches in schema of parquet output, encoded in memory and passed to writer per batch
+pub struct AsyncArrowOutput<W> {
+    schema: ProjectedSchema,
+    writer: W,
+    encoder: StreamWriter<Vec<u8>>,
+    optional: OptionalColumns,
+    bytes: usize,
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncArrowOutput<W> {
+
+    pub fn new(writer: W, compression: ArrowCompression, optional: OptionalColumns, columns: Option<&[OutputColumn]>) -> tokio::io::Result<Self> {
+        let schema = ProjectedSchema::new(optional, columns);
+        let compression = match compression {
+            ArrowCompression::None => None,
+            ArrowCompression::Lz4 => Some(CompressionType::LZ4_FRAME),
+            ArrowCompression::Zstd => Some(CompressionType::ZSTD),
+        };
+        let ipc_opts = IpcWriteOptions::default().try_with_compression(compression).map_err(std::io::Error::other)?;
+        let encoder = StreamWriter::try_new_with_options(Vec::new(), &schema.output, ipc_opts).map_err(std::io::Error::other)?;
+        Ok(Self {
+            schema,
+            writer,
+            encoder,
+            optional,
+            bytes: 0,
+        })
+    }
+
+    pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        self.write_rows(v, diff_flag, &[]).await
+    }
+
+    async fn write_rows(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8, dups: &[DupGroup]) -> tokio::io::Result<()> {
+
+        if v.is_empty() {
+            return Ok(());
+        }
+
+        let batch = self.schema.batch(self.optional, &v, diff_flag, dups);
+        self.encoder.write(&batch).map_err(std::io::Error::other)?;
+        self.write_encoded().await
+    }
+
+    // pass encoded messages to writer, schema message goes with first batch
+    async fn write_encoded(&mut self) -> tokio::io::Result<()> {
+        let buf = self.encoder.get_mut();
+        self.writer.write_all(buf).await?;
+        self.bytes += buf.len();
+        buf.clear();
+        Ok(())
+    }
+
+    // end of stream marker is written on close, a stream without it is still readable up to last batch
+    pub async fn close(mut self) -> tokio::io::Result<()> {
+        self.encoder.finish().map_err(std::io::Error::other)?;
+        self.write_encoded().await?;
+        self.writer.flush().await?;
+        self.writer.shutdown().await
+    }
+}
+
+// one object per line in json, with status of diff flag in diff mode
+pub struct AsyncNdjsonOutput<W> {
+    writer: W,
+    optional: OptionalColumns,
+    // fields of --output-columns in order, all if none
+    columns: Option<Vec<&'static str>>,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct NdjsonRow<'a> {
+    key: &'a str,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    object_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_latest: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_delete_marker: Option<bool>,
+    size: u64,
+    last_modified: serde_json::Value,
+    etag: String,
+    storage_class: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff_attrs: Option<Vec<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dup_group: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dup_count: Option<u64>,
+    // null if failed to fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tags: Option<Option<serde_json::Map<String, serde_json::Value>>>,
+    // null if not in list response
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_id: Option<Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    owner_display_name: Option<Option<&'a str>>,
+    // null if object has none or failed to fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<Option<&'a str>>,
+    // fields of HeadObject response, null if failed to fetch
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    head: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+// status name of diff flag
+pub fn diff_status(diff_flag: u8) -> &'static str {
+    match diff_flag {
+        0 => "equal",
+        1 => "only_left",
+        2 => "only_right",
+        3 => "changed",
+        _ => "unknown",
+    }
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncNdjsonOutput<W> {
+
+    pub fn new(writer: W, optional: OptionalColumns, columns: Option<&[OutputColumn]>) -> Self {
+        Self {
+            writer,
+            optional,
+            columns: columns.map(|columns| columns.iter().map(|c| c.name()).collect()),
+            bytes: 0,
+        }
+    }
+
+    pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        self.write_rows(v, diff_flag, &[]).await
+    }
+
+    async fn write_rows(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8, dups: &[DupGroup]) -> tokio::io::Result<()> {
+
+        let status = if self.optional.diff_mode { Some(diff_status(diff_flag)) } else { None };
+        let time_format = TimeFormat::current();
+        for (i, (key, props)) in v.iter().enumerate() {
+            let dup = dups.get(i).filter(|_| self.optional.duplicates);
+            let (key, version_id) = key.split_version();
+            let versions = self.optional.versions;
+            let row = NdjsonRow {
+                key,
+                object_type: self.optional.shallow.then(|| props.object_type()),
+                version_id: if versions { Some(version_id.unwrap_or_default()) } else { None },
+                is_latest: versions.then(|| props.is_latest()),
+                is_delete_marker: versions.then(|| props.is_delete_marker()),
+                size: props.size(),
+                last_modified: time_format.render_json(props.last_modified()),
+                etag: props.etag_string(),
+                storage_class: props.storage_class(),
+                status,
+                diff_attrs: status.map(|_| props.diff_attrs()),
+                dup_group: dup.map(|d| d.0),
+                dup_count: dup.map(|d| d.1),
+                tags: self.optional.tags.then(|| props.tags_json()),
+                owner_id: self.optional.owner.then(|| props.owner().and_then(|o| o.id.as_deref())),
+                owner_display_name: self.optional.owner.then(|| props.owner().and_then(|o| o.display_name.as_deref())),
+                checksum: self.optional.checksum.then(|| props.checksum()),
+                head: self.optional.enrich.then(|| HeadColumn::ALL.iter()
+                    .map(|c| (c.name().to_string(), c.json(props.head())))
+                    .collect()),
+            };
+            let mut line = match &self.columns {
+                Some(columns) => projected_json(&row, columns)?,
+                None => serde_json::to_vec(&row)?,
+            };
+            line.push(b'\n');
+            self.writer.write_all(&line).await?;
+            self.bytes += line.len();
+        }
+
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> tokio::io::Result<()> {
+        self.writer.flush().await?;
+        self.writer.shutdown().await
+    }
+}
+
+// json object of given fields of row in their order, with json of all fields only ordered by name
+fn projected_json(row: &NdjsonRow, columns: &[&str]) -> serde_json::Result<Vec<u8>> {
+    let value = serde_json::to_value(row)?;
+    let fields: Vec<String> = columns.iter()
+        .filter_map(|name| value.get(name).map(|v| format!("\"{}\":{}", name, v)))
+        .collect();
+    Ok(format!("{{{}}}", fields.join(",")).into_bytes())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputColumn {
+    Key,
+    Size,
+    LastModified,
+    Etag,
+    Status,
+    DiffAttrs,
+    VersionId,
+    IsLatest,
+    IsDeleteMarker,
+    StorageClass,
+    Type,
+    DupGroup,
+    DupCount,
+    Tags,
+    OwnerId,
+    OwnerDisplayName,
+    Checksum,
+    Head(HeadColumn),
+}
+
+impl OutputColumn {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Key => "key",
+            Self::Size => "size",
+            Self::LastModified => "last_modified",
+            Self::Etag => "etag",
+            Self::Status => "status",
+            Self::DiffAttrs => "diff_attrs",
+            Self::VersionId => "version_id",
+            Self::IsLatest => "is_latest",
+            Self::IsDeleteMarker => "is_delete_marker",
+            Self::StorageClass => "storage_class",
+            Self::Type => "type",
+            Self::DupGroup => "dup_group",
+            Self::DupCount => "dup_count",
+            Self::Tags => "tags",
+            Self::OwnerId => "owner_id",
+            Self::OwnerDisplayName => "owner_display_name",
+            Self::Checksum => "checksum",
+            Self::Head(c) => c.name(),
+        }
+    }
+
+    // column of parquet and arrow ipc schema
+    fn field_name(&self) -> &'static str {
+        match self {
+            Self::Key => "Key",
+            Self::Size => "Size",
+            Self::LastModified => "LastModified",
+            Self::Etag => "ETag",
+            Self::Status => "DiffFlag",
+            Self::DiffAttrs => "DiffAttrs",
+            Self::VersionId => "VersionId",
+            Self::IsLatest => "IsLatest",
+            Self::IsDeleteMarker => "IsDeleteMarker",
+            Self::StorageClass => "StorageClass",
+            Self::Type => "Type",
+            Self::DupGroup => "DupGroup",
+            Self::DupCount => "DupCount",
+            Self::Tags => "Tags",
+            Self::OwnerId => "OwnerId",
+            Self::OwnerDisplayName => "OwnerDisplayName",
+            Self::Checksum => "Checksum",
+            Self::Head(c) => c.field_name(),
+        }
+    }
+}
+
+// columns of HeadObject response with --enrich-all
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeadColumn {
+    ContentType,
+    ContentEncoding,
+    ContentLanguage,
+    ContentDisposition,
+    CacheControl,
+    ServerSideEncryption,
+    SseKmsKeyId,
+    Metadata,
+    PartsCount,
+    TagCount,
+    ReplicationStatus,
+    ObjectLockMode,
+    Restore,
+}
+
+impl HeadColumn {
+    pub const ALL: [HeadColumn; 13] = [Self::ContentType, Self::ContentEncoding, Self::ContentLanguage, Self::ContentDisposition,
+        Self::CacheControl, Self::ServerSideEncryption, Self::SseKmsKeyId, Self::Metadata, Self::PartsCount, Self::TagCount,
+        Self::ReplicationStatus, Self::ObjectLockMode, Self::Restore];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::ContentType => "content_type",
+            Self::ContentEncoding => "content_encoding",
+            Self::ContentLanguage => "content_language",
+            Self::ContentDisposition => "content_disposition",
+            Self::CacheControl => "cache_control",
+            Self::ServerSideEncryption => "server_side_encryption",
+            Self::SseKmsKeyId => "sse_kms_key_id",
+            Self::Metadata => "metadata",
+            Self::PartsCount => "parts_count",
+            Self::TagCount => "tag_count",
+            Self::ReplicationStatus => "replication_status",
+            Self::ObjectLockMode => "object_lock_mode",
+            Self::Restore => "restore",
+        }
+    }
+
+    fn field_name(&self) -> &'static str {
+        match self {
+            Self::ContentType => "ContentType",
+            Self::ContentEncoding => "ContentEncoding",
+            Self::ContentLanguage => "ContentLanguage",
+            Self::ContentDisposition => "ContentDisposition",
+            Self::CacheControl => "CacheControl",
+            Self::ServerSideEncryption => "ServerSideEncryption",
+            Self::SseKmsKeyId => "SSEKMSKeyId",
+            Self::Metadata => "Metadata",
+            Self::PartsCount => "PartsCount",
+            Self::TagCount => "TagCount",
+            Self::ReplicationStatus => "ReplicationStatus",
+            Self::ObjectLockMode => "ObjectLockMode",
+            Self::Restore => "Restore",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Self::PartsCount | Self::TagCount => DataType::UInt32,
+            _ => DataType::Utf8,
+        }
+    }
+
+    fn count(&self, head: &ObjectHead) -> Option<u32> {
+        match self {
+            Self::PartsCount => head.parts_count,
+            Self::TagCount => head.tag_count,
+            _ => None,
+        }
+    }
+
+    // value of csv, metadata in json object of name to value
+    fn text(&self, head: &ObjectHead) -> Option<String> {
+        match self {
+            Self::ContentType => head.content_type.clone(),
+            Self::ContentEncoding => head.content_encoding.clone(),
+            Self::ContentLanguage => head.content_language.clone(),
+            Self::ContentDisposition => head.content_disposition.clone(),
+            Self::CacheControl => head.cache_control.clone(),
+            Self::ServerSideEncryption => head.server_side_encryption.clone(),
+            Self::SseKmsKeyId => head.sse_kms_key_id.clone(),
+            Self::Metadata => serde_json::to_string(&head.metadata).ok(),
+            Self::PartsCount | Self::TagCount => self.count(head).map(|n| n.to_string()),
+            Self::ReplicationStatus => head.replication_status.clone(),
+            Self::ObjectLockMode => head.object_lock_mode.clone(),
+            Self::Restore => head.restore.clone(),
+        }
+    }
+
+    fn json(&self, head: Option<&ObjectHead>) -> serde_json::Value {
+        match (self, head) {
+            (_, None) => serde_json::Value::Null,
+            (Self::Metadata, Some(head)) => serde_json::to_value(&head.metadata).unwrap_or_default(),
+            (Self::PartsCount | Self::TagCount, Some(head)) => self.count(head).map_or(serde_json::Value::Null, |n| n.into()),
+            (_, Some(head)) => self.text(head).map_or(serde_json::Value::Null, serde_json::Value::String),
+        }
+    }
+
+    fn array(&self, v: &[(ObjectKey, ObjectProps)]) -> ArrayRef {
+        match self.data_type() {
+            DataType::UInt32 => Arc::new(UInt32Array::from_iter(v.iter().map(|(_, p)| p.head().and_then(|h| self.count(h))))),
+            _ => Arc::new(StringArray::from_iter(v.iter().map(|(_, p)| p.head().and_then(|h| self.text(h))))),
+        }
+    }
+}
+
+// object fields known to S3 but not captured during list
+const OUTPUT_COLUMNS_NOT_CAPTURED: [&str; 2] = ["checksum_algorithm", "restore_status"];
+
+// parse "--columns" in comma separated list, default columns are used if not given
+pub fn parse_columns(columns: Option<&str>, optional: &OptionalColumns) -> Result<Vec<OutputColumn>, OptionError> {
+
+    let OptionalColumns { diff_mode, versions, shallow, duplicates, tags, owner, checksum, enrich } = *optional;
+    let default_columns = match (diff_mode, versions) {
+        (false, false) => "key,size,last_modified,etag",
+        (true, false) => "key,size,last_modified,etag,status,diff_attrs",
+        (false, true) => "key,version_id,size,last_modified,etag,is_latest,is_delete_marker",
+        (true, true) => "key,version_id,size,last_modified,etag,is_latest,is_delete_marker,status,diff_attrs",
+    };
+    // type of row follows key in delimiter list
+    let mut default_columns = if shallow { default_columns.replacen("key,", "key,type,", 1) } else { default_columns.to_string() };
+    if duplicates {
+        default_columns.push_str(",dup_group,dup_count");
+    }
+    if tags {
+        default_columns.push_str(",tags");
+    }
+    if owner {
+        default_columns.push_str(",owner_id,owner_display_name");
+    }
+    if checksum {
+        default_columns.push_str(",checksum");
+    }
+    if enrich {
+        for c in HeadColumn::ALL {
+            default_columns.push(',');
+            default_columns.push_str(c.name());
+        }
+    }
+    parse_column_names("columns", columns.unwrap_or(&default_columns), optional)
+}
+
+// parse "--output-columns" in comma separated list, names as of "--columns" for all output formats
+pub fn parse_output_columns(columns: &str, optional: &OptionalColumns) -> Result<Vec<OutputColumn>, OptionError> {
+    parse_column_names("output-columns", columns, optional)
+}
+
+// columns of names available with optional ones, errors are of given option
+fn parse_column_names(option: &'static str, columns: &str, optional: &OptionalColumns) -> Result<Vec<OutputColumn>, OptionError> {
+
+    let OptionalColumns { diff_mode, versions, shallow, duplicates, tags, owner, checksum, enrich } = *optional;
+    let mut v = Vec::new();
+    for name in columns.split(',').map(|s| s.trim()) {
+        let column = match name {
+            "key" => OutputColumn::Key,
+            "size" => OutputColumn::Size,
+            "last_modified" => OutputColumn::LastModified,
+            "etag" => OutputColumn::Etag,
+            "storage_class" => OutputColumn::StorageClass,
+            "status" if diff_mode => OutputColumn::Status,
+            "diff_attrs" if diff_mode => OutputColumn::DiffAttrs,
+            "status" | "diff_attrs" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available in diff mode")));
+            },
+            "version_id" if versions => OutputColumn::VersionId,
+            "is_latest" if versions => OutputColumn::IsLatest,
+            "is_delete_marker" if versions => OutputColumn::IsDeleteMarker,
+            "version_id" | "is_latest" | "is_delete_marker" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --versions")));
+            },
+            "type" if shallow => OutputColumn::Type,
+            "type" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --delimiter")));
+            },
+            "dup_group" if duplicates => OutputColumn::DupGroup,
+            "dup_count" if duplicates => OutputColumn::DupCount,
+            "dup_group" | "dup_count" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --find-duplicates")));
+            },
+            "tags" if tags => OutputColumn::Tags,
+            "tags" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --with-tags")));
+            },
+            "owner_id" if owner => OutputColumn::OwnerId,
+            "owner_display_name" if owner => OutputColumn::OwnerDisplayName,
+            "owner_id" | "owner_display_name" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --with-owner")));
+            },
+            "checksum" if checksum => OutputColumn::Checksum,
+            "checksum" => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --with-checksum")));
+            },
+            name if enrich && HeadColumn::from_name(name).is_some() => OutputColumn::Head(HeadColumn::from_name(name).unwrap()),
+            name if HeadColumn::from_name(name).is_some() => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is only available with --enrich-all")));
+            },
+            name if OUTPUT_COLUMNS_NOT_CAPTURED.contains(&name) => {
+                return Err(OptionError::new(option, format!("column \"{name}\" is not captured during list")));
+            },
+            name => {
+                return Err(OptionError::new(option, format!("unknown column \"{name}\"")));
+            },
+        };
+        if v.contains(&column) {
+            return Err(OptionError::new(option, format!("duplicated column \"{name}\"")));
+        }
+        v.push(column);
+    }
+
+    Ok(v)
+}
+
+// quote field per RFC 4180 if it contains comma, quote or line break
+pub(crate) fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains([',', '"', '\r', '\n']) {
+        std::borrow::Cow::Owned(format!("\"{}\"", s.replace('"', "\"\"")))
+    } else {
+        std::borrow::Cow::Borrowed(s)
+    }
+}
+
+// csv rows of selected columns with header row
+pub struct AsyncCsvOutput<W> {
+    writer: W,
+    columns: Vec<OutputColumn>,
+    bytes: usize,
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncCsvOutput<W> {
+
+    // header row is skipped if appending to existing file
+    pub async fn new(mut writer: W, columns: &[OutputColumn], header: bool) -> tokio::io::Result<Self> {
+        let mut bytes = 0;
+        if header {
+            let names: Vec<&str> = columns.iter().map(|c| c.name()).collect();
+            let line = format!("{}\r\n", names.join(","));
+            writer.write_all(line.as_bytes()).await?;
+            bytes += line.len();
+        }
+
+        Ok(Self {
+            writer,
+            columns: columns.to_vec(),
+            bytes,
+        })
+    }
+
+    pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        self.write_rows(v, diff_flag, &[]).await
+    }
+
+    async fn write_rows(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8, dups: &[DupGroup]) -> tokio::io::Result<()> {
+
+        let time_format = TimeFormat::current();
+        for (i, (key, props)) in v.iter().enumerate() {
+            let (key_str, version_id) = key.split_version();
+            let dup = dups.get(i).copied().unwrap_or_default();
+            let fields: Vec<String> = self.columns.iter().map(|c| match c {
+                OutputColumn::Key => csv_field(key_str).into_owned(),
+                OutputColumn::Size => props.size().to_string(),
+                OutputColumn::LastModified => time_format.render_text(props.last_modified()),
+                OutputColumn::Etag => props.etag_string(),
+                OutputColumn::Status => diff_status(diff_flag).to_string(),
+                OutputColumn::DiffAttrs => csv_field(&props.diff_attrs().join(",")).into_owned(),
+                OutputColumn::VersionId => csv_field(version_id.unwrap_or_default()).into_owned(),
+                OutputColumn::IsLatest => props.is_latest().to_string(),
+                OutputColumn::IsDeleteMarker => props.is_delete_marker().to_string(),
+                OutputColumn::StorageClass => props.storage_class().to_string(),
+                OutputColumn::Type => props.object_type().to_string(),
+                OutputColumn::DupGroup => dup.0.to_string(),
+                OutputColumn::DupCount => dup.1.to_string(),
+                OutputColumn::Tags => props.tags_json()
+                    .map_or(String::new(), |t| csv_field(&serde_json::Value::Object(t).to_string()).into_owned()),
+                OutputColumn::OwnerId => csv_field(props.owner().and_then(|o| o.id.as_deref()).unwrap_or_default()).into_owned(),
+                OutputColumn::OwnerDisplayName => csv_field(props.owner().and_then(|o| o.display_name.as_deref()).unwrap_or_default()).into_owned(),
+                OutputColumn::Checksum => props.checksum().unwrap_or_default().to_string(),
+                OutputColumn::Head(c) => props.head().and_then(|h| c.text(h)).map_or(String::new(), |t| csv_field(&t).into_owned()),
+            }).collect();
+            let line = fields.join(",") + "\r\n";
+            self.writer.write_all(line.as_bytes()).await?;
+            self.bytes += line.len();
+        }
+
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> tokio::io::Result<()> {
+        self.writer.flush().await?;
+        self.writer.shutdown().await
+    }
+}
+
+// keys only, one per line or each ended by NUL, keys with newline are skipped unless NUL terminated
+pub struct AsyncKeysOutput<W> {
+    writer: W,
+    null_terminated: bool,
+    bytes: usize,
+    skipped: usize,
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncKeysOutput<W> {
+
+    pub fn new(writer: W, null_terminated: bool) -> Self {
+        Self {
+            writer,
+            null_terminated,
+            bytes: 0,
+            skipped: 0,
+        }
+    }
+
+    // version id of object is left out, each version repeats its key
+    pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, _diff_flag: u8) -> tokio::io::Result<()> {
+        let terminator = if self.null_terminated { b'\0' } else { b'\n' };
+        let mut buf = Vec::new();
+        for (key, _) in v.iter() {
+            let (key_str, _) = key.split_version();
+            if !self.null_terminated && key_str.contains('\n') {
+                self.skipped += 1;
+                continue;
+            }
+            buf.extend_from_slice(key_str.as_bytes());
+            buf.push(terminator);
+        }
+        self.writer.write_all(&buf).await?;
+        self.bytes += buf.len();
+        Ok(())
+    }
+
+    pub async fn close(mut self) -> tokio::io::Result<()> {
+        if self.skipped > 0 {
+            warn!("{} keys with newline not written, use --null-terminated to keep them", fmt_count(self.skipped));
+        }
+        self.writer.flush().await?;
+        self.writer.shutdown().await
+    }
+}
+
+// object metadata writer of selected output format
+pub enum AsyncObjectOutput<W> {
+    Parquet(Box<AsyncParquetOutput<W>>),
+    Arrow(Box<AsyncArrowOutput<W>>),
+    Ndjson(AsyncNdjsonOutput<W>),
+    Csv(AsyncCsvOutput<W>),
+    Keys(AsyncKeysOutput<W>),
+}
+
+impl<W: AsyncWrite + Unpin + Send> AsyncObjectOutput<W> {
+
+    pub async fn write(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8) -> tokio::io::Result<()> {
+        match self {
+            Self::Parquet(output) => output.write(v, diff_flag).await,
+            Self::Arrow(output) => output.write(v, diff_flag).await,
+            Self::Ndjson(output) => output.write(v, diff_flag).await,
+            Self::Csv(output) => output.write(v, diff_flag).await,
+            Self::Keys(output) => output.write(v, diff_flag).await,
+        }
+    }
+
+    // rows of duplicate groups, each with its group id and member count
+    pub async fn write_duplicates(&mut self, v: Vec<(ObjectKey, ObjectProps)>, diff_flag: u8, dups: &[DupGroup]) -> tokio::io::Result<()> {
+        match self {
+            Self::Parquet(output) => output.write_rows(v, diff_flag, dups).await,
+            Self::Arrow(output) => output.write_rows(v, diff_flag, dups).await,
+            Self::Ndjson(output) => output.write_rows(v, diff_flag, dups).await,
+            Self::Csv(output) => output.write_rows(v, diff_flag, dups).await,
+            Self::Keys(output) => output.write(v, diff_flag).await,
+        }
+    }
+
+    // flush buffered rows to file, parquet output only closes current row group
+    pub async fn flush(&mut self) -> tokio::io::Result<()> {
+        match self {
+            Self::Parquet(output) => output.flush().await,
+            Self::Arrow(output) => output.writer.flush().await,
+            Self::Ndjson(output) => output.writer.flush().await,
+            Self::Csv(output) => output.writer.flush().await,
+            Self::Keys(output) => output.writer.flush().await,
+        }
+    }
+
+    // bytes written so far, of parquet output with encoded row group in progress
+    pub fn bytes_written(&self) -> usize {
+        match self {
+            Self::Parquet(output) => output.writer.bytes_written() + output.writer.in_progress_size(),
+            Self::Arrow(output) => output.bytes,
+            Self::Ndjson(output) => output.bytes,
+            Self::Csv(output) => output.bytes,
+            Self::Keys(output) => output.bytes,
+        }
+    }
+
+    // key-value metadata in footer of parquet output from count of rows written, none for other formats
+    pub fn append_footer(&mut self, metadata: impl FnOnce(usize) -> Vec<(String, String)>) {
+        if let Self::Parquet(output) = self {
+            for (key, value) in metadata(output.rows) {
+                output.writer.append_key_value_metadata(KeyValue::new(key, value));
+            }
+        }
+    }
+
+    pub async fn close(self) -> tokio::io::Result<()> {
+        match self {
+            Self::Parquet(output) => (*output).close().await,
+            Self::Arrow(output) => (*output).close().await,
+            Self::Ndjson(output) => output.close().await,
+            Self::Csv(output) => output.close().await,
+            Self::Keys(output) => output.close().await,
+        }
+    }
+}
+
+const GZIP_MAGIC: &[u8; 2] = b"\x1f\x8b";
+const ZSTD_MAGIC: &[u8; 4] = b"\x28\xb5\x2f\xfd";
+
+enum Encoder {
+    Plain,
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(compression: KsCompression) -> std::io::Result<Self> {
+        Ok(match compression {
+            KsCompression::None => Encoder::Plain,
+            KsCompression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+            KsCompression::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 0)?),
+        })
+    }
+
+    // encode into buffer of encoder and move encoded bytes so far to output
+    fn encode(&mut self, buf: &[u8], output: &mut Vec<u8>) -> std::io::Result<()> {
+        use std::io::Write;
+        let encoded = match self {
+            Encoder::Plain => { output.extend_from_slice(buf); return Ok(()) },
+            Encoder::Gzip(e) => { e.write_all(buf)?; e.get_mut() },
+            Encoder::Zstd(e) => { e.write_all(buf)?; e.get_mut() },
+        };
+        output.append(encoded);
+        Ok(())
+    }
+
+    // write trailer of gzip member or zstd frame to output
+    fn finish(self, output: &mut Vec<u8>) -> std::io::Result<()> {
+        let mut rest = match self {
+            Encoder::Plain => return Ok(()),
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        output.append(&mut rest);
+        Ok(())
+    }
+}
+
+/*
+ * async writer through a sync encoder, encoded bytes are passed to inner writer once over 1MB.
+ * flush ends current gzip member or zstd frame and starts a new one, so whatever is flushed is a
+ * complete file that gunzip or zstd reads, concatenated members and frames are one stream to them.
+ */
+pub struct CompressedWriter<W> {
+    inner: W,
+    compression: KsCompression,
+    // none once shut down
+    encoder: Option<Encoder>,
+    // anything written to encoder since its member or frame started
+    dirty: bool,
+    // encoded bytes not taken by inner writer yet, from pos
+    pending: Vec<u8>,
+    pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> CompressedWriter<W> {
+    pub fn new(inner: W, compression: KsCompression) -> std::io::Result<Self> {
+        Ok(Self { inner, compression, encoder: Some(Encoder::new(compression)?), dirty: false, pending: Vec::new(), pos: 0 })
+    }
+
+    // write trailer of encoder and flush inner writer
+    pub async fn finish(mut self) -> std::io::Result<W> {
+        self.shutdown().await?;
+        Ok(self.inner)
+    }
+
+    fn poll_pending(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        while self.pos < self.pending.len() {
+            let n = std::task::ready!(std::pin::Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pos..]))?;
+            if n == 0 {
+                return std::task::Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pos += n;
+        }
+        self.pending.clear();
+        self.pos = 0;
+        std::task::Poll::Ready(Ok(()))
+    }
+
+    // end current member or frame, a new encoder takes writes after it
+    fn end_encoder(&mut self, restart: bool) -> std::io::Result<()> {
+        let next = if restart { Some(Encoder::new(self.compression)?) } else { None };
+        if let Some(encoder) = std::mem::replace(&mut self.encoder, next) {
+            encoder.finish(&mut self.pending)?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for CompressedWriter<W> {
+    fn poll_write(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>, buf: &[u8]) -> std::task::Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if this.compression == KsCompression::None && this.pending.is_empty() {
+            return std::pin::Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        if this.pending.len() >= MB {
+            std::task::ready!(this.poll_pending(cx))?;
+        }
+        let Some(encoder) = this.encoder.as_mut() else {
+            return std::task::Poll::Ready(Err(std::io::Error::other("write after shutdown")));
+        };
+        encoder.encode(buf, &mut this.pending)?;
+        this.dirty |= !buf.is_empty();
+        std::task::Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.dirty && this.compression != KsCompression::None {
+            this.end_encoder(true)?;
+        }
+        std::task::ready!(this.poll_pending(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: std::pin::Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> std::task::Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.encoder.is_some() {
+            this.end_encoder(false)?;
+        }
+        std::task::ready!(this.poll_pending(cx))?;
+        std::pin::Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+// buffered reader of file, decompressed if it starts with magic of gzip or zstd
+pub fn open_decompressed(path: &str) -> std::io::Result<Box<dyn std::io::BufRead>> {
+    use std::io::BufRead;
+    let mut reader = std::io::BufReader::with_capacity(MB, std::fs::File::open(path)?);
+    let head = reader.fill_buf()?;
+    let reader: Box<dyn BufRead> = if head.starts_with(ZSTD_MAGIC) {
+        Box::new(std::io::BufReader::new(zstd::stream::read::Decoder::with_buffer(reader)?))
+    } else if head.starts_with(GZIP_MAGIC) {
+        Box::new(std::io::BufReader::new(flate2::read::MultiGzDecoder::new(reader)))
+    } else {
+        Box::new(reader)
+    };
+    Ok(reader)
+}
+
+// human readable output toggled by "--humanize", only for log lines
+static HUMANIZE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_humanize(enabled: bool) {
+    HUMANIZE.store(enabled, Ordering::Relaxed);
+}
+
+// format count with thousands separator if humanized, e.g. "1,234,567"
+pub fn fmt_count(n: usize) -> String {
+    fmt_count_with(n, HUMANIZE.load(Ordering::Relaxed))
+}
+
+fn fmt_count_with(n: usize, humanize: bool) -> String {
+    let digits = n.to_string();
+    if !humanize {
+        return digits;
+    }
+
+    let mut s = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            s.push(',');
+        }
+        s.push(c);
+    }
+    s
+}
+
+// format bytes in binary units if humanized, e.g. "1.2 TiB"
+pub fn fmt_bytes(n: u64) -> String {
+    fmt_bytes_with(n, HUMANIZE.load(Ordering::Relaxed))
+}
+
+fn fmt_bytes_with(n: u64, humanize: bool) -> String {
+    if !humanize {
+        return n.to_string();
+    }
+    fmt_bytes_human(n)
+}
+
+// unix seconds as UTC datetime, e.g. "2024-01-01T00:00:00Z"
+pub fn fmt_rfc3339(secs: u64) -> String {
+    chrono::DateTime::from_timestamp(secs as i64, 0).map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+        .unwrap_or_else(|| secs.to_string())
+}
+
+// format bytes in binary units regardless of "--humanize"
+pub fn fmt_bytes_human(n: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// parse size like "8GB", "512MiB" or "1024", units are in power of 1024
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    let pos = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(pos);
+    let num: usize = num.parse().map_err(|_| format!("invalid size \"{s}\""))?;
+    let scale = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => KB,
+        "M" | "MB" | "MIB" => MB,
+        "G" | "GB" | "GIB" => GB,
+        "T" | "TB" | "TIB" => GB * 1024,
+        other => return Err(format!("unknown size unit \"{other}\"")),
+    };
+    num.checked_mul(scale).ok_or_else(|| format!("size \"{s}\" overflow"))
+}
+
+// parse percent like "0.5" or "10", above 0 and up to 100
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s.trim().trim_end_matches('%').parse().map_err(|_| format!("invalid percent \"{s}\""))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        return Err(format!("percent \"{s}\" out of range, should be above 0 and up to 100"));
+    }
+    Ok(percent)
+}
+
+pub const DEFAULT_BLOOM_FILTER_FPP: f64 = 0.01;
+
+// parse probability like "0.01", above 0 and below 1
+pub fn parse_probability(s: &str) -> Result<f64, String> {
+    let p: f64 = s.trim().parse().map_err(|_| format!("invalid probability \"{s}\""))?;
+    if !(p > 0.0 && p < 1.0) {
+        return Err(format!("probability \"{s}\" out of range, should be above 0 and below 1"));
+    }
+    Ok(p)
+}
+
+// custom user agent, printable ascii only as it goes into http header as is
+pub fn parse_user_agent(s: &str) -> Result<String, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty user agent".to_string());
+    }
+    if !s.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return Err(format!("user agent \"{s}\" should be printable ascii"));
+    }
+    Ok(s.to_string())
+}
+
+// custom endpoint url, a bare host is taken as https and trailing slashes are dropped, port and path are kept
+pub fn parse_endpoint_url(s: &str) -> Result<String, String> {
+    let url = s.trim();
+    if url.is_empty() {
+        return Err("empty endpoint url".to_string());
+    }
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None => ("https".to_string(), url),
+    };
+    if scheme != "http" && scheme != "https" {
+        return Err(format!("scheme \"{scheme}\" of endpoint url \"{url}\" should be http or https"));
+    }
+    let rest = rest.trim_end_matches('/');
+    let (authority, path) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+    if path.contains(['?', '#']) {
+        return Err(format!("endpoint url \"{url}\" should have no query or fragment"));
+    }
+    if authority.contains('@') {
+        return Err(format!("endpoint url \"{url}\" should have no user info, credentials come from AWS profile or environment"));
+    }
+    let port = match authority.strip_prefix('[') {
+        // ipv6 literal, e.g. [::1]:9000
+        Some(v6) => match v6.split_once(']') {
+            Some((addr, port)) if !addr.is_empty() && addr.chars().all(|c| c.is_ascii_hexdigit() || c == ':' || c == '.')
+                && (port.is_empty() || port.starts_with(':')) => port.strip_prefix(':'),
+            _ => return Err(format!("invalid ipv6 address of endpoint url \"{url}\"")),
+        },
+        None => {
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            };
+            if host.is_empty() || !host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_') {
+                return Err(format!("invalid host \"{host}\" of endpoint url \"{url}\""));
+            }
+            port
+        },
+    };
+    if let Some(port) = port {
+        if !port.parse::<u16>().is_ok_and(|p| p > 0) {
+            return Err(format!("invalid port \"{port}\" of endpoint url \"{url}\", should be 1 to 65535"));
+        }
+    }
+    Ok(format!("{scheme}://{authority}{path}"))
+}
+
+// characters of --hash-charset sorted as keys are, in utf-8 binary order, duplicates dropped
+pub fn parse_hash_charset(s: &str) -> Result<String, String> {
+    let mut chars: Vec<char> = s.chars().collect();
+    chars.sort();
+    chars.dedup();
+    if chars.len() < 2 {
+        return Err(format!("hash charset \"{s}\" should have at least 2 characters"));
+    }
+    if chars.len() > MAX_HASH_CHARSET_LEN {
+        return Err(format!("hash charset \"{s}\" should have at most {} characters, one partition each", MAX_HASH_CHARSET_LEN));
+    }
+    Ok(chars.into_iter().collect())
+}
+
+// resident set size of current process in bytes, only available on linux
+pub fn current_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * KB)
+}
+
+// peak resident set size of current process in bytes so far, only available on linux
+pub fn peak_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * KB)
+}
+
+// soft and hard limit of open file descriptors of process, "unlimited" as u64::MAX, none if unknown
+// rlim_t is not u64 on all targets
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit only writes to the rlimit passed in
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return None;
+    }
+    let value = |v: libc::rlim_t| if v == libc::RLIM_INFINITY { u64::MAX } else { v as u64 };
+    Some((value(limit.rlim_cur), value(limit.rlim_max)))
+}
+
+#[cfg(not(unix))]
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    None
+}
+
+// set soft limit of open file descriptors, hard limit kept as is
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+pub fn set_nofile_soft_limit(soft: u64) -> std::io::Result<()> {
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    // SAFETY: getrlimit and setrlimit only access the rlimit passed in
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        limit.rlim_cur = soft as libc::rlim_t;
+        if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_nofile_soft_limit(_soft: u64) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "no RLIMIT_NOFILE on this platform"))
+}
+
+// profile names defined in aws shared config or credentials file,
+// config file sections are "[profile name]" except for "[default]"
+fn aws_profile_names(content: &str, config_file: bool) -> Vec<String> {
+    content.lines()
+        .filter_map(|l| l.trim().strip_prefix('[')?.strip_suffix(']'))
+        .filter_map(|section| {
+            let section = section.trim();
+            match section.strip_prefix("profile") {
+                Some(name) if config_file && name.starts_with(char::is_whitespace) => Some(name.trim()),
+                _ if config_file && section != "default" => None,
+                _ => Some(section),
+            }
+        })
+        .map(|name| name.to_string())
+        .collect()
+}
+
+// check profile is defined in ~/.aws/config or ~/.aws/credentials, or files given by AWS_CONFIG_FILE, AWS_SHARED_CREDENTIALS_FILE
+pub fn aws_profile_exists(name: &str) -> bool {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let files = [
+        (std::env::var("AWS_CONFIG_FILE").unwrap_or_else(|_| format!("{home}/.aws/config")), true),
+        (std::env::var("AWS_SHARED_CREDENTIALS_FILE").unwrap_or_else(|_| format!("{home}/.aws/credentials")), false),
+    ];
+    files.iter().any(|(file, config_file)| {
+        std::fs::read_to_string(file)
+            .map(|content| aws_profile_names(&content, *config_file).iter().any(|n| n == name))
+            .unwrap_or(false)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn optional(diff_mode: bool, versions: bool, shallow: bool, duplicates: bool, tags: bool) -> OptionalColumns {
+        OptionalColumns { diff_mode, versions, shallow, duplicates, tags, owner: false, checksum: false, enrich: false }
+    }
+
+    #[test]
+    fn test_humanize() {
+        // global flag is left alone, other tests running at the same time format with it
+        assert_eq!(fmt_count_with(1234567, false), "1234567");
+        assert_eq!(fmt_bytes_with(1234567, false), "1234567");
+
+        assert_eq!(fmt_count_with(0, true), "0");
+        assert_eq!(fmt_count_with(999, true), "999");
+        assert_eq!(fmt_count_with(1000, true), "1,000");
+        assert_eq!(fmt_count_with(1234567, true), "1,234,567");
+        assert_eq!(fmt_bytes_with(512, true), "512 B");
+        assert_eq!(fmt_bytes_with(1536, true), "1.5 KiB");
+        assert_eq!(fmt_bytes_with(1_319_413_953_331, true), "1.2 TiB");
+        assert_eq!(fmt_bytes_human(1536), "1.5 KiB");
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_output() {
+        let rows = || vec![(ObjectKey::from("a/\"b\".txt"), ObjectProps::default())];
+
+        let mut buf = Vec::new();
+        let mut output = AsyncNdjsonOutput::new(&mut buf, OptionalColumns::default(), None);
+        output.write(rows(), 1).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(),
+            "{\"key\":\"a/\\\"b\\\".txt\",\"size\":0,\"last_modified\":\"1970-01-01T00:00:00Z\",\"etag\":\"00000000000000000000000000000000\",\"storage_class\":\"STANDARD\"}\n");
+
+        let mut buf = Vec::new();
+        let mut output = AsyncNdjsonOutput::new(&mut buf, OptionalColumns { diff_mode: true, ..Default::default() }, None);
+        output.write(rows(), 3).await.unwrap();
+        output.close().await.unwrap();
+        assert!(String::from_utf8(buf).unwrap().ends_with(",\"status\":\"changed\",\"diff_attrs\":[]}\n"));
+    }
+
+    #[tokio::test]
+    async fn test_arrow_output() {
+        use arrow_array::cast::AsArray;
+        use arrow_ipc::reader::StreamReader;
+
+        for compression in [ArrowCompression::None, ArrowCompression::Lz4, ArrowCompression::Zstd] {
+            let mut buf = Vec::new();
+            let mut output = AsyncArrowOutput::new(&mut buf, compression, optional(true, false, false, false, false), None).unwrap();
+            output.write(vec![(ObjectKey::from("a"), ObjectProps::default())], 1).await.unwrap();
+            output.write(vec![(ObjectKey::from("b"), ObjectProps::default())], 2).await.unwrap();
+            output.close().await.unwrap();
+
+            let reader = StreamReader::try_new(std::io::Cursor::new(buf), None).unwrap();
+            assert_eq!(reader.schema(), object_schema(optional(true, false, false, false, false)));
+            let batches: Vec<RecordBatch> = reader.map(|b| b.unwrap()).collect();
+            assert_eq!(batches.len(), 2);
+            assert_eq!(batches[1].column(0).as_string::<i32>().value(0), "b");
+            assert_eq!(batches[1].column_by_name("DiffFlag").unwrap().as_primitive::<arrow_array::types::UInt8Type>().value(0), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_output_columns() {
+        use arrow_ipc::reader::StreamReader;
+
+        let columns = parse_output_columns("size,key", &OptionalColumns::default()).unwrap();
+        assert!(parse_output_columns("key,owner_id", &OptionalColumns::default()).is_err());
+
+        let mut buf = Vec::new();
+        let mut output = AsyncArrowOutput::new(&mut buf, ArrowCompression::None, OptionalColumns::default(), Some(&columns)).unwrap();
+        output.write(vec![(ObjectKey::from("a"), ObjectProps::default())], 1).await.unwrap();
+        output.close().await.unwrap();
+        let reader = StreamReader::try_new(std::io::Cursor::new(buf), None).unwrap();
+        let names: Vec<_> = reader.schema().fields().iter().map(|f| f.name().clone()).collect();
+        assert_eq!(names, ["Size", "Key"]);
+        assert_eq!(reader.map(|b| b.unwrap().num_columns()).collect::<Vec<_>>(), [2]);
+
+        let mut buf = Vec::new();
+        let mut output = AsyncNdjsonOutput::new(&mut buf, OptionalColumns::default(), Some(&columns));
+        output.write(vec![(ObjectKey::from("a"), ObjectProps::default())], 1).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "{\"size\":0,\"key\":\"a\"}\n");
+    }
+
+    #[tokio::test]
+    async fn test_head_columns() {
+        use arrow_ipc::reader::StreamReader;
+
+        let enrich = OptionalColumns { enrich: true, ..Default::default() };
+        let columns = parse_columns(None, &enrich).unwrap();
+        assert_eq!(columns.len(), 4 + HeadColumn::ALL.len());
+        assert_eq!(columns[4], OutputColumn::Head(HeadColumn::ContentType));
+        assert!(parse_columns(Some("key,content_type"), &OptionalColumns::default()).is_err());
+
+        let mut props = ObjectProps::default();
+        props.set_head(ObjectHead {
+            content_type: Some("text/plain".to_string()),
+            metadata: [("team".to_string(), "a,b".to_string())].into_iter().collect(),
+            parts_count: Some(3),
+            ..Default::default()
+        });
+        let rows = || vec![(ObjectKey::from("a"), props.clone()), (ObjectKey::from("b"), ObjectProps::default())];
+
+        let picked = parse_columns(Some("key,content_type,metadata,parts_count,restore"), &enrich).unwrap();
+        let mut buf = Vec::new();
+        let mut output = AsyncCsvOutput::new(&mut buf, &picked, true).await.unwrap();
+        output.write(rows(), 0).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(),
+            "key,content_type,metadata,parts_count,restore\r\na,text/plain,\"{\"\"team\"\":\"\"a,b\"\"}\",3,\r\nb,,,,\r\n");
+
+        let mut buf = Vec::new();
+        let mut output = AsyncNdjsonOutput::new(&mut buf, enrich, Some(&picked));
+        output.write(rows(), 0).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "{\"key\":\"a\",\"content_type\":\"text/plain\",\"metadata\":{\"team\":\"a,b\"},\"parts_count\":3,\"restore\":null}\n\
+            {\"key\":\"b\",\"content_type\":null,\"metadata\":null,\"parts_count\":null,\"restore\":null}\n");
+
+        // wide schema, every column of HeadObject nullable
+        let mut buf = Vec::new();
+        let mut output = AsyncArrowOutput::new(&mut buf, ArrowCompression::None, enrich, None).unwrap();
+        output.write(rows(), 0).await.unwrap();
+        output.close().await.unwrap();
+        let reader = StreamReader::try_new(std::io::Cursor::new(buf), None).unwrap();
+        let schema = reader.schema();
+        assert_eq!(schema.fields().len(), 6 + HeadColumn::ALL.len());
+        assert_eq!(schema.field_with_name("PartsCount").unwrap().data_type(), &DataType::UInt32);
+        let batch = reader.map(|b| b.unwrap()).next().unwrap();
+        let content_types = batch.column_by_name("ContentType").unwrap();
+        assert_eq!((content_types.null_count(), batch.column_by_name("Metadata").unwrap().null_count()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_keys_output() {
+        let rows = || vec![(ObjectKey::from("a/b"), ObjectProps::default()), (ObjectKey::from("c\nd"), ObjectProps::default())];
+        let mut buf = Vec::new();
+        let mut output = AsyncKeysOutput::new(&mut buf, false);
+        output.write(rows(), 1).await.unwrap();
+        assert_eq!(output.skipped, 1);
+        output.close().await.unwrap();
+        assert_eq!(buf, b"a/b\n");
+
+        let mut buf = Vec::new();
+        let mut output = AsyncKeysOutput::new(&mut buf, true);
+        output.write(rows(), 1).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(buf, b"a/b\0c\nd\0");
+    }
+
+    #[tokio::test]
+    async fn test_parquet_writer_props() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let write = |opts: ParquetOptions| async move {
+            let rows = vec![(ObjectKey::from("a"), ObjectProps::default()), (ObjectKey::from("b"), ObjectProps::default())];
+            let mut buf = Vec::new();
+            let mut output = AsyncParquetOutput::new(&mut buf, &opts, OptionalColumns::default(), None);
+            output.write(rows, 1).await.unwrap();
+            output.close().await.unwrap();
+            SerializedFileReader::new(bytes::Bytes::from(buf)).unwrap()
+        };
+
+        let reader = write(ParquetOptions { sorted: true, bloom_filter_fpp: Some(0.01), ..Default::default() }).await;
+        let row_group = reader.metadata().row_group(0);
+        assert_eq!(row_group.sorting_columns().cloned(), Some(vec![SortingColumn { column_idx: 0, descending: false, nulls_first: false }]));
+        assert!(row_group.column(0).bloom_filter_offset().is_some());
+        assert!(row_group.column(1).statistics().is_some_and(|s| s.min_bytes_opt().is_some()));
+
+        let reader = write(ParquetOptions::default()).await;
+        let row_group = reader.metadata().row_group(0);
+        assert!(row_group.sorting_columns().is_none());
+        assert!(row_group.column(0).bloom_filter_offset().is_none());
+
+        // byte limit is checked within one large batch, not only after it
+        let rows: Vec<_> = (0..10 * PARQUET_BYTES_CHECK_ROWS).map(|i| (ObjectKey::from(format!("key/{:08}", i).as_str()), ObjectProps::default())).collect();
+        let mut buf = Vec::new();
+        let opts = ParquetOptions { max_row_group_bytes: Some(64 * KB), ..Default::default() };
+        let mut output = AsyncParquetOutput::new(&mut buf, &opts, OptionalColumns::default(), None);
+        output.write(rows, 1).await.unwrap();
+        output.close().await.unwrap();
+        let reader = SerializedFileReader::new(bytes::Bytes::from(buf)).unwrap();
+        assert!(reader.metadata().num_row_groups() > 1);
+        assert_eq!(reader.metadata().file_metadata().num_rows() as usize, 10 * PARQUET_BYTES_CHECK_ROWS);
+        assert!(reader.metadata().row_groups().iter().all(|rg| rg.num_rows() as usize <= 9 * PARQUET_BYTES_CHECK_ROWS));
+    }
+
+    #[tokio::test]
+    async fn test_csv_output() {
+        assert_eq!(parse_columns(None, &optional(false, false, false, false, false)).unwrap().len(), 4);
+        assert_eq!(parse_columns(None, &optional(true, false, false, false, false)).unwrap().len(), 6);
+        assert_eq!(parse_columns(Some("key, status"), &optional(true, false, false, false, false)), Ok(vec![OutputColumn::Key, OutputColumn::Status]));
+        assert!(parse_columns(Some("key,status"), &optional(false, false, false, false, false)).is_err());
+        assert!(parse_columns(Some("key,diff_attrs"), &optional(false, false, false, false, false)).is_err());
+        assert!(parse_columns(Some("key,version_id"), &optional(false, false, false, false, false)).is_err());
+        assert_eq!(parse_columns(None, &optional(true, true, false, false, false)).unwrap().len(), 9);
+        assert!(parse_columns(Some("key,owner"), &optional(false, false, false, false, false)).is_err());
+        assert!(parse_columns(Some("key,foo"), &optional(false, false, false, false, false)).is_err());
+        assert!(parse_columns(Some("key,key"), &optional(false, false, false, false, false)).is_err());
+        assert!(parse_columns(Some("key,type"), &optional(false, false, false, false, false)).is_err());
+        assert_eq!(parse_columns(None, &optional(false, false, true, false, false)).unwrap()[1], OutputColumn::Type);
+        assert_eq!(parse_columns(None, &optional(false, false, false, true, false)).unwrap()[4..], [OutputColumn::DupGroup, OutputColumn::DupCount]);
+        assert!(parse_columns(Some("key,dup_group"), &optional(false, false, false, false, false)).is_err());
+        assert_eq!(parse_columns(None, &optional(false, false, false, false, true)).unwrap()[4], OutputColumn::Tags);
+        assert!(parse_columns(Some("key,tags"), &optional(false, false, false, false, false)).is_err());
+        let owner = OptionalColumns { owner: true, ..Default::default() };
+        assert_eq!(parse_columns(None, &owner).unwrap()[4..], [OutputColumn::OwnerId, OutputColumn::OwnerDisplayName]);
+        assert!(parse_columns(Some("key,owner_id"), &optional(false, false, false, false, false)).is_err());
+        let checksum = OptionalColumns { checksum: true, ..Default::default() };
+        assert_eq!(parse_columns(None, &checksum).unwrap()[4..], [OutputColumn::Checksum]);
+        assert!(parse_columns(Some("key,checksum"), &optional(false, false, false, false, false)).is_err());
+
+        let columns = parse_columns(Some("key,size,status"), &optional(true, false, false, false, false)).unwrap();
+        let rows = vec![
+            (ObjectKey::from("a,b/\"c\".txt"), ObjectProps::default()),
+            (ObjectKey::from("plain.txt"), ObjectProps::default()),
+        ];
+        let mut buf = Vec::new();
+        let mut output = AsyncCsvOutput::new(&mut buf, &columns, true).await.unwrap();
+        output.write(rows, 2).await.unwrap();
+        output.close().await.unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(),
+            "key,size,status\r\n\"a,b/\"\"c\"\".txt\",0,only_right\r\nplain.txt,0,only_right\r\n");
+    }
+
+    #[test]
+    fn test_parse_parquet_compression() {
+        assert_eq!(parse_parquet_compression("none"), Ok(Compression::UNCOMPRESSED));
+        assert_eq!(parse_parquet_compression("lz4"), Ok(Compression::LZ4_RAW));
+        assert_eq!(parse_parquet_compression("gzip"), Ok(ParquetOptions::default().compression));
+        assert_eq!(parse_parquet_compression("zstd:9"), Ok(Compression::ZSTD(ZstdLevel::try_new(9).unwrap())));
+        assert!(parse_parquet_compression("zstd:99").is_err());
+        assert!(parse_parquet_compression("snappy:1").is_err());
+        assert!(parse_parquet_compression("brotli").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("8GB"), Ok(8 * GB));
+        assert_eq!(parse_size("512mib"), Ok(512 * MB));
+        assert_eq!(parse_size("4 K"), Ok(4 * KB));
+        assert!(parse_size("GB").is_err());
+        assert!(parse_size("8XB").is_err());
+        if cfg!(target_os = "linux") {
+            assert!(current_rss().is_some());
+        }
+    }
+
+    #[test]
+    fn test_parse_percent() {
+        assert_eq!(parse_percent("0.5"), Ok(0.5));
+        assert_eq!(parse_percent("100%"), Ok(100.0));
+        assert!(parse_percent("0").is_err());
+        assert!(parse_percent("101").is_err());
+        assert!(parse_percent("NaN").is_err());
+    }
+
+    #[test]
+    fn test_parse_probability() {
+        assert_eq!(parse_probability("0.01"), Ok(0.01));
+        assert!(parse_probability("0").is_err());
+        assert!(parse_probability("1").is_err());
+        assert!(parse_probability("x").is_err());
+    }
+
+    #[test]
+    fn test_parse_user_agent() {
+        assert_eq!(parse_user_agent(" team-a/backup "), Ok("team-a/backup".to_string()));
+        assert!(parse_user_agent("").is_err());
+        assert!(parse_user_agent("a\r\nx-evil: 1").is_err());
+        assert!(parse_user_agent("ü").is_err());
+    }
+
+    #[test]
+    fn test_parse_endpoint_url() {
+        assert_eq!(parse_endpoint_url("minio.internal:9000"), Ok("https://minio.internal:9000".to_string()));
+        assert_eq!(parse_endpoint_url("HTTP://127.0.0.1:5055/"), Ok("http://127.0.0.1:5055".to_string()));
+        assert_eq!(parse_endpoint_url("https://ceph.example.com/s3//"), Ok("https://ceph.example.com/s3".to_string()));
+        assert_eq!(parse_endpoint_url("http://[::1]:9000"), Ok("http://[::1]:9000".to_string()));
+        assert!(parse_endpoint_url("ftp://host").is_err());
+        assert!(parse_endpoint_url("http://host:99999").is_err());
+        assert!(parse_endpoint_url("http:/host").is_err());
+        assert!(parse_endpoint_url("http://user:pw@host").is_err());
+        assert!(parse_endpoint_url("https://my host").is_err());
+        assert!(parse_endpoint_url("https://host/?x=1").is_err());
+        assert!(parse_endpoint_url("").is_err());
+    }
+
+    #[test]
+    fn test_parse_hash_charset() {
+        assert_eq!(parse_hash_charset("fedcba9876543210"), Ok("0123456789abcdef".to_string()));
+        assert_eq!(parse_hash_charset("zaAz"), Ok("Aaz".to_string()));
+        assert!(parse_hash_charset("aa").is_err());
+        assert!(parse_hash_charset("").is_err());
+    }
+
+    #[test]
+    fn test_aws_profile_names() {
+        let config = "[default]\nregion = us-east-1\n[profile prod]\nrole_arn = x\n[ profile  dev ]\n[sso-session corp]\n[profiler]\n";
+        assert_eq!(aws_profile_names(config, true), vec!["default", "prod", "dev"]);
+        let credentials = "[default]\naws_access_key_id = x\n\n[prod]\n# [commented]\n";
+        assert_eq!(aws_profile_names(credentials, false), vec!["default", "prod"]);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_writer() {
+        use std::io::BufRead;
+        let lines: Vec<String> = (0..100000).map(|i| format!("\"prefix/{}\",\"{}\"", i, i)).collect();
+        for compression in [KsCompression::None, KsCompression::Gzip, KsCompression::Zstd] {
+            let filename = std::env::temp_dir().join(format!("ks-{}{}", std::process::id(), compression.suffix()));
+            let file = tokio::fs::File::create(&filename).await.unwrap();
+            let mut writer = CompressedWriter::new(file, compression).unwrap();
+            for line in lines.iter() {
+                writer.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+            }
+            writer.finish().await.unwrap();
+
+            let read: Vec<String> = open_decompressed(filename.to_str().unwrap()).unwrap().lines().map(|l| l.unwrap()).collect();
+            std::fs::remove_file(&filename).unwrap();
+            assert_eq!(read, lines, "{:?}", compression);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nofile_limit() {
+        let (soft, hard) = nofile_limit().unwrap();
+        assert!(soft <= hard);
+        // setting the current soft limit again leaves it as is
+        set_nofile_soft_limit(soft).unwrap();
+        assert_eq!(nofile_limit(), Some((soft, hard)));
+        if hard != u64::MAX {
+            assert!(set_nofile_soft_limit(hard + 1).is_err());
+        }
+    }
+}
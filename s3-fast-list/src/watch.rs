@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use log::{info, warn, error};
+use crate::core::{self, S3TaskContext, ObjectKey, ObjectProps, OutputFormat};
+use crate::coverage::PartitionStatus;
+use crate::data_map::KeySpaceHints;
+use crate::tasks_s3;
+use crate::utils::{self, csv_field};
+
+const WATCH_CSV_HEADER: &str = "timestamp,iteration,change,key,size,last_modified,etag";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WatchChange {
+    Added,
+    Deleted,
+    Changed,
+}
+
+impl WatchChange {
+    fn name(&self) -> &'static str {
+        match self {
+            WatchChange::Added => "added",
+            WatchChange::Deleted => "deleted",
+            WatchChange::Changed => "changed",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WatchRow<'a> {
+    timestamp: u64,
+    iteration: usize,
+    change: &'static str,
+    key: &'a str,
+    size: u64,
+    last_modified: u64,
+    etag: String,
+}
+
+// objects of one complete listing by key, only the previous one is kept between iterations
+type Snapshot = HashMap<String, ObjectProps>;
+
+// changes from previous to current listing sorted by key, deleted ones carry props last seen
+fn diff_snapshots<'a>(prev: &'a Snapshot, current: &'a Snapshot) -> Vec<(WatchChange, &'a str, &'a ObjectProps)> {
+    let mut changes: Vec<_> = current.iter().filter_map(|(key, props)| match prev.get(key) {
+        None => Some((WatchChange::Added, key.as_str(), props)),
+        Some(last) if last.size() != props.size() || last.last_modified() != props.last_modified()
+            || last.etag() != props.etag() => Some((WatchChange::Changed, key.as_str(), props)),
+        Some(_) => None,
+    }).collect();
+    changes.extend(prev.iter()
+        .filter(|(key, _)| !current.contains_key(*key))
+        .map(|(key, props)| (WatchChange::Deleted, key.as_str(), props)));
+    changes.sort_by(|a, b| a.1.cmp(b.1));
+    changes
+}
+
+// list all start prefixes once into a new snapshot, none if listing is interrupted or any partition failed
+async fn list_snapshot(ctx: &S3TaskContext, start_prefixes: Vec<(String, KeySpaceHints)>, flat_concurrency: usize) -> Option<Snapshot> {
+
+    let (data_map_channel, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    // channel is closed once all list tasks holding a sender are finished or aborted
+    let collector = tokio::spawn(async move {
+        let mut snapshot = Snapshot::new();
+        while let Some(output) = rx.recv().await {
+            for (prefix, objects) in output {
+                for (name, props) in objects {
+                    snapshot.insert(ObjectKey::encode(&prefix, &name).as_str().to_string(), props);
+                }
+            }
+        }
+        snapshot
+    });
+
+    // pairs are driven on the thread of this task, as flat list reactor does
+    let mut list_ctx = ctx.clone();
+    list_ctx.data_map_channel = data_map_channel;
+    let mut start_prefixes = start_prefixes;
+    let mut records = Vec::new();
+    let done = tasks_s3::run_key_space_pairs(&list_ctx, &mut start_prefixes, flat_concurrency, &mut records).await;
+    drop(list_ctx);
+
+    let snapshot = collector.await.ok()?;
+    (done && records.iter().all(|r| r.status == PartitionStatus::Complete)).then_some(snapshot)
+}
+
+async fn write_changes<W: AsyncWrite + Unpin>(writer: &mut W, output_format: OutputFormat, iteration: usize,
+        changes: &[(WatchChange, &str, &ObjectProps)]) -> tokio::io::Result<()> {
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    for (change, key, props) in changes {
+        let line = match output_format {
+            OutputFormat::Csv => format!("{},{},{},{},{},{},{}\r\n", timestamp, iteration, change.name(), csv_field(key),
+                props.size(), props.last_modified(), props.etag_string()),
+            _ => {
+                let row = WatchRow {
+                    timestamp,
+                    iteration,
+                    change: change.name(),
+                    key,
+                    size: props.size(),
+                    last_modified: props.last_modified(),
+                    etag: props.etag_string(),
+                };
+                serde_json::to_string(&row)? + "\n"
+            },
+        };
+        writer.write_all(line.as_bytes()).await?;
+    }
+    // changes of each iteration are visible to readers of output file right away
+    writer.flush().await
+}
+
+// sleep between iterations, cut short on quit
+async fn wait_interval(ctx: &S3TaskContext, interval_secs: u64) {
+    let deadline = tokio::time::Instant::now() + tokio::time::Duration::from_secs(interval_secs);
+    while !ctx.is_quit() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    }
+}
+
+// task to re-list start prefixes every interval until quit, in place of list and data map task,
+// changes against previous listing are appended to output file
+pub async fn watch_main_task(ctx: &S3TaskContext, start_prefixes: Vec<(String, KeySpaceHints)>, flat_concurrency: usize,
+        interval_secs: u64, output_format: OutputFormat, filename: &str) {
+
+    ctx.start();
+    ctx.g_state.wait_to_start().await;
+
+    info!("Watch Task - {} - started, re-list every {}s", ctx.s3_bucket_name, interval_secs);
+
+    let file = match tokio::fs::OpenOptions::new().create(true).append(true).open(filename).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Watch Task - {} - unable to open output file {}: {}", ctx.s3_bucket_name, filename, e);
+            ctx.g_state.set_list_failed();
+            ctx.complete();
+            ctx.g_state.quit();
+            return;
+        }
+    };
+    let header = output_format == OutputFormat::Csv && file.metadata().await.map(|m| m.len() == 0).unwrap_or(false);
+    let mut writer = tokio::io::BufWriter::new(file);
+    if header {
+        if let Err(e) = writer.write_all(format!("{}\r\n", WATCH_CSV_HEADER).as_bytes()).await {
+            error!("Watch Task - {} - unable to write output file {}: {}", ctx.s3_bucket_name, filename, e);
+        }
+    }
+
+    let mut prev: Option<Snapshot> = None;
+    let mut iterations = 0;
+    let mut totals = [0usize; 3];
+    while !ctx.is_quit() {
+        iterations += 1;
+        let started = tokio::time::Instant::now();
+        let Some(current) = list_snapshot(ctx, start_prefixes.clone(), flat_concurrency).await else {
+            if !ctx.is_quit() {
+                warn!("Watch Task - {} - iteration {} incomplete, not compared to previous one", ctx.s3_bucket_name, iterations);
+                wait_interval(ctx, interval_secs).await;
+            }
+            continue;
+        };
+
+        match &prev {
+            None => {
+                info!("Watch Task - {} - iteration {} listed {} objects in {}s, baseline of next one", ctx.s3_bucket_name,
+                    iterations, utils::fmt_count(current.len()), started.elapsed().as_secs());
+            },
+            Some(prev) => {
+                let changes = diff_snapshots(prev, &current);
+                let mut counts = [0usize; 3];
+                for (change, _, _) in changes.iter() {
+                    counts[*change as usize] += 1;
+                }
+                info!("Watch Task - {} - iteration {} listed {} objects in {}s, {} added, {} deleted, {} changed",
+                    ctx.s3_bucket_name, iterations, utils::fmt_count(current.len()), started.elapsed().as_secs(),
+                    utils::fmt_count(counts[0]), utils::fmt_count(counts[1]), utils::fmt_count(counts[2]));
+                if let Err(e) = write_changes(&mut writer, output_format, iterations, &changes).await {
+                    error!("Watch Task - {} - unable to write output file {}: {}", ctx.s3_bucket_name, filename, e);
+                    ctx.g_state.set_list_failed();
+                    break;
+                }
+                for (total, count) in totals.iter_mut().zip(counts) {
+                    *total += count;
+                }
+            },
+        }
+        // previous snapshot is dropped here, memory is bound to two listings at most
+        prev = Some(current);
+        wait_interval(ctx, interval_secs).await;
+    }
+
+    if let Err(e) = writer.shutdown().await {
+        error!("Watch Task - {} - unable to close output file {}: {}", ctx.s3_bucket_name, filename, e);
+    }
+    info!("Summary - watch of {} iterations, {} added, {} deleted, {} changed, written to {}", iterations,
+        utils::fmt_count(totals[0]), utils::fmt_count(totals[1]), utils::fmt_count(totals[2]), filename);
+    ctx.complete();
+    ctx.g_state.quit();
+    tokio::time::sleep(tokio::time::Duration::from_secs(core::DEFAULT_TASK_COMPLETE_QUIT_WAIT_SECS)).await;
+    info!("Watch Task - {} - quit", ctx.s3_bucket_name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_s3::types::Object;
+
+    fn props(size: i64, etag: &str) -> ObjectProps {
+        let obj = Object::builder().key("k").size(size).e_tag(format!("\"{}\"", etag)).build();
+        (&obj).into()
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let etag = "d41d8cd98f00b204e9800998ecf8427e";
+        let prev: Snapshot = [("a", props(1, etag)), ("b", props(2, etag)), ("c", props(3, etag))]
+            .into_iter().map(|(k, p)| (k.to_string(), p)).collect();
+        let current: Snapshot = [("a", props(1, etag)), ("b", props(5, etag)), ("d", props(4, etag))]
+            .into_iter().map(|(k, p)| (k.to_string(), p)).collect();
+
+        let changes: Vec<_> = diff_snapshots(&prev, &current).into_iter().map(|(c, k, p)| (c, k, p.size())).collect();
+        assert_eq!(changes, vec![
+            (WatchChange::Changed, "b", 5),
+            (WatchChange::Deleted, "c", 3),
+            (WatchChange::Added, "d", 4),
+        ]);
+        assert!(diff_snapshots(&current, &current).is_empty());
+    }
+}